@@ -3,9 +3,8 @@ use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut client = Client::new()?;
-    client.open(".")?;
-    let buffer = client.current_buffer()?;
-    client.run_event_filter(&buffer, Filter)?;
+    let buffer = client.open(".")?;
+    client.run_event_filter(buffer, Filter)?;
 
     Ok(())
 }
@@ -0,0 +1,351 @@
+//! Typed addresses for setting the dot and xdot of a buffer
+use std::{fmt, str::FromStr};
+
+/// An error encountered while parsing or validating an [Addr].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrError {
+    /// The input was empty or did not start with a valid address.
+    NotAnAddress,
+    /// A regular expression was missing its closing delimiter.
+    UnclosedDelimiter,
+    /// A mark name was not a single alphanumeric character.
+    InvalidMark(char),
+    /// Unexpected trailing input following a valid address.
+    TrailingInput(String),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAnAddress => write!(f, "not an address"),
+            Self::UnclosedDelimiter => write!(f, "unclosed regex delimiter"),
+            Self::InvalidMark(c) => write!(f, "'{c}' is not a valid mark name"),
+            Self::TrailingInput(s) => write!(f, "unexpected trailing input: {s:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AddrError {}
+
+/// A single position within a buffer.
+///
+/// Lines and columns are 1-based, matching the address syntax used by ad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pos {
+    /// `.` the current dot
+    Current,
+    /// `0` the start of the buffer
+    Bof,
+    /// `$` the end of the buffer
+    Eof,
+    /// `n` the given line
+    Line(usize),
+    /// `+n` or `-n` a line relative to the current dot
+    RelativeLine(isize),
+    /// `#n` the given character offset
+    Char(usize),
+    /// `+#n` or `-#n` a character offset relative to the current dot
+    RelativeChar(isize),
+    /// `n:m` the given line and column
+    LineAndColumn(usize, usize),
+    /// `/re/` the next match of a regular expression
+    Regex(String),
+    /// `-/re/` the previous match of a regular expression
+    RegexBack(String),
+    /// `'a` the position of a named mark
+    Mark(char),
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Current => write!(f, "."),
+            Self::Bof => write!(f, "0"),
+            Self::Eof => write!(f, "$"),
+            Self::Line(n) => write!(f, "{n}"),
+            Self::RelativeLine(n) if *n < 0 => write!(f, "-{}", n.unsigned_abs()),
+            Self::RelativeLine(n) => write!(f, "+{n}"),
+            Self::Char(n) => write!(f, "#{n}"),
+            Self::RelativeChar(n) if *n < 0 => write!(f, "-#{}", n.unsigned_abs()),
+            Self::RelativeChar(n) => write!(f, "+#{n}"),
+            Self::LineAndColumn(line, col) => write!(f, "{line}:{col}"),
+            Self::Regex(re) => write!(f, "/{}/", escape_delimiter(re)),
+            Self::RegexBack(re) => write!(f, "-/{}/", escape_delimiter(re)),
+            Self::Mark(c) => write!(f, "'{c}"),
+        }
+    }
+}
+
+/// An address identifying a range of text within a buffer.
+///
+/// Addresses can either be constructed directly or parsed from a string using [str::parse], which
+/// validates that the string is an address ad will accept. Only single positions and ranges
+/// between two positions are supported: the `+` and `-` suffixes of ad's full address syntax
+/// are not.
+///
+/// ```
+/// use ad_client::{Addr, Pos};
+///
+/// let addr: Addr = "3,/foo/".parse().unwrap();
+/// assert_eq!(addr, Addr::Range(Pos::Line(3), Pos::Regex("foo".to_string())));
+/// assert_eq!(addr.to_string(), "3,/foo/");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Addr {
+    /// A single position
+    Pos(Pos),
+    /// The range from the start of the first position to the end of the second
+    Range(Pos, Pos),
+}
+
+impl Addr {
+    /// The full contents of the buffer.
+    pub fn full() -> Self {
+        Self::Range(Pos::Bof, Pos::Eof)
+    }
+
+    /// The given line.
+    pub fn line(n: usize) -> Self {
+        Self::Pos(Pos::Line(n))
+    }
+
+    /// The range of characters from `from` up to and including `to`.
+    pub fn chars(from: usize, to: usize) -> Self {
+        Self::Range(Pos::Char(from), Pos::Char(to))
+    }
+
+    /// The position of the named mark.
+    pub fn mark(name: char) -> Result<Self, AddrError> {
+        validate_mark(name)?;
+        Ok(Self::Pos(Pos::Mark(name)))
+    }
+
+    /// Check that any marks used in this address have valid names.
+    pub fn validate(&self) -> Result<(), AddrError> {
+        let check = |p: &Pos| match p {
+            Pos::Mark(c) => validate_mark(*c),
+            _ => Ok(()),
+        };
+
+        match self {
+            Self::Pos(p) => check(p),
+            Self::Range(from, to) => check(from).and(check(to)),
+        }
+    }
+}
+
+impl From<Pos> for Addr {
+    fn from(p: Pos) -> Self {
+        Self::Pos(p)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pos(p) => write!(f, "{p}"),
+            Self::Range(from, to) => write!(f, "{from},{to}"),
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = AddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_end();
+        let mut p = Parser { s, pos: 0 };
+
+        // As with ad, a missing start or end of a range defaults to Bof and Eof respectively
+        let start = p.parse_pos()?;
+        let addr = if p.eat(',') {
+            let end = p.parse_pos()?;
+            Addr::Range(start.unwrap_or(Pos::Bof), end.unwrap_or(Pos::Eof))
+        } else {
+            Addr::Pos(start.ok_or(AddrError::NotAnAddress)?)
+        };
+
+        match p.rest() {
+            "" => Ok(addr),
+            rest => Err(AddrError::TrailingInput(rest.to_string())),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.s[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn num(&mut self) -> Option<usize> {
+        let digits = self
+            .rest()
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        let n = self.rest()[..digits].parse().ok()?;
+        self.pos += digits;
+
+        Some(n)
+    }
+
+    fn regex(&mut self) -> Result<String, AddrError> {
+        let mut re = String::new();
+        let mut prev = '/';
+
+        let s = self.s;
+        for ch in s[self.pos..].chars() {
+            self.pos += ch.len_utf8();
+            if ch == '/' && prev != '\\' {
+                return Ok(re);
+            }
+            re.push(ch);
+            prev = ch;
+        }
+
+        Err(AddrError::UnclosedDelimiter)
+    }
+
+    fn parse_pos(&mut self) -> Result<Option<Pos>, AddrError> {
+        let sign = match self.peek() {
+            Some('+') => Some(1),
+            Some('-') => Some(-1),
+            _ => None,
+        };
+        if sign.is_some() {
+            self.pos += 1;
+        }
+
+        let pos = match (self.peek(), sign) {
+            (Some('.'), None) => {
+                self.pos += 1;
+                Pos::Current
+            }
+            (Some('$'), None) => {
+                self.pos += 1;
+                Pos::Eof
+            }
+            (Some('#'), sign) => {
+                self.pos += 1;
+                let n = self.num().ok_or(AddrError::NotAnAddress)?;
+                match sign {
+                    None => Pos::Char(n),
+                    Some(s) => Pos::RelativeChar(s * n as isize),
+                }
+            }
+            (Some(c), sign) if c.is_ascii_digit() => {
+                let n = self.num().ok_or(AddrError::NotAnAddress)?;
+                match sign {
+                    None if self.eat(':') => {
+                        let col = self.num().ok_or(AddrError::NotAnAddress)?;
+                        Pos::LineAndColumn(n, col)
+                    }
+                    None if n == 0 => Pos::Bof,
+                    None => Pos::Line(n),
+                    Some(s) => Pos::RelativeLine(s * n as isize),
+                }
+            }
+            (Some('/'), sign) => {
+                self.pos += 1;
+                let re = self.regex()?;
+                match sign {
+                    Some(-1) => Pos::RegexBack(re),
+                    _ => Pos::Regex(re),
+                }
+            }
+            (Some('\''), None) => {
+                self.pos += 1;
+                let c = self.peek().ok_or(AddrError::NotAnAddress)?;
+                validate_mark(c)?;
+                self.pos += c.len_utf8();
+                Pos::Mark(c)
+            }
+            (_, Some(_)) => return Err(AddrError::NotAnAddress),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(pos))
+    }
+}
+
+fn validate_mark(c: char) -> Result<(), AddrError> {
+    if c.is_alphanumeric() {
+        Ok(())
+    } else {
+        Err(AddrError::InvalidMark(c))
+    }
+}
+
+fn escape_delimiter(re: &str) -> String {
+    let mut s = String::with_capacity(re.len());
+    let mut prev = '\0';
+    for ch in re.chars() {
+        if ch == '/' && prev != '\\' {
+            s.push('\\');
+        }
+        s.push(ch);
+        prev = ch;
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case(".", Addr::Pos(Pos::Current); "current")]
+    #[test_case("0,$", Addr::full(); "full")]
+    #[test_case(",", Addr::full(); "implicit full")]
+    #[test_case("5", Addr::line(5); "line")]
+    #[test_case("3:7", Addr::Pos(Pos::LineAndColumn(3, 7)); "line and column")]
+    #[test_case("1:1,2:4", Addr::Range(Pos::LineAndColumn(1, 1), Pos::LineAndColumn(2, 4)); "addr file contents")]
+    #[test_case("#3,#10", Addr::chars(3, 10); "chars")]
+    #[test_case("-2,+#4", Addr::Range(Pos::RelativeLine(-2), Pos::RelativeChar(4)); "relative")]
+    #[test_case("/a\\/b/,-/c/", Addr::Range(Pos::Regex("a\\/b".to_string()), Pos::RegexBack("c".to_string())); "regex")]
+    #[test_case("'a,'b", Addr::Range(Pos::Mark('a'), Pos::Mark('b')); "marks")]
+    #[test]
+    fn valid_addrs_round_trip(s: &str, expected: Addr) {
+        let addr: Addr = s.parse().unwrap();
+        assert_eq!(addr, expected);
+
+        let reparsed: Addr = addr.to_string().parse().unwrap();
+        assert_eq!(reparsed, expected);
+    }
+
+    #[test_case("", AddrError::NotAnAddress; "empty")]
+    #[test_case("foo", AddrError::NotAnAddress; "not an address")]
+    #[test_case("/foo", AddrError::UnclosedDelimiter; "unclosed regex")]
+    #[test_case("'!", AddrError::InvalidMark('!'); "invalid mark")]
+    #[test_case("3 x", AddrError::TrailingInput(" x".to_string()); "trailing input")]
+    #[test_case("+$", AddrError::NotAnAddress; "relative eof")]
+    #[test]
+    fn invalid_addrs_are_rejected(s: &str, expected: AddrError) {
+        assert_eq!(s.parse::<Addr>(), Err(expected));
+    }
+
+    #[test]
+    fn regex_delimiters_are_escaped() {
+        let addr = Addr::Pos(Pos::Regex("a/b".to_string()));
+        assert_eq!(addr.to_string(), "/a\\/b/");
+    }
+}
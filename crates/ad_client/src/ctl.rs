@@ -0,0 +1,259 @@
+//! Typed commands for ad's ctl file
+use std::fmt;
+
+/// The position of the current line within the window when using [Ctl::Viewport].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viewport {
+    /// Place the current line at the top of the window
+    Top,
+    /// Place the current line at the center of the window
+    Center,
+    /// Place the current line at the bottom of the window
+    Bottom,
+}
+
+/// A command that can be written to ad's ctl file.
+///
+/// Commands apply to the currently active buffer unless they specify a buffer id. The [Display]
+/// implementation renders a command in the form expected by ad.
+///
+/// [Display]: fmt::Display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ctl {
+    /// Switch to the buffer with the given id
+    Buffer(usize),
+    /// Switch to the next buffer
+    BufferNext,
+    /// Switch to the previous buffer
+    BufferPrev,
+    /// Change the editor working directory (to `$HOME` if no path is given)
+    ChangeDirectory(Option<String>),
+    /// Delete the active buffer, discarding any pending changes if `force` is set
+    DeleteBuffer {
+        /// Discard pending changes
+        force: bool,
+    },
+    /// Display a message in the status line
+    Echo(String),
+    /// Run an Edit program against the active buffer
+    Edit(String),
+    /// Smart expand the current cursor position into a range
+    ExpandDot,
+    /// Search files under the working directory for a regex
+    Grep(String),
+    /// Re-run the most recent grep
+    GrepRefresh,
+    /// Display the help file
+    Help,
+    /// Set the named mark to the current dot of the active buffer
+    Mark(char),
+    /// Mark a buffer (the active one if no id is given) as clean
+    MarkClean(Option<usize>),
+    /// Open the given file path in a new buffer
+    Open(String),
+    /// Print the current editor working directory
+    Pwd,
+    /// Exit the editor, discarding pending changes if `force` is set
+    Quit {
+        /// Discard pending changes
+        force: bool,
+    },
+    /// Refresh a buffer (the active one if no id is given) from the file on disk
+    ReloadBuffer(Option<usize>),
+    /// Reload the editor config file
+    ReloadConfig,
+    /// Collect replacements across files for review
+    Replace(String),
+    /// Apply the replacements collected by [Ctl::Replace]
+    ReplaceApply,
+    /// Set a config property (`bg-color=#ebdbb2`)
+    Set(String),
+    /// Open ad's internal logs in a new buffer
+    ViewLogs,
+    /// Reposition the window around the current line
+    Viewport(Viewport),
+    /// Save the active buffer, optionally to a new path
+    Write {
+        /// The path to save to
+        path: Option<String>,
+        /// Ignore changes made to the file on disk
+        force: bool,
+    },
+    /// Save the active buffer and exit
+    WriteQuit {
+        /// Ignore changes made to the file on disk and discard other changes
+        force: bool,
+    },
+    /// Run a shell command (`!cmd`)
+    ShellRun(String),
+    /// Pipe dot through a shell command, replacing it with the output (`|cmd`)
+    ShellPipe(String),
+    /// Replace dot with the output of a shell command (`<cmd`)
+    ShellReplace(String),
+    /// Send dot to a shell command as stdin (`>cmd`)
+    ShellSend(String),
+}
+
+impl fmt::Display for Ctl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bang = |force: &bool| if *force { "!" } else { "" };
+
+        match self {
+            Self::Buffer(id) => write!(f, "buffer {id}"),
+            Self::BufferNext => write!(f, "buffer-next"),
+            Self::BufferPrev => write!(f, "buffer-prev"),
+            Self::ChangeDirectory(None) => write!(f, "cd"),
+            Self::ChangeDirectory(Some(path)) => write!(f, "cd {path}"),
+            Self::DeleteBuffer { force } => write!(f, "delete-buffer{}", bang(force)),
+            Self::Echo(msg) => write!(f, "echo {msg}"),
+            Self::Edit(prog) => write!(f, "Edit {prog}"),
+            Self::ExpandDot => write!(f, "expand-dot"),
+            Self::Grep(re) => write!(f, "grep {re}"),
+            Self::GrepRefresh => write!(f, "grep-refresh"),
+            Self::Help => write!(f, "help"),
+            Self::Mark(name) => write!(f, "mark {name}"),
+            Self::MarkClean(None) => write!(f, "mark-clean"),
+            Self::MarkClean(Some(id)) => write!(f, "mark-clean {id}"),
+            Self::Open(path) => write!(f, "open {path}"),
+            Self::Pwd => write!(f, "pwd"),
+            Self::Quit { force } => write!(f, "quit{}", bang(force)),
+            Self::ReloadBuffer(None) => write!(f, "reload-buffer"),
+            Self::ReloadBuffer(Some(id)) => write!(f, "reload-buffer {id}"),
+            Self::ReloadConfig => write!(f, "reload-config"),
+            Self::Replace(input) => write!(f, "replace {input}"),
+            Self::ReplaceApply => write!(f, "replace-apply"),
+            Self::Set(prop) => write!(f, "set {prop}"),
+            Self::ViewLogs => write!(f, "view-logs"),
+            Self::Viewport(Viewport::Top) => write!(f, "viewport-top"),
+            Self::Viewport(Viewport::Center) => write!(f, "viewport-center"),
+            Self::Viewport(Viewport::Bottom) => write!(f, "viewport-bottom"),
+            Self::Write { path: None, force } => write!(f, "write{}", bang(force)),
+            Self::Write {
+                path: Some(path),
+                force,
+            } => write!(f, "write{} {path}", bang(force)),
+            Self::WriteQuit { force } => write!(f, "write-quit{}", bang(force)),
+            Self::ShellRun(cmd) => write!(f, "!{cmd}"),
+            Self::ShellPipe(cmd) => write!(f, "|{cmd}"),
+            Self::ShellReplace(cmd) => write!(f, "<{cmd}"),
+            Self::ShellSend(cmd) => write!(f, ">{cmd}"),
+        }
+    }
+}
+
+impl Ctl {
+    /// Check that the arguments of this command are ones ad will accept.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Mark(name) if !name.is_alphanumeric() => {
+                Err(format!("'{name}' is not a valid mark name"))
+            }
+            Self::Edit(s) | Self::Grep(s) | Self::Open(s) | Self::Replace(s) if s.is_empty() => {
+                Err(format!("no argument provided for '{self}'"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A command that can be written to the ctl file of an individual buffer.
+///
+/// Unlike [Ctl], these are applied to the given buffer without changing which buffer is focused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferCtl {
+    /// Mark the buffer as clean
+    Clean,
+    /// Mark the buffer as dirty
+    Dirty,
+    /// Save the buffer to the file backing it
+    Save,
+    /// Reload the buffer from the file backing it
+    Get,
+    /// Rename the buffer
+    Name(String),
+    /// Scroll the buffer so that its dot is in the center of the window
+    Show,
+    /// Delete the buffer, discarding any pending changes if `force` is set
+    Delete {
+        /// Discard pending changes
+        force: bool,
+    },
+    /// Undo the last edit made to the buffer
+    Undo,
+    /// Redo the last edit undone in the buffer
+    Redo,
+    /// Scroll the buffer by the given number of lines (negative values scroll up)
+    Scroll(isize),
+    /// Prevent (`true`) or allow (`false`) edits to the buffer
+    ReadOnly(bool),
+    /// Run an Edit program against the buffer
+    Edit(String),
+}
+
+impl fmt::Display for BufferCtl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clean => write!(f, "clean"),
+            Self::Dirty => write!(f, "dirty"),
+            Self::Save => write!(f, "save"),
+            Self::Get => write!(f, "get"),
+            Self::Name(name) => write!(f, "name {name}"),
+            Self::Show => write!(f, "show"),
+            Self::Delete { force: false } => write!(f, "delete"),
+            Self::Delete { force: true } => write!(f, "delete!"),
+            Self::Undo => write!(f, "undo"),
+            Self::Redo => write!(f, "redo"),
+            Self::Scroll(n) => write!(f, "scroll {n}"),
+            Self::ReadOnly(true) => write!(f, "readonly"),
+            Self::ReadOnly(false) => write!(f, "readwrite"),
+            Self::Edit(prog) => write!(f, "edit {prog}"),
+        }
+    }
+}
+
+impl BufferCtl {
+    /// Check that the arguments of this command are ones ad will accept.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Name(s) | Self::Edit(s) if s.trim().is_empty() => {
+                Err(format!("no argument provided for '{self}'"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case(Ctl::Buffer(3), "buffer 3"; "buffer")]
+    #[test_case(Ctl::ChangeDirectory(None), "cd"; "cd home")]
+    #[test_case(Ctl::DeleteBuffer { force: true }, "delete-buffer!"; "forced")]
+    #[test_case(Ctl::Edit("x/foo/ d".to_string()), "Edit x/foo/ d"; "edit")]
+    #[test_case(Ctl::MarkClean(Some(2)), "mark-clean 2"; "optional id")]
+    #[test_case(Ctl::Viewport(Viewport::Center), "viewport-center"; "viewport")]
+    #[test_case(Ctl::Write { path: Some("out.txt".to_string()), force: false }, "write out.txt"; "write as")]
+    #[test_case(Ctl::ShellPipe("sort".to_string()), "|sort"; "shell pipe")]
+    #[test]
+    fn commands_render_correctly(cmd: Ctl, expected: &str) {
+        assert_eq!(cmd.to_string(), expected);
+    }
+
+    #[test_case(BufferCtl::Scroll(-3), "scroll -3"; "scroll")]
+    #[test_case(BufferCtl::ReadOnly(false), "readwrite"; "read write")]
+    #[test_case(BufferCtl::Edit(", x/a/ c/b/".to_string()), "edit , x/a/ c/b/"; "edit")]
+    #[test]
+    fn buffer_commands_render_correctly(cmd: BufferCtl, expected: &str) {
+        assert_eq!(cmd.to_string(), expected);
+    }
+
+    #[test_case(Ctl::Mark('a'), true; "valid mark")]
+    #[test_case(Ctl::Mark('-'), false; "invalid mark")]
+    #[test_case(Ctl::Edit(String::new()), false; "empty edit")]
+    #[test]
+    fn validate_works(cmd: Ctl, valid: bool) {
+        assert_eq!(cmd.validate().is_ok(), valid);
+    }
+}
@@ -0,0 +1,145 @@
+//! Diagnostics that can be written to the diagnostics file of a buffer
+use std::{fmt, str::FromStr};
+
+/// How serious a [Diagnostic] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Informational messages
+    Info,
+    /// Potential problems
+    Warning,
+    /// Errors
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info" => Ok(Self::Info),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("unknown severity: {s:?}")),
+        }
+    }
+}
+
+/// A message attached to a range of text within a buffer, such as those reported by linters
+/// and compilers.
+///
+/// Positions are 1-based `(line, column)` pairs and the end position is exclusive. An empty
+/// range covers the single character at its start. Once set, diagnostics are kept anchored to
+/// the text they cover as the buffer is edited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The position of the first character covered by the diagnostic
+    pub start: (usize, usize),
+    /// The position following the last character covered by the diagnostic
+    pub end: (usize, usize),
+    /// How serious the diagnostic is
+    pub severity: Severity,
+    /// The message to display for the diagnostic
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let (start, end) = (self.start, self.end);
+        if start.0 == 0 || start.1 == 0 || end.0 == 0 || end.1 == 0 {
+            return Err("diagnostic positions are 1-based".to_string());
+        } else if end < start {
+            return Err(format!("diagnostic ends before it starts: {self}"));
+        } else if self.message.contains('\n') {
+            return Err("diagnostic messages must be a single line".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ((sl, sc), (el, ec)) = (self.start, self.end);
+        write!(f, "{sl}:{sc}-{el}:{ec} {} {}", self.severity, self.message)
+    }
+}
+
+impl FromStr for Diagnostic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.strip_suffix('\n').unwrap_or(s);
+        let invalid = || format!("invalid diagnostic: {s:?}");
+        let parse_pos = |p: &str| -> Option<(usize, usize)> {
+            let (line, col) = p.split_once(':')?;
+            Some((line.parse().ok()?, col.parse().ok()?))
+        };
+
+        let mut fields = line.splitn(3, ' ');
+        let (start, end) = fields
+            .next()
+            .and_then(|r| r.split_once('-'))
+            .and_then(|(start, end)| Some((parse_pos(start)?, parse_pos(end)?)))
+            .ok_or_else(invalid)?;
+        let severity = fields.next().ok_or_else(invalid)?.parse()?;
+        let message = fields.next().unwrap_or_default().to_string();
+
+        Ok(Self {
+            start,
+            end,
+            severity,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn diagnostic(start: (usize, usize), end: (usize, usize), message: &str) -> Diagnostic {
+        Diagnostic {
+            start,
+            end,
+            severity: Severity::Warning,
+            message: message.to_string(),
+        }
+    }
+
+    #[test_case("2:9-2:10 warning unused variable\n", Ok(diagnostic((2, 9), (2, 10), "unused variable")); "valid")]
+    #[test_case("2:9-2:10 warning", Ok(diagnostic((2, 9), (2, 10), "")); "no message")]
+    #[test_case("2:9-2:10 fatal oops", Err("unknown severity: \"fatal\"".to_string()); "unknown severity")]
+    #[test_case("2:9 warning oops", Err("invalid diagnostic: \"2:9 warning oops\"".to_string()); "missing end")]
+    #[test]
+    fn parse_diagnostic_works(s: &str, expected: Result<Diagnostic, String>) {
+        assert_eq!(s.parse::<Diagnostic>(), expected);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let d = diagnostic((1, 4), (3, 1), "spans lines");
+        assert_eq!(d.to_string().parse::<Diagnostic>(), Ok(d));
+    }
+
+    #[test_case(diagnostic((0, 1), (1, 1), "x"); "zero line")]
+    #[test_case(diagnostic((2, 1), (1, 1), "x"); "backwards")]
+    #[test_case(diagnostic((1, 1), (1, 2), "x\ny"); "multi line message")]
+    #[test]
+    fn invalid_diagnostics_are_rejected(d: Diagnostic) {
+        assert!(d.validate().is_err());
+    }
+}
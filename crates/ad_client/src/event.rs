@@ -69,7 +69,7 @@ pub trait EventFilter {
 }
 
 pub(crate) fn run_filter<F>(
-    buffer: &str,
+    buffer: usize,
     mut filter: F,
     client: &mut Client,
 ) -> Result<(), Box<dyn Error>>
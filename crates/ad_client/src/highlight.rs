@@ -0,0 +1,148 @@
+//! Style spans that can be written to the highlights file of a buffer
+use std::{fmt, str::FromStr};
+
+/// The named styles that can be applied to a [Highlight].
+///
+/// Faces are rendered using the corresponding colors from the editor colorscheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    /// Unstyled text
+    Default,
+    /// Comments
+    Comment,
+    /// String literals
+    String,
+    /// Language keywords
+    Keyword,
+    /// Control flow keywords
+    ControlFlow,
+    /// Definitions such as function and type names
+    Definition,
+    /// Punctuation
+    Punctuation,
+    /// Underlined as an error
+    Error,
+    /// Underlined as a warning
+    Warning,
+    /// Underlined as information
+    Info,
+}
+
+impl fmt::Display for Face {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Default => "default",
+            Self::Comment => "comment",
+            Self::String => "string",
+            Self::Keyword => "keyword",
+            Self::ControlFlow => "control-flow",
+            Self::Definition => "definition",
+            Self::Punctuation => "punctuation",
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for Face {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "comment" => Ok(Self::Comment),
+            "string" => Ok(Self::String),
+            "keyword" => Ok(Self::Keyword),
+            "control-flow" => Ok(Self::ControlFlow),
+            "definition" => Ok(Self::Definition),
+            "punctuation" => Ok(Self::Punctuation),
+            "error" => Ok(Self::Error),
+            "warning" => Ok(Self::Warning),
+            "info" => Ok(Self::Info),
+            _ => Err(format!("unknown face: {s:?}")),
+        }
+    }
+}
+
+/// A styled range of characters within a buffer.
+///
+/// Highlights are kept anchored to the text they cover as the buffer is edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Highlight {
+    /// The character offset of the first styled character
+    pub from: usize,
+    /// The character offset following the last styled character
+    pub to: usize,
+    /// The style to apply
+    pub face: Face,
+}
+
+impl Highlight {
+    /// Construct a new highlight for the half open character range `from..to`.
+    pub fn new(from: usize, to: usize, face: Face) -> Self {
+        Self { from, to, face }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.from >= self.to {
+            return Err(format!("empty highlight: {} {}", self.from, self.to));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Highlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.from, self.to, self.face)
+    }
+}
+
+impl FromStr for Highlight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid highlight: {s:?}");
+        let fields: Vec<&str> = s.split_whitespace().collect();
+
+        match fields[..] {
+            [from, to, face] => Ok(Self {
+                from: from.parse().map_err(|_| invalid())?,
+                to: to.parse().map_err(|_| invalid())?,
+                face: face.parse()?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case(Face::Default; "default")]
+    #[test_case(Face::ControlFlow; "control flow")]
+    #[test_case(Face::Warning; "warning")]
+    #[test]
+    fn face_round_trips(face: Face) {
+        assert_eq!(face.to_string().parse::<Face>(), Ok(face));
+    }
+
+    #[test_case("3 7 keyword\n", Ok(Highlight::new(3, 7, Face::Keyword)); "valid")]
+    #[test_case("3 7 sparkly", Err("unknown face: \"sparkly\"".to_string()); "unknown face")]
+    #[test_case("3 keyword", Err("invalid highlight: \"3 keyword\"".to_string()); "missing field")]
+    #[test]
+    fn parse_highlight_works(s: &str, expected: Result<Highlight, String>) {
+        assert_eq!(s.parse::<Highlight>(), expected);
+    }
+
+    #[test]
+    fn empty_highlights_are_invalid() {
+        assert!(Highlight::new(4, 4, Face::Error).validate().is_err());
+        assert!(Highlight::new(4, 5, Face::Error).validate().is_ok());
+    }
+}
@@ -11,11 +11,107 @@
     clippy::undocumented_unsafe_blocks
 )]
 use ninep::client::{ReadLineIter, UnixClient};
-use std::{error::Error, io, os::unix::net::UnixStream};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, ErrorKind},
+    os::unix::net::UnixStream,
+};
 
+mod addr;
+mod ctl;
+mod diagnostic;
 mod event;
+mod highlight;
+mod log;
+mod observer;
 
+pub use addr::{Addr, AddrError, Pos};
+pub use ctl::{BufferCtl, Ctl, Viewport};
+pub use diagnostic::{Diagnostic, Severity};
 pub use event::{EventFilter, Outcome};
+pub use highlight::{Face, Highlight};
+pub use log::{LogEvent, LogEvents, LogKind};
+pub use observer::{BufferEvent, BufferEvents, Change, EventKind};
+
+/// An entry in the index of open buffers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferInfo {
+    /// The id of the buffer
+    pub id: usize,
+    /// The full name of the buffer: the path of the file for buffers backed by a file
+    pub filename: String,
+}
+
+/// The state of a buffer as reported by its ctl file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferMetadata {
+    /// The id of the buffer
+    pub id: usize,
+    /// The kind of buffer: one of "file", "directory", "virtual", "output" or "unnamed"
+    pub kind: String,
+    /// The full name of the buffer
+    pub name: String,
+    /// The number of characters in the buffer
+    pub chars: usize,
+    /// The number of lines in the buffer
+    pub lines: usize,
+    /// Whether or not the buffer has unsaved changes
+    pub dirty: bool,
+    /// Whether or not edits to the buffer are currently disallowed
+    pub read_only: bool,
+}
+
+fn parse_metadata(s: &str) -> io::Result<BufferMetadata> {
+    let fields: HashMap<&str, &str> = s.lines().filter_map(|l| l.split_once('\t')).collect();
+    let field = |key: &str| {
+        fields
+            .get(key)
+            .copied()
+            .ok_or_else(|| invalid_data(format!("missing buffer metadata field: {key}")))
+    };
+    let parsed = |key: &str| -> io::Result<usize> {
+        field(key)?
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid buffer metadata field: {key}")))
+    };
+
+    Ok(BufferMetadata {
+        id: parsed("id")?,
+        kind: field("kind")?.to_string(),
+        name: field("name")?.to_string(),
+        chars: parsed("chars")?,
+        lines: parsed("lines")?,
+        dirty: field("dirty")? == "true",
+        read_only: field("readonly")? == "true",
+    })
+}
+
+fn parse_index(s: &str) -> io::Result<Vec<BufferInfo>> {
+    s.lines()
+        .map(|line| match line.split_once('\t') {
+            Some((id, filename)) => Ok(BufferInfo {
+                id: parse_id(id)?,
+                filename: filename.to_string(),
+            }),
+            None => Err(invalid_data(format!("invalid index line: {line:?}"))),
+        })
+        .collect()
+}
+
+fn parse_id(s: &str) -> io::Result<usize> {
+    s.trim()
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid buffer id: {s:?}")))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
 
 /// A simple 9p client for ad
 #[derive(Debug)]
@@ -31,42 +127,66 @@ impl Client {
         })
     }
 
-    pub(crate) fn event_lines(&mut self, buffer: &str) -> io::Result<ReadLineIter<UnixStream>> {
+    pub(crate) fn event_lines(&mut self, buffer: usize) -> io::Result<ReadLineIter<UnixStream>> {
         self.inner.iter_lines(format!("buffers/{buffer}/event"))
     }
 
-    pub(crate) fn write_event(&mut self, buffer: &str, event_line: &str) -> io::Result<()> {
+    pub(crate) fn write_event(&mut self, buffer: usize, event_line: &str) -> io::Result<()> {
         self.inner
             .write_str(format!("buffers/{buffer}/event"), 0, event_line)?;
         Ok(())
     }
 
     /// Get the currently active buffer id.
-    pub fn current_buffer(&mut self) -> io::Result<String> {
-        self.inner.read_str("buffers/current")
+    pub fn current_buffer(&mut self) -> io::Result<usize> {
+        parse_id(&self.inner.read_str("buffers/current")?)
+    }
+
+    /// Create a new, empty buffer without changing focus and return its id.
+    ///
+    /// The buffer can be named using [BufferCtl::Name]: names of the form `+name` create an
+    /// in-memory buffer that is never written to disk.
+    pub fn new_buffer(&mut self) -> io::Result<usize> {
+        parse_id(&self.inner.read_str("buffers/new")?)
+    }
+
+    /// List the currently open buffers.
+    pub fn buffers(&mut self) -> io::Result<Vec<BufferInfo>> {
+        parse_index(&self.inner.read_str("buffers/index")?)
     }
 
-    fn _read_buffer_file(&mut self, buffer: &str, file: &str) -> io::Result<String> {
+    fn _read_buffer_file(&mut self, buffer: usize, file: &str) -> io::Result<String> {
         self.inner.read_str(format!("buffers/{buffer}/{file}"))
     }
 
+    fn _read_buffer_addr(&mut self, buffer: usize, file: &str) -> io::Result<Addr> {
+        self._read_buffer_file(buffer, file)?
+            .parse()
+            .map_err(|e: AddrError| invalid_data(e.to_string()))
+    }
+
+    /// Read the current state of the given buffer from its ctl file.
+    pub fn read_metadata(&mut self, buffer: usize) -> io::Result<BufferMetadata> {
+        parse_metadata(&self._read_buffer_file(buffer, "ctl")?)
+    }
+
     /// Read the contents of the dot of the given buffer
-    pub fn read_dot(&mut self, buffer: &str) -> io::Result<String> {
+    pub fn read_dot(&mut self, buffer: usize) -> io::Result<String> {
         self._read_buffer_file(buffer, "dot")
     }
 
     /// Read the body of the given buffer.
-    pub fn read_body(&mut self, buffer: &str) -> io::Result<String> {
+    pub fn read_body(&mut self, buffer: usize) -> io::Result<String> {
         self._read_buffer_file(buffer, "body")
     }
 
     /// Read the current dot address of the given buffer.
-    pub fn read_addr(&mut self, buffer: &str) -> io::Result<String> {
-        self._read_buffer_file(buffer, "addr")
+    pub fn read_addr(&mut self, buffer: usize) -> io::Result<Addr> {
+        self._read_buffer_addr(buffer, "addr")
     }
 
     /// Read the filename of the given buffer
-    pub fn read_filename(&mut self, buffer: &str) -> io::Result<String> {
+    pub fn read_filename(&mut self, buffer: usize) -> io::Result<String> {
         self._read_buffer_file(buffer, "filename")
     }
 
@@ -74,21 +194,37 @@ impl Client {
     ///
     /// This is only used by the filesystem interface of `ad` and will not affect the current
     /// editor state.
-    pub fn read_xaddr(&mut self, buffer: &str) -> io::Result<String> {
-        self._read_buffer_file(buffer, "xaddr")
+    pub fn read_xaddr(&mut self, buffer: usize) -> io::Result<Addr> {
+        self._read_buffer_addr(buffer, "xaddr")
+    }
+
+    /// Read the highlights that have been set for the given buffer.
+    pub fn read_highlights(&mut self, buffer: usize) -> io::Result<Vec<Highlight>> {
+        self._read_buffer_file(buffer, "highlights")?
+            .lines()
+            .map(|line| line.parse().map_err(invalid_data))
+            .collect()
+    }
+
+    /// Read the diagnostics that have been set for the given buffer.
+    pub fn read_diagnostics(&mut self, buffer: usize) -> io::Result<Vec<Diagnostic>> {
+        self._read_buffer_file(buffer, "diagnostics")?
+            .lines()
+            .map(|line| line.parse().map_err(invalid_data))
+            .collect()
     }
 
     /// Read the x-dot of the given buffer.
     ///
     /// This is only used by the filesystem interface of `ad` and will not affect the current
     /// editor state.
-    pub fn read_xdot(&mut self, buffer: &str) -> io::Result<String> {
+    pub fn read_xdot(&mut self, buffer: usize) -> io::Result<String> {
         self._read_buffer_file(buffer, "xdot")
     }
 
     fn _write_buffer_file(
         &mut self,
-        buffer: &str,
+        buffer: usize,
         file: &str,
         offset: u64,
         content: &[u8],
@@ -98,57 +234,259 @@ impl Client {
     }
 
     /// Replace the dot of the given buffer with the provided string.
-    pub fn write_dot(&mut self, buffer: &str, content: &str) -> io::Result<usize> {
+    pub fn write_dot(&mut self, buffer: usize, content: &str) -> io::Result<usize> {
         self._write_buffer_file(buffer, "dot", 0, content.as_bytes())
     }
 
     /// Write the provided string to the specified offset in the given buffer.
-    pub fn write_body(&mut self, buffer: &str, offset: u64, content: &str) -> io::Result<usize> {
+    pub fn write_body(&mut self, buffer: usize, offset: u64, content: &str) -> io::Result<usize> {
         self._write_buffer_file(buffer, "body", offset, content.as_bytes())
     }
 
     /// Set the addr of the given buffer.
-    pub fn write_addr(&mut self, buffer: &str, addr: &str) -> io::Result<usize> {
-        self._write_buffer_file(buffer, "addr", 0, addr.as_bytes())
+    pub fn write_addr(&mut self, buffer: usize, addr: &Addr) -> io::Result<()> {
+        addr.validate().map_err(|e| invalid_input(e.to_string()))?;
+        self._write_buffer_file(buffer, "addr", 0, addr.to_string().as_bytes())?;
+
+        Ok(())
     }
 
     /// Replace the xdot of the given buffer with the provided string.
-    pub fn write_xdot(&mut self, buffer: &str, offset: u64, content: &str) -> io::Result<usize> {
+    pub fn write_xdot(&mut self, buffer: usize, offset: u64, content: &str) -> io::Result<usize> {
         self._write_buffer_file(buffer, "xdot", offset, content.as_bytes())
     }
 
     /// Set the xaddr of the given buffer.
-    pub fn write_xaddr(&mut self, buffer: &str, content: &str) -> io::Result<usize> {
-        self._write_buffer_file(buffer, "xaddr", 0, content.as_bytes())
+    pub fn write_xaddr(&mut self, buffer: usize, addr: &Addr) -> io::Result<()> {
+        addr.validate().map_err(|e| invalid_input(e.to_string()))?;
+        self._write_buffer_file(buffer, "xaddr", 0, addr.to_string().as_bytes())?;
+
+        Ok(())
     }
 
-    fn _ctl(&mut self, command: &str, args: &str) -> io::Result<()> {
-        self.inner
-            .write("ctl", 0, format!("{command} {args}").as_bytes())?;
+    /// Append the provided string to the output of the given buffer.
+    ///
+    /// Output is written to a `+output` buffer associated with the directory of the given buffer.
+    pub fn write_output(&mut self, buffer: usize, content: &str) -> io::Result<usize> {
+        self._write_buffer_file(buffer, "output", 0, content.as_bytes())
+    }
+
+    /// Add the provided highlights to the given buffer.
+    ///
+    /// Highlights are merged with the built-in syntax highlighting for the buffer and take
+    /// precedence over it where they overlap.
+    pub fn add_highlights(&mut self, buffer: usize, highlights: &[Highlight]) -> io::Result<()> {
+        let mut s = String::new();
+        for h in highlights.iter() {
+            h.validate().map_err(invalid_input)?;
+            s.push_str(&format!("{h}\n"));
+        }
+        self._write_buffer_file(buffer, "highlights", 0, s.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Remove highlights from the given buffer.
+    ///
+    /// If a range is provided then only highlights overlapping the half open range of characters
+    /// `from..to` are removed, otherwise all highlights are removed.
+    pub fn clear_highlights(
+        &mut self,
+        buffer: usize,
+        range: Option<(usize, usize)>,
+    ) -> io::Result<()> {
+        let cmd = match range {
+            Some((from, to)) if from >= to => {
+                return Err(invalid_input(format!("empty range: {from} {to}")))
+            }
+            Some((from, to)) => format!("clear {from} {to}\n"),
+            None => "clear\n".to_string(),
+        };
+        self._write_buffer_file(buffer, "highlights", 0, cmd.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Replace the diagnostics for the given buffer with those provided.
+    ///
+    /// Lines with diagnostics are marked in the sign column of the editor and the text they
+    /// cover is underlined. Passing an empty slice clears all diagnostics from the buffer.
+    pub fn set_diagnostics(&mut self, buffer: usize, diagnostics: &[Diagnostic]) -> io::Result<()> {
+        let mut s = "clear\n".to_string();
+        for d in diagnostics.iter() {
+            d.validate().map_err(invalid_input)?;
+            s.push_str(&format!("{d}\n"));
+        }
+        self._write_buffer_file(buffer, "diagnostics", 0, s.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Send a command to ad's ctl file.
+    pub fn ctl(&mut self, cmd: Ctl) -> io::Result<()> {
+        cmd.validate().map_err(invalid_input)?;
+        self.inner.write_str("ctl", 0, &cmd.to_string())?;
+
+        Ok(())
+    }
+
+    /// Send a command to the ctl file of the given buffer.
+    ///
+    /// Buffer commands are applied without changing the focused buffer.
+    pub fn buffer_ctl(&mut self, buffer: usize, cmd: BufferCtl) -> io::Result<()> {
+        cmd.validate().map_err(invalid_input)?;
+        self._write_buffer_file(buffer, "ctl", 0, cmd.to_string().as_bytes())?;
 
         Ok(())
     }
 
     /// Echo a string message in the status line.
     pub fn echo(&mut self, msg: impl AsRef<str>) -> io::Result<()> {
-        self._ctl("echo", msg.as_ref())
+        self.ctl(Ctl::Echo(msg.as_ref().to_string()))
+    }
+
+    /// Open the requested file, returning the id of the buffer it was opened in.
+    ///
+    /// If the file is already open then its existing buffer is focused and its id is returned.
+    /// Paths that do not exist on disk are opened in a new, empty buffer.
+    pub fn open(&mut self, path: impl AsRef<str>) -> io::Result<usize> {
+        self.ctl(Ctl::Open(path.as_ref().to_string()))?;
+        self.current_buffer()
     }
 
-    /// Open the requested file.
-    pub fn open(&mut self, path: impl AsRef<str>) -> io::Result<()> {
-        self._ctl("open", path.as_ref())
+    /// Focus the buffer with the given id.
+    pub fn focus(&mut self, buffer: usize) -> io::Result<()> {
+        self.ctl(Ctl::Buffer(buffer))
     }
 
     /// Reload the currently active buffer.
     pub fn reload_current_buffer(&mut self) -> io::Result<()> {
-        self._ctl("reload", "")
+        self.ctl(Ctl::ReloadBuffer(None))
+    }
+
+    /// Set the named mark to the current dot of the active buffer.
+    ///
+    /// Marks can then be used in addresses via [Addr::mark].
+    pub fn set_mark(&mut self, name: char) -> io::Result<()> {
+        self.ctl(Ctl::Mark(name))
+    }
+
+    /// Run an Edit program against the given buffer without changing the focused buffer.
+    pub fn run_edit(&mut self, buffer: usize, prog: impl AsRef<str>) -> io::Result<()> {
+        self.buffer_ctl(buffer, BufferCtl::Edit(prog.as_ref().to_string()))
+    }
+
+    /// Prompt the user to select one of the provided lines using the minibuffer, blocking until
+    /// they do so.
+    ///
+    /// Returns `None` if the user cancels the selection.
+    pub fn minibuffer_select<S: AsRef<str>>(
+        &mut self,
+        prompt: Option<&str>,
+        lines: &[S],
+    ) -> io::Result<Option<String>> {
+        if let Some(prompt) = prompt {
+            self.inner
+                .write_str("ctl", 0, &format!("minibuffer-prompt {prompt}"))?;
+        }
+
+        let content: String = lines.iter().map(|l| format!("{}\n", l.as_ref())).collect();
+        self.inner.write_str("minibuffer", 0, &content)?;
+
+        let selection = self.inner.read_str("minibuffer")?;
+        let selection = selection.trim_end_matches('\n');
+
+        if selection.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(selection.to_string()))
+        }
+    }
+
+    /// Stream events from ad's log as buffers are opened, closed, focused and saved.
+    ///
+    /// The returned iterator blocks until each new event is available.
+    pub fn log_events(&mut self) -> io::Result<LogEvents> {
+        Ok(LogEvents {
+            lines: self.inner.iter_lines("log")?,
+        })
+    }
+
+    /// Stream changes made to any buffer without taking control of how input is handled.
+    ///
+    /// Only events of the given kinds are reported, with all kinds being reported if `kinds` is
+    /// empty. The returned iterator blocks until each new event is available.
+    pub fn events(&mut self, kinds: &[EventKind]) -> io::Result<BufferEvents> {
+        self.observe("events".to_string(), kinds)
+    }
+
+    /// Stream changes made to the given buffer without taking control of how input is handled.
+    ///
+    /// See [Client::events] for details.
+    pub fn buffer_events(
+        &mut self,
+        buffer: usize,
+        kinds: &[EventKind],
+    ) -> io::Result<BufferEvents> {
+        self.observe(format!("buffers/{buffer}/events"), kinds)
+    }
+
+    fn observe(&mut self, path: String, kinds: &[EventKind]) -> io::Result<BufferEvents> {
+        if !kinds.is_empty() {
+            let kinds: Vec<String> = kinds.iter().map(|k| k.to_string()).collect();
+            self.inner.write_str(path.clone(), 0, &kinds.join(" "))?;
+        }
+
+        Ok(BufferEvents {
+            lines: self.inner.iter_lines(path)?,
+        })
     }
 
     /// Run a provided [EventFilter] until it exits or errors
-    pub fn run_event_filter<F>(&mut self, buffer: &str, filter: F) -> Result<(), Box<dyn Error>>
+    pub fn run_event_filter<F>(&mut self, buffer: usize, filter: F) -> Result<(), Box<dyn Error>>
     where
         F: EventFilter,
     {
         event::run_filter(buffer, filter, self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_index_works() {
+        let index = "1\t/home/me/notes.md\n3\t+output\n";
+        let expected = vec![
+            BufferInfo {
+                id: 1,
+                filename: "/home/me/notes.md".to_string(),
+            },
+            BufferInfo {
+                id: 3,
+                filename: "+output".to_string(),
+            },
+        ];
+
+        assert_eq!(parse_index(index).unwrap(), expected);
+        assert!(parse_index("not an index line\n").is_err());
+    }
+
+    #[test]
+    fn parse_metadata_works() {
+        let raw = "id\t2\nkind\tfile\nname\t/tmp/foo\nchars\t12\nlines\t3\ndirty\ttrue\nreadonly\tfalse\n";
+        let expected = BufferMetadata {
+            id: 2,
+            kind: "file".to_string(),
+            name: "/tmp/foo".to_string(),
+            chars: 12,
+            lines: 3,
+            dirty: true,
+            read_only: false,
+        };
+
+        assert_eq!(parse_metadata(raw).unwrap(), expected);
+        assert!(parse_metadata("id\t2\n").is_err());
+    }
+}
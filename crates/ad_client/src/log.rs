@@ -0,0 +1,80 @@
+//! Streaming of events from ad's log file
+use ninep::client::ReadLineIter;
+use std::{os::unix::net::UnixStream, str::FromStr};
+
+/// The kind of change to a buffer reported in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogKind {
+    /// The buffer was opened
+    Open,
+    /// The buffer was closed
+    Close,
+    /// The buffer became the active buffer
+    Focus,
+    /// The buffer was saved
+    Save,
+}
+
+/// An event read from ad's log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEvent {
+    /// The id of the buffer the event relates to
+    pub id: usize,
+    /// What happened to the buffer
+    pub kind: LogKind,
+}
+
+impl FromStr for LogEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, kind) = s
+            .trim_end()
+            .split_once(' ')
+            .ok_or_else(|| format!("invalid log line: {s:?}"))?;
+
+        let id = id
+            .parse()
+            .map_err(|_| format!("invalid buffer id: {id:?}"))?;
+        let kind = match kind {
+            "open" => LogKind::Open,
+            "close" => LogKind::Close,
+            "focus" => LogKind::Focus,
+            "save" => LogKind::Save,
+            _ => return Err(format!("unknown log event: {kind:?}")),
+        };
+
+        Ok(Self { id, kind })
+    }
+}
+
+/// A blocking iterator of [LogEvent]s as they occur within the editor.
+///
+/// Lines that can not be parsed as a [LogEvent] are skipped.
+#[derive(Debug)]
+pub struct LogEvents {
+    pub(crate) lines: ReadLineIter<UnixStream>,
+}
+
+impl Iterator for LogEvents {
+    type Item = LogEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.by_ref().find_map(|line| line.parse().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case("1 open\n", Ok(LogEvent { id: 1, kind: LogKind::Open }); "open")]
+    #[test_case("12 save", Ok(LogEvent { id: 12, kind: LogKind::Save }); "no newline")]
+    #[test_case("x focus", Err("invalid buffer id: \"x\"".to_string()); "invalid id")]
+    #[test_case("3 explode", Err("unknown log event: \"explode\"".to_string()); "unknown kind")]
+    #[test]
+    fn parse_log_event_works(s: &str, expected: Result<LogEvent, String>) {
+        assert_eq!(s.parse::<LogEvent>(), expected);
+    }
+}
@@ -0,0 +1,207 @@
+//! Passive observation of changes made within the editor
+use ninep::client::ReadLineIter;
+use std::{fmt, os::unix::net::UnixStream, str::FromStr};
+
+/// The kinds of change that can be observed using [Client::events] and [Client::buffer_events].
+///
+/// [Client::events]: crate::Client::events
+/// [Client::buffer_events]: crate::Client::buffer_events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Text was inserted into a buffer
+    Insert,
+    /// Text was deleted from a buffer
+    Delete,
+    /// The dot of a buffer changed
+    Dot,
+    /// A buffer was saved
+    Save,
+    /// The editor mode changed
+    Mode,
+    /// A buffer became the active buffer
+    Focus,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+            Self::Dot => "dot",
+            Self::Save => "save",
+            Self::Mode => "mode",
+            Self::Focus => "focus",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+/// A change observed within the editor.
+///
+/// Character offsets for inserts and deletes are the half open range of characters that were
+/// modified. Those for dot are the start and end of the new dot, inclusive of the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Text was inserted into the buffer
+    Insert {
+        /// The character offset the text was inserted at
+        from: usize,
+        /// The character offset following the inserted text
+        to: usize,
+        /// The text that was inserted
+        txt: String,
+    },
+    /// Text was deleted from the buffer
+    Delete {
+        /// The character offset of the first deleted character
+        from: usize,
+        /// The character offset following the last deleted character
+        to: usize,
+    },
+    /// The dot of the buffer changed
+    Dot {
+        /// The character offset of the start of dot
+        from: usize,
+        /// The character offset of the end of dot
+        to: usize,
+    },
+    /// The buffer was saved
+    Save,
+    /// The editor changed to the named mode while the buffer was active
+    Mode(String),
+    /// The buffer became the active buffer
+    Focus,
+}
+
+impl Change {
+    /// The kind of this change
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Insert { .. } => EventKind::Insert,
+            Self::Delete { .. } => EventKind::Delete,
+            Self::Dot { .. } => EventKind::Dot,
+            Self::Save => EventKind::Save,
+            Self::Mode(_) => EventKind::Mode,
+            Self::Focus => EventKind::Focus,
+        }
+    }
+}
+
+/// An event read from one of ad's events files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferEvent {
+    /// The id of the buffer the event relates to
+    pub id: usize,
+    /// What changed
+    pub change: Change,
+}
+
+impl FromStr for BufferEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.strip_suffix('\n').unwrap_or(s);
+        let invalid = || format!("invalid event line: {s:?}");
+        let usize_field = |f: Option<&str>| -> Result<usize, String> {
+            f.and_then(|f| f.parse().ok()).ok_or_else(invalid)
+        };
+
+        let mut fields = line.splitn(3, ' ');
+        let id = usize_field(fields.next())?;
+        let kind = fields.next().ok_or_else(invalid)?;
+        let rest = fields.next();
+
+        let change = match kind {
+            "insert" => {
+                let mut fields = rest.ok_or_else(invalid)?.splitn(3, ' ');
+                Change::Insert {
+                    from: usize_field(fields.next())?,
+                    to: usize_field(fields.next())?,
+                    txt: unescape(fields.next().unwrap_or_default()),
+                }
+            }
+            "delete" | "dot" => {
+                let mut fields = rest.ok_or_else(invalid)?.split(' ');
+                let (from, to) = (usize_field(fields.next())?, usize_field(fields.next())?);
+                if kind == "delete" {
+                    Change::Delete { from, to }
+                } else {
+                    Change::Dot { from, to }
+                }
+            }
+            "mode" => Change::Mode(rest.ok_or_else(invalid)?.to_string()),
+            "save" => Change::Save,
+            "focus" => Change::Focus,
+            _ => return Err(format!("unknown event: {kind:?}")),
+        };
+
+        Ok(Self { id, change })
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut txt = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                txt.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                txt.push('\\');
+            }
+            _ => txt.push(ch),
+        }
+    }
+
+    txt
+}
+
+/// A blocking iterator of [BufferEvent]s as they occur within the editor.
+///
+/// Lines that can not be parsed as a [BufferEvent] are skipped.
+#[derive(Debug)]
+pub struct BufferEvents {
+    pub(crate) lines: ReadLineIter<UnixStream>,
+}
+
+impl Iterator for BufferEvents {
+    type Item = BufferEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.by_ref().find_map(|line| line.parse().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn ins(from: usize, to: usize, txt: &str) -> Change {
+        Change::Insert {
+            from,
+            to,
+            txt: txt.to_string(),
+        }
+    }
+
+    #[test_case("1 insert 3 4 a\n", Ok(ins(3, 4, "a")); "insert")]
+    #[test_case("1 insert 3 4  \n", Ok(ins(3, 4, " ")); "insert space")]
+    #[test_case("1 insert 3 8 a\\nb\\\\n\n", Ok(ins(3, 8, "a\nb\\n")); "insert escaped")]
+    #[test_case("2 delete 0 10\n", Ok(Change::Delete { from: 0, to: 10 }); "delete")]
+    #[test_case("2 dot 4 7\n", Ok(Change::Dot { from: 4, to: 7 }); "dot")]
+    #[test_case("2 mode INSERT\n", Ok(Change::Mode("INSERT".to_string())); "mode")]
+    #[test_case("5 save\n", Ok(Change::Save); "save")]
+    #[test_case("5 focus", Ok(Change::Focus); "no newline")]
+    #[test_case("5 dot 4\n", Err("invalid event line: \"5 dot 4\\n\"".to_string()); "missing field")]
+    #[test_case("5 explode\n", Err("unknown event: \"explode\"".to_string()); "unknown kind")]
+    #[test]
+    fn parse_buffer_event_works(s: &str, expected: Result<Change, String>) {
+        let res = s.parse::<BufferEvent>().map(|evt| evt.change);
+        assert_eq!(res, expected);
+    }
+}
@@ -0,0 +1,15 @@
+//! Decoding arbitrary bytes as an Rmessage must never panic and anything that decodes
+//! successfully must re-encode to a message that decodes to the same value.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use ninep::protocol::{Format9p, Rmessage};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = Rmessage::read_from(&mut &data[..]) {
+        let mut buf = Vec::new();
+        if msg.write_to(&mut buf).is_ok() {
+            assert_eq!(buf.len(), msg.n_bytes());
+            assert_eq!(Rmessage::read_from(&mut &buf[..]).unwrap(), msg);
+        }
+    }
+});
@@ -0,0 +1,149 @@
+//! Pluggable authentication for 9p servers.
+//!
+//! Authentication follows the flow described in [attach(5)](http://man.cat-v.org/plan_9/5/attach):
+//! a client sends a Tauth message to establish an afid and then reads a challenge from it before
+//! writing back its response. Once the response has been verified the afid can be provided in a
+//! Tattach message for the same uname and aname in order to access the file tree.
+use crate::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The number of random bytes in a [SharedSecret] challenge.
+const CHALLENGE_LEN: usize = 32;
+
+/// A challenge / response authentication scheme used to verify the identity of clients before
+/// they are able to attach to a [Server](crate::server::Server).
+///
+/// Once a client has authenticated, its uname is the one passed to each of the methods on
+/// [Serve9p](crate::server::Serve9p) so that implementations are able to make per-user permission
+/// checks in [walk](crate::server::Serve9p::walk), [open](crate::server::Serve9p::open) etc.
+pub trait Auth: fmt::Debug + Send + Sync + 'static {
+    /// Generate a new challenge for the given user attempting to access the file tree named by
+    /// aname. The challenge is returned to the client when it reads from its afid.
+    fn challenge(&self, uname: &str, aname: &str) -> Result<Vec<u8>>;
+
+    /// Verify the response written to an afid by a client for a challenge previously returned by
+    /// [Auth::challenge]. Returning `Ok` grants the user access to the file tree named by aname.
+    fn verify(&self, uname: &str, aname: &str, challenge: &[u8], response: &[u8]) -> Result<()>;
+}
+
+/// Authentication using a secret shared between the server and its clients.
+///
+/// Challenges are random nonces and clients must respond with the HMAC-SHA256 of the uname, aname
+/// and challenge keyed using the shared secret (see [SharedSecret::response]). The secret itself
+/// is never sent over the connection.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SharedSecret {
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for SharedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSecret").finish_non_exhaustive()
+    }
+}
+
+impl SharedSecret {
+    /// Create a new [SharedSecret] from the given bytes.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Read a shared secret from the given file, ignoring any trailing whitespace.
+    ///
+    /// The file must only be accessible by its owner (mode 0600 or stricter) and contain a
+    /// non-empty secret.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "{} must not be accessible by other users: mode is {:o}, expected 600",
+                    path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+
+        let mut secret = fs::read(path)?;
+        while secret.last().is_some_and(|b| b.is_ascii_whitespace()) {
+            secret.pop();
+        }
+
+        if secret.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is empty", path.display()),
+            ));
+        }
+
+        Ok(Self { secret })
+    }
+
+    /// The response a client should write to its afid for a given challenge.
+    pub fn response(&self, uname: &str, aname: &str, challenge: &[u8]) -> Vec<u8> {
+        self.mac(uname, aname, challenge)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self, uname: &str, aname: &str, challenge: &[u8]) -> HmacSha256 {
+        // HMAC accepts keys of any length so this can't fail
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("valid key length");
+        mac.update(uname.as_bytes());
+        mac.update(&[0]);
+        mac.update(aname.as_bytes());
+        mac.update(&[0]);
+        mac.update(challenge);
+
+        mac
+    }
+}
+
+impl Auth for SharedSecret {
+    fn challenge(&self, _uname: &str, _aname: &str) -> Result<Vec<u8>> {
+        let mut buf = vec![0; CHALLENGE_LEN];
+        getrandom::getrandom(&mut buf).map_err(|e| format!("unable to generate challenge: {e}"))?;
+
+        Ok(buf)
+    }
+
+    fn verify(&self, uname: &str, aname: &str, challenge: &[u8], response: &[u8]) -> Result<()> {
+        // verify_slice compares in constant time to avoid leaking how much of the response
+        // was correct
+        self.mac(uname, aname, challenge)
+            .verify_slice(response)
+            .map_err(|_| "authentication failed".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case("alice", "", true; "matching")]
+    #[test_case("bob", "", false; "different uname")]
+    #[test_case("alice", "other", false; "different aname")]
+    #[test]
+    fn shared_secret_verify_works(uname: &str, aname: &str, ok: bool) {
+        let s = SharedSecret::new("secret");
+        let challenge = s.challenge("alice", "").unwrap();
+        let response = s.response(uname, aname, &challenge);
+
+        assert_eq!(s.verify("alice", "", &challenge, &response).is_ok(), ok);
+    }
+}
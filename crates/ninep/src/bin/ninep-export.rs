@@ -0,0 +1,121 @@
+//! Export a directory over 9p using [ninep::export::Export].
+use ninep::{auth::SharedSecret, export::Export, server::Server};
+use std::{env, path::Path, process::exit};
+
+const USAGE: &str = "\
+usage:
+  ninep-export [-r] [-n name] dir                    Serve dir on a unix socket in the namespace
+  ninep-export [-r] -p port [-s secret-file] dir     Serve dir over tcp on 127.0.0.1:port
+
+  -r                Export dir read only
+  -n name           The socket name to use (defaults to the name of dir)
+  -p port           Serve over tcp instead of a unix socket
+  -s secret-file    Require clients to authenticate using the secret in secret-file
+
+  ninep-export -h | --help    Print this help message
+";
+
+#[derive(Debug, Default)]
+struct Args {
+    read_only: bool,
+    name: Option<String>,
+    port: Option<u16>,
+    secret_file: Option<String>,
+    dir: Option<String>,
+}
+
+fn main() {
+    let args = parse_args();
+    let dir = match args.dir {
+        Some(dir) => dir,
+        None => fatal("no directory provided"),
+    };
+
+    let mut export = match Export::new(&dir) {
+        Ok(export) => export,
+        Err(e) => fatal(&format!("unable to export {dir}: {e}")),
+    };
+    if args.read_only {
+        export = export.read_only();
+    }
+    let mut server = Server::new(export);
+
+    let handle = match args.port {
+        Some(port) => {
+            if let Some(path) = args.secret_file {
+                match SharedSecret::from_file(&path) {
+                    Ok(secret) => server = server.with_auth(secret),
+                    Err(e) => fatal(&format!("unable to load secret from {path}: {e}")),
+                }
+            }
+            eprintln!("exporting {dir} on 127.0.0.1:{port}");
+            server.serve_tcp(port)
+        }
+
+        None => {
+            if args.secret_file.is_some() {
+                fatal("-s is only supported when serving over tcp");
+            }
+            let name = args.name.unwrap_or_else(|| default_name(&dir));
+            match server.serve_socket(&name) {
+                Ok(handle) => {
+                    eprintln!("exporting {dir} as {name}");
+                    handle
+                }
+                Err(e) => fatal(&format!("unable to serve {name}: {e}")),
+            }
+        }
+    };
+
+    _ = handle.join();
+}
+
+fn parse_args() -> Args {
+    let mut args = env::args().skip(1);
+    let mut parsed = Args::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| match args.next() {
+            Some(v) => v,
+            None => fatal(&format!("no value provided for {flag}")),
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => show_help(),
+            "-r" => parsed.read_only = true,
+            "-n" => parsed.name = Some(value("-n")),
+            "-p" => match value("-p").parse() {
+                Ok(port) => parsed.port = Some(port),
+                Err(e) => fatal(&format!("invalid port: {e}")),
+            },
+            "-s" => parsed.secret_file = Some(value("-s")),
+            flag if flag.starts_with('-') => fatal(&format!("unknown flag: {flag}")),
+            dir if parsed.dir.is_none() => parsed.dir = Some(dir.to_string()),
+            _ => fatal("only a single directory can be exported"),
+        }
+    }
+
+    parsed
+}
+
+fn default_name(dir: &str) -> String {
+    match Path::new(dir).canonicalize() {
+        Ok(p) => match p.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "root".to_string(),
+        },
+        Err(_) => dir.to_string(),
+    }
+}
+
+fn fatal(msg: &str) -> ! {
+    eprintln!("{msg}\n\n{USAGE}");
+    exit(1);
+}
+
+fn show_help() -> ! {
+    println!("ninep-export v{}", env!("CARGO_PKG_VERSION"));
+    println!("\nExport a directory over 9p\n");
+    println!("{USAGE}");
+    exit(0);
+}
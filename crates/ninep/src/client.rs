@@ -1,7 +1,15 @@
 //! A simple 9p client for building out application specific client applications.
+//!
+//! Requests made by a [Client] (and any of its clones) are multiplexed over a single connection
+//! using distinct tags: a background thread reads each R-message from the server and hands it to
+//! the caller waiting on that tag, so a request that blocks on the server does not prevent other
+//! requests from being answered.
 use crate::{
+    auth::SharedSecret,
     fs::{Mode, Perm, Stat},
-    protocol::{Data, Format9p, RawStat, Rdata, Rmessage, Tdata, Tmessage},
+    namespace::socket_path,
+    protocol::{Data, Format9p, RawStat, Rdata, Rmessage, Tdata, Tmessage, IO_HEADER_SIZE},
+    trace::{Direction, Role, Tracer},
     Stream,
 };
 use std::{
@@ -9,10 +17,16 @@ use std::{
     collections::HashMap,
     env,
     io::{self, Cursor, ErrorKind},
-    mem,
+    mem::{self, size_of},
     net::{TcpStream, ToSocketAddrs},
     os::unix::net::UnixStream,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::spawn,
+    time::Duration,
 };
 
 // TODO:
@@ -40,12 +54,21 @@ macro_rules! expect_rmessage {
 
 const MSIZE: u32 = u16::MAX as u32;
 const VERSION: &str = "9P2000";
+const NOTAG: u16 = u16::MAX;
 
 fn err<T, E>(e: E) -> io::Result<T>
 where
     E: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    Err(io::Error::new(io::ErrorKind::Other, e))
+    Err(io::Error::other(e))
+}
+
+#[inline]
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
 }
 
 /// A client that operates over an underlying [UnixStream].
@@ -58,6 +81,9 @@ pub type TcpClient = Client<TcpStream>;
 ///
 /// Support for each of the operations exposed by this client is determined by the server
 /// implementation that it is connected to.
+///
+/// Cloning a client is cheap and all clones share the same underlying connection. Requests from
+/// different clones (or threads) are pipelined rather than waiting for each other to complete.
 #[derive(Debug)]
 pub struct Client<S>
 where
@@ -66,7 +92,7 @@ where
     /// The shared inner client holding our connection to the server
     ///
     /// Shared between clones
-    inner: Arc<Mutex<ClientInner<S>>>,
+    inner: Arc<ClientInner<S>>,
     msize: u32,
 }
 
@@ -82,16 +108,160 @@ where
     }
 }
 
+/// Requests awaiting a response from the server, keyed by tag.
+#[derive(Debug, Default)]
+struct Pending {
+    next_tag: u16,
+    waiting: HashMap<u16, Sender<Rmessage>>,
+    closed: bool,
+}
+
+impl Pending {
+    fn register(&mut self, tag: Option<u16>) -> io::Result<(u16, Receiver<Rmessage>)> {
+        if self.closed {
+            return err("connection closed");
+        }
+
+        let tag = match tag {
+            Some(tag) if !self.waiting.contains_key(&tag) => tag,
+            Some(tag) => return err(format!("tag {tag} is in use")),
+            None => self.next_free_tag()?,
+        };
+
+        let (tx, rx) = channel();
+        self.waiting.insert(tag, tx);
+
+        Ok((tag, rx))
+    }
+
+    fn next_free_tag(&mut self) -> io::Result<u16> {
+        for _ in 0..NOTAG {
+            let tag = self.next_tag;
+            self.next_tag = (self.next_tag + 1) % NOTAG;
+            if !self.waiting.contains_key(&tag) {
+                return Ok(tag);
+            }
+        }
+
+        err("too many outstanding requests")
+    }
+}
+
+/// A single connection to a 9p server that multiplexes requests using their tags.
+#[derive(Debug)]
+struct Conn<S>
+where
+    S: Stream,
+{
+    writer: Mutex<S>,
+    pending: Arc<Mutex<Pending>>,
+    trace: Option<(String, Tracer)>,
+}
+
+impl<S> Conn<S>
+where
+    S: Stream,
+{
+    /// Take ownership of the stream and spawn a thread for dispatching responses.
+    fn new(stream: S) -> io::Result<Self> {
+        let mut reader = stream.try_clone().map_err(io::Error::other)?;
+        let pending: Arc<Mutex<Pending>> = Default::default();
+        let p = pending.clone();
+        let trace = Tracer::from_env(Role::Client).map(|t| (Tracer::client_conn(), t));
+        let t = trace.clone();
+
+        spawn(move || loop {
+            match Rmessage::read_from_with_limit(&mut reader, MSIZE) {
+                Ok(r) => {
+                    if let Some((conn, tracer)) = &t {
+                        let mut buf = Vec::new();
+                        if r.write_to(&mut buf).is_ok() {
+                            tracer.record(conn, Direction::Received, &buf);
+                        }
+                    }
+
+                    if let Some(tx) = lock(&p).waiting.remove(&r.tag) {
+                        _ = tx.send(r);
+                    }
+                }
+
+                Err(_) => {
+                    // Dropping the senders wakes up anyone still waiting on a response
+                    let mut p = lock(&p);
+                    p.closed = true;
+                    p.waiting.clear();
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            writer: Mutex::new(stream),
+            pending,
+            trace,
+        })
+    }
+
+    fn start(&self, tag: Option<u16>, content: Tdata) -> io::Result<(u16, Receiver<Rmessage>)> {
+        let (tag, rx) = lock(&self.pending).register(tag)?;
+
+        let mut buf = Vec::new();
+        Tmessage { tag, content }.write_to(&mut buf)?;
+        // Recording before writing ensures that the request is always traced before its response
+        if let Some((conn, tracer)) = &self.trace {
+            tracer.record(conn, Direction::Sent, &buf);
+        }
+        if let Err(e) = lock(&self.writer).write_all(&buf) {
+            lock(&self.pending).waiting.remove(&tag);
+            return Err(e);
+        }
+
+        Ok((tag, rx))
+    }
+
+    /// Flush an outstanding request, returning its response if it arrived before the flush was
+    /// acknowledged by the server.
+    fn flush(&self, old_tag: u16, rx: &Receiver<Rmessage>) -> io::Result<Option<Rmessage>> {
+        let res = self
+            .start(None, Tdata::Flush { old_tag })
+            .and_then(|(_, frx)| recv(&frx));
+
+        // Once Rflush has been received the server will not reply to old_tag so it can be reused
+        lock(&self.pending).waiting.remove(&old_tag);
+        res?;
+
+        Ok(rx.try_recv().ok())
+    }
+
+    fn shutdown(&self) {
+        _ = lock(&self.writer).shutdown();
+    }
+}
+
+fn max_read_count(msize: u32) -> u32 {
+    msize.saturating_sub(IO_HEADER_SIZE as u32).max(1)
+}
+
+fn recv(rx: &Receiver<Rmessage>) -> io::Result<Rmessage> {
+    match rx.recv() {
+        Ok(Rmessage {
+            content: Rdata::Error { ename },
+            ..
+        }) => err(ename),
+        Ok(msg) => Ok(msg),
+        Err(_) => err("connection closed"),
+    }
+}
+
 #[derive(Debug)]
 struct ClientInner<S>
 where
     S: Stream,
 {
-    stream: S,
+    conn: Conn<S>,
     uname: String,
-    msize: u32,
-    fids: HashMap<String, u32>,
-    next_fid: u32,
+    fids: Mutex<HashMap<String, u32>>,
+    next_fid: AtomicU32,
 }
 
 impl<S> Drop for ClientInner<S>
@@ -99,10 +269,11 @@ where
     S: Stream,
 {
     fn drop(&mut self) {
-        let fids = std::mem::take(&mut self.fids);
+        let fids = mem::take(&mut *lock(&self.fids));
         for (_, fid) in fids.into_iter() {
-            _ = self.send(0, Tdata::Clunk { fid });
+            _ = self.send(Tdata::Clunk { fid });
         }
+        self.conn.shutdown();
     }
 }
 
@@ -110,24 +281,126 @@ impl<S> ClientInner<S>
 where
     S: Stream,
 {
-    fn send(&mut self, tag: u16, content: Tdata) -> io::Result<Rmessage> {
-        let t = Tmessage { tag, content };
-        t.write_to(&mut self.stream)?;
+    fn send(&self, content: Tdata) -> io::Result<Rmessage> {
+        let (_, rx) = self.conn.start(None, content)?;
+        recv(&rx)
+    }
+
+    fn next_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Run the challenge / response exchange for [SharedSecret] authentication over a new afid,
+    /// returning the afid to use when attaching.
+    fn authenticate(
+        &self,
+        uname: &str,
+        aname: &str,
+        msize: u32,
+        secret: &SharedSecret,
+    ) -> io::Result<u32> {
+        let afid = self.next_fid();
+        self.send(Tdata::Auth {
+            afid,
+            uname: uname.to_string(),
+            aname: aname.to_string(),
+        })?;
+
+        let resp = self.send(Tdata::Read {
+            fid: afid,
+            offset: 0,
+            count: max_read_count(msize),
+        })?;
+        let Data(challenge) = expect_rmessage!(resp, Read { data });
+
+        let response = secret.response(uname, aname, &challenge);
+        self.send(Tdata::Write {
+            fid: afid,
+            offset: 0,
+            data: Data(response),
+        })?;
+
+        Ok(afid)
+    }
+}
 
-        match Rmessage::read_from(&mut self.stream)? {
-            Rmessage {
+/// An in-flight request made using [Client::request].
+///
+/// Dropping a request before its response has been received cancels it by sending a Tflush to
+/// the server.
+#[derive(Debug)]
+pub struct Request<S>
+where
+    S: Stream,
+{
+    inner: Arc<ClientInner<S>>,
+    tag: u16,
+    rx: Receiver<Rmessage>,
+    done: bool,
+}
+
+impl<S> Request<S>
+where
+    S: Stream,
+{
+    /// Block until the response to this request is received.
+    pub fn wait(mut self) -> io::Result<Rdata> {
+        self.done = true;
+        recv(&self.rx).map(|r| r.content)
+    }
+
+    /// Block for up to the given duration waiting for the response to this request, returning
+    /// `None` if it has not been received by the time the timeout expires.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> io::Result<Option<Rdata>> {
+        if self.done {
+            return err("response already received");
+        }
+
+        match self.rx.recv_timeout(timeout) {
+            Ok(Rmessage {
                 content: Rdata::Error { ename },
                 ..
-            } => err(ename),
-            msg => Ok(msg),
+            }) => {
+                self.done = true;
+                err(ename)
+            }
+            Ok(r) => {
+                self.done = true;
+                Ok(Some(r.content))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.done = true;
+                err("connection closed")
+            }
         }
     }
 
-    fn next_fid(&mut self) -> u32 {
-        let fid = self.next_fid;
-        self.next_fid += 1;
+    /// Cancel this request by sending a Tflush to the server.
+    ///
+    /// If the server responded to the request before the flush was processed then the response
+    /// is returned and should be handled as if the request had not been cancelled.
+    pub fn cancel(mut self) -> io::Result<Option<Rdata>> {
+        self.done = true;
+        match self.inner.conn.flush(self.tag, &self.rx)? {
+            Some(Rmessage {
+                content: Rdata::Error { ename },
+                ..
+            }) => err(ename),
+            Some(r) => Ok(Some(r.content)),
+            None => Ok(None),
+        }
+    }
+}
 
-        fid
+impl<S> Drop for Request<S>
+where
+    S: Stream,
+{
+    fn drop(&mut self) {
+        if !self.done {
+            _ = self.inner.conn.flush(self.tag, &self.rx);
+        }
     }
 }
 
@@ -139,35 +412,18 @@ impl Client<UnixStream> {
         aname: impl Into<String>,
     ) -> io::Result<Self> {
         let stream = UnixStream::connect(path)?;
-        let mut fids = HashMap::new();
-        fids.insert(String::new(), 0);
 
-        let mut client = Self {
-            inner: Arc::new(Mutex::new(ClientInner {
-                stream,
-                uname,
-                msize: MSIZE,
-                fids,
-                next_fid: 1,
-            })),
-            msize: MSIZE,
-        };
-        client.connect(aname)?;
-
-        Ok(client)
+        Self::new_from_stream(uname, stream, aname, None)
     }
 
-    /// Create a new [Client] connected to a unix socket at the given aname under the default
-    /// namespace.
-    ///
-    /// The default namespace is located in /tmp/ns.$USER.:0/
-    pub fn new_unix(ns: impl Into<String>, aname: impl Into<String>) -> io::Result<Self> {
-        let ns = ns.into();
+    /// Create a new [Client] connected to the server with the given name in the current
+    /// [namespace](crate::namespace).
+    pub fn new_unix(name: impl AsRef<str>, aname: impl Into<String>) -> io::Result<Self> {
         let uname = match env::var("USER") {
             Ok(s) => s,
             Err(_) => return err("USER env var not set"),
         };
-        let path = format!("/tmp/ns.{uname}.:0/{ns}");
+        let path = socket_path(name.as_ref())?;
 
         Self::new_unix_with_explicit_path(uname, path, aname)
     }
@@ -180,22 +436,24 @@ impl Client<TcpStream> {
         T: ToSocketAddrs,
     {
         let stream = TcpStream::connect(addr)?;
-        let mut fids = HashMap::new();
-        fids.insert(String::new(), 0);
 
-        let mut client = Self {
-            inner: Arc::new(Mutex::new(ClientInner {
-                stream,
-                uname,
-                msize: MSIZE,
-                fids,
-                next_fid: 1,
-            })),
-            msize: MSIZE,
-        };
-        client.connect(aname)?;
+        Self::new_from_stream(uname, stream, aname, None)
+    }
 
-        Ok(client)
+    /// Create a new [Client] connected to a tcp socket at the specified address, authenticating
+    /// with the server using the given [SharedSecret].
+    pub fn new_tcp_with_auth<T>(
+        uname: String,
+        addr: T,
+        aname: impl Into<String>,
+        secret: &SharedSecret,
+    ) -> io::Result<Self>
+    where
+        T: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(addr)?;
+
+        Self::new_from_stream(uname, stream, aname, Some(secret))
     }
 }
 
@@ -203,80 +461,141 @@ impl<S> Client<S>
 where
     S: Stream,
 {
-    #[inline]
-    fn inner(&mut self) -> MutexGuard<'_, ClientInner<S>> {
-        match self.inner.lock() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        }
+    pub(crate) fn new_from_stream(
+        uname: String,
+        stream: S,
+        aname: impl Into<String>,
+        secret: Option<&SharedSecret>,
+    ) -> io::Result<Self> {
+        let mut fids = HashMap::new();
+        fids.insert(String::new(), 0);
+
+        let mut client = Self {
+            inner: Arc::new(ClientInner {
+                conn: Conn::new(stream)?,
+                uname,
+                fids: Mutex::new(fids),
+                next_fid: AtomicU32::new(1),
+            }),
+            msize: MSIZE,
+        };
+        client.connect(aname, secret)?;
+
+        Ok(client)
     }
 
     /// Establish our connection to the target 9p server and begin the session.
-    fn connect(&mut self, aname: impl Into<String>) -> io::Result<()> {
-        let mut inner = self.inner();
-        let resp = inner.send(
-            u16::MAX,
+    fn connect(
+        &mut self,
+        aname: impl Into<String>,
+        secret: Option<&SharedSecret>,
+    ) -> io::Result<()> {
+        let inner = &self.inner;
+        let (_, rx) = inner.conn.start(
+            Some(NOTAG),
             Tdata::Version {
                 msize: MSIZE,
                 version: VERSION.to_string(),
             },
         )?;
+        let resp = recv(&rx)?;
 
         let (msize, version) = expect_rmessage!(resp, Version { msize, version });
         if version != VERSION {
             return err("server version not supported");
         }
-        inner.msize = msize;
         let uname = inner.uname.clone();
+        let aname = aname.into();
 
-        inner.send(
-            0,
-            Tdata::Attach {
-                fid: 0,
-                afid: u32::MAX, // no auth
-                uname,
-                aname: aname.into(),
-            },
-        )?;
+        let afid = match secret {
+            Some(secret) => inner.authenticate(&uname, &aname, msize, secret)?,
+            None => u32::MAX, // no auth
+        };
+
+        let res = inner.send(Tdata::Attach {
+            fid: 0,
+            afid,
+            uname,
+            aname,
+        });
+
+        if secret.is_some() {
+            _ = inner.send(Tdata::Clunk { fid: afid });
+        }
+        res?;
 
-        drop(inner);
         self.msize = msize;
 
         Ok(())
     }
 
+    /// Send a raw request to the server without waiting for its response.
+    ///
+    /// The returned [Request] can be used to wait for the response or to cancel the request
+    /// using a Tflush. Fids used in requests should be obtained using [Client::walk].
+    pub fn request(&self, content: Tdata) -> io::Result<Request<S>> {
+        let (tag, rx) = self.inner.conn.start(None, content)?;
+
+        Ok(Request {
+            inner: self.inner.clone(),
+            tag,
+            rx,
+            done: false,
+        })
+    }
+
     /// Associate the given path with a new fid.
     pub fn walk(&mut self, path: impl Into<String>) -> io::Result<u32> {
-        let mut inner = self.inner();
         let path = path.into();
-        if let Some(fid) = inner.fids.get(&path) {
+        if let Some(fid) = lock(&self.inner.fids).get(&path) {
             return Ok(*fid);
         }
 
-        let new_fid = inner.next_fid();
+        let new_fid = self.walk_new_fid(&path)?;
 
-        inner.send(
-            0,
-            Tdata::Walk {
-                fid: 0,
-                new_fid,
-                wnames: path.split('/').map(Into::into).collect(),
-            },
-        )?;
+        // Another clone may have walked to the same path while we were waiting
+        let fid = *lock(&self.inner.fids).entry(path).or_insert(new_fid);
+        if fid != new_fid {
+            _ = self.inner.send(Tdata::Clunk { fid: new_fid });
+        }
 
-        inner.fids.insert(path, new_fid);
+        Ok(fid)
+    }
 
-        Ok(new_fid)
+    /// Walk to the given path using a fid that is not shared with other requests.
+    fn walk_new_fid(&self, path: &str) -> io::Result<u32> {
+        let new_fid = self.inner.next_fid();
+        let wnames: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(Into::into)
+            .collect();
+        let n = wnames.len();
+
+        let resp = self.inner.send(Tdata::Walk {
+            fid: 0,
+            new_fid,
+            wnames,
+        })?;
+
+        // A walk that fails part way through returns the qids walked so far and does not
+        // associate new_fid with anything
+        match resp.content {
+            Rdata::Walk { wqids } if wqids.len() < n => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{path}: file not found"),
+            )),
+            _ => Ok(new_fid),
+        }
     }
 
     /// Free server side state for the given fid.
     ///
     /// Clunks of the root fid (0) will be ignored
     pub fn clunk(&mut self, fid: u32) -> io::Result<()> {
-        let mut inner = self.inner();
         if fid != 0 {
-            inner.send(0, Tdata::Clunk { fid })?;
-            inner.fids.retain(|_, v| *v != fid);
+            lock(&self.inner.fids).retain(|_, v| *v != fid);
+            self.inner.send(Tdata::Clunk { fid })?;
         }
 
         Ok(())
@@ -284,7 +603,7 @@ where
 
     /// Free server side state for the given path.
     pub fn clunk_path(&mut self, path: impl Into<String>) -> io::Result<()> {
-        let fid = match self.inner().fids.get(&path.into()) {
+        let fid = match lock(&self.inner.fids).get(&path.into()) {
             Some(fid) => *fid,
             None => return Ok(()),
         };
@@ -295,8 +614,7 @@ where
     /// Request the current [Stat] of the file or directory identified by the given path.
     pub fn stat(&mut self, path: impl Into<String>) -> io::Result<Stat> {
         let fid = self.walk(path)?;
-        let mut inner = self.inner();
-        let resp = inner.send(0, Tdata::Stat { fid })?;
+        let resp = self.inner.send(Tdata::Stat { fid })?;
         let raw_stat = expect_rmessage!(resp, Stat { stat, .. });
 
         match raw_stat.try_into() {
@@ -305,8 +623,13 @@ where
         }
     }
 
+    /// The largest count we can request in a Tread while keeping the Rread within msize.
+    fn max_read_count(&self) -> u32 {
+        max_read_count(self.msize)
+    }
+
     fn _read_count(&mut self, fid: u32, offset: u64, count: u32) -> io::Result<Vec<u8>> {
-        let resp = self.inner().send(0, Tdata::Read { fid, offset, count })?;
+        let resp = self.inner.send(Tdata::Read { fid, offset, count })?;
         let Data(data) = expect_rmessage!(resp, Read { data });
 
         Ok(data)
@@ -315,9 +638,9 @@ where
     fn _read_all(&mut self, path: impl Into<String>, mode: Mode) -> io::Result<Vec<u8>> {
         let fid = self.walk(path)?;
         let mode = mode.bits();
-        self.inner().send(0, Tdata::Open { fid, mode })?;
+        self.inner.send(Tdata::Open { fid, mode })?;
 
-        let count = self.msize;
+        let count = self.max_read_count();
         let mut bytes = Vec::new();
         let mut offset = 0;
         loop {
@@ -375,8 +698,8 @@ where
     pub fn iter_chunks(&mut self, path: impl Into<String>) -> io::Result<ChunkIter<S>> {
         let fid = self.walk(path)?;
         let mode = Mode::FILE.bits();
-        let count = self.msize;
-        self.inner().send(0, Tdata::Open { fid, mode })?;
+        let count = self.max_read_count();
+        self.inner.send(Tdata::Open { fid, mode })?;
 
         Ok(ChunkIter {
             client: self.clone(),
@@ -390,8 +713,8 @@ where
     pub fn iter_lines(&mut self, path: impl Into<String>) -> io::Result<ReadLineIter<S>> {
         let fid = self.walk(path)?;
         let mode = Mode::FILE.bits();
-        let count = self.msize;
-        self.inner().send(0, Tdata::Open { fid, mode })?;
+        let count = self.max_read_count();
+        self.inner.send(Tdata::Open { fid, mode })?;
 
         Ok(ReadLineIter {
             client: self.clone(),
@@ -415,18 +738,14 @@ where
         let mut cur = 0;
         let header_size = 4 + 8 + 4; // fid + offset + data len
         let chunk_size = (self.msize - header_size) as usize;
-        let mut inner = self.inner();
 
         while cur <= len {
             let end = min(cur + chunk_size, len);
-            let resp = inner.send(
-                0,
-                Tdata::Write {
-                    fid,
-                    offset,
-                    data: Data(content[cur..end].to_vec()),
-                },
-            )?;
+            let resp = self.inner.send(Tdata::Write {
+                fid,
+                offset,
+                data: Data(content[cur..end].to_vec()),
+            })?;
             let n = expect_rmessage!(resp, Write { count });
             if n == 0 {
                 break;
@@ -460,27 +779,77 @@ where
         perms: Perm,
         mode: Mode,
     ) -> io::Result<()> {
-        let fid = self.walk(dir)?;
-        self.inner().send(
-            0,
-            Tdata::Create {
-                fid,
-                name: name.into(),
-                perm: perms.bits(),
-                mode: mode.bits(),
-            },
-        )?;
+        // A successful create moves the fid to the new file so we avoid using a cached one
+        let fid = self.walk_new_fid(&dir.into())?;
+        let res = self.inner.send(Tdata::Create {
+            fid,
+            name: name.into(),
+            perm: perms.bits(),
+            mode: mode.bits(),
+        });
+        _ = self.inner.send(Tdata::Clunk { fid });
+        res?;
+
+        Ok(())
+    }
+
+    /// Rename the file at `path` to `name` within the same directory.
+    pub fn rename(&mut self, path: impl Into<String>, name: impl Into<String>) -> io::Result<()> {
+        let (path, name) = (path.into(), name.into());
+        let mut stat = RawStat::null();
+        stat.size += name.len() as u16;
+        stat.name = name;
+
+        self.write_stat(path.clone(), stat)?;
+        self.forget(&path);
+
+        Ok(())
+    }
+
+    /// Truncate (or extend) the file at `path` to be `len` bytes long.
+    pub fn truncate(&mut self, path: impl Into<String>, len: u64) -> io::Result<()> {
+        let mut stat = RawStat::null();
+        stat.length = len;
+
+        self.write_stat(path, stat)
+    }
+
+    fn write_stat(&mut self, path: impl Into<String>, stat: RawStat) -> io::Result<()> {
+        let fid = self.walk(path)?;
+        self.inner.send(Tdata::Wstat {
+            fid,
+            size: stat.size + size_of::<u16>() as u16,
+            stat,
+        })?;
 
         Ok(())
     }
 
     /// Attempt to remove a file from the connected filesystem.
     pub fn remove(&mut self, path: impl Into<String>) -> io::Result<()> {
-        let fid = self.walk(path)?;
-        self.inner().send(0, Tdata::Remove { fid })?;
+        let path = path.into();
+        let fid = self.walk(path.clone())?;
+
+        // The fid is clunked by the server regardless of whether or not the remove succeeds
+        lock(&self.inner.fids).retain(|_, v| *v != fid);
+        self.inner.send(Tdata::Remove { fid })?;
 
         Ok(())
     }
+
+    /// Clunk the fids we hold for `path` and anything beneath it after it has been renamed.
+    fn forget(&mut self, path: &str) {
+        let prefix = format!("{path}/");
+        let fids: Vec<u32> = lock(&self.inner.fids)
+            .iter()
+            .filter(|(p, _)| *p == path || p.starts_with(&prefix))
+            .map(|(_, &fid)| fid)
+            .collect();
+
+        for fid in fids {
+            _ = self.clunk(fid);
+        }
+    }
 }
 
 /// An iterator of [Vec<u8>] chunks out of a given file.
@@ -570,3 +939,143 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Qid;
+
+    const ROOT_QID: Qid = Qid {
+        ty: 0x80,
+        version: 0,
+        path: 0,
+    };
+
+    /// Run a fake server on one end of a socket pair, handling version and attach before passing
+    /// all other T-messages to `handle` in order to generate responses.
+    fn client_with_server<F>(mut handle: F) -> UnixClient
+    where
+        F: FnMut(Tmessage) -> Vec<Rmessage> + Send + 'static,
+    {
+        let (client, mut server) = UnixStream::pair().unwrap();
+
+        spawn(move || {
+            while let Ok(t) = Tmessage::read_from(&mut server) {
+                let responses = match t.content {
+                    Tdata::Version { msize, version } => vec![Rmessage {
+                        tag: t.tag,
+                        content: Rdata::Version { msize, version },
+                    }],
+                    Tdata::Attach { .. } => vec![Rmessage {
+                        tag: t.tag,
+                        content: Rdata::Attach { aqid: ROOT_QID },
+                    }],
+                    Tdata::Walk { .. } => vec![Rmessage {
+                        tag: t.tag,
+                        content: Rdata::Walk {
+                            wqids: vec![ROOT_QID],
+                        },
+                    }],
+                    Tdata::Clunk { .. } => vec![Rmessage {
+                        tag: t.tag,
+                        content: Rdata::Clunk {},
+                    }],
+                    _ => handle(t),
+                };
+
+                for r in responses {
+                    r.write_to(&mut server).unwrap();
+                }
+            }
+        });
+
+        Client::new_from_stream("test".to_string(), client, "", None).unwrap()
+    }
+
+    fn read(fid: u32) -> Tdata {
+        Tdata::Read {
+            fid,
+            offset: 0,
+            count: 100,
+        }
+    }
+
+    fn read_resp(tag: u16, fid: u32) -> Rmessage {
+        Rmessage {
+            tag,
+            content: Rdata::Read {
+                data: Data(fid.to_string().into_bytes()),
+            },
+        }
+    }
+
+    #[test]
+    fn responses_are_dispatched_by_tag() {
+        // Hold the first read until the second arrives and then reply in reverse order
+        let mut held = None;
+        let client = client_with_server(move |t| match (t.content, held.take()) {
+            (Tdata::Read { fid, .. }, None) => {
+                held = Some((t.tag, fid));
+                vec![]
+            }
+            (Tdata::Read { fid, .. }, Some((tag, held_fid))) => {
+                vec![read_resp(t.tag, fid), read_resp(tag, held_fid)]
+            }
+            (content, _) => panic!("unexpected message: {content:?}"),
+        });
+
+        let r1 = client.request(read(1)).unwrap();
+        let r2 = client.clone().request(read(2)).unwrap();
+
+        assert_eq!(
+            r2.wait().unwrap(),
+            Rdata::Read {
+                data: Data(b"2".to_vec())
+            }
+        );
+        assert_eq!(
+            r1.wait().unwrap(),
+            Rdata::Read {
+                data: Data(b"1".to_vec())
+            }
+        );
+    }
+
+    #[test]
+    fn cancelled_requests_are_flushed() {
+        let (tx, rx) = channel();
+        let client = client_with_server(move |t| match t.content {
+            // Never reply to reads of fid 1
+            Tdata::Read { fid: 1, .. } => vec![],
+            Tdata::Read { fid, .. } => vec![read_resp(t.tag, fid)],
+            Tdata::Flush { old_tag } => {
+                tx.send(old_tag).unwrap();
+                vec![Rmessage {
+                    tag: t.tag,
+                    content: Rdata::Flush {},
+                }]
+            }
+            content => panic!("unexpected message: {content:?}"),
+        });
+
+        let mut r = client.request(read(1)).unwrap();
+        let tag = r.tag;
+        assert_eq!(r.wait_timeout(Duration::from_millis(10)).unwrap(), None);
+        assert_eq!(r.cancel().unwrap(), None);
+        assert_eq!(rx.try_recv(), Ok(tag));
+
+        // Dropping an outstanding request also flushes it
+        let r = client.request(read(1)).unwrap();
+        let tag = r.tag;
+        drop(r);
+        assert_eq!(rx.try_recv(), Ok(tag));
+
+        // The connection is still usable after flushing
+        assert_eq!(
+            client.request(read(2)).unwrap().wait().unwrap(),
+            Rdata::Read {
+                data: Data(b"2".to_vec())
+            }
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! Exporting a directory on the local filesystem over 9p.
+//!
+//! [Export] implements [Serve9p] on top of a real directory tree, supporting reading and writing
+//! files, creating and removing files and directories, and using wstat to rename, truncate,
+//! chmod and update the modification time of files:
+//!
+//! ```no_run
+//! use ninep::{export::Export, server::Server};
+//!
+//! let export = Export::new("/tmp/scratch")?;
+//! _ = Server::new(export).serve_socket("scratch")?.join();
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! All file access is performed as the user running the server so the permissions of that user
+//! determine what clients are able to do. Clients are confined to the exported directory: walking
+//! to ".." from the root of the export remains at the root and symlinks that resolve to a
+//! location outside of the export are treated as inaccessible. Files other than regular files and
+//! directories (sockets, fifos, devices etc) are not exported.
+use crate::{
+    fs::{FileMeta, FileType, IoUnit, Mode, Perm, Stat, QID_ROOT},
+    server::{
+        ClientId, ReadOutcome, Serve9p, E_IS_DIR, E_NO_SUCH_FILE, E_PERMISSION_DENIED,
+        E_UNSUPPORTED,
+    },
+    Result,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, DirBuilder, File, FileTimes, OpenOptions, Permissions},
+    io::{self, ErrorKind},
+    os::unix::fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const E_INVALID_NAME: &str = "invalid file name";
+const E_EXISTS: &str = "file already exists";
+const E_READ_ONLY: &str = "export is read only";
+
+/// The open mode bit requesting that a file be truncated.
+const OPEN_TRUNC: u8 = 0x10;
+
+/// A [Serve9p] implementation exporting a directory from the local filesystem.
+///
+/// Qids are allocated per path within the export and are kept when files are renamed.
+#[derive(Debug)]
+pub struct Export {
+    root: PathBuf,
+    read_only: bool,
+    uid: u32,
+    paths: HashMap<u64, PathBuf>,
+    qids: HashMap<PathBuf, u64>,
+    next_qid: u64,
+}
+
+impl Export {
+    /// Export the given directory.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+
+        Ok(Self {
+            root,
+            read_only: false,
+            // SAFETY: getuid is always successful and has no side effects
+            uid: unsafe { libc::getuid() },
+            paths: [(QID_ROOT, PathBuf::new())].into_iter().collect(),
+            qids: [(PathBuf::new(), QID_ROOT)].into_iter().collect(),
+            next_qid: QID_ROOT + 1,
+        })
+    }
+
+    /// Reject any requests that would modify the exported directory.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// The qid for the given path within the export, allocating a new one if needed.
+    fn qid_for(&mut self, rel: &Path) -> u64 {
+        if let Some(&qid) = self.qids.get(rel) {
+            return qid;
+        }
+
+        let qid = self.next_qid;
+        self.next_qid += 1;
+        self.qids.insert(rel.to_path_buf(), qid);
+        self.paths.insert(qid, rel.to_path_buf());
+
+        qid
+    }
+
+    fn forget(&mut self, rel: &Path) {
+        let removed: Vec<PathBuf> = self
+            .qids
+            .keys()
+            .filter(|p| p.starts_with(rel))
+            .cloned()
+            .collect();
+
+        for p in removed {
+            if let Some(qid) = self.qids.remove(&p) {
+                self.paths.remove(&qid);
+            }
+        }
+    }
+
+    fn rel_path(&self, qid: u64) -> Result<&Path> {
+        match self.paths.get(&qid) {
+            Some(p) => Ok(p),
+            None => Err(E_NO_SUCH_FILE.to_string()),
+        }
+    }
+
+    /// Resolve a path within the export to its location on disk, ensuring that it does not
+    /// escape the export after following symlinks.
+    fn resolve(&self, rel: &Path) -> Result<PathBuf> {
+        let path = fs::canonicalize(self.root.join(rel)).map_err(io_err)?;
+        if !path.starts_with(&self.root) {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        Ok(path)
+    }
+
+    fn host_path(&self, qid: u64) -> Result<PathBuf> {
+        self.resolve(self.rel_path(qid)?)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(E_READ_ONLY.to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn stat_for(&mut self, rel: &Path, uname: &str) -> Result<Stat> {
+        let meta = fs::metadata(self.resolve(rel)?).map_err(io_err)?;
+        if !meta.is_dir() && !meta.is_file() {
+            return Err(E_UNSUPPORTED.to_string());
+        }
+
+        let qid = self.qid_for(rel);
+        let name = match rel.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "/".to_string(),
+        };
+        let fm = if meta.is_dir() {
+            FileMeta::dir(name, qid)
+        } else {
+            FileMeta::file(name, qid)
+        };
+
+        // Only user names are available to us so files belonging to the user running the
+        // server are reported as belonging to the connected user.
+        let (owner, group) = if meta.uid() == self.uid {
+            (uname.to_string(), uname.to_string())
+        } else {
+            (meta.uid().to_string(), meta.gid().to_string())
+        };
+
+        Ok(Stat {
+            fm,
+            perms: Perm::new(meta.mode() & 0o777),
+            n_bytes: if meta.is_dir() { 0 } else { meta.len() },
+            last_accesses: meta.accessed().unwrap_or(UNIX_EPOCH),
+            last_modified: meta.modified().unwrap_or(UNIX_EPOCH),
+            owner: owner.clone(),
+            group,
+            last_modified_by: owner,
+        })
+    }
+
+    fn rename(&mut self, qid: u64, name: &str) -> Result<()> {
+        let rel = self.rel_path(qid)?.to_path_buf();
+        if rel.as_os_str().is_empty() {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+        validate_name(name)?;
+
+        let new_rel = rel.with_file_name(name);
+        let (from, to) = (self.root.join(&rel), self.root.join(&new_rel));
+        self.resolve(rel.parent().unwrap_or(Path::new("")))?;
+        if fs::symlink_metadata(&to).is_ok() {
+            return Err(E_EXISTS.to_string());
+        }
+        fs::rename(&from, &to).map_err(io_err)?;
+
+        // Move the qids of the renamed file and anything beneath it to their new paths
+        let moved: Vec<(PathBuf, u64)> = self
+            .qids
+            .iter()
+            .filter(|(p, _)| p.starts_with(&rel))
+            .map(|(p, &qid)| (p.clone(), qid))
+            .collect();
+
+        self.forget(&new_rel);
+        for (p, _) in moved.iter() {
+            self.qids.remove(p);
+        }
+        for (p, qid) in moved {
+            // Joining an empty suffix would add a trailing slash
+            let p = match p.strip_prefix(&rel).expect("filtered above") {
+                suffix if suffix.as_os_str().is_empty() => new_rel.clone(),
+                suffix => new_rel.join(suffix),
+            };
+            self.qids.insert(p.clone(), qid);
+            self.paths.insert(qid, p);
+        }
+
+        Ok(())
+    }
+}
+
+impl Serve9p for Export {
+    fn walk(
+        &mut self,
+        _cid: ClientId,
+        parent_qid: u64,
+        child: &str,
+        uname: &str,
+    ) -> Result<FileMeta> {
+        let parent = self.rel_path(parent_qid)?;
+        let rel = if child == ".." {
+            parent.parent().unwrap_or(Path::new("")).to_path_buf()
+        } else {
+            validate_name(child)?;
+            parent.join(child)
+        };
+
+        Ok(self.stat_for(&rel, uname)?.fm)
+    }
+
+    fn open(
+        &mut self,
+        _cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        mode: Mode,
+        _uname: &str,
+    ) -> Result<IoUnit> {
+        let path = self.host_path(qid)?;
+
+        if mode.bits() & OPEN_TRUNC != 0 {
+            self.check_writable()?;
+            if path.is_dir() {
+                return Err(E_IS_DIR.to_string());
+            }
+            OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(&path)
+                .map_err(io_err)?;
+        }
+
+        Ok(0)
+    }
+
+    fn create(
+        &mut self,
+        _cid: ClientId,
+        parent: u64,
+        name: &str,
+        perm: Perm,
+        _mode: Mode,
+        uname: &str,
+    ) -> Result<(FileMeta, IoUnit)> {
+        self.check_writable()?;
+        validate_name(name)?;
+        let rel = self.rel_path(parent)?.join(name);
+        let path = self.host_path(parent)?.join(name);
+        let mode = perm.bits() & 0o777;
+
+        let res = if perm.contains(Perm::DIR) {
+            DirBuilder::new().mode(mode).create(&path)
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode)
+                .open(&path)
+                .map(|_| ())
+        };
+
+        match res {
+            Ok(()) => Ok((self.stat_for(&rel, uname)?.fm, 0)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(E_EXISTS.to_string()),
+            Err(e) => Err(io_err(e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        offset: usize,
+        count: usize,
+        _uname: &str,
+    ) -> Result<ReadOutcome> {
+        let f = File::open(self.host_path(qid)?).map_err(io_err)?;
+        let mut buf = vec![0; count];
+        let mut n = 0;
+
+        while n < count {
+            match f.read_at(&mut buf[n..], (offset + n) as u64) {
+                Ok(0) => break,
+                Ok(k) => n += k,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(io_err(e)),
+            }
+        }
+        buf.truncate(n);
+
+        Ok(ReadOutcome::Immediate(buf))
+    }
+
+    fn read_dir(&mut self, _cid: ClientId, qid: u64, uname: &str) -> Result<Vec<Stat>> {
+        let rel = self.rel_path(qid)?.to_path_buf();
+        let mut names: Vec<String> = fs::read_dir(self.host_path(qid)?)
+            .map_err(io_err)?
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .collect();
+        names.sort();
+
+        // Entries that are inaccessible or not exported are skipped
+        Ok(names
+            .into_iter()
+            .filter_map(|name| self.stat_for(&rel.join(name), uname).ok())
+            .collect())
+    }
+
+    fn write(
+        &mut self,
+        _cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        offset: usize,
+        data: Vec<u8>,
+        _uname: &str,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        let f = OpenOptions::new()
+            .write(true)
+            .open(self.host_path(qid)?)
+            .map_err(io_err)?;
+        f.write_all_at(&data, offset as u64).map_err(io_err)?;
+
+        Ok(data.len())
+    }
+
+    fn remove(&mut self, _cid: ClientId, qid: u64, _uname: &str) -> Result<()> {
+        self.check_writable()?;
+        let rel = self.rel_path(qid)?.to_path_buf();
+        if rel.as_os_str().is_empty() {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        // Symlinks are removed rather than the file they point to
+        let path = self.root.join(&rel);
+        self.resolve(rel.parent().unwrap_or(Path::new("")))?;
+        let meta = fs::symlink_metadata(&path).map_err(io_err)?;
+        if meta.is_dir() {
+            fs::remove_dir(&path).map_err(io_err)?;
+        } else {
+            fs::remove_file(&path).map_err(io_err)?;
+        }
+        self.forget(&rel);
+
+        Ok(())
+    }
+
+    fn stat(&mut self, _cid: ClientId, qid: u64, uname: &str) -> Result<Stat> {
+        let rel = self.rel_path(qid)?.to_path_buf();
+        self.stat_for(&rel, uname)
+    }
+
+    fn write_stat(&mut self, cid: ClientId, qid: u64, stat: Stat, uname: &str) -> Result<()> {
+        let current = self.stat(cid, qid, uname)?;
+        if stat == current {
+            return Ok(());
+        }
+        self.check_writable()?;
+        let path = self.host_path(qid)?;
+
+        if stat.n_bytes != current.n_bytes {
+            if current.fm.ty == FileType::Directory {
+                return Err(E_IS_DIR.to_string());
+            }
+            let f = OpenOptions::new().write(true).open(&path).map_err(io_err)?;
+            f.set_len(stat.n_bytes).map_err(io_err)?;
+        }
+
+        if stat.perms != current.perms {
+            let mode = stat.perms.bits() & 0o777;
+            fs::set_permissions(&path, Permissions::from_mode(mode)).map_err(io_err)?;
+        }
+
+        // Times are only sent with second precision
+        let changed = |a: SystemTime, b: SystemTime| secs(a) != secs(b);
+        let mut times = FileTimes::new();
+        let mut set_times = false;
+        if changed(stat.last_accesses, current.last_accesses) {
+            times = times.set_accessed(stat.last_accesses);
+            set_times = true;
+        }
+        if changed(stat.last_modified, current.last_modified) {
+            times = times.set_modified(stat.last_modified);
+            set_times = true;
+        }
+        if set_times {
+            File::open(&path)
+                .and_then(|f| f.set_times(times))
+                .map_err(io_err)?;
+        }
+
+        // Ownership changes are not supported so differing owners are ignored
+        if stat.fm.name != current.fm.name {
+            self.rename(qid, &stat.fm.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// File names must be a single, normal path component.
+fn validate_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(c)), None) if c == name => Ok(()),
+        _ => Err(E_INVALID_NAME.to_string()),
+    }
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn io_err(e: io::Error) -> String {
+    match e.kind() {
+        ErrorKind::NotFound => E_NO_SUCH_FILE.to_string(),
+        ErrorKind::PermissionDenied => E_PERMISSION_DENIED.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::Client, server::Server};
+    use simple_test_case::test_case;
+    use std::{env, os::unix::fs::symlink, os::unix::net::UnixStream, process};
+
+    const CID: ClientId = ClientId(0);
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("ninep-export-test.{}.{name}", process::id()));
+            _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("export/sub")).unwrap();
+            fs::write(dir.join("export/foo"), "foo contents").unwrap();
+            fs::write(dir.join("export/sub/bar"), "bar contents").unwrap();
+            fs::write(dir.join("secret"), "secret contents").unwrap();
+            symlink(dir.join("secret"), dir.join("export/escape")).unwrap();
+            symlink("sub/bar", dir.join("export/inside")).unwrap();
+
+            Self(dir)
+        }
+
+        fn export(&self) -> PathBuf {
+            self.0.join("export")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn walk_path(e: &mut Export, path: &str) -> Result<u64> {
+        let mut qid = QID_ROOT;
+        for name in path.split('/') {
+            qid = e.walk(CID, qid, name, "test")?.qid;
+        }
+
+        Ok(qid)
+    }
+
+    fn read_str(e: &mut Export, path: &str) -> Result<String> {
+        let qid = walk_path(e, path)?;
+        match e.read(CID, 0, qid, 0, 1024, "test")? {
+            ReadOutcome::Immediate(data) => Ok(String::from_utf8(data).unwrap()),
+            ReadOutcome::Blocked(_) => panic!("unexpected blocked read"),
+        }
+    }
+
+    #[test_case("foo", Ok("foo contents"); "file")]
+    #[test_case("sub/bar", Ok("bar contents"); "nested file")]
+    #[test_case("sub/../foo", Ok("foo contents"); "dot dot")]
+    #[test_case("../../foo", Ok("foo contents"); "dot dot at root")]
+    #[test_case("inside", Ok("bar contents"); "symlink within export")]
+    #[test_case("escape", Err(E_PERMISSION_DENIED); "symlink outside of export")]
+    #[test_case("../secret", Err(E_NO_SUCH_FILE); "dot dot does not escape")]
+    #[test_case("missing", Err(E_NO_SUCH_FILE); "missing file")]
+    #[test_case("./foo", Err(E_INVALID_NAME); "dot")]
+    #[test]
+    fn walk_is_confined_to_the_export(path: &str, expected: std::result::Result<&str, &str>) {
+        let tmp = TempDir::new(&path.replace(['/', '.'], "_"));
+        let mut e = Export::new(tmp.export()).unwrap();
+
+        let res = read_str(&mut e, path);
+
+        assert_eq!(res, expected.map(String::from).map_err(String::from));
+    }
+
+    #[test]
+    fn read_dir_skips_inaccessible_entries() {
+        let tmp = TempDir::new("read_dir");
+        let mut e = Export::new(tmp.export()).unwrap();
+
+        let names: Vec<String> = e
+            .read_dir(CID, QID_ROOT, "test")
+            .unwrap()
+            .into_iter()
+            .map(|s| s.fm.name)
+            .collect();
+
+        assert_eq!(names, vec!["foo", "inside", "sub"]);
+    }
+
+    #[test]
+    fn read_only_exports_reject_modifications() {
+        let tmp = TempDir::new("read_only");
+        let mut e = Export::new(tmp.export()).unwrap().read_only();
+        let qid = walk_path(&mut e, "foo").unwrap();
+
+        assert!(e
+            .write(CID, 0, qid, 0, b"updated".to_vec(), "test")
+            .is_err());
+        assert!(e.remove(CID, qid, "test").is_err());
+        assert!(e
+            .create(CID, QID_ROOT, "new", Perm::OWNER_READ, Mode::FILE, "test")
+            .is_err());
+        assert_eq!(read_str(&mut e, "foo").unwrap(), "foo contents");
+    }
+
+    #[test]
+    fn files_can_be_managed_over_9p() {
+        let tmp = TempDir::new("client");
+        let mut server = Server::new(Export::new(tmp.export()).unwrap()).without_tracer();
+        let (client, srv) = UnixStream::pair().unwrap();
+        server.spawn_session(srv);
+        let mut client = Client::new_from_stream("test".to_string(), client, "", None).unwrap();
+        let rw = Perm::OWNER_READ | Perm::OWNER_WRITE;
+
+        client.create("sub", "new", rw, Mode::FILE).unwrap();
+        client.write_str("sub/new", 0, "hello, world").unwrap();
+        assert_eq!(client.read_str("sub/new").unwrap(), "hello, world");
+        assert!(client.create("sub", "new", rw, Mode::FILE).is_err());
+
+        client.truncate("sub/new", 5).unwrap();
+        assert_eq!(client.stat("sub/new").unwrap().n_bytes, 5);
+        client.rename("sub/new", "renamed").unwrap();
+        assert_eq!(client.read_str("sub/renamed").unwrap(), "hello");
+        assert!(client.read_str("sub/new").is_err());
+        assert!(client.rename("sub/renamed", "../escaped").is_err());
+        assert!(client.rename("sub/renamed", "bar").is_err());
+
+        client
+            .create("", "dir", rw | Perm::OWNER_EXEC | Perm::DIR, Mode::FILE)
+            .unwrap();
+        assert!(tmp.export().join("dir").is_dir());
+        client.rename("sub", "moved").unwrap();
+        assert_eq!(client.read_str("moved/bar").unwrap(), "bar contents");
+
+        assert!(client.remove("moved").is_err());
+        client.remove("moved/renamed").unwrap();
+        client.remove("moved/bar").unwrap();
+        client.remove("moved").unwrap();
+        assert!(!tmp.export().join("moved").exists());
+
+        let names: Vec<String> = client
+            .read_dir("")
+            .unwrap()
+            .into_iter()
+            .map(|s| s.fm.name)
+            .collect();
+        // "inside" is now a dangling symlink so it is no longer listed
+        assert_eq!(names, vec!["dir", "foo"]);
+    }
+}
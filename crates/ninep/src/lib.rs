@@ -13,14 +13,19 @@
 
 use std::{
     io::{Read, Write},
-    net::TcpStream,
+    net::{Shutdown, TcpStream},
     os::unix::net::UnixStream,
 };
 
+pub mod auth;
 pub mod client;
+pub mod export;
 pub mod fs;
+pub mod namespace;
 pub mod protocol;
 pub mod server;
+pub mod sfs;
+pub mod trace;
 
 use protocol::{Format9p, Rdata, Rmessage};
 
@@ -48,16 +53,30 @@ pub trait Stream: Read + Write + Send + Sized + 'static {
         let r: Rmessage = (tag, resp).into();
         let _ = r.write_to(self);
     }
+
+    /// Shut down both halves of the underlying connection, unblocking any pending reads on clones
+    /// of this stream.
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 impl Stream for UnixStream {
     fn try_clone(&self) -> Result<Self> {
         self.try_clone().map_err(|e| e.to_string())
     }
+
+    fn shutdown(&self) -> Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both).map_err(|e| e.to_string())
+    }
 }
 
 impl Stream for TcpStream {
     fn try_clone(&self) -> Result<Self> {
         self.try_clone().map_err(|e| e.to_string())
     }
+
+    fn shutdown(&self) -> Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both).map_err(|e| e.to_string())
+    }
 }
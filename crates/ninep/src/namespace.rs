@@ -0,0 +1,315 @@
+//! Locating and managing unix sockets within the current namespace.
+//!
+//! Following the conventions used by [plan9port](https://9fans.github.io/plan9port/man/man1/intro.html)
+//! the namespace is a directory containing the unix sockets of running 9p servers, so that
+//! clients are able to connect to a server using only its name.
+//!
+//! Sockets bound by a [Server](crate::server::Server), along with any aliases still referring to
+//! them, are removed when the process receives SIGINT or SIGTERM (so long as no other handler has
+//! been installed for those signals). Programs exiting normally should call [remove_sockets]
+//! before they do so.
+use libc::{c_int, sigaction, SA_RESETHAND, SIGINT, SIGTERM, SIG_DFL};
+use std::{
+    env,
+    ffi::{CString, OsStr},
+    fs::{self, DirBuilder},
+    io::{self, ErrorKind},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, DirBuilderExt, FileTypeExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process, ptr,
+    sync::{Mutex, Once},
+};
+
+/// Sockets bound by this process along with any aliases that have been created for them.
+///
+/// Paths are held as [CString]s so that they can be used directly from a signal handler.
+#[derive(Debug, Default)]
+struct Registry {
+    sockets: Vec<CString>,
+    /// (alias, socket) pairs
+    aliases: Vec<(CString, CString)>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    sockets: Vec::new(),
+    aliases: Vec::new(),
+});
+static INSTALL_HANDLERS: Once = Once::new();
+
+/// The namespace directory used to locate unix sockets.
+///
+/// As with plan9port this is `$NAMESPACE` if it is set and `/tmp/ns.$USER.$DISPLAY` otherwise,
+/// with `$DISPLAY` defaulting to `:0`. If `$USER` is not set then `$XDG_RUNTIME_DIR/ns.$DISPLAY`
+/// is used instead.
+pub fn namespace() -> io::Result<String> {
+    let var = |k: &str| env::var(k).ok().filter(|s| !s.is_empty());
+
+    resolve_namespace(
+        var("NAMESPACE"),
+        var("USER"),
+        var("DISPLAY"),
+        var("XDG_RUNTIME_DIR"),
+    )
+    .ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            "unable to determine namespace: none of $NAMESPACE, $USER or $XDG_RUNTIME_DIR are set",
+        )
+    })
+}
+
+fn resolve_namespace(
+    namespace: Option<String>,
+    user: Option<String>,
+    display: Option<String>,
+    runtime_dir: Option<String>,
+) -> Option<String> {
+    if namespace.is_some() {
+        return namespace;
+    }
+
+    // plan9port canonicalises ":0.0" to ":0"
+    let display = display.unwrap_or_else(|| ":0".to_string());
+    let display = display.strip_suffix(".0").unwrap_or(&display);
+
+    match (user, runtime_dir) {
+        (Some(user), _) => Some(format!("/tmp/ns.{user}.{display}")),
+        (None, Some(dir)) => Some(format!("{dir}/ns.{display}")),
+        (None, None) => None,
+    }
+}
+
+/// The unix socket path that will be used for a given server name.
+pub fn socket_path(name: &str) -> io::Result<String> {
+    Ok(format!("{}/{name}", namespace()?))
+}
+
+/// Point `alias` at the socket for `name` within the namespace, replacing any existing alias.
+///
+/// This allows clients to connect to one of several running servers using a well known name. The
+/// alias is removed by [remove_sockets] if it still refers to `name`.
+pub fn alias_socket(name: &str, alias: &str) -> io::Result<()> {
+    let ns = namespace()?;
+    let target = PathBuf::from(format!("{ns}/{name}"));
+    let link = PathBuf::from(format!("{ns}/{alias}"));
+
+    // Refuse to replace a socket belonging to a server that is not using aliases
+    if let Ok(meta) = fs::symlink_metadata(&link) {
+        if meta.file_type().is_socket() && UnixStream::connect(&link).is_ok() {
+            return Err(in_use(&link));
+        }
+    }
+
+    // Renaming over the existing alias means clients never see it missing
+    let tmp = PathBuf::from(format!("{ns}/.{alias}.{}", process::id()));
+    _ = fs::remove_file(&tmp);
+    symlink(&target, &tmp)?;
+    fs::rename(&tmp, &link)?;
+
+    // Paths containing nul bytes will already have been rejected when creating the symlink
+    if let (Some(link), Some(target)) = (cstring(&link), cstring(&target)) {
+        let mut reg = lock();
+        if !reg.aliases.iter().any(|(l, _)| l == &link) {
+            reg.aliases.push((link, target));
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove all sockets bound by this process along with any aliases still referring to them.
+pub fn remove_sockets() {
+    let mut reg = lock();
+    for (link, target) in reg.aliases.drain(..) {
+        let link = Path::new(OsStr::from_bytes(link.as_bytes()));
+        if fs::read_link(link).is_ok_and(|p| p.as_os_str().as_bytes() == target.as_bytes()) {
+            _ = fs::remove_file(link);
+        }
+    }
+    for path in reg.sockets.drain(..) {
+        _ = fs::remove_file(OsStr::from_bytes(path.as_bytes()));
+    }
+}
+
+fn cstring(path: &Path) -> Option<CString> {
+    CString::new(path.as_os_str().as_bytes()).ok()
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn in_use(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::AddrInUse,
+        format!("a server is already listening on {}", path.display()),
+    )
+}
+
+/// Whether the symlink at `link` currently points to `target`, using only async-signal-safe
+/// functions.
+fn links_to(link: &CString, target: &CString) -> bool {
+    let mut buf = [0u8; libc::PATH_MAX as usize];
+    // SAFETY: link is a valid nul terminated string and buf is large enough for any path
+    let n = unsafe { libc::readlink(link.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+
+    n >= 0 && &buf[..n as usize] == target.as_bytes()
+}
+
+extern "C" fn remove_sockets_and_reraise(sig: c_int) {
+    // Only the unlinking of sockets and aliases is performed here as it is safe to do from a
+    // signal handler. If the registry is currently locked then we give up rather than risking a
+    // deadlock.
+    if let Ok(reg) = REGISTRY.try_lock() {
+        for (link, target) in reg.aliases.iter() {
+            if links_to(link, target) {
+                // SAFETY: link is a valid nul terminated string
+                unsafe { libc::unlink(link.as_ptr()) };
+            }
+        }
+        for path in reg.sockets.iter() {
+            // SAFETY: path is a valid nul terminated string
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+    }
+
+    // SAFETY: our handler is installed with SA_RESETHAND so the default action has already been
+    // restored and re-raising the signal terminates the process as it would have done had our
+    // handler not been installed
+    unsafe { libc::raise(sig) };
+}
+
+fn install_signal_handlers() {
+    INSTALL_HANDLERS.call_once(|| {
+        for sig in [SIGINT, SIGTERM] {
+            // SAFETY: our handler only calls async-signal-safe functions and is only installed
+            // if no other handler has been set for the signal.
+            unsafe {
+                let mut prev: sigaction = std::mem::zeroed();
+                if libc::sigaction(sig, ptr::null(), &mut prev) != 0 || prev.sa_sigaction != SIG_DFL
+                {
+                    continue;
+                }
+
+                let mut action: sigaction = std::mem::zeroed();
+                action.sa_sigaction = remove_sockets_and_reraise as *const () as usize;
+                action.sa_flags = SA_RESETHAND;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(sig, &action, ptr::null_mut());
+            }
+        }
+    });
+}
+
+/// A unix socket bound within the namespace that is removed when dropped.
+#[derive(Debug)]
+pub(crate) struct Socket {
+    pub(crate) path: String,
+    pub(crate) listener: UnixListener,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        lock()
+            .sockets
+            .retain(|p| p.as_bytes() != self.path.as_bytes());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Socket {
+    /// Bind a new socket with the given name in the namespace, creating the namespace directory
+    /// if needed.
+    pub(crate) fn bind(name: &str) -> io::Result<Self> {
+        let ns = namespace()?;
+        DirBuilder::new().recursive(true).mode(0o700).create(&ns)?;
+
+        Self::bind_path(format!("{ns}/{name}"))
+    }
+
+    /// Stale sockets left behind by servers that did not exit cleanly are replaced but sockets
+    /// that are still accepting connections are left untouched.
+    fn bind_path(path: String) -> io::Result<Self> {
+        if fs::symlink_metadata(&path).is_ok() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(in_use(Path::new(&path)));
+            }
+            fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        if let Ok(s) = CString::new(path.clone()) {
+            lock().sockets.push(s);
+        }
+        install_signal_handlers();
+
+        Ok(Self { path, listener })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn s(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test_case(s("/ns"), s("alice"), None, None, s("/ns"); "namespace is preferred")]
+    #[test_case(None, s("alice"), None, s("/run"), s("/tmp/ns.alice.:0"); "default display")]
+    #[test_case(None, s("alice"), s(":1.0"), None, s("/tmp/ns.alice.:1"); "display is canonicalised")]
+    #[test_case(None, None, s(":1"), s("/run/user/1000"), s("/run/user/1000/ns.:1"); "runtime dir")]
+    #[test_case(None, None, None, None, None; "nothing set")]
+    #[test]
+    fn resolve_namespace_works(
+        ns: Option<String>,
+        user: Option<String>,
+        display: Option<String>,
+        runtime_dir: Option<String>,
+        expected: Option<String>,
+    ) {
+        assert_eq!(resolve_namespace(ns, user, display, runtime_dir), expected);
+    }
+
+    #[test]
+    fn links_to_works() {
+        let dir = env::temp_dir().join(format!("ninep-links-test.{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (link, target, other) = (dir.join("link"), dir.join("target"), dir.join("other"));
+        _ = fs::remove_file(&link);
+        symlink(&target, &link).unwrap();
+
+        let c = |p: &Path| cstring(p).unwrap();
+        assert!(links_to(&c(&link), &c(&target)));
+        assert!(!links_to(&c(&link), &c(&other)));
+        assert!(!links_to(&c(&other), &c(&target)));
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn live_sockets_are_not_replaced() {
+        let dir = env::temp_dir().join(format!("ninep-ns-test.{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("live").display().to_string();
+
+        let sock = Socket::bind_path(path.clone()).unwrap();
+        let err = Socket::bind_path(path.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+
+        drop(sock);
+        assert!(fs::symlink_metadata(&path).is_err());
+
+        // Dropping a bare listener leaves a stale socket behind
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(fs::symlink_metadata(&path).is_ok());
+
+        assert!(Socket::bind_path(path.clone()).is_ok());
+        _ = fs::remove_dir_all(&dir);
+    }
+}
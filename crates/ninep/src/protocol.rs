@@ -1,6 +1,11 @@
 //! 9p protocol implementation
 //!
 //!   http://man.cat-v.org/plan_9/5/
+//!
+//! Along with the base 9P2000 protocol, the additional messages defined by the 9P2000.L dialect
+//! used by the Linux kernel v9fs client are supported:
+//!
+//!   https://github.com/chaos/diod/blob/master/protocol.md
 use std::{
     fmt,
     io::{self, Cursor, ErrorKind, Read, Write},
@@ -15,6 +20,13 @@ pub const MAX_DATA_SIZE_FIELD: usize = u32::MAX as usize;
 /// The maximum number of bytes we allow in a Data buffer: a client attempting
 /// to use more than this is an error.
 pub const MAX_DATA_LEN: usize = 32 * 1024 * 1024;
+/// The size of the header preceding the data in a Twrite or Rread message, matching the value used
+/// by plan 9.
+pub const IO_HEADER_SIZE: usize = 24;
+/// The maximum size of any message we are willing to decode when no msize has been negotiated.
+pub const MAX_MESSAGE_SIZE: u32 = (MAX_DATA_LEN + IO_HEADER_SIZE) as u32;
+/// The smallest valid message: size[4] type[1] tag[2]
+const MIN_MESSAGE_SIZE: u32 = 4 + 1 + 2;
 
 /// Something that can be encoded to and decoded 9p protocol messages.
 ///
@@ -130,8 +142,10 @@ impl<T: Format9p + std::fmt::Debug> Format9p for Vec<T> {
     }
 
     fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        // The buffer is grown as elements are decoded rather than allocated up front so that a
+        // malformed count can not be used to force a large allocation
         let n = u16::read_from(r)? as usize;
-        let mut buf = Vec::with_capacity(n);
+        let mut buf = Vec::new();
 
         for _ in 0..n {
             buf.push(T::read_from(r)?);
@@ -215,7 +229,8 @@ impl Format9p for Data {
             ));
         }
 
-        let mut buf = Vec::with_capacity(len);
+        // As with Vec<T> we only allocate for the data that is actually present
+        let mut buf = Vec::new();
         r.take(len as u64).read_to_end(&mut buf)?;
         let actual = buf.len();
 
@@ -240,6 +255,50 @@ struct MessageType(u8);
 
 #[allow(non_upper_case_globals)]
 impl MessageType {
+    // 9P2000.L
+    //   https://github.com/torvalds/linux/blob/master/include/net/9p/9p.h
+    // Tlerror = 6,
+    const Rlerror: Self = Self(7);
+    const Tstatfs: Self = Self(8);
+    const Rstatfs: Self = Self(9);
+    const Tlopen: Self = Self(12);
+    const Rlopen: Self = Self(13);
+    const Tlcreate: Self = Self(14);
+    const Rlcreate: Self = Self(15);
+    const Tsymlink: Self = Self(16);
+    const Rsymlink: Self = Self(17);
+    const Tmknod: Self = Self(18);
+    const Rmknod: Self = Self(19);
+    const Trename: Self = Self(20);
+    const Rrename: Self = Self(21);
+    const Treadlink: Self = Self(22);
+    const Rreadlink: Self = Self(23);
+    const Tgetattr: Self = Self(24);
+    const Rgetattr: Self = Self(25);
+    const Tsetattr: Self = Self(26);
+    const Rsetattr: Self = Self(27);
+    const Txattrwalk: Self = Self(30);
+    const Rxattrwalk: Self = Self(31);
+    const Txattrcreate: Self = Self(32);
+    const Rxattrcreate: Self = Self(33);
+    const Treaddir: Self = Self(40);
+    const Rreaddir: Self = Self(41);
+    const Tfsync: Self = Self(50);
+    const Rfsync: Self = Self(51);
+    const Tlock: Self = Self(52);
+    const Rlock: Self = Self(53);
+    const Tgetlock: Self = Self(54);
+    const Rgetlock: Self = Self(55);
+    const Tlink: Self = Self(70);
+    const Rlink: Self = Self(71);
+    const Tmkdir: Self = Self(72);
+    const Rmkdir: Self = Self(73);
+    const Trenameat: Self = Self(74);
+    const Rrenameat: Self = Self(75);
+    const Tunlinkat: Self = Self(76);
+    const Runlinkat: Self = Self(77);
+
+    // 9P2000
     const Tversion: Self = Self(100);
     const Rversion: Self = Self(101);
 
@@ -325,6 +384,13 @@ macro_rules! impl_message_datatype {
                 Ok(Self { $($field,)* })
             }
         }
+
+        #[cfg(test)]
+        impl tests::Arbitrary for $struct {
+            fn arbitrary(_rng: &mut tests::Rng) -> Self {
+                Self { $($field: tests::Arbitrary::arbitrary(_rng),)* }
+            }
+        }
     };
 }
 
@@ -373,9 +439,28 @@ macro_rules! impl_message_format {
             }
 
             fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+                Self::read_from_with_limit(r, MAX_MESSAGE_SIZE)
+            }
+        }
+
+        impl $message_ty {
+            /// Decode a message, rejecting it before reading any further if its size field exceeds
+            /// max_size (typically the msize negotiated for the connection).
+            pub fn read_from_with_limit<R: Read>(r: &mut R, max_size: u32) -> io::Result<Self> {
                 // the size field includes the number of bytes for the field itself so we
                 // trim that off before decoding the rest of the message
                 let size = u32::read_from(r)?;
+                if size < MIN_MESSAGE_SIZE {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("message too short: size={size}"),
+                    ));
+                } else if size > max_size {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("message too long: max={max_size} size={size}"),
+                    ));
+                }
                 let r = &mut r.take((size - 4) as u64);
 
                 let mut ty_buf = [0u8];
@@ -395,10 +480,27 @@ macro_rules! impl_message_format {
                     )),
                 };
 
+                // Protocol extensions may append additional fields to existing messages (such as
+                // the n_uname field added to Tattach by 9P2000.u and 9P2000.L) so anything we
+                // have not parsed is discarded to keep the stream aligned on message boundaries.
+                io::copy(r, &mut io::sink())?;
+
                 Ok(Self { tag, content })
             }
         }
 
+        #[cfg(test)]
+        impl $enum_ty {
+            /// One randomly generated instance of every variant
+            pub(crate) fn arbitrary_variants(rng: &mut tests::Rng) -> Vec<Self> {
+                vec![$(
+                    $enum_ty::$enum_variant {
+                        $($field: tests::Arbitrary::arbitrary(rng),)*
+                    },
+                )+]
+            }
+        }
+
     };
 }
 
@@ -493,6 +595,34 @@ impl_message_datatype!(
     }
 );
 
+impl RawStat {
+    /// A stat with every field set to the "don't touch" value described in stat(5), for use as the
+    /// starting point of a Twstat message that only modifies some fields.
+    pub fn null() -> Self {
+        let mut stat = Self {
+            size: 0,
+            ty: u16::MAX,
+            dev: u32::MAX,
+            qid: Qid {
+                ty: u8::MAX,
+                version: u32::MAX,
+                path: u64::MAX,
+            },
+            mode: u32::MAX,
+            atime: u32::MAX,
+            mtime: u32::MAX,
+            length: u64::MAX,
+            name: String::new(),
+            uid: String::new(),
+            gid: String::new(),
+            muid: String::new(),
+        };
+        stat.size = (stat.n_bytes() - size_of::<u16>()) as u16;
+
+        stat
+    }
+}
+
 impl_message_datatype!(
     /// A qid represents the server's unique identification for the file being accessed: two files
     /// on the same server hierarchy are the same if and only if their qids are the same.
@@ -658,6 +788,246 @@ impl_tmessages! {
         /// The stat data to be written
         stat: RawStat,
     }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#statfs----get-file-system-information
+    /// size[4] Tstatfs tag[2] | fid[4]
+    Statfs => Tstatfs {
+        /// The fid to request file system information for
+        fid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lopen----open-a-file
+    /// size[4] Tlopen tag[2] | fid[4] flags[4]
+    Lopen => Tlopen {
+        /// The fid to open
+        fid: u32,
+        /// Linux open(2) flags
+        flags: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lcreate----create-regular-file
+    /// size[4] Tlcreate tag[2] | fid[4] name[s] flags[4] mode[4] gid[4]
+    Lcreate => Tlcreate {
+        /// The fid of the directory to create the file in
+        fid: u32,
+        /// The name of the new file
+        name: String,
+        /// Linux open(2) flags
+        flags: u32,
+        /// Linux mode bits
+        mode: u32,
+        /// The group id of the new file
+        gid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#symlink----create-symbolic-link
+    /// size[4] Tsymlink tag[2] | fid[4] name[s] symtgt[s] gid[4]
+    Symlink => Tsymlink {
+        /// The fid of the directory to create the link in
+        fid: u32,
+        /// The name of the link
+        name: String,
+        /// The target of the link
+        target: String,
+        /// The group id of the link
+        gid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#mknod----create-a-device-node
+    /// size[4] Tmknod tag[2] | dfid[4] name[s] mode[4] major[4] minor[4] gid[4]
+    Mknod => Tmknod {
+        /// The fid of the directory to create the node in
+        dfid: u32,
+        /// The name of the node
+        name: String,
+        /// Linux mode bits
+        mode: u32,
+        /// The major device number
+        major: u32,
+        /// The minor device number
+        minor: u32,
+        /// The group id of the node
+        gid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#rename----rename-a-file
+    /// size[4] Trename tag[2] | fid[4] dfid[4] name[s]
+    Rename => Trename {
+        /// The fid to rename
+        fid: u32,
+        /// The fid of the directory to move the file to
+        dfid: u32,
+        /// The new name of the file
+        name: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#readlink----read-value-of-symbolic-link
+    /// size[4] Treadlink tag[2] | fid[4]
+    Readlink => Treadlink {
+        /// The fid of the link to read
+        fid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#getattr----get-file-attributes
+    /// size[4] Tgetattr tag[2] | fid[4] request_mask[8]
+    Getattr => Tgetattr {
+        /// The fid to request attributes for
+        fid: u32,
+        /// Bitmask of the attributes being requested
+        request_mask: u64,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#setattr----set-file-attributes
+    /// size[4] Tsetattr tag[2] | fid[4] valid[4] mode[4] uid[4] gid[4] size[8]
+    ///                           atime_sec[8] atime_nsec[8] mtime_sec[8] mtime_nsec[8]
+    Setattr => Tsetattr {
+        /// The fid to update attributes for
+        fid: u32,
+        /// Bitmask of the attributes being set
+        valid: u32,
+        /// Linux mode bits
+        mode: u32,
+        /// Owner user id
+        uid: u32,
+        /// Owner group id
+        gid: u32,
+        /// Size in bytes
+        size: u64,
+        /// Last access time: seconds
+        atime_sec: u64,
+        /// Last access time: nanoseconds
+        atime_nsec: u64,
+        /// Last modification time: seconds
+        mtime_sec: u64,
+        /// Last modification time: nanoseconds
+        mtime_nsec: u64,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#xattrwalk----prepare-to-read-a-file-extended-attribute
+    /// size[4] Txattrwalk tag[2] | fid[4] newfid[4] name[s]
+    Xattrwalk => Txattrwalk {
+        /// The fid to read extended attributes from
+        fid: u32,
+        /// The fid to use for reading the attribute
+        new_fid: u32,
+        /// The name of the attribute
+        name: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#xattrcreate----prepare-to-set-extended-attribute
+    /// size[4] Txattrcreate tag[2] | fid[4] name[s] attr_size[8] flags[4]
+    Xattrcreate => Txattrcreate {
+        /// The fid to set an extended attribute on
+        fid: u32,
+        /// The name of the attribute
+        name: String,
+        /// The size of the attribute value
+        attr_size: u64,
+        /// Linux setxattr(2) flags
+        flags: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#readdir---read-a-directory
+    /// size[4] Treaddir tag[2] | fid[4] offset[8] count[4]
+    Readdir => Treaddir {
+        /// The fid of the directory to read
+        fid: u32,
+        /// The offset returned with the last directory entry previously read
+        offset: u64,
+        /// The maximum number of bytes to return
+        count: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#fsync----flush-any-cached-data-to-disk
+    /// size[4] Tfsync tag[2] | fid[4]
+    Fsync => Tfsync {
+        /// The fid to sync
+        fid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lock----acquire-or-release-a-posix-record-lock
+    /// size[4] Tlock tag[2] | fid[4] type[1] flags[4] start[8] length[8] proc_id[4] client_id[s]
+    Lock => Tlock {
+        /// The fid to lock
+        fid: u32,
+        /// The type of lock
+        ty: u8,
+        /// Lock flags
+        flags: u32,
+        /// The starting offset of the lock
+        start: u64,
+        /// The length of the lock
+        length: u64,
+        /// The process id of the lock holder
+        proc_id: u32,
+        /// The client id of the lock holder
+        client_id: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#getlock----test-for-the-existence-of-a-posix-record-lock
+    /// size[4] Tgetlock tag[2] | fid[4] type[1] start[8] length[8] proc_id[4] client_id[s]
+    Getlock => Tgetlock {
+        /// The fid to check
+        fid: u32,
+        /// The type of lock
+        ty: u8,
+        /// The starting offset of the lock
+        start: u64,
+        /// The length of the lock
+        length: u64,
+        /// The process id of the lock holder
+        proc_id: u32,
+        /// The client id of the lock holder
+        client_id: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#link----create-hard-link
+    /// size[4] Tlink tag[2] | dfid[4] fid[4] name[s]
+    Link => Tlink {
+        /// The fid of the directory to create the link in
+        dfid: u32,
+        /// The fid of the link target
+        fid: u32,
+        /// The name of the link
+        name: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#mkdir----create-a-directory
+    /// size[4] Tmkdir tag[2] | dfid[4] name[s] mode[4] gid[4]
+    Mkdir => Tmkdir {
+        /// The fid of the directory to create the new directory in
+        dfid: u32,
+        /// The name of the new directory
+        name: String,
+        /// Linux mode bits
+        mode: u32,
+        /// The group id of the new directory
+        gid: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#renameat----rename-a-file-or-directory
+    /// size[4] Trenameat tag[2] | olddirfid[4] oldname[s] newdirfid[4] newname[s]
+    Renameat => Trenameat {
+        /// The fid of the directory containing the file
+        old_dfid: u32,
+        /// The current name of the file
+        old_name: String,
+        /// The fid of the directory to move the file to
+        new_dfid: u32,
+        /// The new name of the file
+        new_name: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#unlinkat----unlink-a-file-or-directory
+    /// size[4] Tunlinkat tag[2] | dfid[4] name[s] flags[4]
+    Unlinkat => Tunlinkat {
+        /// The fid of the directory containing the file
+        dfid: u32,
+        /// The name of the file to remove
+        name: String,
+        /// Linux unlinkat(2) flags
+        flags: u32,
+    }
 }
 
 /// The Plan 9 File Protocol, 9P, is used for messages between clients and servers. A client
@@ -776,6 +1146,195 @@ impl_rmessages! {
     /// http://man.cat-v.org/plan_9/5/stat
     /// size[4] Rwstat tag[2]
     Wstat => Rwstat {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lerror----return-error-code
+    /// size[4] Rlerror tag[2] | ecode[4]
+    Lerror => Rlerror {
+        /// The Linux errno value for the error being returned
+        ecode: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#statfs----get-file-system-information
+    /// size[4] Rstatfs tag[2] | type[4] bsize[4] blocks[8] bfree[8] bavail[8]
+    ///                          files[8] ffree[8] fsid[8] namelen[4]
+    Statfs => Rstatfs {
+        /// The type of file system
+        ty: u32,
+        /// Optimal transfer block size
+        bsize: u32,
+        /// Total data blocks in file system
+        blocks: u64,
+        /// Free blocks in file system
+        bfree: u64,
+        /// Free blocks available to unprivileged users
+        bavail: u64,
+        /// Total file nodes in file system
+        files: u64,
+        /// Free file nodes in file system
+        ffree: u64,
+        /// File system id
+        fsid: u64,
+        /// Maximum length of file names
+        namelen: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lopen----open-a-file
+    /// size[4] Rlopen tag[2] | qid[13] iounit[4]
+    Lopen => Rlopen {
+        /// Qid of the opened resource
+        qid: Qid,
+        /// IO unit for subsequent read / write operations
+        iounit: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lcreate----create-regular-file
+    /// size[4] Rlcreate tag[2] | qid[13] iounit[4]
+    Lcreate => Rlcreate {
+        /// Qid of the created resource
+        qid: Qid,
+        /// IO unit for subsequent read / write operations
+        iounit: u32,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#symlink----create-symbolic-link
+    /// size[4] Rsymlink tag[2] | qid[13]
+    Symlink => Rsymlink {
+        /// Qid of the created link
+        qid: Qid,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#mknod----create-a-device-node
+    /// size[4] Rmknod tag[2] | qid[13]
+    Mknod => Rmknod {
+        /// Qid of the created node
+        qid: Qid,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#rename----rename-a-file
+    /// size[4] Rrename tag[2]
+    Rename => Rrename {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#readlink----read-value-of-symbolic-link
+    /// size[4] Rreadlink tag[2] | target[s]
+    Readlink => Rreadlink {
+        /// The target of the link
+        target: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#getattr----get-file-attributes
+    /// size[4] Rgetattr tag[2] | valid[8] qid[13] mode[4] uid[4] gid[4] nlink[8] rdev[8]
+    ///                           size[8] blksize[8] blocks[8] atime_sec[8] atime_nsec[8]
+    ///                           mtime_sec[8] mtime_nsec[8] ctime_sec[8] ctime_nsec[8]
+    ///                           btime_sec[8] btime_nsec[8] gen[8] data_version[8]
+    Getattr => Rgetattr {
+        /// Bitmask of the attributes being returned
+        valid: u64,
+        /// Qid of the resource
+        qid: Qid,
+        /// Linux mode bits
+        mode: u32,
+        /// Owner user id
+        uid: u32,
+        /// Owner group id
+        gid: u32,
+        /// Number of hard links
+        nlink: u64,
+        /// Device id
+        rdev: u64,
+        /// Size in bytes
+        size: u64,
+        /// Block size for file system IO
+        blksize: u64,
+        /// Number of 512 byte blocks allocated
+        blocks: u64,
+        /// Last access time: seconds
+        atime_sec: u64,
+        /// Last access time: nanoseconds
+        atime_nsec: u64,
+        /// Last modification time: seconds
+        mtime_sec: u64,
+        /// Last modification time: nanoseconds
+        mtime_nsec: u64,
+        /// Last status change time: seconds
+        ctime_sec: u64,
+        /// Last status change time: nanoseconds
+        ctime_nsec: u64,
+        /// Creation time: seconds
+        btime_sec: u64,
+        /// Creation time: nanoseconds
+        btime_nsec: u64,
+        /// Reserved
+        gen: u64,
+        /// Reserved
+        data_version: u64,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#setattr----set-file-attributes
+    /// size[4] Rsetattr tag[2]
+    Setattr => Rsetattr {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#xattrwalk----prepare-to-read-a-file-extended-attribute
+    /// size[4] Rxattrwalk tag[2] | size[8]
+    Xattrwalk => Rxattrwalk {
+        /// The size of the attribute value
+        size: u64,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#xattrcreate----prepare-to-set-extended-attribute
+    /// size[4] Rxattrcreate tag[2]
+    Xattrcreate => Rxattrcreate {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#readdir---read-a-directory
+    /// size[4] Rreaddir tag[2] | count[4] data[count]
+    Readdir => Rreaddir {
+        /// Directory entries of the form qid[13] offset[8] type[1] name[s]
+        data: Data,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#fsync----flush-any-cached-data-to-disk
+    /// size[4] Rfsync tag[2]
+    Fsync => Rfsync {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#lock----acquire-or-release-a-posix-record-lock
+    /// size[4] Rlock tag[2] | status[1]
+    Lock => Rlock {
+        /// The outcome of the lock request
+        status: u8,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#getlock----test-for-the-existence-of-a-posix-record-lock
+    /// size[4] Rgetlock tag[2] | type[1] start[8] length[8] proc_id[4] client_id[s]
+    Getlock => Rgetlock {
+        /// The type of the conflicting lock (or F_UNLCK if there is none)
+        ty: u8,
+        /// The starting offset of the lock
+        start: u64,
+        /// The length of the lock
+        length: u64,
+        /// The process id of the lock holder
+        proc_id: u32,
+        /// The client id of the lock holder
+        client_id: String,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#link----create-hard-link
+    /// size[4] Rlink tag[2]
+    Link => Rlink {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#mkdir----create-a-directory
+    /// size[4] Rmkdir tag[2] | qid[13]
+    Mkdir => Rmkdir {
+        /// Qid of the created directory
+        qid: Qid,
+    }
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#renameat----rename-a-file-or-directory
+    /// size[4] Rrenameat tag[2]
+    Renameat => Rrenameat {}
+
+    /// https://github.com/chaos/diod/blob/master/protocol.md#unlinkat----unlink-a-file-or-directory
+    /// size[4] Runlinkat tag[2]
+    Unlinkat => Runlinkat {}
 }
 
 #[cfg(test)]
@@ -890,4 +1449,197 @@ mod tests {
             }),
         }
     }
+
+    #[test]
+    fn unparsed_trailing_fields_are_discarded() {
+        let mut buf = Vec::new();
+        // A 9P2000.L Tattach carries an additional n_uname[4] field
+        let attach = Tmessage {
+            tag: 1,
+            content: Tdata::Attach {
+                fid: 0,
+                afid: u32::MAX,
+                uname: "test".to_string(),
+                aname: "".to_string(),
+            },
+        };
+        ((attach.n_bytes() + 4) as u32).write_to(&mut buf).unwrap();
+        MessageType::Tattach.0.write_to(&mut buf).unwrap();
+        let mut tmp = Vec::new();
+        attach.write_to(&mut tmp).unwrap();
+        buf.extend_from_slice(&tmp[5..]);
+        1000u32.write_to(&mut buf).unwrap();
+
+        let clunk = Tmessage {
+            tag: 2,
+            content: Tdata::Clunk { fid: 0 },
+        };
+        clunk.write_to(&mut buf).unwrap();
+
+        let mut cur = Cursor::new(buf);
+        assert_eq!(Tmessage::read_from(&mut cur).unwrap(), attach);
+        assert_eq!(Tmessage::read_from(&mut cur).unwrap(), clunk);
+    }
+
+    /// A small xorshift PRNG so that generated test cases are reproducible from their seed.
+    #[derive(Debug)]
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        pub(crate) fn new(seed: u64) -> Self {
+            Self(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+        }
+
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    pub(crate) trait Arbitrary {
+        fn arbitrary(rng: &mut Rng) -> Self;
+    }
+
+    macro_rules! impl_arbitrary_u {
+        ($($ty:ty),+) => {
+            $(impl Arbitrary for $ty {
+                fn arbitrary(rng: &mut Rng) -> Self {
+                    rng.next() as $ty
+                }
+            })+
+        };
+    }
+
+    impl_arbitrary_u!(u8, u16, u32, u64);
+
+    impl Arbitrary for String {
+        fn arbitrary(rng: &mut Rng) -> Self {
+            let chars: Vec<char> = "abcXYZ019 ./-_é世界".chars().collect();
+            (0..rng.below(12))
+                .map(|_| chars[rng.below(chars.len())])
+                .collect()
+        }
+    }
+
+    impl Arbitrary for Data {
+        fn arbitrary(rng: &mut Rng) -> Self {
+            Data((0..rng.below(64)).map(|_| rng.next() as u8).collect())
+        }
+    }
+
+    impl<T: Arbitrary> Arbitrary for Vec<T> {
+        fn arbitrary(rng: &mut Rng) -> Self {
+            (0..rng.below(5)).map(|_| T::arbitrary(rng)).collect()
+        }
+    }
+
+    fn encode<T: Format9p>(t: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        t.write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), t.n_bytes(), "n_bytes is incorrect");
+
+        buf
+    }
+
+    fn arbitrary_messages(seed: u64) -> (Vec<Tmessage>, Vec<Rmessage>) {
+        let mut rng = Rng::new(seed);
+        let ts = Tdata::arbitrary_variants(&mut rng)
+            .into_iter()
+            .map(|content| Tmessage {
+                tag: Arbitrary::arbitrary(&mut rng),
+                content,
+            })
+            .collect();
+        let rs = Rdata::arbitrary_variants(&mut rng)
+            .into_iter()
+            .map(|content| Rmessage {
+                tag: Arbitrary::arbitrary(&mut rng),
+                content,
+            })
+            .collect();
+
+        (ts, rs)
+    }
+
+    #[test]
+    fn all_messages_round_trip() {
+        for seed in 0..64 {
+            let (ts, rs) = arbitrary_messages(seed);
+
+            for t in ts {
+                let buf = encode(&t);
+                assert_eq!(
+                    Tmessage::read_from(&mut &buf[..]).unwrap(),
+                    t,
+                    "seed={seed}"
+                );
+            }
+
+            for r in rs {
+                let buf = encode(&r);
+                assert_eq!(
+                    Rmessage::read_from(&mut &buf[..]).unwrap(),
+                    r,
+                    "seed={seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_errors() {
+        let (ts, rs) = arbitrary_messages(42);
+        let bufs: Vec<Vec<u8>> = ts.iter().map(encode).chain(rs.iter().map(encode)).collect();
+
+        for buf in bufs {
+            for n in 0..buf.len() {
+                assert!(Tmessage::read_from(&mut &buf[..n]).is_err());
+                assert!(Rmessage::read_from(&mut &buf[..n]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_do_not_panic() {
+        let mut rng = Rng::new(7);
+        let (ts, _) = arbitrary_messages(7);
+        let valid: Vec<Vec<u8>> = ts.iter().map(encode).collect();
+
+        for i in 0..10_000 {
+            // Corrupting valid messages gets us further into decoding than purely random input
+            let mut buf = if i % 2 == 0 {
+                valid[rng.below(valid.len())].clone()
+            } else {
+                (0..rng.below(64)).map(|_| rng.next() as u8).collect()
+            };
+            for _ in 0..rng.below(4) {
+                if !buf.is_empty() {
+                    let j = rng.below(buf.len());
+                    buf[j] = rng.next() as u8;
+                }
+            }
+
+            _ = Tmessage::read_from(&mut &buf[..]);
+            _ = Rmessage::read_from(&mut &buf[..]);
+        }
+    }
+
+    #[test_case(3; "shorter than the header")]
+    #[test_case(1024; "larger than the limit")]
+    #[test_case(u32::MAX; "maximum size field")]
+    #[test]
+    fn invalid_sizes_are_rejected(size: u32) {
+        let mut buf = Vec::new();
+        size.write_to(&mut buf).unwrap();
+        buf.extend_from_slice(&[MessageType::Tclunk.0, 0, 0, 0, 0, 0, 0]);
+
+        let err = Tmessage::read_from_with_limit(&mut &buf[..], 512).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
 }
@@ -1,57 +1,37 @@
 //! Traits for implementing a 9p fileserver
 use crate::{
+    auth::Auth,
     fs::{FileMeta, FileType, IoUnit, Mode, Perm, Stat, QID_ROOT},
-    protocol::{Data, Format9p, Qid, RawStat, Rdata, Tdata, Tmessage, MAX_DATA_LEN},
+    namespace::Socket,
+    protocol::{
+        Data, Format9p, Qid, RawStat, Rdata, Tdata, Tmessage, IO_HEADER_SIZE, MAX_DATA_LEN,
+    },
+    trace::{conn_id, Role, Traced, Tracer},
     Result, Stream,
 };
 use std::{
     cmp::min,
     collections::btree_map::{BTreeMap, Entry},
-    env, fs,
+    fs, io,
     mem::size_of,
     net::TcpListener,
-    os::unix::net::UnixListener,
-    sync::{mpsc::Receiver, Arc, Mutex, RwLock},
+    os::unix::fs::MetadataExt,
+    sync::{
+        mpsc::{channel, Receiver, SendError, Sender},
+        Arc, Mutex, RwLock,
+    },
     thread::{spawn, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 /// Marker afid to denode that auth is not required for establishing connections
 pub const AFID_NO_AUTH: u32 = u32::MAX;
 
-#[derive(Debug)]
-struct Socket {
-    path: String,
-    listener: UnixListener,
-}
-
-impl Drop for Socket {
-    fn drop(&mut self) {
-        let _ = fs::remove_file(&self.path);
-    }
-}
-
-/// The unix socket path that will be used for a given server name.
-pub fn socket_path(name: &str) -> String {
-    let uname = env::var("USER").unwrap();
-    let socket_dir = format!("/tmp/ns.{uname}.:0");
-    format!("{socket_dir}/{name}")
-}
+/// Marker fid passed to [Serve9p::clunk] for files that were created without being associated
+/// with a client fid.
+pub const NO_FID: u32 = u32::MAX;
 
-fn unix_socket(name: &str) -> Socket {
-    let uname = env::var("USER").unwrap();
-    let socket_dir = format!("/tmp/ns.{uname}.:0");
-    let _ = fs::create_dir_all(&socket_dir);
-    let path = format!("{socket_dir}/{name}");
-
-    // FIXME: really we should be handling this on exit but we'll need to catch
-    // ctrl-c to do that properly. For now this works but it means that if you
-    // start a second file server with the same name then it'll remove the socket
-    // for the first.
-    let _ = fs::remove_file(&path);
-    let listener = UnixListener::bind(&path).unwrap();
-
-    Socket { path, listener }
-}
+pub use crate::namespace::socket_path;
 
 fn tcp_socket(port: u16) -> TcpListener {
     let addr = format!("127.0.0.1:{port}");
@@ -61,28 +41,188 @@ fn tcp_socket(port: u16) -> TcpListener {
 // Error messages
 const E_NO_VERSION_MESSAGE: &str = "first message must be Tversion";
 const E_AUTH_NOT_REQUIRED: &str = "authentication not required";
+const E_AUTH_REQUIRED: &str = "authentication required";
+const E_AUTH_INCOMPLETE: &str = "authentication not complete";
+const E_AUTH_MISMATCH: &str = "afid was not authenticated for this uname and aname";
+const E_AUTH_FAILED: &str = "authentication failed";
 const E_DUPLICATE_FID: &str = "duplicate fid";
 const E_UNKNOWN_FID: &str = "unknown fid";
 const E_UNKNOWN_ROOT: &str = "unknown root directory";
-const E_WALK_NON_DIR: &str = "walk in non-directory";
+pub(crate) const E_WALK_NON_DIR: &str = "walk in non-directory";
 const E_CREATE_NON_DIR: &str = "create in non-directory";
 const E_INVALID_OFFSET: &str = "invalid offset for read on directory";
+const E_DUPLICATE_TAG: &str = "duplicate tag";
+pub(crate) const E_NO_SUCH_FILE: &str = "file not found";
+pub(crate) const E_UNSUPPORTED: &str = "operation not supported";
+pub(crate) const E_PERMISSION_DENIED: &str = "permission denied";
+pub(crate) const E_IS_DIR: &str = "is a directory";
 
 const UNKNOWN_VERSION: &str = "unknown";
 const SUPPORTED_VERSION: &str = "9P2000";
+const LINUX_VERSION: &str = "9P2000.L";
+
+// Linux errno values returned to 9P2000.L clients in Rlerror messages
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const ENOTDIR: u32 = 20;
+const EISDIR: u32 = 21;
+const EINVAL: u32 = 22;
+const EPROTO: u32 = 71;
+const EOPNOTSUPP: u32 = 95;
+
+// Linux constants used by 9P2000.L messages
+const O_ACCMODE: u32 = 0o3;
+const O_TRUNC: u32 = 0o1000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const F_UNLCK: u8 = 2;
+const P9_LOCK_SUCCESS: u8 = 0;
+const P9_GETATTR_BASIC: u64 = 0x000007ff;
+const P9_SETATTR_MODE: u32 = 0x00000001;
+const P9_SETATTR_SIZE: u32 = 0x00000008;
+const P9_SETATTR_ATIME: u32 = 0x00000010;
+const P9_SETATTR_MTIME: u32 = 0x00000020;
+const P9_SETATTR_ATIME_SET: u32 = 0x00000080;
+const P9_SETATTR_MTIME_SET: u32 = 0x00000100;
+const V9FS_MAGIC: u32 = 0x01021997;
+const OPEN_TRUNC: u8 = 0x10;
+
+/// The protocol dialect negotiated with a client in its Tversion message.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    /// Plain 9P2000
+    #[default]
+    Plan9,
+    /// 9P2000.L as spoken by the Linux kernel v9fs client
+    Linux,
+}
+
+impl Dialect {
+    /// Version strings may carry a period separated suffix identifying a protocol extension. We
+    /// support 9P2000.L directly and fall back to plain 9P2000 for any other extension (such as
+    /// 9P2000.u) as permitted by version(5).
+    fn negotiate(version: &str) -> Option<Self> {
+        if version == LINUX_VERSION {
+            Some(Self::Linux)
+        } else if version.split('.').next() == Some(SUPPORTED_VERSION) {
+            Some(Self::Plan9)
+        } else {
+            None
+        }
+    }
+
+    fn version(&self) -> &'static str {
+        match self {
+            Self::Plan9 => SUPPORTED_VERSION,
+            Self::Linux => LINUX_VERSION,
+        }
+    }
+}
+
+/// Map an error message to the Linux errno value sent to 9P2000.L clients.
+fn linux_errno(ename: &str) -> u32 {
+    match ename {
+        E_NO_SUCH_FILE => ENOENT,
+        E_UNKNOWN_FID | E_DUPLICATE_FID => EBADF,
+        E_WALK_NON_DIR | E_CREATE_NON_DIR => ENOTDIR,
+        E_IS_DIR => EISDIR,
+        E_INVALID_OFFSET | E_DUPLICATE_TAG => EINVAL,
+        E_NO_VERSION_MESSAGE => EPROTO,
+        E_AUTH_NOT_REQUIRED | E_UNSUPPORTED => EOPNOTSUPP,
+        E_AUTH_REQUIRED | E_AUTH_INCOMPLETE | E_AUTH_MISMATCH | E_AUTH_FAILED
+        | E_PERMISSION_DENIED => EACCES,
+        _ => EIO,
+    }
+}
 
 /// An opaque client ID that can be used by server implementations to determine which client a
 /// request originated from by comparing equality.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct ClientId(u64);
+pub struct ClientId(pub(crate) u64);
 
 /// The outcome of a client attempting to [read](Serve9p::read) a given file.
 #[derive(Debug)]
 pub enum ReadOutcome {
     /// The data is immediately available.
     Immediate(Vec<u8>),
-    /// No response should be sent until data is received on the provided channel
-    Blocked(Receiver<Vec<u8>>),
+    /// No response should be sent until data is sent using the [ReadSender] returned alongside
+    /// this outcome by [ReadOutcome::blocked].
+    ///
+    /// Other requests on the same connection continue to be handled while the read is pending. If
+    /// the client flushes the request then no reply is sent and any further attempts to send data
+    /// for it will fail.
+    Blocked(BlockedRead),
+}
+
+impl ReadOutcome {
+    /// Create a new blocked read along with the [ReadSender] used to reply to it.
+    pub fn blocked() -> (ReadSender, Self) {
+        let (tx, rx) = channel();
+        let blocked = BlockedRead {
+            rx,
+            cancel: tx.clone(),
+        };
+
+        (ReadSender { tx }, Self::Blocked(blocked))
+    }
+}
+
+#[derive(Debug)]
+enum ReadMsg {
+    Data(Vec<u8>),
+    /// The [ReadSender] was dropped without sending any data
+    Closed,
+    /// The client flushed the read
+    Cancelled,
+}
+
+/// Used to send the data for a [ReadOutcome::Blocked] read.
+///
+/// Dropping the sender without sending any data replies to the read with no data.
+#[derive(Debug)]
+pub struct ReadSender {
+    tx: Sender<ReadMsg>,
+}
+
+impl ReadSender {
+    /// Send the data for the read, returning it as an error if the read has been flushed or the
+    /// client has disconnected.
+    pub fn send(&self, data: Vec<u8>) -> std::result::Result<(), SendError<Vec<u8>>> {
+        self.tx.send(ReadMsg::Data(data)).map_err(|e| match e.0 {
+            ReadMsg::Data(data) => SendError(data),
+            _ => unreachable!("only data is sent by ReadSender::send"),
+        })
+    }
+}
+
+impl Drop for ReadSender {
+    fn drop(&mut self) {
+        _ = self.tx.send(ReadMsg::Closed);
+    }
+}
+
+/// The receiving half of a [ReadOutcome::Blocked] read.
+#[derive(Debug)]
+pub struct BlockedRead {
+    rx: Receiver<ReadMsg>,
+    /// Held by the server so that it is able to wake the thread waiting on the read if the
+    /// client flushes it
+    cancel: Sender<ReadMsg>,
+}
+
+impl BlockedRead {
+    /// Block until data is sent for this read, returning `None` if the [ReadSender] was dropped
+    /// without sending anything.
+    pub fn recv(self) -> Option<Vec<u8>> {
+        match self.rx.recv() {
+            Ok(ReadMsg::Data(data)) => Some(data),
+            _ => None,
+        }
+    }
 }
 
 /// A type capable of handling [9p](http://9p.cat-v.org/) requests in order to implement a
@@ -102,13 +242,12 @@ pub enum ReadOutcome {
 /// [Server] handles establishing and maintaining per-client sessions along with all of their `fids`,
 /// as such, [Serve9p] only needs to worry about maintaining `qids` for resources.
 ///
+/// The `fid` used by the client is passed to [open](Serve9p::open), [read](Serve9p::read),
+/// [write](Serve9p::write) and [clunk](Serve9p::clunk) so that implementations are able to track
+/// state for each open file: a single client may have the same file open more than once.
+///
 /// The source code of [Server] is a useful reference for those wanting to learn more.
 pub trait Serve9p: Send + 'static {
-    // #[allow(unused_variables)]
-    // fn auth(&mut self, afid: u32, uname: &str, aname: &str) -> Result<Qid> {
-    //     Err("authentication not required".to_string())
-    // }
-
     /// Lookup a child node under a known parent directory by name.
     ///
     /// `9p` Twalk messages received by the server will specify a full path from a known parent
@@ -128,13 +267,23 @@ pub trait Serve9p: Send + 'static {
     /// Open an existing file in the requested mode for subsequent I/O via [read](Serve9p::read) and
     /// [write](Serve9p::write) calls.
     ///
+    /// The low two bits of `mode` hold the requested access (0 for read, 1 for write, 2 for read
+    /// and write and 3 for execute) with 0x10 being set if the file should be truncated.
+    ///
     /// The return of this method is an [IoUnit] used to inform the client of the maximum number of
     /// bytes that will be supported per read/write call on this resource.
-    fn open(&mut self, cid: ClientId, qid: u64, mode: Mode, uname: &str) -> Result<IoUnit>;
+    fn open(
+        &mut self,
+        cid: ClientId,
+        fid: u32,
+        qid: u64,
+        mode: Mode,
+        uname: &str,
+    ) -> Result<IoUnit>;
 
     /// Clunk a currently open file.
     #[allow(unused_variables)]
-    fn clunk(&mut self, cid: ClientId, qid: u64) {}
+    fn clunk(&mut self, cid: ClientId, fid: u32, qid: u64) {}
 
     /// Create a new file in the given parent directory.
     fn create(
@@ -148,9 +297,13 @@ pub trait Serve9p: Send + 'static {
     ) -> Result<(FileMeta, IoUnit)>;
 
     /// Read `count` bytes from the requested file starting from the given `offset`.
+    ///
+    /// Any data returned beyond `count` bytes, either immediately or for a blocked read, is
+    /// discarded.
     fn read(
         &mut self,
         cid: ClientId,
+        fid: u32,
         qid: u64,
         offset: usize,
         count: usize,
@@ -164,6 +317,7 @@ pub trait Serve9p: Send + 'static {
     fn write(
         &mut self,
         cid: ClientId,
+        fid: u32,
         qid: u64,
         offset: usize,
         data: Vec<u8>,
@@ -177,6 +331,11 @@ pub trait Serve9p: Send + 'static {
     fn stat(&mut self, cid: ClientId, qid: u64, uname: &str) -> Result<Stat>;
 
     /// Attempt to set the machine independent "directory entry" for the given resource.
+    ///
+    /// The provided [Stat] is the current one returned by [stat](Serve9p::stat) with any changes
+    /// requested by the client applied, so implementations should compare the two to determine
+    /// what has been modified: a change of name is a rename within the same directory and a
+    /// change of size truncates the file.
     fn write_stat(&mut self, cid: ClientId, qid: u64, stat: Stat, uname: &str) -> Result<()>;
 }
 
@@ -190,6 +349,8 @@ where
     msize: u32,
     roots: BTreeMap<String, u64>,
     qids: Arc<RwLock<BTreeMap<u64, FileMeta>>>,
+    auth: Option<Arc<dyn Auth>>,
+    tracer: Option<Tracer>,
     next_client_id: u64,
 }
 
@@ -215,54 +376,84 @@ where
             msize: MAX_DATA_LEN as u32,
             roots,
             qids: Arc::new(RwLock::new(qids)),
+            auth: None,
+            tracer: Tracer::from_env(Role::Server),
             next_client_id: 0,
         }
     }
 
+    /// Require clients to authenticate using the given [Auth] implementation before they are
+    /// able to attach.
+    ///
+    /// By default no authentication is required and any client able to connect to the server is
+    /// able to attach using any uname.
+    pub fn with_auth(mut self, auth: impl Auth) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Record every message sent and received by this server using the given [Tracer].
+    ///
+    /// By default servers trace to the file named by `$NINEP_TRACE` if it is set (see the
+    /// [trace](crate::trace) module for details).
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub(crate) fn without_tracer(mut self) -> Self {
+        self.tracer = None;
+        self
+    }
+
     /// Bind this server to the specified port and serve over a tcp socket.
     pub fn serve_tcp(mut self, port: u16) -> JoinHandle<()> {
         spawn(move || {
             let listener = tcp_socket(port);
 
             for stream in listener.incoming() {
-                let stream = stream.unwrap();
-                let session = Session::new_unattached(
-                    ClientId(self.next_client_id),
-                    self.msize,
-                    self.roots.clone(),
-                    self.s.clone(),
-                    self.qids.clone(),
-                    stream,
-                );
-
-                self.next_client_id += 1;
-                spawn(move || session.handle_connection());
+                self.spawn_session(stream.unwrap());
             }
         })
     }
 
-    /// Bind this server to the specified path and serve over a unix socket.
-    pub fn serve_socket(mut self, socket_name: impl Into<String>) -> JoinHandle<()> {
-        let socket_name = socket_name.into();
-
-        spawn(move || {
-            let sock = unix_socket(&socket_name);
+    /// Bind this server to a unix socket with the given name in the current
+    /// [namespace](crate::namespace) and serve over it.
+    ///
+    /// Returns an error if the socket can not be bound, including if another server is already
+    /// listening using the same name.
+    pub fn serve_socket(mut self, socket_name: impl AsRef<str>) -> io::Result<JoinHandle<()>> {
+        let sock = Socket::bind(socket_name.as_ref())?;
 
+        Ok(spawn(move || {
             for stream in sock.listener.incoming() {
-                let stream = stream.unwrap();
-                let session = Session::new_unattached(
-                    ClientId(self.next_client_id),
-                    self.msize,
-                    self.roots.clone(),
-                    self.s.clone(),
-                    self.qids.clone(),
-                    stream,
-                );
-
-                self.next_client_id += 1;
-                spawn(move || session.handle_connection());
+                self.spawn_session(stream.unwrap());
             }
-        })
+        }))
+    }
+
+    /// Handle a new client connection on its own thread.
+    pub(crate) fn spawn_session<U>(&mut self, stream: U)
+    where
+        U: Stream,
+    {
+        let stream = Traced::new(stream, conn_id(self.next_client_id), self.tracer.clone());
+
+        let session = match Session::new_unattached(
+            ClientId(self.next_client_id),
+            self.msize,
+            self.roots.clone(),
+            self.s.clone(),
+            self.qids.clone(),
+            self.auth.clone(),
+            stream,
+        ) {
+            Ok(session) => session,
+            Err(_) => return,
+        };
+
+        self.next_client_id += 1;
+        spawn(move || session.handle_connection());
     }
 }
 
@@ -272,6 +463,16 @@ trait SessionType: Send {}
 #[derive(Debug, Default)]
 struct Unattached {
     seen_version: bool,
+    afids: BTreeMap<u32, AuthFid>,
+}
+
+/// The state of an in progress authentication attempt on an afid.
+#[derive(Debug)]
+struct AuthFid {
+    uname: String,
+    aname: String,
+    challenge: Vec<u8>,
+    verified: bool,
 }
 
 impl SessionType for Unattached {}
@@ -292,6 +493,66 @@ impl Attached {
     }
 }
 
+/// The write half of a connection along with the tags of any blocked reads that are still
+/// awaiting a reply.
+///
+/// All replies are written while holding the lock around this struct so that responses sent from
+/// blocked read threads are never interleaved with each other or with those sent by the session
+/// itself.
+#[derive(Debug)]
+struct Replies<U>
+where
+    U: Stream,
+{
+    stream: U,
+    in_flight: BTreeMap<u16, (u64, Sender<ReadMsg>)>,
+    next_id: u64,
+}
+
+impl<U> Replies<U>
+where
+    U: Stream,
+{
+    fn new(stream: U) -> Self {
+        Self {
+            stream,
+            in_flight: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Register a new blocked read for the given tag, returning the id that must be provided when
+    /// replying to it.
+    fn register(&mut self, tag: u16, cancel: Sender<ReadMsg>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.in_flight.insert(tag, (id, cancel));
+
+        id
+    }
+
+    /// Cancel the blocked read for the given tag (if there is one) without replying to it.
+    fn cancel(&mut self, tag: u16) {
+        if let Some((_, cancel)) = self.in_flight.remove(&tag) {
+            _ = cancel.send(ReadMsg::Cancelled);
+        }
+    }
+
+    fn cancel_all(&mut self) {
+        for (_, (_, cancel)) in std::mem::take(&mut self.in_flight) {
+            _ = cancel.send(ReadMsg::Cancelled);
+        }
+    }
+
+    /// Reply to a blocked read so long as it has not been flushed in the meantime.
+    fn reply_pending(&mut self, tag: u16, id: u64, resp: Result<Rdata>) {
+        if matches!(self.in_flight.get(&tag), Some((pending, _)) if *pending == id) {
+            self.in_flight.remove(&tag);
+            self.stream.reply(tag, resp);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Session<T, S, U>
 where
@@ -305,7 +566,10 @@ where
     roots: BTreeMap<String, u64>,
     s: Arc<Mutex<S>>,
     qids: Arc<RwLock<BTreeMap<u64, FileMeta>>>,
+    auth: Option<Arc<dyn Auth>>,
     stream: U,
+    replies: Arc<Mutex<Replies<U>>>,
+    dialect: Dialect,
 }
 
 impl<T, S, U> Session<T, S, U>
@@ -319,7 +583,41 @@ where
     }
 
     fn reply(&mut self, tag: u16, resp: Result<Rdata>) {
-        self.stream.reply(tag, resp);
+        let resp = match (self.dialect, resp) {
+            (Dialect::Linux, Err(ename)) => Ok(Rdata::Lerror {
+                ecode: linux_errno(&ename),
+            }),
+            (_, resp) => resp,
+        };
+
+        self.replies.lock().unwrap().stream.reply(tag, resp);
+    }
+
+    fn tag_in_flight(&self, tag: u16) -> bool {
+        self.replies.lock().unwrap().in_flight.contains_key(&tag)
+    }
+
+    /// Abort all outstanding blocked reads on this connection without replying to them.
+    fn abort_in_flight(&self) {
+        self.replies.lock().unwrap().cancel_all();
+    }
+
+    /// The flush request is sent when a client wishes to abort a previously issued request that
+    /// has not yet been replied to: oldtag identifies the message being flushed.
+    /// The server should answer the flush message immediately. If it recognizes oldtag as the tag
+    /// of a pending transaction, it should abort any pending response and discard that tag. In
+    /// either case, it should respond with an Rflush echoing the tag (not oldtag) of the Tflush
+    /// message. A Tflush can never be responded to by an Rerror message.
+    /// When the client sends a Tflush, it must wait to receive the corresponding Rflush before
+    /// reusing oldtag for subsequent messages. If a response to the flushed request is received
+    /// before the Rflush, the client must honor the response as if it had not been flushed, since
+    /// the completed request may signify a state change in the server.
+    fn handle_flush(&mut self, tag: u16, old_tag: u16) {
+        // Holding the lock while replying guarantees that any reply already sent for old_tag is
+        // written before the Rflush and that no reply for it will be sent afterwards.
+        let mut replies = self.replies.lock().unwrap();
+        replies.cancel(old_tag);
+        replies.stream.reply(tag, Ok(Rdata::Flush {}));
     }
 
     /// The version request negotiates the protocol version and message size to be used on the
@@ -350,38 +648,23 @@ where
     /// connection is aborted; all active fids are freed (‘clunked’) automatically. The set of
     /// messages between version requests is called a session.
     fn handle_version(&mut self, msize: u32, version: String) -> Result<Rdata> {
-        let server_version = if version != SUPPORTED_VERSION {
-            UNKNOWN_VERSION
-        } else {
-            SUPPORTED_VERSION
+        let server_version = match Dialect::negotiate(&version) {
+            Some(dialect) => {
+                self.dialect = dialect;
+                dialect.version()
+            }
+            None => UNKNOWN_VERSION,
         };
 
+        // Subsequent messages larger than the negotiated msize are rejected. A Tversion resets
+        // the session so this is negotiated from scratch rather than from any previous value.
+        self.msize = min(MAX_DATA_LEN as u32, msize);
+
         Ok(Rdata::Version {
-            msize: min(self.msize, msize),
+            msize: self.msize,
             version: server_version.to_string(),
         })
     }
-
-    /// If the client does wish to authenticate, it must acquire and validate an afid using an auth
-    /// message before doing the attach.
-    /// The auth message contains afid, a new fid to be established for authentication, and the
-    /// uname and aname that will be those of the following attach message. If the server does not
-    /// require authentication, it returns Rerror to the Tauth message.
-    /// If the server does require authentication, it returns aqid defining a file of type QTAUTH
-    /// (see intro(9P)) that may be read and written (using read and write messages in the usual
-    /// way) to execute an authentication protocol. That protocol’s definition is not part of 9P
-    /// itself.
-    /// Once the protocol is complete, the same afid is presented in the attach message for the
-    /// user, granting entry. The same validated afid may be used for multiple attach messages with
-    /// the same uname and aname.
-    #[allow(unused_variables)]
-    fn handle_auth(&mut self, afid: u32, uname: String, aname: String) -> Result<Rdata> {
-        // TODO: handle auth
-        // let aqid = self.s.lock().unwrap().auth(afid, &uname, &aname)?;
-        // Ok(Rdata::Auth { aqid })
-
-        Err(E_AUTH_NOT_REQUIRED.to_string())
-    }
 }
 
 impl<S, U> Session<Unattached, S, U>
@@ -395,17 +678,23 @@ where
         roots: BTreeMap<String, u64>,
         s: Arc<Mutex<S>>,
         qids: Arc<RwLock<BTreeMap<u64, FileMeta>>>,
+        auth: Option<Arc<dyn Auth>>,
         stream: U,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        let replies = Arc::new(Mutex::new(Replies::new(stream.try_clone()?)));
+
+        Ok(Self {
             client_id,
             state: Unattached::default(),
             msize,
             roots,
             s,
             qids,
+            auth,
             stream,
-        }
+            replies,
+            dialect: Dialect::default(),
+        })
     }
 
     fn into_attached(self, ty: Attached) -> Session<Attached, S, U> {
@@ -415,18 +704,32 @@ where
             roots,
             s,
             qids,
+            auth,
             stream,
+            replies,
+            dialect,
             ..
         } = self;
 
-        Session::new_attached(client_id, ty, msize, roots, s, qids, stream)
+        Session {
+            client_id,
+            state: ty,
+            msize,
+            roots,
+            s,
+            qids,
+            auth,
+            stream,
+            replies,
+            dialect,
+        }
     }
 
     fn handle_connection(mut self) {
         use Tdata::*;
 
         loop {
-            let t = match Tmessage::read_from(&mut self.stream) {
+            let t = match Tmessage::read_from_with_limit(&mut self.stream, self.msize) {
                 Ok(t) => t,
                 Err(_) => return,
             };
@@ -435,7 +738,8 @@ where
 
             let resp = match content {
                 Version { msize, version } => {
-                    self.state.seen_version = version == SUPPORTED_VERSION;
+                    self.state.seen_version = Dialect::negotiate(&version).is_some();
+                    self.state.afids.clear();
                     self.handle_version(msize, version)
                 }
 
@@ -472,6 +776,14 @@ where
                     return self.into_attached(st).handle_connection();
                 }
 
+                // Reads and writes prior to attaching are only valid for afids
+                Read { fid, offset, count } => self.handle_auth_read(fid, offset, count),
+                Write { fid, data, .. } => self.handle_auth_write(fid, data.0),
+                Clunk { fid } => match self.state.afids.remove(&fid) {
+                    Some(_) => Ok(Rdata::Clunk {}),
+                    None => Err(E_UNKNOWN_FID.to_string()),
+                },
+
                 _ => Err("session is unattached".into()),
             };
 
@@ -493,7 +805,7 @@ where
     fn handle_attach(
         &mut self,
         root_fid: u32,
-        _afid: u32,
+        afid: u32,
         uname: String,
         aname: String,
     ) -> Result<(Attached, Qid)> {
@@ -502,13 +814,106 @@ where
             None => return Err(E_UNKNOWN_ROOT.to_string()),
         };
 
-        // TODO: handle checking afids (AFID_NO_AUTH should be accepted if there is no auth)
+        // If authentication is not required then the afid is ignored
+        if self.auth.is_some() {
+            match self.state.afids.get(&afid) {
+                _ if afid == AFID_NO_AUTH => return Err(E_AUTH_REQUIRED.to_string()),
+                None => return Err(E_UNKNOWN_FID.to_string()),
+                Some(a) if !a.verified => return Err(E_AUTH_INCOMPLETE.to_string()),
+                Some(a) if a.uname != uname || a.aname != aname => {
+                    return Err(E_AUTH_MISMATCH.to_string())
+                }
+                Some(_) => (),
+            }
+        }
 
         let st = Attached::new(uname, root_fid, root_qid);
         let aqid = self.qid(root_qid).expect("to have root qid");
 
         Ok((st, aqid))
     }
+
+    /// If the client does wish to authenticate, it must acquire and validate an afid using an auth
+    /// message before doing the attach.
+    /// The auth message contains afid, a new fid to be established for authentication, and the
+    /// uname and aname that will be those of the following attach message. If the server does not
+    /// require authentication, it returns Rerror to the Tauth message.
+    /// If the server does require authentication, it returns aqid defining a file of type QTAUTH
+    /// (see intro(9P)) that may be read and written (using read and write messages in the usual
+    /// way) to execute an authentication protocol. That protocol’s definition is not part of 9P
+    /// itself.
+    /// Once the protocol is complete, the same afid is presented in the attach message for the
+    /// user, granting entry. The same validated afid may be used for multiple attach messages with
+    /// the same uname and aname.
+    ///
+    /// The protocol run over the afid is a single challenge / response exchange: reading the afid
+    /// returns the challenge from [Auth::challenge] and the client then writes its response which
+    /// is checked using [Auth::verify].
+    fn handle_auth(&mut self, afid: u32, uname: String, aname: String) -> Result<Rdata> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Err(E_AUTH_NOT_REQUIRED.to_string()),
+        };
+
+        if afid == AFID_NO_AUTH || self.state.afids.contains_key(&afid) {
+            return Err(E_DUPLICATE_FID.to_string());
+        }
+
+        let challenge = auth.challenge(&uname, &aname)?;
+        self.state.afids.insert(
+            afid,
+            AuthFid {
+                uname,
+                aname,
+                challenge,
+                verified: false,
+            },
+        );
+
+        Ok(Rdata::Auth {
+            aqid: Qid {
+                ty: Mode::AUTH.bits(),
+                version: 0,
+                path: afid as u64,
+            },
+        })
+    }
+
+    fn handle_auth_read(&mut self, afid: u32, offset: u64, count: u32) -> Result<Rdata> {
+        let a = match self.state.afids.get(&afid) {
+            Some(a) => a,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+
+        let start = min(offset as usize, a.challenge.len());
+        let end = min(start + count as usize, a.challenge.len());
+
+        Ok(Rdata::Read {
+            data: Data(a.challenge[start..end].to_vec()),
+        })
+    }
+
+    fn handle_auth_write(&mut self, afid: u32, response: Vec<u8>) -> Result<Rdata> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+        let a = match self.state.afids.get_mut(&afid) {
+            Some(a) => a,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+
+        if let Err(e) = auth.verify(&a.uname, &a.aname, &a.challenge, &response) {
+            // A failed attempt invalidates the afid so that responses can not be guessed
+            self.state.afids.remove(&afid);
+            return Err(e);
+        }
+        a.verified = true;
+
+        Ok(Rdata::Write {
+            count: response.len() as u32,
+        })
+    }
 }
 
 macro_rules! file_meta {
@@ -525,31 +930,11 @@ where
     S: Serve9p,
     U: Stream,
 {
-    fn new_attached(
-        client_id: ClientId,
-        state: Attached,
-        msize: u32,
-        roots: BTreeMap<String, u64>,
-        s: Arc<Mutex<S>>,
-        qids: Arc<RwLock<BTreeMap<u64, FileMeta>>>,
-        stream: U,
-    ) -> Self {
-        Self {
-            client_id,
-            state,
-            msize,
-            roots,
-            s,
-            qids,
-            stream,
-        }
-    }
-
     /// Explicitly clunk all
     fn clunk_and_clear(&mut self) {
         let mut guard = self.s.lock().unwrap();
-        for &qid in self.state.fids.values() {
-            guard.clunk(self.client_id, qid);
+        for (&fid, &qid) in self.state.fids.iter() {
+            guard.clunk(self.client_id, fid, qid);
         }
         self.state.fids.clear();
     }
@@ -558,24 +943,36 @@ where
         use Tdata::*;
 
         loop {
-            let t = match Tmessage::read_from(&mut self.stream) {
+            let t = match Tmessage::read_from_with_limit(&mut self.stream, self.msize) {
                 Ok(t) => t,
-                Err(_) => return self.clunk_and_clear(),
+                Err(_) => {
+                    self.abort_in_flight();
+                    return self.clunk_and_clear();
+                }
             };
 
             let Tmessage { tag, content } = t;
 
+            if self.tag_in_flight(tag) {
+                self.reply(tag, Err(E_DUPLICATE_TAG.to_string()));
+                continue;
+            }
+
             let resp = match content {
                 Version { msize, version } => {
                     let res = self.handle_version(msize, version);
                     if res.is_ok() {
+                        self.abort_in_flight();
                         self.clunk_and_clear();
                     }
 
                     res
                 }
                 Auth { .. } | Attach { .. } => Err("session is already attached".into()),
-                Flush { .. } => Ok(Rdata::Flush {}),
+                Flush { old_tag } => {
+                    self.handle_flush(tag, old_tag);
+                    continue;
+                }
 
                 Walk {
                     fid,
@@ -584,13 +981,13 @@ where
                 } => self.handle_walk(fid, new_fid, wnames),
                 Clunk { fid } => self.handle_clunk(fid),
                 Stat { fid } => self.handle_stat(fid),
-                Open { fid, mode } => self.handle_open(fid, Mode::new(mode)),
+                Open { fid, mode } => self.handle_open(fid, open_mode_9p(mode)),
                 Create {
                     fid,
                     name,
                     perm,
                     mode,
-                } => self.handle_create(fid, name, Perm::new(perm), Mode::new(mode)),
+                } => self.handle_create(fid, name, Perm::new(perm), open_mode_9p(mode)),
                 Read { fid, offset, count } => match self.handle_read(tag, fid, offset, count) {
                     Ok(Some(resp)) => Ok(resp),
                     Err(err) => Err(err),
@@ -599,6 +996,66 @@ where
                 Write { fid, offset, data } => self.handle_write(fid, offset, data.0),
                 Remove { fid } => self.handle_remove(fid),
                 Wstat { fid, stat, .. } => self.handle_wstat(fid, stat),
+
+                // 9P2000.L
+                Statfs { .. } => Ok(statfs()),
+                Lopen { fid, flags } => self.handle_lopen(fid, flags),
+                Lcreate {
+                    fid,
+                    name,
+                    flags,
+                    mode,
+                    ..
+                } => self.handle_lcreate(fid, name, flags, mode),
+                Getattr { fid, .. } => self.handle_getattr(fid),
+                Setattr {
+                    fid,
+                    valid,
+                    mode,
+                    size,
+                    atime_sec,
+                    atime_nsec,
+                    mtime_sec,
+                    mtime_nsec,
+                    ..
+                } => self.handle_setattr(
+                    fid,
+                    valid,
+                    mode,
+                    size,
+                    systime(atime_sec, atime_nsec),
+                    systime(mtime_sec, mtime_nsec),
+                ),
+                Readdir { fid, offset, count } => self.handle_readdir(fid, offset, count),
+                Fsync { .. } => Ok(Rdata::Fsync {}),
+                Lock { .. } => Ok(Rdata::Lock {
+                    status: P9_LOCK_SUCCESS,
+                }),
+                Getlock {
+                    start,
+                    length,
+                    proc_id,
+                    client_id,
+                    ..
+                } => Ok(Rdata::Getlock {
+                    ty: F_UNLCK,
+                    start,
+                    length,
+                    proc_id,
+                    client_id,
+                }),
+                Mkdir {
+                    dfid, name, mode, ..
+                } => self.handle_mkdir(dfid, name, mode),
+                Unlinkat { dfid, name, .. } => self.handle_unlinkat(dfid, name),
+                Symlink { .. }
+                | Mknod { .. }
+                | Rename { .. }
+                | Readlink { .. }
+                | Xattrwalk { .. }
+                | Xattrcreate { .. }
+                | Link { .. }
+                | Renameat { .. } => Err(E_UNSUPPORTED.to_string()),
             };
 
             self.reply(tag, resp);
@@ -672,7 +1129,15 @@ where
         let mut qid = fm.qid;
 
         for name in wnames.iter() {
-            let fm = s.walk(self.client_id, qid, name, &self.state.uname)?;
+            let fm = match s.walk(self.client_id, qid, name, &self.state.uname) {
+                Ok(fm) => fm,
+                // A walk that fails part way through returns the qids that were walked so far
+                // and an error is only returned if the first element could not be walked
+                Err(_) if !wqids.is_empty() => break,
+                // 9P2000.L clients rely on ENOENT to identify missing files
+                Err(_) if self.dialect == Dialect::Linux => return Err(E_NO_SUCH_FILE.to_string()),
+                Err(e) => return Err(e),
+            };
             qid = fm.qid;
             wqids.push(fm.as_qid());
             qids.insert(qid, fm);
@@ -695,7 +1160,7 @@ where
         match self.state.fids.entry(fid) {
             Entry::Occupied(ent) => {
                 let qid = ent.remove();
-                self.s.lock().unwrap().clunk(self.client_id, qid);
+                self.s.lock().unwrap().clunk(self.client_id, fid, qid);
 
                 Ok(Rdata::Clunk {})
             }
@@ -719,17 +1184,18 @@ where
         Ok(Rdata::Stat { size, stat })
     }
 
+    /// Fields the client has asked to leave unchanged are filled in from the current [Stat] for
+    /// the file so that [Serve9p::write_stat] always receives a complete entry.
     fn handle_wstat(&mut self, fid: u32, raw_stat: RawStat) -> Result<Rdata> {
-        let stat: Stat = raw_stat.try_into()?;
         let fm = match file_meta!(self, fid) {
             Some(fm) => fm,
             None => return Err(E_UNKNOWN_FID.to_string()),
         };
 
-        self.s
-            .lock()
-            .unwrap()
-            .write_stat(self.client_id, fm.qid, stat, &self.state.uname)?;
+        let mut s = self.s.lock().unwrap();
+        let current = s.stat(self.client_id, fm.qid, &self.state.uname)?;
+        let stat = apply_raw_stat(current, raw_stat);
+        s.write_stat(self.client_id, fm.qid, stat, &self.state.uname)?;
 
         Ok(Rdata::Wstat {})
     }
@@ -743,7 +1209,7 @@ where
             self.s
                 .lock()
                 .unwrap()
-                .open(self.client_id, fm.qid, mode, &self.state.uname)?;
+                .open(self.client_id, fid, fm.qid, mode, &self.state.uname)?;
 
         Ok(Rdata::Open {
             qid: fm.as_qid(),
@@ -752,6 +1218,23 @@ where
     }
 
     fn handle_create(&mut self, fid: u32, name: String, perm: Perm, mode: Mode) -> Result<Rdata> {
+        let (fm, iounit) = self.create(fid, name, perm, mode)?;
+
+        // fid is now changed to point to the newly created file rather than the parent
+        let qid = fm.as_qid();
+        self.state.fids.insert(fid, fm.qid);
+
+        Ok(Rdata::Create { qid, iounit })
+    }
+
+    /// Create a new file in the directory identified by fid.
+    fn create(
+        &mut self,
+        fid: u32,
+        name: String,
+        perm: Perm,
+        mode: Mode,
+    ) -> Result<(FileMeta, IoUnit)> {
         let fm = match file_meta!(self, fid) {
             Some(fm) => fm,
             None => return Err(E_UNKNOWN_FID.to_string()),
@@ -769,12 +1252,13 @@ where
             &self.state.uname,
         )?;
 
-        // fid is now changed to point to the newly created file rather than the parent
-        let qid = fm.as_qid();
-        self.state.fids.insert(fid, fm.qid);
-        self.qids.write().unwrap().entry(fm.qid).or_insert(fm);
+        self.qids
+            .write()
+            .unwrap()
+            .entry(fm.qid)
+            .or_insert(fm.clone());
 
-        Ok(Rdata::Create { qid, iounit })
+        Ok((fm, iounit))
     }
 
     // The read request asks for count bytes of data from the file identified by fid, which must be
@@ -806,29 +1290,45 @@ where
             return Err(format!("offset too large: {offset} > {}", u32::MAX));
         }
 
+        // Replies larger than the negotiated msize would be rejected by the client so the data
+        // returned by the server implementation is truncated to fit if needed
+        let count = min(
+            count as usize,
+            (self.msize as usize).saturating_sub(IO_HEADER_SIZE),
+        );
+
         let data = match fm.ty {
-            Directory => self.read_dir(fm.qid, offset as usize, count as usize)?,
+            Directory => self.read_dir(fm.qid, offset as usize, count)?,
             Regular | AppendOnly | Exclusive => {
                 let outcome = self.s.lock().unwrap().read(
                     self.client_id,
+                    fid,
                     fm.qid,
                     offset as usize,
-                    count as usize,
+                    count,
                     &self.state.uname,
                 )?;
 
                 match outcome {
-                    ReadOutcome::Immediate(data) => data,
-                    ReadOutcome::Blocked(chan) => {
-                        // Cloning the stream can
-                        let mut stream = match self.stream.try_clone() {
-                            Ok(stream) => stream,
-                            Err(err) => return Err(err),
-                        };
+                    ReadOutcome::Immediate(mut data) => {
+                        data.truncate(count);
+                        data
+                    }
+                    ReadOutcome::Blocked(BlockedRead { rx, cancel }) => {
+                        let replies = self.replies.clone();
+                        let id = replies.lock().unwrap().register(tag, cancel);
 
                         spawn(move || {
-                            let data = chan.recv().unwrap_or_default();
-                            stream.reply(tag, Ok(Rdata::Read { data: Data(data) }));
+                            let mut data = match rx.recv() {
+                                Ok(ReadMsg::Data(data)) => data,
+                                Ok(ReadMsg::Closed) | Err(_) => Vec::new(),
+                                // The read was flushed so drop the receiver without replying
+                                Ok(ReadMsg::Cancelled) => return,
+                            };
+                            data.truncate(count);
+
+                            let resp = Ok(Rdata::Read { data: Data(data) });
+                            replies.lock().unwrap().reply_pending(tag, id, resp);
                         });
 
                         return Ok(None);
@@ -891,6 +1391,7 @@ where
 
         let count = self.s.lock().unwrap().write(
             self.client_id,
+            fid,
             fm.qid,
             offset as usize,
             data,
@@ -900,17 +1401,854 @@ where
         Ok(Rdata::Write { count })
     }
 
+    /// The fid is clunked whether or not the remove succeeds.
     fn handle_remove(&mut self, fid: u32) -> Result<Rdata> {
         let fm = match file_meta!(self, fid) {
             Some(fm) => fm,
             None => return Err(E_UNKNOWN_FID.to_string()),
         };
+        self.state.fids.remove(&fid);
+
+        let mut s = self.s.lock().unwrap();
+        let res = s.remove(self.client_id, fm.qid, &self.state.uname);
+        s.clunk(self.client_id, fid, fm.qid);
+        res?;
+
+        Ok(Rdata::Remove {})
+    }
+}
+
+/// Handlers for the additional messages defined by 9P2000.L, mapped on to the [Serve9p] trait.
+///
+/// See https://github.com/chaos/diod/blob/master/protocol.md for the semantics of each message.
+impl<S, U> Session<Attached, S, U>
+where
+    S: Serve9p,
+    U: Stream,
+{
+    /// Open the file identified by fid using Linux open(2) flags.
+    fn handle_lopen(&mut self, fid: u32, flags: u32) -> Result<Rdata> {
+        let fm = match file_meta!(self, fid) {
+            Some(fm) => fm,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+        let iounit = self.s.lock().unwrap().open(
+            self.client_id,
+            fid,
+            fm.qid,
+            open_mode(flags),
+            &self.state.uname,
+        )?;
+
+        Ok(Rdata::Lopen {
+            qid: fm.as_qid(),
+            iounit,
+        })
+    }
 
-        self.s
+    /// Create and open a regular file in the directory identified by fid, after which fid will
+    /// refer to the new file.
+    fn handle_lcreate(&mut self, fid: u32, name: String, flags: u32, mode: u32) -> Result<Rdata> {
+        let (fm, iounit) = self.create(fid, name, Perm::new(mode & 0o777), open_mode(flags))?;
+        self.state.fids.insert(fid, fm.qid);
+
+        Ok(Rdata::Lcreate {
+            qid: fm.as_qid(),
+            iounit,
+        })
+    }
+
+    /// Create a directory in the directory identified by dfid. Unlike Tcreate, no fid is
+    /// associated with the new directory so it is clunked once it has been created.
+    fn handle_mkdir(&mut self, dfid: u32, name: String, mode: u32) -> Result<Rdata> {
+        let perm = Perm::DIR | Perm::new(mode & 0o777);
+        let (fm, _) = self.create(dfid, name, perm, Mode::FILE)?;
+        self.s.lock().unwrap().clunk(self.client_id, NO_FID, fm.qid);
+
+        Ok(Rdata::Mkdir { qid: fm.as_qid() })
+    }
+
+    /// Remove the named file from the directory identified by dfid.
+    fn handle_unlinkat(&mut self, dfid: u32, name: String) -> Result<Rdata> {
+        let fm = match file_meta!(self, dfid) {
+            Some(fm) => fm,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+        if fm.ty != FileType::Directory {
+            return Err(E_WALK_NON_DIR.to_string());
+        }
+
+        let mut s = self.s.lock().unwrap();
+        let fm = s
+            .walk(self.client_id, fm.qid, &name, &self.state.uname)
+            .map_err(|_| E_NO_SUCH_FILE.to_string())?;
+        s.remove(self.client_id, fm.qid, &self.state.uname)?;
+
+        Ok(Rdata::Unlinkat {})
+    }
+
+    /// Linux attributes are derived from the [Stat] for the file. As [Stat] only holds user
+    /// names rather than ids, files are reported as being owned by the user running the server.
+    fn handle_getattr(&mut self, fid: u32) -> Result<Rdata> {
+        let fm = match file_meta!(self, fid) {
+            Some(fm) => fm,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+        let stat = self
+            .s
             .lock()
             .unwrap()
-            .remove(self.client_id, fm.qid, &self.state.uname)?;
+            .stat(self.client_id, fm.qid, &self.state.uname)?;
 
-        Ok(Rdata::Remove {})
+        let (ty, nlink) = match fm.ty {
+            FileType::Directory => (S_IFDIR, 2),
+            _ => (S_IFREG, 1),
+        };
+        let (uid, gid) = process_ids();
+        let (atime_sec, atime_nsec) = unix_time(stat.last_accesses);
+        let (mtime_sec, mtime_nsec) = unix_time(stat.last_modified);
+
+        Ok(Rdata::Getattr {
+            valid: P9_GETATTR_BASIC,
+            qid: fm.as_qid(),
+            mode: ty | (stat.perms.bits() & 0o777),
+            uid,
+            gid,
+            nlink,
+            rdev: 0,
+            size: stat.n_bytes,
+            blksize: 4096,
+            blocks: stat.n_bytes.div_ceil(512),
+            atime_sec,
+            atime_nsec,
+            mtime_sec,
+            mtime_nsec,
+            ctime_sec: mtime_sec,
+            ctime_nsec: mtime_nsec,
+            btime_sec: 0,
+            btime_nsec: 0,
+            gen: 0,
+            data_version: 0,
+        })
+    }
+
+    /// Attribute changes are applied by updating the current [Stat] for the file and passing it
+    /// to [Serve9p::write_stat]. Ownership changes are ignored.
+    fn handle_setattr(
+        &mut self,
+        fid: u32,
+        valid: u32,
+        mode: u32,
+        size: u64,
+        atime: SystemTime,
+        mtime: SystemTime,
+    ) -> Result<Rdata> {
+        const SUPPORTED: u32 =
+            P9_SETATTR_MODE | P9_SETATTR_SIZE | P9_SETATTR_ATIME | P9_SETATTR_MTIME;
+
+        if valid & SUPPORTED == 0 {
+            return Ok(Rdata::Setattr {});
+        }
+
+        let fm = match file_meta!(self, fid) {
+            Some(fm) => fm,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+
+        let mut s = self.s.lock().unwrap();
+        let mut stat = s.stat(self.client_id, fm.qid, &self.state.uname)?;
+
+        if valid & P9_SETATTR_MODE != 0 {
+            stat.perms = Perm::new(mode & 0o777);
+        }
+        if valid & P9_SETATTR_SIZE != 0 {
+            stat.n_bytes = size;
+        }
+        if valid & P9_SETATTR_ATIME != 0 {
+            stat.last_accesses = if valid & P9_SETATTR_ATIME_SET != 0 {
+                atime
+            } else {
+                SystemTime::now()
+            };
+        }
+        if valid & P9_SETATTR_MTIME != 0 {
+            stat.last_modified = if valid & P9_SETATTR_MTIME_SET != 0 {
+                mtime
+            } else {
+                SystemTime::now()
+            };
+        }
+
+        s.write_stat(self.client_id, fm.qid, stat, &self.state.uname)?;
+
+        Ok(Rdata::Setattr {})
+    }
+
+    /// Directory entries are returned in the order provided by [Serve9p::read_dir], using the
+    /// index of the following entry as the offset for each so that reads can be resumed.
+    fn handle_readdir(&mut self, fid: u32, offset: u64, count: u32) -> Result<Rdata> {
+        let fm = match file_meta!(self, fid) {
+            Some(fm) => fm,
+            None => return Err(E_UNKNOWN_FID.to_string()),
+        };
+        if fm.ty != FileType::Directory {
+            return Err(E_WALK_NON_DIR.to_string());
+        }
+
+        let stats = self
+            .s
+            .lock()
+            .unwrap()
+            .read_dir(self.client_id, fm.qid, &self.state.uname)?;
+
+        let mut buf = Vec::with_capacity(count as usize);
+        let mut qids = self.qids.write().unwrap();
+
+        for (i, stat) in stats.into_iter().enumerate().skip(offset as usize) {
+            let ty = match stat.fm.ty {
+                FileType::Directory => DT_DIR,
+                _ => DT_REG,
+            };
+
+            let mut tmp = Vec::new();
+            stat.fm.as_qid().write_to(&mut tmp).unwrap();
+            (i as u64 + 1).write_to(&mut tmp).unwrap();
+            ty.write_to(&mut tmp).unwrap();
+            stat.fm.name.write_to(&mut tmp).unwrap();
+
+            if buf.len() + tmp.len() > count as usize {
+                break;
+            }
+            buf.extend(tmp);
+            qids.entry(stat.fm.qid).or_insert(stat.fm);
+        }
+
+        Ok(Rdata::Readdir { data: Data(buf) })
+    }
+}
+
+/// Convert Linux open(2) flags to a 9p open mode.
+fn open_mode(flags: u32) -> Mode {
+    let mut mode = (flags & O_ACCMODE) as u8;
+    if flags & O_TRUNC != 0 {
+        mode |= OPEN_TRUNC;
+    }
+
+    open_mode_9p(mode)
+}
+
+/// The access mode held in the low bits of a 9p open mode does not correspond to any of the
+/// named flags of [Mode] so the raw bits are retained rather than truncated.
+fn open_mode_9p(mode: u8) -> Mode {
+    Mode::from_bits_retain(mode)
+}
+
+/// Virtual filesystems have no meaningful block or inode counts so only the type and maximum
+/// name length are reported.
+fn statfs() -> Rdata {
+    Rdata::Statfs {
+        ty: V9FS_MAGIC,
+        bsize: 4096,
+        blocks: 0,
+        bfree: 0,
+        bavail: 0,
+        files: 0,
+        ffree: 0,
+        fsid: 0,
+        namelen: u16::MAX as u32,
+    }
+}
+
+/// Apply the fields of a Twstat message to the current [Stat] of a file. As described in stat(5),
+/// integer fields set to all ones and empty strings leave the corresponding value unchanged.
+fn apply_raw_stat(mut stat: Stat, raw: RawStat) -> Stat {
+    let time = |t: u32| UNIX_EPOCH + Duration::from_secs(t as u64);
+
+    if !raw.name.is_empty() {
+        stat.fm.name = raw.name;
+    }
+    if raw.mode != u32::MAX {
+        stat.perms = Perm::new(raw.mode & 0x0000FFFF);
+    }
+    if raw.atime != u32::MAX {
+        stat.last_accesses = time(raw.atime);
+    }
+    if raw.mtime != u32::MAX {
+        stat.last_modified = time(raw.mtime);
+    }
+    if raw.length != u64::MAX {
+        stat.n_bytes = raw.length;
+    }
+    if !raw.uid.is_empty() {
+        stat.owner = raw.uid;
+    }
+    if !raw.gid.is_empty() {
+        stat.group = raw.gid;
+    }
+    if !raw.muid.is_empty() {
+        stat.last_modified_by = raw.muid;
+    }
+
+    stat
+}
+
+/// The uid and gid of the current process.
+fn process_ids() -> (u32, u32) {
+    match fs::metadata("/proc/self") {
+        Ok(m) => (m.uid(), m.gid()),
+        Err(_) => (0, 0),
+    }
+}
+
+fn unix_time(t: SystemTime) -> (u64, u64) {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs(), d.subsec_nanos() as u64),
+        Err(_) => (0, 0),
+    }
+}
+
+fn systime(secs: u64, nsecs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::new(secs, nsecs as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::SharedSecret, protocol::Rmessage};
+    use simple_test_case::test_case;
+    use std::os::unix::net::UnixStream;
+
+    const QID_BLOCKING: u64 = 1;
+    const QID_IMMEDIATE: u64 = 2;
+
+    #[derive(Debug, Default)]
+    struct Blocking {
+        pending: Vec<ReadSender>,
+    }
+
+    impl Serve9p for Blocking {
+        fn walk(&mut self, _: ClientId, _: u64, child: &str, _: &str) -> Result<FileMeta> {
+            match child {
+                "blocking" => Ok(FileMeta::file(child, QID_BLOCKING)),
+                "immediate" => Ok(FileMeta::file(child, QID_IMMEDIATE)),
+                _ => Err("no such file".to_string()),
+            }
+        }
+
+        fn open(&mut self, _: ClientId, _: u32, _: u64, _: Mode, _: &str) -> Result<IoUnit> {
+            Ok(0)
+        }
+
+        fn create(
+            &mut self,
+            _: ClientId,
+            _: u64,
+            _: &str,
+            _: Perm,
+            _: Mode,
+            _: &str,
+        ) -> Result<(FileMeta, IoUnit)> {
+            Err("not supported".to_string())
+        }
+
+        fn read(
+            &mut self,
+            _: ClientId,
+            _: u32,
+            qid: u64,
+            _: usize,
+            _: usize,
+            _: &str,
+        ) -> Result<ReadOutcome> {
+            if qid == QID_IMMEDIATE {
+                return Ok(ReadOutcome::Immediate(b"immediate".to_vec()));
+            }
+
+            let (tx, outcome) = ReadOutcome::blocked();
+            self.pending.push(tx);
+
+            Ok(outcome)
+        }
+
+        fn read_dir(&mut self, _: ClientId, _: u64, _: &str) -> Result<Vec<Stat>> {
+            Ok(vec![
+                stat(FileMeta::file("blocking", QID_BLOCKING)),
+                stat(FileMeta::file("immediate", QID_IMMEDIATE)),
+            ])
+        }
+
+        fn write(
+            &mut self,
+            _: ClientId,
+            _: u32,
+            _: u64,
+            _: usize,
+            _: Vec<u8>,
+            _: &str,
+        ) -> Result<usize> {
+            Err("not supported".to_string())
+        }
+
+        fn remove(&mut self, _: ClientId, _: u64, _: &str) -> Result<()> {
+            Err("not supported".to_string())
+        }
+
+        fn stat(&mut self, _: ClientId, _: u64, _: &str) -> Result<Stat> {
+            Err("not supported".to_string())
+        }
+
+        fn write_stat(&mut self, _: ClientId, _: u64, _: Stat, _: &str) -> Result<()> {
+            Err("not supported".to_string())
+        }
+    }
+
+    fn stat(fm: FileMeta) -> Stat {
+        Stat {
+            fm,
+            perms: Perm::OWNER_READ,
+            n_bytes: 0,
+            last_accesses: UNIX_EPOCH,
+            last_modified: UNIX_EPOCH,
+            owner: "test".to_string(),
+            group: "test".to_string(),
+            last_modified_by: "test".to_string(),
+        }
+    }
+
+    struct TestClient {
+        stream: UnixStream,
+        s: Arc<Mutex<Blocking>>,
+    }
+
+    impl TestClient {
+        fn new() -> Self {
+            Self::new_with_version(SUPPORTED_VERSION)
+        }
+
+        fn new_with_version(version: &str) -> Self {
+            let mut c = Self::new_unattached(version, None);
+            c.send(
+                0,
+                Tdata::Attach {
+                    fid: 0,
+                    afid: AFID_NO_AUTH,
+                    uname: "test".to_string(),
+                    aname: "".to_string(),
+                },
+            );
+            for (fid, name) in [(1, "blocking"), (2, "immediate")] {
+                c.send(
+                    0,
+                    Tdata::Walk {
+                        fid: 0,
+                        new_fid: fid,
+                        wnames: vec![name.to_string()],
+                    },
+                );
+                c.send(0, Tdata::Open { fid, mode: 0 });
+            }
+
+            c
+        }
+
+        fn new_unattached(version: &str, auth: Option<Arc<dyn Auth>>) -> Self {
+            let (client, server) = UnixStream::pair().unwrap();
+            let s = Arc::new(Mutex::new(Blocking::default()));
+            let roots: BTreeMap<String, u64> = [("".to_string(), QID_ROOT)].into_iter().collect();
+            let qids = roots
+                .iter()
+                .map(|(p, &qid)| (qid, FileMeta::dir(p.clone(), qid)))
+                .collect();
+            let session = Session::new_unattached(
+                ClientId(0),
+                MAX_DATA_LEN as u32,
+                roots,
+                s.clone(),
+                Arc::new(RwLock::new(qids)),
+                auth,
+                server,
+            )
+            .unwrap();
+
+            spawn(move || session.handle_connection());
+
+            let mut c = Self { stream: client, s };
+            c.send(
+                0,
+                Tdata::Version {
+                    msize: MAX_DATA_LEN as u32,
+                    version: version.to_string(),
+                },
+            );
+
+            c
+        }
+
+        fn send_no_reply(&mut self, tag: u16, content: Tdata) {
+            Tmessage { tag, content }
+                .write_to(&mut self.stream)
+                .unwrap();
+        }
+
+        fn recv(&mut self) -> Rmessage {
+            Rmessage::read_from(&mut self.stream).unwrap()
+        }
+
+        fn send(&mut self, tag: u16, content: Tdata) -> Rmessage {
+            self.send_no_reply(tag, content);
+            let r = self.recv();
+            assert!(!matches!(r.content, Rdata::Error { .. }), "{r:?}");
+
+            r
+        }
+
+        fn wait_for_pending_read(&self) -> ReadSender {
+            loop {
+                if let Some(tx) = self.s.lock().unwrap().pending.pop() {
+                    return tx;
+                }
+            }
+        }
+    }
+
+    fn read(fid: u32) -> Tdata {
+        Tdata::Read {
+            fid,
+            offset: 0,
+            count: 100,
+        }
+    }
+
+    #[test]
+    fn blocked_reads_do_not_block_other_requests() {
+        let mut c = TestClient::new();
+        c.send_no_reply(1, read(1));
+        let tx = c.wait_for_pending_read();
+
+        let r = c.send(2, read(2));
+        assert_eq!(r.tag, 2);
+        assert_eq!(
+            r.content,
+            Rdata::Read {
+                data: Data(b"immediate".to_vec())
+            }
+        );
+
+        tx.send(b"blocked".to_vec()).unwrap();
+        let r = c.recv();
+        assert_eq!(r.tag, 1);
+        assert_eq!(
+            r.content,
+            Rdata::Read {
+                data: Data(b"blocked".to_vec())
+            }
+        );
+    }
+
+    #[test]
+    fn flushed_reads_are_never_replied_to() {
+        let mut c = TestClient::new();
+        c.send_no_reply(1, read(1));
+        let tx = c.wait_for_pending_read();
+
+        let r = c.send(2, Tdata::Flush { old_tag: 1 });
+        assert_eq!(r.tag, 2);
+        assert_eq!(r.content, Rdata::Flush {});
+
+        // The flushed tag can be reused and the data for the original read is discarded
+        _ = tx.send(b"flushed".to_vec());
+        let r = c.send(1, read(2));
+        assert_eq!(r.tag, 1);
+        assert_eq!(
+            r.content,
+            Rdata::Read {
+                data: Data(b"immediate".to_vec())
+            }
+        );
+
+        c.stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(Rmessage::read_from(&mut c.stream).is_err());
+    }
+
+    #[test]
+    fn messages_larger_than_msize_close_the_connection() {
+        let mut c = TestClient::new_unattached(SUPPORTED_VERSION, None);
+        let r = c.send(
+            0,
+            Tdata::Version {
+                msize: 1024,
+                version: SUPPORTED_VERSION.to_string(),
+            },
+        );
+        assert!(matches!(r.content, Rdata::Version { msize: 1024, .. }));
+
+        // The server may hang up before we finish writing
+        let t = Tmessage {
+            tag: 1,
+            content: Tdata::Write {
+                fid: 0,
+                offset: 0,
+                data: Data(vec![0; 2048]),
+            },
+        };
+        _ = t.write_to(&mut c.stream);
+
+        c.stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let err = Rmessage::read_from(&mut c.stream).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn msize_is_renegotiated_by_each_version_message() {
+        let mut c = TestClient::new_unattached(SUPPORTED_VERSION, None);
+        for msize in [1024, MAX_DATA_LEN as u32] {
+            let r = c.send(
+                0,
+                Tdata::Version {
+                    msize,
+                    version: SUPPORTED_VERSION.to_string(),
+                },
+            );
+            assert!(
+                matches!(r.content, Rdata::Version { msize: m, .. } if m == msize),
+                "{r:?}"
+            );
+        }
+    }
+
+    #[test_case(1; "blocked")]
+    #[test_case(2; "immediate")]
+    #[test]
+    fn read_data_is_truncated_to_count(fid: u32) {
+        let mut c = TestClient::new();
+        c.send_no_reply(
+            1,
+            Tdata::Read {
+                fid,
+                offset: 0,
+                count: 3,
+            },
+        );
+        if fid == 1 {
+            _ = c.wait_for_pending_read().send(b"blocked".to_vec());
+        }
+
+        let r = c.recv();
+        let expected = if fid == 1 { b"blo" } else { b"imm" };
+        assert_eq!(
+            r.content,
+            Rdata::Read {
+                data: Data(expected.to_vec())
+            }
+        );
+    }
+
+    #[test]
+    fn tags_in_flight_are_rejected() {
+        let mut c = TestClient::new();
+        c.send_no_reply(1, read(1));
+        let _tx = c.wait_for_pending_read();
+
+        c.send_no_reply(1, read(2));
+        let r = c.recv();
+        assert_eq!(r.tag, 1);
+        assert_eq!(
+            r.content,
+            Rdata::Error {
+                ename: E_DUPLICATE_TAG.to_string()
+            }
+        );
+    }
+
+    #[test_case("9P2000", Some(Dialect::Plan9); "plain")]
+    #[test_case("9P2000.L", Some(Dialect::Linux); "linux")]
+    #[test_case("9P2000.u", Some(Dialect::Plan9); "unix falls back to plain")]
+    #[test_case("9P1000", None; "unknown version")]
+    #[test]
+    fn negotiate_works(version: &str, expected: Option<Dialect>) {
+        assert_eq!(Dialect::negotiate(version), expected);
+    }
+
+    #[test]
+    fn linux_clients_receive_errno_values() {
+        let mut c = TestClient::new_with_version(LINUX_VERSION);
+        c.send_no_reply(
+            1,
+            Tdata::Walk {
+                fid: 0,
+                new_fid: 3,
+                wnames: vec!["missing".to_string()],
+            },
+        );
+
+        let r = c.recv();
+        assert_eq!(r.content, Rdata::Lerror { ecode: ENOENT });
+    }
+
+    #[test_case(SUPPORTED_VERSION; "plan9")]
+    #[test_case(LINUX_VERSION; "linux")]
+    #[test]
+    fn partial_walks_return_the_qids_walked(version: &str) {
+        let mut c = TestClient::new_with_version(version);
+        let r = c.send(
+            1,
+            Tdata::Walk {
+                fid: 0,
+                new_fid: 3,
+                wnames: vec!["blocking".to_string(), "missing".to_string()],
+            },
+        );
+
+        match r.content {
+            Rdata::Walk { wqids } => {
+                assert_eq!(wqids.len(), 1);
+                assert_eq!(wqids[0].path, QID_BLOCKING);
+            }
+            content => panic!("expected Rwalk, got {content:?}"),
+        }
+
+        // The new fid is only bound if every element was walked
+        c.send_no_reply(1, Tdata::Clunk { fid: 3 });
+        assert!(matches!(
+            c.recv().content,
+            Rdata::Error { .. } | Rdata::Lerror { .. }
+        ));
+    }
+
+    #[test_case(0, &["blocking", "immediate"]; "from start")]
+    #[test_case(1, &["immediate"]; "resuming from offset")]
+    #[test_case(2, &[]; "end of directory")]
+    #[test]
+    fn readdir_works(offset: u64, expected: &[&str]) {
+        let mut c = TestClient::new_with_version(LINUX_VERSION);
+        let r = c.send(
+            1,
+            Tdata::Readdir {
+                fid: 0,
+                offset,
+                count: 1024,
+            },
+        );
+
+        let data = match r.content {
+            Rdata::Readdir { data } => data.0,
+            content => panic!("expected Rreaddir, got {content:?}"),
+        };
+
+        let mut cur = std::io::Cursor::new(data);
+        let mut names = Vec::new();
+        while (cur.position() as usize) < cur.get_ref().len() {
+            Qid::read_from(&mut cur).unwrap();
+            let next = u64::read_from(&mut cur).unwrap();
+            assert_eq!(u8::read_from(&mut cur).unwrap(), DT_REG);
+            names.push(String::read_from(&mut cur).unwrap());
+            assert_eq!(next as usize, offset as usize + names.len());
+        }
+
+        assert_eq!(names, expected);
+    }
+
+    fn attach(c: &mut TestClient, afid: u32, uname: &str) -> Rdata {
+        c.send_no_reply(
+            1,
+            Tdata::Attach {
+                fid: 0,
+                afid,
+                uname: uname.to_string(),
+                aname: "".to_string(),
+            },
+        );
+
+        c.recv().content
+    }
+
+    #[test]
+    fn auth_is_not_required_by_default() {
+        let mut c = TestClient::new_unattached(SUPPORTED_VERSION, None);
+        c.send_no_reply(
+            1,
+            Tdata::Auth {
+                afid: 1,
+                uname: "test".to_string(),
+                aname: "".to_string(),
+            },
+        );
+
+        let r = c.recv();
+        assert_eq!(
+            r.content,
+            Rdata::Error {
+                ename: E_AUTH_NOT_REQUIRED.to_string()
+            }
+        );
+    }
+
+    #[test_case("secret", "test", None; "correct secret")]
+    #[test_case("wrong", "test", Some(E_AUTH_FAILED); "incorrect secret")]
+    #[test_case("secret", "other", Some(E_AUTH_MISMATCH); "attach with different uname")]
+    #[test]
+    fn shared_secret_auth_works(client_secret: &str, attach_uname: &str, err: Option<&str>) {
+        let secret = SharedSecret::new("secret");
+        let mut c = TestClient::new_unattached(SUPPORTED_VERSION, Some(Arc::new(secret)));
+
+        assert_eq!(
+            attach(&mut c, AFID_NO_AUTH, "test"),
+            Rdata::Error {
+                ename: E_AUTH_REQUIRED.to_string()
+            }
+        );
+
+        c.send(
+            1,
+            Tdata::Auth {
+                afid: 1,
+                uname: "test".to_string(),
+                aname: "".to_string(),
+            },
+        );
+        let challenge = match c.send(1, read(1)).content {
+            Rdata::Read { data } => data.0,
+            content => panic!("expected Rread, got {content:?}"),
+        };
+
+        let response = SharedSecret::new(client_secret).response("test", "", &challenge);
+        c.send_no_reply(
+            1,
+            Tdata::Write {
+                fid: 1,
+                offset: 0,
+                data: Data(response),
+            },
+        );
+        let write_resp = c.recv().content;
+
+        match err {
+            Some(E_AUTH_FAILED) => assert_eq!(
+                write_resp,
+                Rdata::Error {
+                    ename: E_AUTH_FAILED.to_string()
+                }
+            ),
+            Some(ename) => assert_eq!(
+                attach(&mut c, 1, attach_uname),
+                Rdata::Error {
+                    ename: ename.to_string()
+                }
+            ),
+            None => assert!(matches!(
+                attach(&mut c, 1, attach_uname),
+                Rdata::Attach { .. }
+            )),
+        }
     }
 }
@@ -0,0 +1,775 @@
+//! A synthetic filesystem built from a tree of callback backed files and directories.
+//!
+//! Implementing [Serve9p] directly requires manually tracking qids for every file being served.
+//! For simple services it is often easier to describe the tree of files that should be exposed
+//! and let [Sfs] handle the bookkeeping:
+//!
+//! ```no_run
+//! use ninep::{
+//!     sfs::{Dir, File, Sfs},
+//!     server::Server,
+//! };
+//! use std::sync::{Arc, Mutex};
+//!
+//! let state = Arc::new(Mutex::new(String::from("initial")));
+//! let (r, w) = (state.clone(), state.clone());
+//!
+//! let root = Dir::root()
+//!     .with_child(File::new("hello").on_read(|_| Ok(b"hello, world\n".to_vec())))
+//!     .with_child(
+//!         File::new("state")
+//!             .on_read(move |_| Ok(r.lock().unwrap().clone().into_bytes()))
+//!             .on_write(move |_, _, data| {
+//!                 let n = data.len();
+//!                 *w.lock().unwrap() = String::from_utf8(data).map_err(|e| e.to_string())?;
+//!                 Ok(n)
+//!             }),
+//!     )
+//!     .with_child(Dir::new("numbers").with_dynamic_children(|_| {
+//!         (1..=3)
+//!             .map(|n| File::new(n.to_string()).on_read(move |_| Ok(n.to_string().into_bytes())))
+//!             .map(Into::into)
+//!             .collect()
+//!     }));
+//!
+//! _ = Server::new(Sfs::new(root)).serve_socket("example")?.join();
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use crate::{
+    fs::{FileMeta, IoUnit, Mode, Perm, Stat, QID_ROOT},
+    server::{
+        ClientId, ReadOutcome, Serve9p, E_IS_DIR, E_NO_SUCH_FILE, E_PERMISSION_DENIED,
+        E_UNSUPPORTED, E_WALK_NON_DIR,
+    },
+    Result,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    time::SystemTime,
+};
+
+type ReadFn = Box<dyn FnMut(&Ctx<'_>) -> Result<Vec<u8>> + Send>;
+type RawReadFn = Box<dyn FnMut(&Ctx<'_>, usize, usize) -> Result<ReadOutcome> + Send>;
+type WriteFn = Box<dyn FnMut(&Ctx<'_>, usize, Vec<u8>) -> Result<usize> + Send>;
+type ChildrenFn = Box<dyn FnMut(&Ctx<'_>) -> Vec<Node> + Send>;
+
+/// Details of the request being handled that are passed to callbacks.
+#[derive(Debug, Clone, Copy)]
+pub struct Ctx<'a> {
+    /// The client making the request
+    pub cid: ClientId,
+    /// The user making the request
+    pub uname: &'a str,
+    /// The path of the file or directory the request is for, relative to the root of the tree
+    pub path: &'a str,
+}
+
+/// A node in an [Sfs] tree.
+#[derive(Debug)]
+pub enum Node {
+    /// A directory
+    Dir(Dir),
+    /// A file
+    File(File),
+}
+
+impl From<Dir> for Node {
+    fn from(d: Dir) -> Self {
+        Self::Dir(d)
+    }
+}
+
+impl From<File> for Node {
+    fn from(f: File) -> Self {
+        Self::File(f)
+    }
+}
+
+impl Node {
+    fn name(&self) -> &str {
+        match self {
+            Self::Dir(d) => &d.name,
+            Self::File(f) => &f.name,
+        }
+    }
+}
+
+/// A directory containing a fixed set of children and optionally children that are generated
+/// on demand.
+pub struct Dir {
+    name: String,
+    perms: Perm,
+    children: Vec<Node>,
+    dynamic: Option<ChildrenFn>,
+}
+
+impl fmt::Debug for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dir")
+            .field("name", &self.name)
+            .field("perms", &self.perms)
+            .field("children", &self.children)
+            .field("dynamic", &self.dynamic.is_some())
+            .finish()
+    }
+}
+
+impl Dir {
+    /// Create a new, empty directory.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            perms: Perm::OWNER_READ | Perm::OWNER_EXEC,
+            children: Vec::new(),
+            dynamic: None,
+        }
+    }
+
+    /// Create a new, empty root directory for an [Sfs] tree.
+    pub fn root() -> Self {
+        Self::new("/")
+    }
+
+    /// Set the permissions for this directory.
+    pub fn with_perms(mut self, perms: Perm) -> Self {
+        self.perms = perms;
+        self
+    }
+
+    /// Add a child to this directory.
+    pub fn with_child(mut self, child: impl Into<Node>) -> Self {
+        self.children.push(child.into());
+        self
+    }
+
+    /// Generate additional children for this directory each time that it is listed or walked.
+    ///
+    /// Generated children are identified by name: the same qid will be used for a given child
+    /// each time that it is generated so long as it is still present.
+    pub fn with_dynamic_children(
+        mut self,
+        f: impl FnMut(&Ctx<'_>) -> Vec<Node> + Send + 'static,
+    ) -> Self {
+        self.dynamic = Some(Box::new(f));
+        self
+    }
+}
+
+/// A file whose contents are provided by callbacks.
+///
+/// Files without a read callback are empty and files without a write callback reject writes.
+pub struct File {
+    name: String,
+    perms: Option<Perm>,
+    read: Option<Read>,
+    write: Option<WriteFn>,
+}
+
+enum Read {
+    Contents(ReadFn),
+    Raw(RawReadFn),
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File")
+            .field("name", &self.name)
+            .field("perms", &self.perms())
+            .finish()
+    }
+}
+
+impl File {
+    /// Create a new, empty file.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            perms: None,
+            read: None,
+            write: None,
+        }
+    }
+
+    /// Set the permissions for this file.
+    ///
+    /// By default files are readable by their owner and writable if a write callback is set.
+    pub fn with_perms(mut self, perms: Perm) -> Self {
+        self.perms = Some(perms);
+        self
+    }
+
+    /// Provide the full contents of the file when it is read. The callback is run for each read
+    /// request with the offset and count of the request being applied to the returned data.
+    pub fn on_read(mut self, f: impl FnMut(&Ctx<'_>) -> Result<Vec<u8>> + Send + 'static) -> Self {
+        self.read = Some(Read::Contents(Box::new(f)));
+        self
+    }
+
+    /// Handle read requests directly, given the offset and count of the request.
+    ///
+    /// Returning [ReadOutcome::Blocked] allows for files that block until new data is available,
+    /// such as event streams.
+    pub fn on_read_raw(
+        mut self,
+        f: impl FnMut(&Ctx<'_>, usize, usize) -> Result<ReadOutcome> + Send + 'static,
+    ) -> Self {
+        self.read = Some(Read::Raw(Box::new(f)));
+        self
+    }
+
+    /// Handle data written to the file at the given offset, returning the number of bytes
+    /// written.
+    pub fn on_write(
+        mut self,
+        f: impl FnMut(&Ctx<'_>, usize, Vec<u8>) -> Result<usize> + Send + 'static,
+    ) -> Self {
+        self.write = Some(Box::new(f));
+        self
+    }
+
+    fn perms(&self) -> Perm {
+        match self.perms {
+            Some(perms) => perms,
+            None if self.write.is_some() => Perm::OWNER_READ | Perm::OWNER_WRITE,
+            None => Perm::OWNER_READ,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    fm: FileMeta,
+    path: String,
+    parent: u64,
+    perms: Perm,
+    kind: Kind,
+}
+
+enum Kind {
+    Dir {
+        children: Vec<u64>,
+        dynamic: Option<ChildrenFn>,
+        generated: Vec<u64>,
+    },
+    File {
+        read: Option<Read>,
+        write: Option<WriteFn>,
+    },
+}
+
+impl fmt::Debug for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dir { children, .. } => {
+                f.debug_struct("Dir").field("children", children).finish()
+            }
+            Self::File { .. } => f.debug_struct("File").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A synthetic filesystem serving a tree of [Dir] and [File] nodes.
+///
+/// See the [module level docs](self) for an example.
+#[derive(Debug)]
+pub struct Sfs {
+    entries: BTreeMap<u64, Entry>,
+    qids: HashMap<String, u64>,
+    next_qid: u64,
+    created: SystemTime,
+}
+
+impl Sfs {
+    /// Create a new [Sfs] serving the given root directory.
+    pub fn new(root: Dir) -> Self {
+        let mut sfs = Self {
+            entries: BTreeMap::new(),
+            qids: HashMap::new(),
+            next_qid: QID_ROOT + 1,
+            created: SystemTime::now(),
+        };
+        sfs.qids.insert(String::new(), QID_ROOT);
+        sfs.insert(QID_ROOT, String::new(), Node::Dir(root));
+
+        sfs
+    }
+
+    /// Register a node and all of its children, returning its qid. Paths that have been seen
+    /// previously retain the same qid.
+    fn insert(&mut self, parent: u64, path: String, node: Node) -> u64 {
+        let qid = match self.qids.get(&path) {
+            Some(&qid) => qid,
+            None => {
+                let qid = self.next_qid;
+                self.next_qid += 1;
+                self.qids.insert(path.clone(), qid);
+                qid
+            }
+        };
+
+        let (fm, perms, kind) = match node {
+            Node::Dir(d) => {
+                let children = d
+                    .children
+                    .into_iter()
+                    .map(|child| {
+                        let child_path = join(&path, child.name());
+                        self.insert(qid, child_path, child)
+                    })
+                    .collect();
+
+                let kind = Kind::Dir {
+                    children,
+                    dynamic: d.dynamic,
+                    generated: Vec::new(),
+                };
+
+                (FileMeta::dir(d.name, qid), d.perms, kind)
+            }
+
+            Node::File(f) => {
+                let perms = f.perms();
+                let kind = Kind::File {
+                    read: f.read,
+                    write: f.write,
+                };
+
+                (FileMeta::file(f.name, qid), perms, kind)
+            }
+        };
+
+        let entry = Entry {
+            fm,
+            path,
+            parent,
+            perms,
+            kind,
+        };
+        self.entries.insert(qid, entry);
+
+        qid
+    }
+
+    /// Remove a node and all of its children.
+    fn remove_entry(&mut self, qid: u64) {
+        if let Some(e) = self.entries.remove(&qid) {
+            self.qids.remove(&e.path);
+            if let Kind::Dir {
+                children,
+                generated,
+                ..
+            } = e.kind
+            {
+                for child in children.into_iter().chain(generated) {
+                    self.remove_entry(child);
+                }
+            }
+        }
+    }
+
+    /// Regenerate the dynamic children of a directory (if it has any), returning the qids of all
+    /// of its children.
+    fn children(&mut self, cid: ClientId, qid: u64, uname: &str) -> Result<Vec<u64>> {
+        let entry = self.entries.get_mut(&qid).ok_or(E_NO_SUCH_FILE)?;
+        let path = entry.path.clone();
+
+        let (children, nodes, prev) = match &mut entry.kind {
+            Kind::File { .. } => return Err(E_WALK_NON_DIR.to_string()),
+            Kind::Dir {
+                children,
+                dynamic: None,
+                ..
+            } => return Ok(children.clone()),
+            Kind::Dir {
+                children,
+                dynamic: Some(f),
+                generated,
+            } => {
+                let ctx = Ctx {
+                    cid,
+                    uname,
+                    path: &path,
+                };
+                (children.clone(), f(&ctx), std::mem::take(generated))
+            }
+        };
+
+        let generated: Vec<u64> = nodes
+            .into_iter()
+            .map(|node| {
+                let child_path = join(&path, node.name());
+                self.insert(qid, child_path, node)
+            })
+            .collect();
+
+        for old in prev.into_iter().filter(|q| !generated.contains(q)) {
+            self.remove_entry(old);
+        }
+
+        if let Some(Entry {
+            kind: Kind::Dir { generated: g, .. },
+            ..
+        }) = self.entries.get_mut(&qid)
+        {
+            g.clone_from(&generated);
+        }
+
+        Ok(children.into_iter().chain(generated).collect())
+    }
+
+    fn stat_entry(&self, e: &Entry, uname: &str) -> Stat {
+        Stat {
+            fm: e.fm.clone(),
+            perms: e.perms,
+            n_bytes: 0,
+            last_accesses: SystemTime::now(),
+            last_modified: self.created,
+            owner: uname.into(),
+            group: uname.into(),
+            last_modified_by: uname.into(),
+        }
+    }
+}
+
+impl Serve9p for Sfs {
+    fn walk(
+        &mut self,
+        cid: ClientId,
+        parent_qid: u64,
+        child: &str,
+        uname: &str,
+    ) -> Result<FileMeta> {
+        // Walking a directory is regarded as executing it
+        let e = self.entries.get(&parent_qid).ok_or(E_NO_SUCH_FILE)?;
+        if !e.perms.contains(Perm::OWNER_EXEC) {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        if child == ".." {
+            return Ok(self.entries[&e.parent].fm.clone());
+        }
+
+        self.children(cid, parent_qid, uname)?
+            .into_iter()
+            .filter_map(|qid| self.entries.get(&qid))
+            .find(|e| e.fm.name == child)
+            .map(|e| e.fm.clone())
+            .ok_or_else(|| E_NO_SUCH_FILE.to_string())
+    }
+
+    fn open(
+        &mut self,
+        _cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        mode: Mode,
+        _uname: &str,
+    ) -> Result<IoUnit> {
+        let e = self.entries.get(&qid).ok_or(E_NO_SUCH_FILE)?;
+        if !e.perms.contains(required_perms(mode)) {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        Ok(0)
+    }
+
+    fn create(
+        &mut self,
+        _cid: ClientId,
+        _parent: u64,
+        _name: &str,
+        _perm: Perm,
+        _mode: Mode,
+        _uname: &str,
+    ) -> Result<(FileMeta, IoUnit)> {
+        Err(E_UNSUPPORTED.to_string())
+    }
+
+    fn read(
+        &mut self,
+        cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        offset: usize,
+        count: usize,
+        uname: &str,
+    ) -> Result<ReadOutcome> {
+        let e = self.entries.get_mut(&qid).ok_or(E_NO_SUCH_FILE)?;
+        if !e.perms.contains(Perm::OWNER_READ) {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        let ctx = Ctx {
+            cid,
+            uname,
+            path: &e.path,
+        };
+
+        match &mut e.kind {
+            Kind::Dir { .. } => Err(E_IS_DIR.to_string()),
+            Kind::File { read: None, .. } => Ok(ReadOutcome::Immediate(Vec::new())),
+            Kind::File {
+                read: Some(Read::Raw(f)),
+                ..
+            } => f(&ctx, offset, count),
+            Kind::File {
+                read: Some(Read::Contents(f)),
+                ..
+            } => {
+                let data = f(&ctx)?;
+                let start = offset.min(data.len());
+                let end = (start + count).min(data.len());
+
+                Ok(ReadOutcome::Immediate(data[start..end].to_vec()))
+            }
+        }
+    }
+
+    fn read_dir(&mut self, cid: ClientId, qid: u64, uname: &str) -> Result<Vec<Stat>> {
+        let stats = self
+            .children(cid, qid, uname)?
+            .into_iter()
+            .filter_map(|qid| self.entries.get(&qid))
+            .map(|e| self.stat_entry(e, uname))
+            .collect();
+
+        Ok(stats)
+    }
+
+    fn write(
+        &mut self,
+        cid: ClientId,
+        _fid: u32,
+        qid: u64,
+        offset: usize,
+        data: Vec<u8>,
+        uname: &str,
+    ) -> Result<usize> {
+        let e = self.entries.get_mut(&qid).ok_or(E_NO_SUCH_FILE)?;
+        if !e.perms.contains(Perm::OWNER_WRITE) {
+            return Err(E_PERMISSION_DENIED.to_string());
+        }
+
+        let ctx = Ctx {
+            cid,
+            uname,
+            path: &e.path,
+        };
+
+        match &mut e.kind {
+            Kind::Dir { .. } => Err(E_IS_DIR.to_string()),
+            Kind::File { write: None, .. } => Err(E_PERMISSION_DENIED.to_string()),
+            Kind::File { write: Some(f), .. } => f(&ctx, offset, data),
+        }
+    }
+
+    fn remove(&mut self, _cid: ClientId, _qid: u64, _uname: &str) -> Result<()> {
+        Err(E_UNSUPPORTED.to_string())
+    }
+
+    fn stat(&mut self, _cid: ClientId, qid: u64, uname: &str) -> Result<Stat> {
+        let e = self.entries.get(&qid).ok_or(E_NO_SUCH_FILE)?;
+
+        Ok(self.stat_entry(e, uname))
+    }
+
+    /// Stat changes are accepted but ignored so that clients truncating files before writing to
+    /// them are able to do so.
+    fn write_stat(&mut self, _cid: ClientId, qid: u64, _stat: Stat, _uname: &str) -> Result<()> {
+        match self.entries.get(&qid) {
+            Some(_) => Ok(()),
+            None => Err(E_NO_SUCH_FILE.to_string()),
+        }
+    }
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}/{name}")
+    }
+}
+
+/// The permissions an entry needs in order to be opened with the given 9p open mode.
+fn required_perms(mode: Mode) -> Perm {
+    const OWRITE: u8 = 1;
+    const ORDWR: u8 = 2;
+    const OEXEC: u8 = 3;
+    const OTRUNC: u8 = 0x10;
+
+    let bits = mode.bits();
+    let mut perms = match bits & 0b11 {
+        OWRITE => Perm::OWNER_WRITE,
+        ORDWR => Perm::OWNER_READ | Perm::OWNER_WRITE,
+        OEXEC => Perm::OWNER_EXEC,
+        _ => Perm::OWNER_READ,
+    };
+    if bits & OTRUNC != 0 {
+        perms |= Perm::OWNER_WRITE;
+    }
+
+    perms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    const CID: ClientId = ClientId(0);
+
+    fn walk_path(sfs: &mut Sfs, path: &str) -> Result<u64> {
+        let mut qid = QID_ROOT;
+        for name in path.split('/') {
+            qid = sfs.walk(CID, qid, name, "test")?.qid;
+        }
+
+        Ok(qid)
+    }
+
+    fn read_str(sfs: &mut Sfs, path: &str) -> Result<String> {
+        let qid = walk_path(sfs, path)?;
+        match sfs.read(CID, 0, qid, 0, 1024, "test")? {
+            ReadOutcome::Immediate(data) => Ok(String::from_utf8(data).unwrap()),
+            ReadOutcome::Blocked(_) => panic!("unexpected blocked read"),
+        }
+    }
+
+    fn test_fs() -> (Sfs, Arc<Mutex<String>>, Arc<AtomicUsize>) {
+        let state = Arc::new(Mutex::new("initial".to_string()));
+        let n = Arc::new(AtomicUsize::new(2));
+        let (r, w, nn) = (state.clone(), state.clone(), n.clone());
+
+        let root = Dir::root()
+            .with_child(File::new("foo").on_read(|_| Ok(b"foo contents".to_vec())))
+            .with_child(Dir::new("bar").with_child(File::new("baz")))
+            .with_child(
+                File::new("rw")
+                    .on_read(move |_| Ok(r.lock().unwrap().clone().into_bytes()))
+                    .on_write(move |_, _, data| {
+                        let n = data.len();
+                        *w.lock().unwrap() = String::from_utf8(data).unwrap();
+                        Ok(n)
+                    }),
+            )
+            .with_child(File::new("secret").with_perms(Perm::empty()))
+            .with_child(
+                Dir::new("locked")
+                    .with_perms(Perm::OWNER_READ)
+                    .with_child(File::new("inner")),
+            )
+            .with_child(Dir::new("dyn").with_dynamic_children(move |ctx| {
+                (0..nn.load(Ordering::Relaxed))
+                    .map(|i| {
+                        let path = format!("{}/{i}", ctx.path);
+                        File::new(i.to_string())
+                            .on_read(move |_| Ok(path.clone().into_bytes()))
+                            .into()
+                    })
+                    .collect()
+            }));
+
+        (Sfs::new(root), state, n)
+    }
+
+    #[test_case("foo", Ok("foo contents"); "static file")]
+    #[test_case("bar/baz", Ok(""); "nested file without read")]
+    #[test_case("dyn/1", Ok("dyn/1"); "dynamic child")]
+    #[test_case("dyn/5", Err(E_NO_SUCH_FILE); "missing dynamic child")]
+    #[test_case("missing", Err(E_NO_SUCH_FILE); "missing static child")]
+    #[test_case("secret", Err(E_PERMISSION_DENIED); "unreadable file")]
+    #[test_case("bar", Err(E_IS_DIR); "directory")]
+    #[test]
+    fn read_works(path: &str, expected: std::result::Result<&str, &str>) {
+        let (mut sfs, _, _) = test_fs();
+        let res = read_str(&mut sfs, path);
+
+        assert_eq!(res, expected.map(String::from).map_err(String::from));
+    }
+
+    #[test_case("foo", 0, Ok(0); "read of readable file")]
+    #[test_case("foo", 1, Err(E_PERMISSION_DENIED); "write of read only file")]
+    #[test_case("foo", 2, Err(E_PERMISSION_DENIED); "rdwr of read only file")]
+    #[test_case("foo", 0x10, Err(E_PERMISSION_DENIED); "truncate of read only file")]
+    #[test_case("foo", 3, Err(E_PERMISSION_DENIED); "exec of file")]
+    #[test_case("rw", 2, Ok(0); "rdwr of writable file")]
+    #[test_case("rw", 0x11, Ok(0); "truncating write of writable file")]
+    #[test_case("secret", 0, Err(E_PERMISSION_DENIED); "read of unreadable file")]
+    #[test_case("bar", 0, Ok(0); "read of directory")]
+    #[test]
+    fn open_checks_permissions(path: &str, mode: u8, expected: std::result::Result<IoUnit, &str>) {
+        let (mut sfs, _, _) = test_fs();
+        let qid = walk_path(&mut sfs, path).unwrap();
+        let res = sfs.open(CID, 0, qid, Mode::from_bits_retain(mode), "test");
+
+        assert_eq!(res, expected.map_err(String::from));
+    }
+
+    #[test_case("bar/baz", true; "executable directory")]
+    #[test_case("bar/..", true; "parent of executable directory")]
+    #[test_case("locked", true; "non-executable directory itself")]
+    #[test_case("locked/inner", false; "child of non-executable directory")]
+    #[test_case("locked/..", false; "parent of non-executable directory")]
+    #[test]
+    fn walk_requires_exec_on_directories(path: &str, ok: bool) {
+        let (mut sfs, _, _) = test_fs();
+        let res = walk_path(&mut sfs, path);
+
+        if ok {
+            assert!(res.is_ok(), "{res:?}");
+        } else {
+            assert_eq!(res, Err(E_PERMISSION_DENIED.to_string()));
+        }
+    }
+
+    #[test]
+    fn write_works() {
+        let (mut sfs, state, _) = test_fs();
+        let qid = walk_path(&mut sfs, "rw").unwrap();
+        let n = sfs.write(CID, 0, qid, 0, b"updated".to_vec(), "test");
+
+        assert_eq!(n, Ok(7));
+        assert_eq!(*state.lock().unwrap(), "updated");
+        assert_eq!(read_str(&mut sfs, "rw").unwrap(), "updated");
+
+        let qid = walk_path(&mut sfs, "foo").unwrap();
+        let res = sfs.write(CID, 0, qid, 0, b"nope".to_vec(), "test");
+        assert_eq!(res, Err(E_PERMISSION_DENIED.to_string()));
+    }
+
+    #[test]
+    fn dynamic_children_keep_their_qids() {
+        let (mut sfs, _, n) = test_fs();
+        let qid = walk_path(&mut sfs, "dyn/1").unwrap();
+        let dir = walk_path(&mut sfs, "dyn").unwrap();
+
+        let names = |sfs: &mut Sfs| -> Vec<String> {
+            let stats = sfs.read_dir(CID, dir, "test").unwrap();
+            stats.into_iter().map(|s| s.fm.name).collect()
+        };
+
+        n.store(3, Ordering::Relaxed);
+        assert_eq!(names(&mut sfs), vec!["0", "1", "2"]);
+        assert_eq!(walk_path(&mut sfs, "dyn/1").unwrap(), qid);
+
+        // Children that are no longer generated are removed
+        n.store(1, Ordering::Relaxed);
+        assert_eq!(names(&mut sfs), vec!["0"]);
+        assert!(sfs.stat(CID, qid, "test").is_err());
+    }
+
+    #[test]
+    fn read_dir_lists_static_children() {
+        let (mut sfs, _, _) = test_fs();
+        let stats = sfs.read_dir(CID, QID_ROOT, "test").unwrap();
+        let names: Vec<String> = stats.into_iter().map(|s| s.fm.name).collect();
+
+        assert_eq!(names, vec!["foo", "bar", "rw", "secret", "locked", "dyn"]);
+    }
+}
@@ -0,0 +1,494 @@
+//! Recording and replaying 9p sessions.
+//!
+//! When the `NINEP_TRACE` environment variable is set to a file path, both [Server]s and
+//! [Client](crate::client::Client)s append a record of every message they send and receive to
+//! that file. Each record is a single line of the form:
+//! ```text
+//! <role> <conn> <dir> <hex> <debug>
+//! ```
+//! where `role` is `s` for a server and `c` for a client, `conn` identifies the connection as
+//! `pid.n`, `dir` is `<` for received messages and `>` for sent messages, `hex` is the wire
+//! encoding of the message and `debug` is its [Tmessage] or [Rmessage] debug representation.
+//!
+//! The server side of a recorded session can be fed back into any [Serve9p] implementation using
+//! a [Replay] in order to check that its responses have not changed.
+use crate::{
+    protocol::{Format9p, Rmessage, Tmessage},
+    server::{Serve9p, Server},
+    Result, Stream,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// The environment variable used to enable tracing.
+pub const TRACE_ENV_VAR: &str = "NINEP_TRACE";
+
+/// The smallest valid message: size[4] type[1] tag[2]
+const MIN_FRAME_LEN: usize = 7;
+
+/// How long [Replay::run] waits for each response before treating it as missing.
+const DEFAULT_REPLAY_TIMEOUT: Duration = Duration::from_secs(1);
+
+static NEXT_CLIENT_CONN: AtomicU64 = AtomicU64::new(0);
+
+/// Which side of a connection a trace record was made by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// A record made by a [Server]
+    Server,
+    /// A record made by a [Client](crate::client::Client)
+    Client,
+}
+
+impl Role {
+    fn as_char(&self) -> char {
+        match self {
+            Self::Server => 's',
+            Self::Client => 'c',
+        }
+    }
+}
+
+/// The direction of a traced message relative to the side of the connection that recorded it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The message was received
+    Received,
+    /// The message was sent
+    Sent,
+}
+
+impl Direction {
+    fn as_char(&self) -> char {
+        match self {
+            Self::Received => '<',
+            Self::Sent => '>',
+        }
+    }
+}
+
+/// A destination for trace records.
+#[derive(Clone)]
+pub struct Tracer {
+    role: Role,
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("role", &self.role)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tracer {
+    /// Create a new [Tracer] writing records to the given writer.
+    pub fn new(role: Role, w: impl Write + Send + 'static) -> Self {
+        Self {
+            role,
+            out: Arc::new(Mutex::new(Box::new(w))),
+        }
+    }
+
+    /// Create a new [Tracer] appending records to the file at the given path.
+    pub fn to_file(role: Role, path: impl AsRef<Path>) -> io::Result<Self> {
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self::new(role, f))
+    }
+
+    /// Create a new [Tracer] appending to the file named by `$NINEP_TRACE` if it is set.
+    pub fn from_env(role: Role) -> Option<Self> {
+        let path = std::env::var(TRACE_ENV_VAR)
+            .ok()
+            .filter(|s| !s.is_empty())?;
+
+        Self::to_file(role, path).ok()
+    }
+
+    /// A new connection identifier for a client within this process.
+    pub(crate) fn client_conn() -> String {
+        conn_id(NEXT_CLIENT_CONN.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Record a single encoded message.
+    pub(crate) fn record(&self, conn: &str, dir: Direction, frame: &[u8]) {
+        let hex: String = frame.iter().map(|b| format!("{b:02x}")).collect();
+        let line = format!(
+            "{} {conn} {} {hex} {}\n",
+            self.role.as_char(),
+            dir.as_char(),
+            describe(frame)
+        );
+
+        // Records are written with a single call so that multiple processes are able to append
+        // to the same file
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        _ = out.write_all(line.as_bytes());
+        _ = out.flush();
+    }
+}
+
+pub(crate) fn conn_id(n: u64) -> String {
+    format!("{}.{n}", process::id())
+}
+
+/// The debug representation of an encoded message. T-messages have even message types and
+/// R-messages have odd ones.
+fn describe(frame: &[u8]) -> String {
+    let res = match frame.get(4) {
+        Some(ty) if ty.is_multiple_of(2) => {
+            Tmessage::read_from(&mut &frame[..]).map(|t| format!("{t:?}"))
+        }
+        Some(_) => Rmessage::read_from(&mut &frame[..]).map(|r| format!("{r:?}")),
+        None => return "<truncated>".to_string(),
+    };
+
+    res.unwrap_or_else(|e| format!("<invalid: {e}>"))
+}
+
+/// A [Stream] that records each complete message read from or written to it.
+#[derive(Debug)]
+pub(crate) struct Traced<S>
+where
+    S: Stream,
+{
+    inner: S,
+    conn: String,
+    tracer: Option<Tracer>,
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+}
+
+impl<S> Traced<S>
+where
+    S: Stream,
+{
+    pub(crate) fn new(inner: S, conn: String, tracer: Option<Tracer>) -> Self {
+        Self {
+            inner,
+            conn,
+            tracer,
+            rbuf: Vec::new(),
+            wbuf: Vec::new(),
+        }
+    }
+}
+
+/// Record any complete messages held in buf, leaving behind any trailing partial message.
+fn drain_frames(tracer: &Tracer, conn: &str, dir: Direction, buf: &mut Vec<u8>) {
+    while buf.len() >= 4 {
+        let size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if size < MIN_FRAME_LEN {
+            // Not a valid frame so there is no way to resynchronise
+            buf.clear();
+            return;
+        } else if buf.len() < size {
+            return;
+        }
+
+        let frame: Vec<u8> = buf.drain(..size).collect();
+        tracer.record(conn, dir, &frame);
+    }
+}
+
+impl<S> Read for Traced<S>
+where
+    S: Stream,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(tracer) = &self.tracer {
+            self.rbuf.extend_from_slice(&buf[..n]);
+            drain_frames(tracer, &self.conn, Direction::Received, &mut self.rbuf);
+        }
+
+        Ok(n)
+    }
+}
+
+impl<S> Write for Traced<S>
+where
+    S: Stream,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(tracer) = &self.tracer {
+            self.wbuf.extend_from_slice(&buf[..n]);
+            drain_frames(tracer, &self.conn, Direction::Sent, &mut self.wbuf);
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S> Stream for Traced<S>
+where
+    S: Stream,
+{
+    fn try_clone(&self) -> Result<Self> {
+        Ok(Self::new(
+            self.inner.try_clone()?,
+            self.conn.clone(),
+            self.tracer.clone(),
+        ))
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.inner.shutdown()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    line: usize,
+    conn: String,
+    dir: Direction,
+    frame: Vec<u8>,
+}
+
+/// The server side of a recorded session that can be replayed against a [Serve9p]
+/// implementation.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    records: Vec<Record>,
+    timeout: Duration,
+}
+
+/// A response produced during a [Replay] that differed from the one that was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The line of the trace containing the expected response
+    pub line: usize,
+    /// The connection the response was expected on
+    pub conn: String,
+    /// The recorded response
+    pub expected: Rmessage,
+    /// The response that was received, if there was one
+    pub actual: Option<Rmessage>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "line {} (conn {}):", self.line, self.conn)?;
+        writeln!(f, "- {:?}", self.expected)?;
+        match &self.actual {
+            Some(r) => writeln!(f, "+ {r:?}"),
+            None => writeln!(f, "+ <no response>"),
+        }
+    }
+}
+
+impl Replay {
+    /// Parse the server records from a trace, ignoring any client records.
+    pub fn parse(r: impl BufRead) -> io::Result<Self> {
+        let mut records = Vec::new();
+
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let invalid = || {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid trace record on line {}: {line}", i + 1),
+                )
+            };
+
+            let mut fields = line.splitn(5, ' ');
+            let (role, conn, dir, hex) =
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(role), Some(conn), Some(dir), Some(hex)) => (role, conn, dir, hex),
+                    _ if line.trim().is_empty() => continue,
+                    _ => return Err(invalid()),
+                };
+
+            if role != "s" {
+                continue;
+            }
+
+            let dir = match dir {
+                "<" => Direction::Received,
+                ">" => Direction::Sent,
+                _ => return Err(invalid()),
+            };
+
+            records.push(Record {
+                line: i + 1,
+                conn: conn.to_string(),
+                dir,
+                frame: parse_hex(hex).ok_or_else(invalid)?,
+            });
+        }
+
+        Ok(Self {
+            records,
+            timeout: DEFAULT_REPLAY_TIMEOUT,
+        })
+    }
+
+    /// Parse the server records from the trace file at the given path.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Set how long to wait for each response before treating it as missing.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send each recorded request to a new server for `s` in the order they were originally
+    /// received, returning any responses that differ from those that were recorded.
+    pub fn run<S>(&self, s: S) -> io::Result<Vec<Mismatch>>
+    where
+        S: Serve9p,
+    {
+        let mut server = Server::new(s).without_tracer();
+        let mut conns: HashMap<&str, UnixStream> = HashMap::new();
+        let mut mismatches = Vec::new();
+
+        for rec in self.records.iter() {
+            let stream = match conns.get_mut(rec.conn.as_str()) {
+                Some(stream) => stream,
+                None => {
+                    let (client, srv) = UnixStream::pair()?;
+                    client.set_read_timeout(Some(self.timeout))?;
+                    server.spawn_session(srv);
+                    conns.entry(&rec.conn).or_insert(client)
+                }
+            };
+
+            match rec.dir {
+                Direction::Received => stream.write_all(&rec.frame)?,
+                Direction::Sent => {
+                    let expected = Rmessage::read_from(&mut &rec.frame[..])?;
+                    let actual = Rmessage::read_from(stream).ok();
+                    if actual.as_ref() != Some(&expected) {
+                        mismatches.push(Mismatch {
+                            line: rec.line,
+                            conn: rec.conn.clone(),
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        for (_, stream) in conns.into_iter() {
+            _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+
+        Ok(mismatches)
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        sfs::{Dir, File, Sfs},
+    };
+
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sfs(foo: &'static str) -> Sfs {
+        Sfs::new(
+            Dir::root().with_child(File::new("foo").on_read(move |_| Ok(foo.as_bytes().to_vec()))),
+        )
+    }
+
+    fn record_session() -> String {
+        let buf = SharedBuf::default();
+        let mut server =
+            Server::new(sfs("foo contents")).with_tracer(Tracer::new(Role::Server, buf.clone()));
+        let (client, srv) = UnixStream::pair().unwrap();
+        server.spawn_session(srv);
+
+        let mut client = Client::new_from_stream("test".to_string(), client, "", None).unwrap();
+        assert_eq!(client.read_str("foo").unwrap(), "foo contents");
+        assert!(client.read_str("missing").is_err());
+
+        let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        drop(client);
+
+        trace
+    }
+
+    #[test]
+    fn messages_are_traced() {
+        let trace = record_session();
+        let lines: Vec<&str> = trace.lines().collect();
+
+        assert!(lines[0].starts_with(&format!("s {}.0 < ", process::id())));
+        assert!(lines[0].contains("Version"));
+        assert!(lines[1].contains(" > "));
+        assert!(lines[1].contains("Version"));
+        assert!(trace.contains("Read { fid:"));
+    }
+
+    #[test]
+    fn replaying_the_same_server_has_no_mismatches() {
+        let replay = Replay::parse(record_session().as_bytes()).unwrap();
+        let mismatches = replay.run(sfs("foo contents")).unwrap();
+
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+    }
+
+    #[test]
+    fn replaying_a_different_server_reports_mismatches() {
+        let replay = Replay::parse(record_session().as_bytes()).unwrap();
+        let mismatches = replay.run(sfs("something else")).unwrap();
+
+        // Both the initial read and the read at the old end of file now differ
+        assert_eq!(mismatches.len(), 2, "{mismatches:?}");
+        assert!(mismatches[0].to_string().contains("+ Rmessage"));
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        let res = Replay::parse("s 1.0 < zz Tmessage\n".as_bytes());
+
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
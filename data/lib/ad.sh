@@ -30,12 +30,42 @@ bufRead() { 9p read "ad/buffers/$1/$2"; }
 # Write a string to the specified buffer file
 bufWrite() { 9p write "ad/buffers/$1/$2"; }
 
+# Write a control message to the ctl file of the specified buffer.
+# Buffer control messages are applied without changing the focused buffer
+bufCtl() {
+  local id="$1"
+  shift
+  echo -n "$*" | bufWrite "$id" ctl
+}
+
+# Replace the diagnostics for the specified buffer with those read from stdin.
+# Each line should be in the form "line:col-line:col severity message"
+setDiagnostics() {
+  {
+    echo clear
+    cat
+  } | bufWrite "$1" diagnostics
+}
+
 # Follow the ad log stream of ongoing buffer events
 adLog() { 9p read ad/log; }
 
+# Follow the stream of changes to all buffers (or just the buffer with the
+# specified id) without intercepting input
+adEvents() {
+  if [ -n "$1" ]; then
+    9p read "ad/buffers/$1/events"
+  else
+    9p read ad/events
+  fi
+}
+
 # Fetch the id of the currently focused buffer
 currentBufferId() { 9p read ad/buffers/current; }
 
+# Create a new buffer without changing focus and print its id
+newBuffer() { 9p read ad/buffers/new; }
+
 # Set focus to the buffer with the specified id
 focusBuffer() { adCtl "buffer $1"; }
 
@@ -46,7 +76,7 @@ clearBuffer() {
 }
 
 # Mark the buffer with the specified id as clean
-markClean() { adCtl "mark-clean $1"; }
+markClean() { bufCtl "$1" clean; }
 
 # Set the cursor position for the specified buffer to the begining of the file
 curToBof() { echo -n 0 | bufWrite "$1" addr; }
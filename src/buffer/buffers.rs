@@ -2,6 +2,7 @@ use crate::{
     buffer::{Buffer, BufferKind, Cur},
     dot::TextObject,
     editor::ViewPort,
+    fsys::Observed,
 };
 use ad_event::Source;
 use std::{
@@ -123,6 +124,15 @@ impl Buffers {
         self.inner.push_front(buf);
     }
 
+    /// Create a new empty, unnamed buffer without changing focus and return its id.
+    pub(crate) fn open_unnamed(&mut self) -> BufferId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.inner.insert(1, Buffer::new_unnamed(id, ""));
+
+        id
+    }
+
     pub(crate) fn open_virtual(&mut self, name: String, content: String) {
         let buf = Buffer::new_virtual(self.next_id, name, content);
         self.record_jump_position();
@@ -172,6 +182,19 @@ impl Buffers {
         self.inner.iter_mut().find(|b| b.id == id)
     }
 
+    /// The open buffer for the file at the given (canonical) path if there is one.
+    pub(crate) fn with_path(&self, path: &Path) -> Option<&Buffer> {
+        self.inner
+            .iter()
+            .find(|b| matches!(&b.kind, BufferKind::File(p) if p == path))
+    }
+
+    pub(crate) fn with_path_mut(&mut self, path: &Path) -> Option<&mut Buffer> {
+        self.inner
+            .iter_mut()
+            .find(|b| matches!(&b.kind, BufferKind::File(p) if p == path))
+    }
+
     pub fn dirty_buffers(&self) -> Vec<String> {
         self.inner
             .iter()
@@ -248,6 +271,14 @@ impl Buffers {
         self.inner.len()
     }
 
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.inner.iter()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        self.inner.iter_mut()
+    }
+
     #[inline]
     pub fn is_empty_scratch(&self) -> bool {
         self.inner.len() == 1 && self.inner[0].is_unnamed() && self.inner[0].txt.is_empty()
@@ -284,6 +315,49 @@ impl Buffers {
 
         self.focus_id(id);
     }
+
+    /// Clear the contents of the output buffer with the given key (creating it if needed) and
+    /// focus it, returning its id.
+    pub(crate) fn reset_output_buffer(&mut self, key: String) -> BufferId {
+        let id = match self
+            .inner
+            .iter_mut()
+            .find(|b| b.kind == BufferKind::Output(key.clone()))
+        {
+            Some(b) => {
+                if b.txt.len_chars() > 0 {
+                    b.pending_changes.push(Observed::Delete {
+                        from: 0,
+                        to: b.txt.len_chars(),
+                    });
+                }
+                b.txt.clear();
+                b.highlights.clear();
+                b.diagnostics.clear();
+                b.dot = Default::default();
+                b.edit_log = Default::default();
+                b.marks = Default::default();
+                b.row_off = 0;
+                b.col_off = 0;
+
+                b.id
+            }
+
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                let b = Buffer::new_output(id, key, String::new());
+                self.record_jump_position();
+                self.inner.push_front(b);
+
+                id
+            }
+        };
+
+        self.focus_id(id);
+
+        id
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
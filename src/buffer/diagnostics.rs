@@ -0,0 +1,384 @@
+//! Diagnostics for a buffer provided by external programs such as linters and compilers.
+//!
+//! Diagnostics are written to the diagnostics file of a buffer in fsys, one per line, in the
+//! form `line:col-line:col severity message`. Lines and columns are 1-based and the end position
+//! is exclusive, with an empty range covering the single character at its start. Writing `clear`
+//! removes all diagnostics from the buffer. Once set, diagnostics are tracked by character offset
+//! so that they continue to cover the same text as the buffer is edited.
+use crate::{
+    buffer::{
+        highlights::{shift_for_delete, shift_for_insert},
+        GapBuffer,
+    },
+    ftype::lex::TokenType,
+};
+use std::fmt;
+
+/// How serious a diagnostic is, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn try_from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "info" => Ok(Self::Info),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("unknown severity: {s}")),
+        }
+    }
+
+    pub(crate) fn token_type(&self) -> TokenType {
+        match self {
+            Self::Info => TokenType::Info,
+            Self::Warning => TokenType::Warning,
+            Self::Error => TokenType::Error,
+        }
+    }
+
+    /// The character used to mark lines with a diagnostic of this severity in the sign column.
+    pub(crate) fn glyph(&self) -> char {
+        match self {
+            Self::Info => 'I',
+            Self::Warning => 'W',
+            Self::Error => 'E',
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+/// A 1-based line and column position.
+type Pos = (usize, usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DiagnosticCmd {
+    Clear,
+    Add {
+        start: Pos,
+        end: Pos,
+        severity: Severity,
+        message: String,
+    },
+}
+
+fn parse_pos(s: &str) -> Option<Pos> {
+    let (line, col) = s.split_once(':')?;
+    let (line, col) = (line.parse().ok()?, col.parse().ok()?);
+    if line == 0 || col == 0 {
+        return None;
+    }
+
+    Some((line, col))
+}
+
+/// Parse the lines written to a diagnostics file, returning an error if any are invalid so that
+/// nothing is applied.
+pub(crate) fn parse_diagnostics(s: &str) -> Result<Vec<DiagnosticCmd>, String> {
+    let mut cmds = Vec::new();
+    for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if line == "clear" {
+            cmds.push(DiagnosticCmd::Clear);
+            continue;
+        }
+
+        let invalid = || format!("invalid diagnostic: {line}");
+        let mut fields = line.splitn(3, ' ');
+        let (start, end) = fields
+            .next()
+            .and_then(|r| r.split_once('-'))
+            .and_then(|(start, end)| Some((parse_pos(start)?, parse_pos(end)?)))
+            .ok_or_else(invalid)?;
+        if end < start {
+            return Err(invalid());
+        }
+        let severity = Severity::try_from_str(fields.next().ok_or_else(invalid)?)?;
+        let message = fields.next().unwrap_or_default().trim().to_string();
+
+        cmds.push(DiagnosticCmd::Add {
+            start,
+            end,
+            severity,
+            message,
+        });
+    }
+
+    Ok(cmds)
+}
+
+/// Convert a 1-based line and column to a character offset. Start positions are clamped to the
+/// last character of the line and end positions, which are exclusive, to the end of the line.
+fn idx_for_pos(txt: &GapBuffer, (line, col): Pos, is_end: bool) -> usize {
+    let line = (line - 1).min(txt.len_lines() - 1);
+    let line_len = txt.line_len_chars(line);
+    let max_col = if is_end {
+        line_len
+    } else {
+        line_len.saturating_sub(1)
+    };
+
+    txt.line_to_char(line) + (col - 1).min(max_col)
+}
+
+fn pos_for_idx(txt: &GapBuffer, idx: usize) -> Pos {
+    let idx = idx.min(txt.len_chars());
+    let line = txt.char_to_line(idx);
+
+    (line + 1, idx - txt.line_to_char(line) + 1)
+}
+
+/// A diagnostic message attached to a range of characters within a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Diagnostic {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+/// The current set of diagnostics for a buffer, ordered by their start position.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub(crate) fn apply(&mut self, cmds: Vec<DiagnosticCmd>, txt: &GapBuffer) {
+        for cmd in cmds.into_iter() {
+            match cmd {
+                DiagnosticCmd::Clear => self.items.clear(),
+                DiagnosticCmd::Add {
+                    start,
+                    end,
+                    severity,
+                    message,
+                } => {
+                    let mut from = idx_for_pos(txt, start, false);
+                    let mut to = idx_for_pos(txt, end, true);
+                    if to == from {
+                        // Empty ranges cover a single character so that they are visible,
+                        // stepping back from the end of the buffer if needed
+                        let len = txt.len_chars();
+                        from = if from == len {
+                            from.saturating_sub(1)
+                        } else {
+                            from
+                        };
+                        to = (from + 1).min(len);
+                    }
+                    let idx = self.items.partition_point(|d| d.from <= from);
+                    self.items.insert(
+                        idx,
+                        Diagnostic {
+                            from,
+                            to,
+                            severity,
+                            message,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The diagnostics overlapping the range of characters `from..to`.
+    pub(crate) fn overlapping(&self, from: usize, to: usize) -> impl Iterator<Item = &Diagnostic> {
+        let n = self.items.partition_point(|d| d.from < to);
+        self.items[..n].iter().filter(move |d| d.to > from)
+    }
+
+    /// The most severe diagnostic starting within the range of characters `from..to`.
+    pub(crate) fn max_severity_starting_in(&self, from: usize, to: usize) -> Option<Severity> {
+        let start = self.items.partition_point(|d| d.from < from);
+        let end = self.items.partition_point(|d| d.from < to);
+
+        self.items[start..end].iter().map(|d| d.severity).max()
+    }
+
+    /// The first diagnostic after (or before) the given character offset, wrapping around the
+    /// buffer.
+    pub(crate) fn next_from(&self, idx: usize, forward: bool) -> Option<&Diagnostic> {
+        if forward {
+            self.items
+                .iter()
+                .find(|d| d.from > idx)
+                .or(self.items.first())
+        } else {
+            self.items
+                .iter()
+                .rev()
+                .find(|d| d.from < idx)
+                .or(self.items.last())
+        }
+    }
+
+    pub(crate) fn insert(&mut self, idx: usize, n: usize) {
+        for d in self.items.iter_mut() {
+            (d.from, d.to) = shift_for_insert(d.from, d.to, idx, n);
+        }
+    }
+
+    /// Shift diagnostics to account for the characters `from..to` being deleted, dropping any
+    /// whose text was deleted entirely.
+    pub(crate) fn delete(&mut self, from: usize, to: usize) {
+        for d in self.items.iter_mut() {
+            (d.from, d.to) = shift_for_delete(d.from, d.to, from, to);
+        }
+        self.items.retain(|d| d.from < d.to);
+    }
+
+    /// Render each diagnostic as a `line:col` location followed by its severity and message.
+    pub(crate) fn locations(&self, txt: &GapBuffer) -> Vec<(Pos, &Diagnostic)> {
+        self.items
+            .iter()
+            .map(|d| (pos_for_idx(txt, d.from), d))
+            .collect()
+    }
+
+    pub(crate) fn as_file_content(&self, txt: &GapBuffer) -> String {
+        self.items
+            .iter()
+            .map(|d| {
+                let (sl, sc) = pos_for_idx(txt, d.from);
+                let (el, ec) = pos_for_idx(txt, d.to);
+                format!("{sl}:{sc}-{el}:{ec} {} {}\n", d.severity, d.message)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    const TXT: &str = "fn main() {\n    let x = 1;\n}\n";
+
+    fn add(start: Pos, end: Pos, severity: Severity, message: &str) -> DiagnosticCmd {
+        DiagnosticCmd::Add {
+            start,
+            end,
+            severity,
+            message: message.to_string(),
+        }
+    }
+
+    fn diagnostics(s: &str) -> (Diagnostics, GapBuffer) {
+        let txt = GapBuffer::from(TXT);
+        let mut d = Diagnostics::default();
+        d.apply(parse_diagnostics(s).unwrap(), &txt);
+
+        (d, txt)
+    }
+
+    #[test_case(
+        "2:9-2:10 warning unused variable: `x`",
+        vec![add((2, 9), (2, 10), Severity::Warning, "unused variable: `x`")];
+        "single"
+    )]
+    #[test_case(
+        "clear\n1:1-1:3 error bad\n",
+        vec![DiagnosticCmd::Clear, add((1, 1), (1, 3), Severity::Error, "bad")];
+        "clear"
+    )]
+    #[test_case("3:1-3:1 info", vec![add((3, 1), (3, 1), Severity::Info, "")]; "no message")]
+    #[test_case("", vec![]; "empty")]
+    #[test]
+    fn parse_diagnostics_works(s: &str, expected: Vec<DiagnosticCmd>) {
+        assert_eq!(parse_diagnostics(s), Ok(expected));
+    }
+
+    #[test_case("2:9-2:10 fatal oops"; "unknown severity")]
+    #[test_case("2:9 error oops"; "missing end")]
+    #[test_case("0:9-2:10 error oops"; "zero line")]
+    #[test_case("2:9-1:10 error oops"; "backwards")]
+    #[test_case("1:1-1:2 info ok\nnope"; "invalid second line")]
+    #[test]
+    fn invalid_diagnostics_are_rejected(s: &str) {
+        assert!(parse_diagnostics(s).is_err());
+    }
+
+    #[test_case("2:9-2:10 warning x", 20, 21; "single char")]
+    #[test_case("2:9-2:9 warning x", 20, 21; "empty range is one char")]
+    #[test_case("1:1-2:1 error x", 0, 12; "multi line")]
+    #[test_case("1:50-1:60 error x", 11, 12; "clamped to line end")]
+    #[test_case("9:1-9:2 error x", 28, 29; "clamped to buffer end")]
+    #[test]
+    fn positions_are_converted_to_offsets(s: &str, from: usize, to: usize) {
+        let (d, _) = diagnostics(s);
+        let found: Vec<(usize, usize)> = d.items.iter().map(|d| (d.from, d.to)).collect();
+
+        assert_eq!(found, vec![(from, to)]);
+    }
+
+    #[test]
+    fn ranges_can_end_at_the_end_of_the_last_line() {
+        let txt = GapBuffer::from("hello");
+        let mut d = Diagnostics::default();
+        d.apply(parse_diagnostics("1:1-1:6 error x").unwrap(), &txt);
+        let found: Vec<(usize, usize)> = d.items.iter().map(|d| (d.from, d.to)).collect();
+
+        assert_eq!(found, vec![(0, 5)]);
+    }
+
+    #[test]
+    fn file_content_round_trips() {
+        let s = "1:4-1:8 info main\n2:9-2:10 warning unused variable\n";
+        let (d, txt) = diagnostics(s);
+
+        assert_eq!(d.as_file_content(&txt), s);
+    }
+
+    #[test]
+    fn diagnostics_are_anchored_through_edits() {
+        let (mut d, _) = diagnostics("2:9-2:10 warning x\n1:4-1:8 info main");
+        d.insert(0, 3);
+        d.delete(20, 30);
+        let found: Vec<(usize, usize)> = d.items.iter().map(|d| (d.from, d.to)).collect();
+
+        assert_eq!(found, vec![(6, 10)]);
+    }
+
+    #[test_case(0, true, Some(3); "forward")]
+    #[test_case(3, true, Some(20); "forward from diagnostic")]
+    #[test_case(25, true, Some(3); "forward wraps")]
+    #[test_case(20, false, Some(3); "backward")]
+    #[test_case(2, false, Some(20); "backward wraps")]
+    #[test]
+    fn next_from_works(idx: usize, forward: bool, expected: Option<usize>) {
+        let (d, _) = diagnostics("2:9-2:10 warning x\n1:4-1:8 info main");
+
+        assert_eq!(d.next_from(idx, forward).map(|d| d.from), expected);
+    }
+
+    #[test]
+    fn max_severity_starting_in_works() {
+        let (d, _) = diagnostics("1:4-1:8 info a\n1:1-1:2 error b\n2:9-2:10 warning c");
+
+        assert_eq!(d.max_severity_starting_in(0, 12), Some(Severity::Error));
+        assert_eq!(d.max_severity_starting_in(12, 27), Some(Severity::Warning));
+        assert_eq!(d.max_severity_starting_in(27, 29), None);
+    }
+}
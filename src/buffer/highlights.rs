@@ -0,0 +1,250 @@
+//! Highlight spans for a buffer provided by external programs.
+//!
+//! Spans are written to the highlights file of a buffer in fsys, one per line, in the form
+//! `from to face` where `from` and `to` are the half open range of characters to style. Writing
+//! `clear` removes all spans and `clear from to` removes any spans overlapping the given range.
+//! As with marks, spans are updated as text is inserted and deleted so that they continue to
+//! cover the same text.
+use crate::ftype::lex::TokenType;
+
+const FACES: [(&str, TokenType); 10] = [
+    ("default", TokenType::Default),
+    ("comment", TokenType::Comment),
+    ("string", TokenType::String),
+    ("keyword", TokenType::Keyword),
+    ("control-flow", TokenType::ControlFlow),
+    ("definition", TokenType::Definition),
+    ("punctuation", TokenType::Punctuation),
+    ("error", TokenType::Error),
+    ("warning", TokenType::Warning),
+    ("info", TokenType::Info),
+];
+
+fn parse_face(s: &str) -> Result<TokenType, String> {
+    FACES
+        .iter()
+        .find(|(name, _)| *name == s)
+        .map(|(_, ty)| *ty)
+        .ok_or_else(|| format!("unknown face: {s}"))
+}
+
+fn face_name(ty: TokenType) -> &'static str {
+    FACES
+        .iter()
+        .find(|(_, t)| *t == ty)
+        .map(|(name, _)| *name)
+        .unwrap_or("default")
+}
+
+/// Update the half open range `from..to` to account for `n` characters being inserted at `idx`.
+pub(super) fn shift_for_insert(from: usize, to: usize, idx: usize, n: usize) -> (usize, usize) {
+    let from = if from >= idx { from + n } else { from };
+    let to = if to > idx { to + n } else { to };
+
+    (from, to)
+}
+
+/// Update the half open range `from..to` to account for the characters `dfrom..dto` being
+/// deleted. Ranges that were entirely deleted are returned as empty.
+pub(super) fn shift_for_delete(from: usize, to: usize, dfrom: usize, dto: usize) -> (usize, usize) {
+    let shift = |idx: usize| {
+        if idx >= dto {
+            idx - (dto - dfrom)
+        } else {
+            idx.min(dfrom)
+        }
+    };
+
+    (shift(from), shift(to))
+}
+
+/// A styled range of characters within a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) ty: TokenType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HighlightCmd {
+    Clear(Option<(usize, usize)>),
+    Add(Span),
+}
+
+/// Parse the lines written to a highlights file, returning an error if any are invalid so that
+/// nothing is applied.
+pub(crate) fn parse_highlights(s: &str) -> Result<Vec<HighlightCmd>, String> {
+    let parse_range = |from: &str, to: &str, line: &str| {
+        let from: usize = from.parse().map_err(|_| format!("invalid span: {line}"))?;
+        let to: usize = to.parse().map_err(|_| format!("invalid span: {line}"))?;
+        if from >= to {
+            return Err(format!("empty span: {line}"));
+        }
+
+        Ok((from, to))
+    };
+
+    let mut cmds = Vec::new();
+    for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let cmd = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["clear"] => HighlightCmd::Clear(None),
+            ["clear", from, to] => HighlightCmd::Clear(Some(parse_range(from, to, line)?)),
+            [from, to, face] => {
+                let (from, to) = parse_range(from, to, line)?;
+                let ty = parse_face(face)?;
+                HighlightCmd::Add(Span { from, to, ty })
+            }
+            _ => return Err(format!("invalid highlight: {line}")),
+        };
+        cmds.push(cmd);
+    }
+
+    Ok(cmds)
+}
+
+/// The current set of highlight spans for a buffer, ordered by their start position.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Highlights {
+    spans: Vec<Span>,
+}
+
+impl Highlights {
+    pub(crate) fn apply(&mut self, cmds: Vec<HighlightCmd>) {
+        for cmd in cmds.into_iter() {
+            match cmd {
+                HighlightCmd::Clear(None) => self.spans.clear(),
+                HighlightCmd::Clear(Some((from, to))) => {
+                    self.spans.retain(|s| s.to <= from || s.from >= to)
+                }
+                HighlightCmd::Add(span) => {
+                    let idx = self.spans.partition_point(|s| s.from <= span.from);
+                    self.spans.insert(idx, span);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.spans.clear();
+    }
+
+    /// The spans overlapping the range of characters `from..to`.
+    pub(crate) fn overlapping(&self, from: usize, to: usize) -> impl Iterator<Item = &Span> {
+        let n = self.spans.partition_point(|s| s.from < to);
+        self.spans[..n].iter().filter(move |s| s.to > from)
+    }
+
+    /// Shift spans to account for `n` characters being inserted at `idx`. Spans containing `idx`
+    /// are extended to cover the inserted text while text inserted at either end of a span is left
+    /// unstyled.
+    pub(crate) fn insert(&mut self, idx: usize, n: usize) {
+        for s in self.spans.iter_mut() {
+            (s.from, s.to) = shift_for_insert(s.from, s.to, idx, n);
+        }
+    }
+
+    /// Shift spans to account for the characters `from..to` being deleted, dropping any spans
+    /// that were entirely contained in the deleted range.
+    pub(crate) fn delete(&mut self, from: usize, to: usize) {
+        for s in self.spans.iter_mut() {
+            (s.from, s.to) = shift_for_delete(s.from, s.to, from, to);
+        }
+        self.spans.retain(|s| s.from < s.to);
+    }
+
+    pub(crate) fn as_file_content(&self) -> String {
+        self.spans
+            .iter()
+            .map(|s| format!("{} {} {}\n", s.from, s.to, face_name(s.ty)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn span(from: usize, to: usize, ty: TokenType) -> Span {
+        Span { from, to, ty }
+    }
+
+    fn highlights(spans: &[Span]) -> Highlights {
+        let mut h = Highlights::default();
+        h.apply(spans.iter().map(|s| HighlightCmd::Add(*s)).collect());
+        h
+    }
+
+    #[test_case("3 7 keyword", vec![HighlightCmd::Add(span(3, 7, TokenType::Keyword))]; "single")]
+    #[test_case("clear\n0 2 error\n", vec![HighlightCmd::Clear(None), HighlightCmd::Add(span(0, 2, TokenType::Error))]; "clear all")]
+    #[test_case("clear 4 9", vec![HighlightCmd::Clear(Some((4, 9)))]; "clear range")]
+    #[test_case("", vec![]; "empty")]
+    #[test]
+    fn parse_highlights_works(s: &str, expected: Vec<HighlightCmd>) {
+        assert_eq!(parse_highlights(s), Ok(expected));
+    }
+
+    #[test_case("3 7 sparkly"; "unknown face")]
+    #[test_case("7 3 string"; "backwards")]
+    #[test_case("3 3 string"; "empty span")]
+    #[test_case("1 2 string\nx 7 comment"; "invalid second line")]
+    #[test]
+    fn invalid_highlights_are_rejected(s: &str) {
+        assert!(parse_highlights(s).is_err());
+    }
+
+    #[test]
+    fn spans_are_kept_in_order() {
+        let h = highlights(&[
+            span(5, 8, TokenType::String),
+            span(0, 2, TokenType::Comment),
+            span(3, 4, TokenType::Error),
+        ]);
+
+        assert_eq!(h.as_file_content(), "0 2 comment\n3 4 error\n5 8 string\n");
+    }
+
+    #[test_case(2, 3, span(7, 11, TokenType::String); "before span")]
+    #[test_case(4, 3, span(7, 11, TokenType::String); "at start of span")]
+    #[test_case(6, 3, span(4, 11, TokenType::String); "within span")]
+    #[test_case(8, 3, span(4, 8, TokenType::String); "at end of span")]
+    #[test]
+    fn insert_works(idx: usize, n: usize, expected: Span) {
+        let mut h = highlights(&[span(4, 8, TokenType::String)]);
+        h.insert(idx, n);
+
+        assert_eq!(h.spans, vec![expected]);
+    }
+
+    #[test_case(0, 2, vec![span(2, 6, TokenType::String)]; "before span")]
+    #[test_case(2, 6, vec![span(2, 4, TokenType::String)]; "overlapping start")]
+    #[test_case(5, 7, vec![span(4, 6, TokenType::String)]; "within span")]
+    #[test_case(3, 9, vec![]; "containing span")]
+    #[test]
+    fn delete_works(from: usize, to: usize, expected: Vec<Span>) {
+        let mut h = highlights(&[span(4, 8, TokenType::String)]);
+        h.delete(from, to);
+
+        assert_eq!(h.spans, expected);
+    }
+
+    #[test]
+    fn overlapping_works() {
+        let h = highlights(&[
+            span(0, 2, TokenType::Comment),
+            span(1, 20, TokenType::String),
+            span(10, 12, TokenType::Error),
+            span(15, 17, TokenType::Keyword),
+        ]);
+        let found: Vec<Span> = h.overlapping(5, 15).copied().collect();
+
+        assert_eq!(
+            found,
+            vec![
+                span(1, 20, TokenType::String),
+                span(10, 12, TokenType::Error)
+            ]
+        );
+    }
+}
@@ -851,6 +851,9 @@ impl<'a> fmt::Display for Slice<'a> {
 impl<'a, 'b> PartialEq<&'b str> for Slice<'a> {
     fn eq(&self, other: &&'b str) -> bool {
         let b = other.as_bytes();
+        if b.len() != self.left.len() + self.right.len() {
+            return false;
+        }
 
         &b[..self.left.len()] == self.left && &b[self.left.len()..] == self.right
     }
@@ -1579,6 +1582,8 @@ mod tests {
         gb.move_gap_to(3);
         let slice = gb.slice(0, 5);
         assert_eq!(slice, "hello");
+        assert_ne!(slice, "hell");
+        assert_ne!(slice, "hello, world");
     }
 
     #[test]
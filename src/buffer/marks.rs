@@ -0,0 +1,122 @@
+//! Named marks within a buffer.
+//!
+//! Marks record a [Dot] under a single character name so that it can be returned to later, either
+//! by jumping to it directly or by using it as an address ('a) in an Edit program. Marks are
+//! updated as text is inserted and deleted so that they continue to refer to the same text.
+use crate::dot::{Cur, Dot, Range};
+use std::collections::BTreeMap;
+
+/// Whether or not the given character can be used as the name of a mark.
+pub(crate) fn is_valid_mark_name(ch: char) -> bool {
+    ch.is_alphanumeric()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Marks {
+    inner: BTreeMap<char, Dot>,
+}
+
+impl Marks {
+    pub(crate) fn get(&self, name: char) -> Option<Dot> {
+        self.inner.get(&name).copied()
+    }
+
+    pub(crate) fn set(&mut self, name: char, dot: Dot) {
+        self.inner.insert(name, dot);
+    }
+
+    /// Shift any marks at or after `idx` to account for `n` characters being inserted.
+    pub(crate) fn insert(&mut self, idx: usize, n: usize) {
+        self.update(|c| {
+            if c.idx >= idx {
+                c.idx += n;
+            }
+        });
+    }
+
+    /// Shift any marks after the deleted range `from..to` back to account for the deletion.
+    /// Marks inside of the range are moved to its start.
+    pub(crate) fn delete(&mut self, from: usize, to: usize) {
+        self.update(|c| {
+            if c.idx >= to {
+                c.idx -= to - from;
+            } else if c.idx > from {
+                c.idx = from;
+            }
+        });
+    }
+
+    pub(crate) fn clamp_idx(&mut self, max_idx: usize) {
+        for dot in self.inner.values_mut() {
+            dot.clamp_idx(max_idx);
+        }
+    }
+
+    fn update(&mut self, mut f: impl FnMut(&mut Cur)) {
+        for dot in self.inner.values_mut() {
+            *dot = match *dot {
+                Dot::Cur { mut c } => {
+                    f(&mut c);
+                    Dot::Cur { c }
+                }
+                Dot::Range {
+                    r:
+                        Range {
+                            mut start,
+                            mut end,
+                            start_active,
+                        },
+                } => {
+                    f(&mut start);
+                    f(&mut end);
+                    Dot::Range {
+                        r: Range {
+                            start,
+                            end,
+                            start_active,
+                        },
+                    }
+                    .collapse_null_range()
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn marks(dot: Dot) -> Marks {
+        let mut m = Marks::default();
+        m.set('a', dot);
+        m
+    }
+
+    #[test_case(Cur::new(5).into(), 2, 3, Cur::new(8).into(); "cur after insert")]
+    #[test_case(Cur::new(5).into(), 5, 3, Cur::new(8).into(); "cur at insert")]
+    #[test_case(Cur::new(5).into(), 7, 3, Cur::new(5).into(); "cur before insert")]
+    #[test_case(Dot::from_char_indices(2, 6), 4, 3, Dot::from_char_indices(2, 9); "insert within range")]
+    #[test_case(Dot::from_char_indices(2, 6), 0, 3, Dot::from_char_indices(5, 9); "insert before range")]
+    #[test]
+    fn insert_works(dot: Dot, idx: usize, n: usize, expected: Dot) {
+        let mut m = marks(dot);
+        m.insert(idx, n);
+
+        assert_eq!(m.get('a'), Some(expected));
+    }
+
+    #[test_case(Cur::new(8).into(), 2, 5, Cur::new(5).into(); "cur after delete")]
+    #[test_case(Cur::new(3).into(), 2, 5, Cur::new(2).into(); "cur within delete")]
+    #[test_case(Cur::new(1).into(), 2, 5, Cur::new(1).into(); "cur before delete")]
+    #[test_case(Dot::from_char_indices(2, 9), 4, 6, Dot::from_char_indices(2, 7); "delete within range")]
+    #[test_case(Dot::from_char_indices(4, 6), 2, 8, Cur::new(2).into(); "delete containing range")]
+    #[test]
+    fn delete_works(dot: Dot, from: usize, to: usize, expected: Dot) {
+        let mut m = marks(dot);
+        m.delete(from, to);
+
+        assert_eq!(m.get('a'), Some(expected));
+    }
+}
@@ -2,10 +2,13 @@
 use crate::{
     config::ColorScheme,
     config_handle,
-    dot::{find::find_forward_wrapping, Cur, Dot, LineRange, Range, TextObject},
+    dot::{
+        find::{find_backward_wrapping, find_forward_wrapping, RegexFind},
+        Cur, Dot, LineRange, Range, TextObject,
+    },
     editor::{Action, ViewPort},
     exec::IterBoundedChars,
-    fsys::InputFilter,
+    fsys::{InputFilter, Observed, PendingChanges},
     ftype::{
         lex::{Token, TokenType, Tokenizer, Tokens},
         try_tokenizer_for_path,
@@ -27,15 +30,31 @@ use tracing::debug;
 use unicode_width::UnicodeWidthChar;
 
 mod buffers;
+mod diagnostics;
 mod edit;
+mod highlights;
 mod internal;
+mod marks;
+mod tabstops;
 
+use diagnostics::Diagnostics;
 use edit::{Edit, EditLog, Kind, Txt};
+use highlights::Highlights;
 pub use internal::{Chars, GapBuffer, IdxChars, Slice};
+use marks::Marks;
+use tabstops::Tabstops;
 
 pub(crate) use buffers::Buffers;
+pub(crate) use diagnostics::{parse_diagnostics, DiagnosticCmd, Severity};
+pub(crate) use highlights::parse_highlights;
+pub(crate) use marks::is_valid_mark_name;
 
 pub(crate) const DEFAULT_OUTPUT_BUFFER: &str = "+output";
+pub(crate) const SEARCH_BUFFER: &str = "+search";
+pub(crate) const REPLACE_BUFFER: &str = "+replace";
+pub(crate) const DIAGNOSTICS_BUFFER: &str = "+diagnostics";
+pub(crate) const REFERENCES_BUFFER: &str = "+references";
+pub(crate) const MACROS_BUFFER: &str = "+macros";
 const HTTPS: &str = "https://";
 const HTTP: &str = "http://";
 
@@ -48,7 +67,7 @@ pub(crate) enum ActionOutcome {
 }
 
 /// Buffer kinds control how each buffer interacts with the rest of the editor functionality
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub(crate) enum BufferKind {
     /// A regular buffer that is backed by a file on disk.
     File(PathBuf),
@@ -59,17 +78,12 @@ pub(crate) enum BufferKind {
     /// An in-memory buffer holding output from commands run within a given directory
     Output(String),
     /// A currently un-named buffer that can be converted to a File buffer when named
+    #[default]
     Unnamed,
     /// State for an active mini-buffer
     MiniBuffer,
 }
 
-impl Default for BufferKind {
-    fn default() -> Self {
-        Self::Unnamed
-    }
-}
-
 impl BufferKind {
     fn display_name(&self, cwd: &Path) -> String {
         match self {
@@ -157,8 +171,14 @@ pub struct Buffer {
     pub(crate) col_off: usize,
     pub(crate) last_save: SystemTime,
     pub(crate) dirty: bool,
+    pub(crate) read_only: bool,
     pub(crate) input_filter: Option<InputFilter>,
+    pub(crate) pending_changes: PendingChanges,
+    pub(crate) highlights: Highlights,
+    pub(crate) diagnostics: Diagnostics,
     edit_log: EditLog,
+    marks: Marks,
+    tabstops: Tabstops,
     tokenizer: Option<Tokenizer>,
 }
 
@@ -180,8 +200,14 @@ impl Buffer {
             last_save: SystemTime::now(),
             dirty: false,
             edit_log: EditLog::default(),
+            marks: Marks::default(),
             tokenizer,
+            read_only: false,
             input_filter: None,
+            pending_changes: PendingChanges::default(),
+            highlights: Highlights::default(),
+            diagnostics: Diagnostics::default(),
+            tabstops: Tabstops::default(),
         })
     }
 
@@ -228,6 +254,7 @@ impl Buffer {
             Ok(_) => {
                 self.dirty = false;
                 self.last_save = SystemTime::now();
+                self.pending_changes.push(Observed::Save);
                 format!("\"{display_path}\" {n_lines}L {n_bytes}B written")
             }
             Err(e) => format!("Unable to save buffer: {e}"),
@@ -247,8 +274,21 @@ impl Buffer {
         };
 
         let n_chars = raw.len();
+        self.pending_changes.push(Observed::Delete {
+            from: 0,
+            to: self.txt.len_chars(),
+        });
+        self.pending_changes.push(Observed::Insert {
+            from: 0,
+            to: raw.chars().count(),
+            txt: raw.clone(),
+        });
         self.txt = GapBuffer::from(raw);
         self.dot.clamp_idx(n_chars);
+        self.marks.clamp_idx(n_chars);
+        self.highlights.clear();
+        self.diagnostics.clear();
+        self.tabstops.clear();
         self.edit_log.clear();
         self.dirty = false;
         self.last_save = SystemTime::now();
@@ -278,8 +318,14 @@ impl Buffer {
             last_save: SystemTime::now(),
             dirty: false,
             edit_log: Default::default(),
+            marks: Default::default(),
             tokenizer: None,
+            read_only: false,
             input_filter: None,
+            pending_changes: PendingChanges::default(),
+            highlights: Highlights::default(),
+            diagnostics: Diagnostics::default(),
+            tabstops: Tabstops::default(),
         }
     }
 
@@ -297,8 +343,14 @@ impl Buffer {
             last_save: SystemTime::now(),
             dirty: false,
             edit_log: EditLog::default(),
+            marks: Marks::default(),
             tokenizer: None,
+            read_only: false,
             input_filter: None,
+            pending_changes: PendingChanges::default(),
+            highlights: Highlights::default(),
+            diagnostics: Diagnostics::default(),
+            tabstops: Tabstops::default(),
         }
     }
 
@@ -324,8 +376,14 @@ impl Buffer {
             last_save: SystemTime::now(),
             dirty: false,
             edit_log: EditLog::default(),
+            marks: Marks::default(),
             tokenizer: None,
+            read_only: false,
             input_filter: None,
+            pending_changes: PendingChanges::default(),
+            highlights: Highlights::default(),
+            diagnostics: Diagnostics::default(),
+            tabstops: Tabstops::default(),
         }
     }
 
@@ -344,8 +402,14 @@ impl Buffer {
             last_save: SystemTime::now(),
             dirty: false,
             edit_log: EditLog::default(),
+            marks: Marks::default(),
             tokenizer: None,
+            read_only: false,
             input_filter: None,
+            pending_changes: PendingChanges::default(),
+            highlights: Highlights::default(),
+            diagnostics: Diagnostics::default(),
+            tabstops: Tabstops::default(),
         }
     }
 
@@ -436,6 +500,30 @@ impl Buffer {
         self.xdot.addr(self)
     }
 
+    /// A summary of the current state of the buffer, one tab separated key value pair per line.
+    ///
+    /// This is the content of the buffer's ctl file in fsys.
+    pub fn metadata(&self) -> String {
+        let kind = match &self.kind {
+            BufferKind::File(_) => "file",
+            BufferKind::Directory(_) => "directory",
+            BufferKind::Virtual(_) => "virtual",
+            BufferKind::Output(_) => "output",
+            BufferKind::Unnamed => "unnamed",
+            BufferKind::MiniBuffer => "minibuffer",
+        };
+
+        format!(
+            "id\t{}\nkind\t{kind}\nname\t{}\nchars\t{}\nlines\t{}\ndirty\t{}\nreadonly\t{}\n",
+            self.id,
+            self.full_name(),
+            self.txt.len_chars(),
+            self.len_lines(),
+            self.dirty,
+            self.read_only,
+        )
+    }
+
     /// The number of lines currently held in the buffer.
     #[inline]
     pub fn len_lines(&self) -> usize {
@@ -627,6 +715,7 @@ impl Buffer {
         lpad: usize,
         screen_cols: usize,
         load_exec_range: Option<(bool, Range)>,
+        search: Option<&mut RegexFind>,
         cs: &ColorScheme,
     ) -> String {
         let map_line_range = |lr| match lr {
@@ -649,14 +738,54 @@ impl Buffer {
             }),
         };
 
-        let mut tks = match dot_range {
-            Some((start, end)) => raw_tks.with_highlighted_dot(start, end, TokenType::Dot),
-            None => match raw_tks {
-                Tokens::Single(tk) => vec![tk],
-                Tokens::Multi(tks) => tks,
-            },
+        let mut tks = match raw_tks {
+            Tokens::Single(tk) => vec![tk],
+            Tokens::Multi(tks) => tks,
         };
 
+        let line_start = self.txt.line_to_char(y);
+        let line_end = line_start + self.txt.line_len_chars(y);
+        let spans = self
+            .highlights
+            .overlapping(line_start, line_end)
+            .map(|s| (s.from, s.to, s.ty))
+            .chain(
+                self.diagnostics
+                    .overlapping(line_start, line_end)
+                    .map(|d| (d.from, d.to, d.severity.token_type())),
+            );
+
+        for (from, to, ty) in spans {
+            let start = from.saturating_sub(line_start);
+            let end = if to > line_end {
+                usize::MAX
+            } else {
+                to - line_start
+            };
+            match self.raw_rline_unchecked(y, lpad, screen_cols, Some((start, end))) {
+                (_, Some((start, end))) if start < end => {
+                    tks = Tokens::Multi(tks).with_highlighted_dot(start, end, ty);
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(f) = search {
+            for lr in f.match_ranges(&self.txt.line(y).to_string()) {
+                match self.raw_rline_unchecked(y, lpad, screen_cols, Some(lr)) {
+                    (_, Some((start, end))) if start < end => {
+                        tks =
+                            Tokens::Multi(tks).with_highlighted_dot(start, end, TokenType::Search);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if let Some((start, end)) = dot_range {
+            tks = Tokens::Multi(tks).with_highlighted_dot(start, end, TokenType::Dot);
+        }
+
         match load_exec_range {
             Some((is_load, rng)) if !self.dot.contains_range(&rng) => {
                 if let Some(lr) = rng.line_range(y, self).map(map_line_range) {
@@ -686,6 +815,139 @@ impl Buffer {
         buf
     }
 
+    /// The most severe diagnostic starting on the given line, used to mark the line in the sign
+    /// column.
+    pub(crate) fn diagnostic_sign(&self, y: usize) -> Option<Severity> {
+        if self.diagnostics.is_empty() {
+            return None;
+        }
+        let line_start = self.txt.line_to_char(y);
+        let line_end = line_start + self.txt.line_len_chars(y);
+
+        self.diagnostics
+            .max_severity_starting_in(line_start, line_end)
+    }
+
+    pub(crate) fn apply_diagnostics(&mut self, cmds: Vec<DiagnosticCmd>) {
+        self.diagnostics.apply(cmds, &self.txt);
+    }
+
+    pub(crate) fn diagnostics_file_content(&self) -> String {
+        self.diagnostics.as_file_content(&self.txt)
+    }
+
+    /// Set dot to the next (or previous) diagnostic in the buffer, wrapping around the buffer,
+    /// returning its severity and message if there was one.
+    pub(crate) fn goto_next_diagnostic(&mut self, forward: bool) -> Option<String> {
+        let idx = self.dot.first_cur().idx;
+        let d = self.diagnostics.next_from(idx, forward)?;
+        let msg = format!("{}: {}", d.severity, d.message);
+        self.dot = Dot::from_char_indices(d.from, d.to.saturating_sub(1).max(d.from));
+
+        Some(msg)
+    }
+
+    /// A line per diagnostic in the `name:line:col: severity: message` form used by compilers so
+    /// that they can be loaded to jump to the diagnostic.
+    pub(crate) fn diagnostics_listing(&self) -> Vec<String> {
+        let name = self.full_name();
+
+        self.diagnostics
+            .locations(&self.txt)
+            .into_iter()
+            .map(|((line, col), d)| format!("{name}:{line}:{col}: {}: {}", d.severity, d.message))
+            .collect()
+    }
+
+    /// Start filling in the tabstops of a snippet that has been inserted at `offset`, selecting
+    /// the first of them.
+    pub(crate) fn start_tabstops(&mut self, stops: Vec<Vec<(usize, usize)>>, offset: usize) {
+        self.tabstops = Tabstops::new(stops, offset);
+        self.select_current_tabstop();
+    }
+
+    /// Move to the next (or previous) tabstop of the snippet being filled in, returning false
+    /// if there is no such snippet.
+    pub(crate) fn goto_tabstop(&mut self, forward: bool) -> bool {
+        if !self.tabstops.is_active() {
+            return false;
+        }
+
+        self.update_tabstop_mirrors();
+        self.tabstops.step(forward);
+        self.select_current_tabstop();
+
+        true
+    }
+
+    /// Update the mirrors of the current tabstop to match its content, ending the snippet if
+    /// dot has been moved outside of the tabstop.
+    pub(crate) fn sync_tabstops(&mut self) {
+        let (from, to) = match self.tabstops.current() {
+            Some(r) => r,
+            None => return,
+        };
+
+        let (start, end) = self.dot.as_char_indices();
+        if start < from || end > to {
+            self.tabstops.clear();
+        } else {
+            self.update_tabstop_mirrors();
+        }
+    }
+
+    fn select_current_tabstop(&mut self) {
+        let (from, to) = match self.tabstops.current() {
+            Some(r) => r,
+            None => return,
+        };
+
+        // A range covering a single character is treated as a cursor when editing so we only
+        // select tabstops that are longer than that.
+        self.dot = if to - from > 1 {
+            Dot::from_char_indices(from, to - 1)
+        } else {
+            Dot::Cur { c: Cur { idx: to } }
+        };
+
+        if self.tabstops.on_last() {
+            self.tabstops.clear();
+        }
+    }
+
+    fn update_tabstop_mirrors(&mut self) {
+        let (from, to) = match self.tabstops.current() {
+            Some(r) => r,
+            None => return,
+        };
+
+        let s = self.txt.slice(from, to).to_string();
+        let n = s.chars().count();
+        // Taken so that the edits made to the mirrors are not tracked as edits to the tabstops
+        let mut tabstops = std::mem::take(&mut self.tabstops);
+
+        let mut i = 1;
+        while let Some((mfrom, mto)) = tabstops.range(i) {
+            i += 1;
+            if self.txt.slice(mfrom, mto) == s {
+                continue;
+            }
+            if mfrom < mto {
+                let dot = Dot::from_char_indices(mfrom, mto - 1).collapse_null_range();
+                self.delete_dot(dot, Some(Source::Keyboard));
+            }
+            if !s.is_empty() {
+                let dot = Dot::Cur {
+                    c: Cur { idx: mfrom },
+                };
+                self.insert_string(dot, s.clone(), Some(Source::Keyboard));
+            }
+            tabstops.mirror_replaced(i - 1, n);
+        }
+
+        self.tabstops = tabstops;
+    }
+
     pub(crate) fn sign_col_dims(&self) -> (usize, usize) {
         let w_lnum = n_digits(self.len_lines());
         let w_sgncol = w_lnum + 2;
@@ -910,6 +1172,24 @@ impl Buffer {
 
     /// The error result of this function is an error string that should be displayed to the user
     pub(crate) fn handle_action(&mut self, a: Action, source: Source) -> Option<ActionOutcome> {
+        let modifies_text = matches!(
+            a,
+            Action::Delete
+                | Action::InsertChar { .. }
+                | Action::InsertString { .. }
+                | Action::Redo
+                | Action::Undo
+                | Action::RawInput {
+                    i: Input::Return | Input::Tab | Input::Char(_)
+                }
+        );
+
+        if self.read_only && modifies_text {
+            return Some(ActionOutcome::SetStatusMessage(
+                "Buffer is read-only".to_string(),
+            ));
+        }
+
         match a {
             Action::Delete => {
                 let (c, deleted) = self.delete_dot(self.dot, Some(source));
@@ -1025,6 +1305,16 @@ impl Buffer {
         self.dot.clamp_idx(self.txt.len_chars());
     }
 
+    /// The current position of the named mark if it has been set.
+    pub(crate) fn mark(&self, name: char) -> Option<Dot> {
+        self.marks.get(name)
+    }
+
+    /// Record the given dot under the named mark, replacing any previous value.
+    pub(crate) fn set_mark(&mut self, name: char, dot: Dot) {
+        self.marks.set(name, dot);
+    }
+
     pub(crate) fn new_edit_log_transaction(&mut self) {
         self.edit_log.new_transaction()
     }
@@ -1126,10 +1416,19 @@ impl Buffer {
 
         let idx = cur.idx;
         self.txt.insert_char(idx, ch);
+        self.marks.insert(idx, 1);
+        self.highlights.insert(idx, 1);
+        self.diagnostics.insert(idx, 1);
+        self.tabstops.insert(idx, 1);
 
         if let (Some(source), Some(f)) = (source, self.input_filter.as_ref()) {
             f.notify_insert(source, idx, idx + 1, &ch.to_string());
         }
+        self.pending_changes.push(Observed::Insert {
+            from: idx,
+            to: idx + 1,
+            txt: ch.to_string(),
+        });
 
         self.edit_log.insert_char(cur, ch);
         self.mark_dirty();
@@ -1156,10 +1455,19 @@ impl Buffer {
             let idx = cur.idx;
             let len = s.chars().count();
             self.txt.insert_str(idx, &s);
+            self.marks.insert(idx, len);
+            self.highlights.insert(idx, len);
+            self.diagnostics.insert(idx, len);
+            self.tabstops.insert(idx, len);
 
             if let (Some(source), Some(f)) = (source, self.input_filter.as_ref()) {
                 f.notify_insert(source, idx, idx + len, &s);
             }
+            self.pending_changes.push(Observed::Insert {
+                from: idx,
+                to: idx + len,
+                txt: s.clone(),
+            });
 
             self.edit_log.insert_string(cur, s);
             cur.idx += len;
@@ -1184,10 +1492,18 @@ impl Buffer {
         if idx < self.txt.len_chars() {
             let ch = self.txt.char(idx);
             self.txt.remove_char(idx);
+            self.marks.delete(idx, idx + 1);
+            self.highlights.delete(idx, idx + 1);
+            self.diagnostics.delete(idx, idx + 1);
+            self.tabstops.delete(idx, idx + 1);
 
             if let (Some(source), Some(f)) = (source, self.input_filter.as_ref()) {
                 f.notify_delete(source, idx, idx + 1);
             }
+            self.pending_changes.push(Observed::Delete {
+                from: idx,
+                to: idx + 1,
+            });
 
             self.edit_log.delete_char(cur, ch);
             self.mark_dirty();
@@ -1205,10 +1521,15 @@ impl Buffer {
 
         let s = self.txt.slice(from, to).to_string();
         self.txt.remove_range(from, to);
+        self.marks.delete(from, to);
+        self.highlights.delete(from, to);
+        self.diagnostics.delete(from, to);
+        self.tabstops.delete(from, to);
 
         if let (Some(source), Some(f)) = (source, self.input_filter.as_ref()) {
             f.notify_delete(source, from, to);
         }
+        self.pending_changes.push(Observed::Delete { from, to });
 
         self.edit_log.delete_string(r.start, s.clone());
         self.mark_dirty();
@@ -1221,6 +1542,25 @@ impl Buffer {
             self.dot = dot;
         }
     }
+
+    /// Set dot to the next match of `f` after the current dot (or the previous match before it
+    /// if `forward` is false), wrapping around the buffer if needed. Returns `false` and leaves
+    /// dot unchanged if there are no matches.
+    pub(crate) fn find_next_regex_match(&mut self, f: &RegexFind, forward: bool) -> bool {
+        let prev = self.dot;
+        let found = if forward {
+            // Step past the current dot so that we don't re-find an existing match
+            let idx = min(self.dot.last_cur().idx + 1, self.txt.len_chars());
+            self.dot = Cur { idx }.into();
+            find_forward_wrapping(f, self)
+        } else {
+            find_backward_wrapping(f, self)
+        };
+
+        self.dot = found.unwrap_or(prev);
+
+        found.is_some()
+    }
 }
 
 fn n_digits(mut n: usize) -> usize {
@@ -1451,6 +1791,72 @@ pub(crate) mod tests {
         Cur { idx }
     }
 
+    #[test]
+    fn goto_next_diagnostic_follows_edits() {
+        let mut b = Buffer::new_virtual(0, "test", "let x = 1;\nlet y = 2;\n");
+        let cmds = parse_diagnostics("2:5-2:6 warning unused y\n1:5-1:6 error bad x").unwrap();
+        b.apply_diagnostics(cmds);
+        b.handle_action(
+            Action::InsertString {
+                s: "// ".to_string(),
+            },
+            Source::Keyboard,
+        );
+
+        let msg = b.goto_next_diagnostic(true);
+        assert_eq!(msg.as_deref(), Some("error: bad x"));
+        assert_eq!(b.dot, Dot::from_char_indices(7, 7));
+        assert_eq!(b.diagnostic_sign(0), Some(Severity::Error));
+
+        let msg = b.goto_next_diagnostic(true);
+        assert_eq!(msg.as_deref(), Some("warning: unused y"));
+        assert_eq!(b.dot, Dot::from_char_indices(18, 18));
+        assert_eq!(
+            b.diagnostics_listing(),
+            vec![
+                "test:1:8: error: bad x".to_string(),
+                "test:2:5: warning: unused y".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_are_recorded_for_observers() {
+        let mut b = Buffer::new_unnamed(0, "foo bar");
+        b.handle_action(
+            Action::InsertString {
+                s: "a\nb".to_string(),
+            },
+            Source::Keyboard,
+        );
+        b.set_dot(TextObject::Arr(Arrow::Left), 1);
+        b.handle_action(Action::Delete, Source::Keyboard);
+        b.handle_action(Action::Undo, Source::Keyboard);
+        b.set_dot(TextObject::BufferEnd, 1);
+
+        let changes = b.pending_changes.take(b.dot);
+
+        assert_eq!(
+            changes,
+            vec![
+                Observed::Insert {
+                    from: 0,
+                    to: 3,
+                    txt: "a\nb".to_string()
+                },
+                Observed::Delete { from: 2, to: 3 },
+                Observed::Insert {
+                    from: 2,
+                    to: 3,
+                    txt: "b".to_string()
+                },
+                Observed::Delete { from: 0, to: 3 },
+                Observed::Dot { from: 7, to: 7 },
+            ]
+        );
+        assert!(b.pending_changes.take(b.dot).is_empty());
+    }
+
     #[test]
     fn undo_string_insert_works() {
         let initial_content = "foo foo foo\n";
@@ -1580,6 +1986,25 @@ pub(crate) mod tests {
         assert_eq!(b.str_contents(), format!("{expected}\n"));
     }
 
+    #[test]
+    fn find_next_regex_match_steps_past_the_current_match() {
+        let mut b = Buffer::new_virtual(0, "test", "x aa x aa");
+        let f = RegexFind::try_new("a+", crate::regex::Syntax::Sam).unwrap();
+        let mut matches = vec![];
+
+        for forward in [true, true, true, false] {
+            assert!(b.find_next_regex_match(&f, forward));
+            matches.push(b.dot);
+        }
+
+        let (first, second) = (Dot::from_char_indices(2, 3), Dot::from_char_indices(7, 8));
+        assert_eq!(matches, vec![first, second, first, second]);
+
+        let f = RegexFind::try_new("c", crate::regex::Syntax::Sam).unwrap();
+        assert!(!b.find_next_regex_match(&f, true));
+        assert_eq!(b.dot, second);
+    }
+
     // NOTE: there was a bug around misunderstanding terminal "cells" in relation to
     //       wide unicode characters
     //       - https://github.com/crossterm-rs/crossterm/issues/458
@@ -0,0 +1,163 @@
+//! The tabstops of a snippet that is being filled in within a buffer.
+//!
+//! Each tabstop is a list of half open ranges of characters where the first is the text being
+//! edited and the rest are mirrors that are updated to match it. As with marks, ranges are
+//! updated as text is inserted and deleted so that they continue to cover the same text, with
+//! text inserted at either end of the current tabstop being treated as part of it.
+use super::highlights::shift_for_delete;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Tabstops {
+    stops: Vec<Vec<(usize, usize)>>,
+    current: usize,
+}
+
+impl Tabstops {
+    /// Start visiting the given tabstops, offsetting their ranges by `offset`.
+    pub(crate) fn new(stops: Vec<Vec<(usize, usize)>>, offset: usize) -> Self {
+        let stops = stops
+            .into_iter()
+            .map(|ranges| {
+                ranges
+                    .into_iter()
+                    .map(|(from, to)| (from + offset, to + offset))
+                    .collect()
+            })
+            .collect();
+
+        Self { stops, current: 0 }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        !self.stops.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.stops.clear();
+        self.current = 0;
+    }
+
+    /// The range of the tabstop currently being edited.
+    pub(crate) fn current(&self) -> Option<(usize, usize)> {
+        self.stops.get(self.current).map(|ranges| ranges[0])
+    }
+
+    /// Whether or not the current tabstop is the final one for the snippet.
+    pub(crate) fn on_last(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    /// Move to the next (or previous) tabstop, staying on the first tabstop if there is no
+    /// previous one.
+    pub(crate) fn step(&mut self, forward: bool) -> Option<(usize, usize)> {
+        if forward && !self.on_last() {
+            self.current += 1;
+        } else if !forward {
+            self.current = self.current.saturating_sub(1);
+        }
+
+        self.current()
+    }
+
+    /// The `i`th range of the current tabstop, where any range after the first is a mirror.
+    pub(crate) fn range(&self, i: usize) -> Option<(usize, usize)> {
+        self.stops.get(self.current)?.get(i).copied()
+    }
+
+    /// Update ranges to account for mirror `i` of the current tabstop having its content
+    /// replaced with `n` characters. Mirrors always follow the range being edited so anything
+    /// preceding them within the current tabstop is left where it is.
+    pub(crate) fn mirror_replaced(&mut self, i: usize, n: usize) {
+        let (from, to) = self.stops[self.current][i];
+        let shift = |idx: usize| idx + n - (to - from);
+
+        for (j, ranges) in self.stops.iter_mut().enumerate() {
+            for (k, r) in ranges.iter_mut().enumerate() {
+                if j == self.current && k <= i {
+                    continue;
+                }
+                if r.0 >= to {
+                    *r = (shift(r.0), shift(r.1));
+                } else if r.1 > to {
+                    r.1 = shift(r.1);
+                }
+            }
+        }
+
+        self.stops[self.current][i] = (from, from + n);
+    }
+
+    /// Shift ranges to account for `n` characters being inserted at `idx`.
+    pub(crate) fn insert(&mut self, idx: usize, n: usize) {
+        for (j, ranges) in self.stops.iter_mut().enumerate() {
+            for (k, r) in ranges.iter_mut().enumerate() {
+                let is_current = j == self.current && k == 0;
+                if r.0 >= idx && !(is_current && r.0 == idx) {
+                    *r = (r.0 + n, r.1 + n);
+                } else if r.1 > idx || (is_current && r.1 == idx) {
+                    r.1 += n;
+                }
+            }
+        }
+    }
+
+    /// Shift ranges to account for the characters `from..to` being deleted.
+    pub(crate) fn delete(&mut self, from: usize, to: usize) {
+        for r in self.stops.iter_mut().flatten() {
+            *r = shift_for_delete(r.0, r.1, from, to);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    fn tabstops() -> Tabstops {
+        // "<div></div>" from "<${1:div}>$0</$1>" expanded at 10
+        Tabstops::new(vec![vec![(1, 4), (7, 10)], vec![(5, 5)]], 10)
+    }
+
+    #[test_case(11, 2, vec![vec![(11, 16), (19, 22)], vec![(17, 17)]]; "start of current")]
+    #[test_case(14, 2, vec![vec![(11, 16), (19, 22)], vec![(17, 17)]]; "end of current")]
+    #[test_case(10, 2, vec![vec![(13, 16), (19, 22)], vec![(17, 17)]]; "before snippet")]
+    #[test_case(15, 2, vec![vec![(11, 14), (19, 22)], vec![(17, 17)]]; "at other tabstop")]
+    #[test_case(30, 2, vec![vec![(11, 14), (17, 20)], vec![(15, 15)]]; "after snippet")]
+    #[test]
+    fn insert_works(idx: usize, n: usize, expected: Vec<Vec<(usize, usize)>>) {
+        let mut t = tabstops();
+        t.insert(idx, n);
+
+        assert_eq!(t.stops, expected);
+    }
+
+    #[test]
+    fn delete_works() {
+        let mut t = tabstops();
+        t.delete(11, 14);
+
+        assert_eq!(t.stops, vec![vec![(11, 11), (14, 17)], vec![(12, 12)]]);
+    }
+
+    #[test]
+    fn mirror_replaced_works() {
+        let mut t = tabstops();
+        t.delete(11, 14);
+        t.insert(11, 1);
+        t.mirror_replaced(1, 1);
+
+        assert_eq!(t.stops, vec![vec![(11, 12), (15, 16)], vec![(13, 13)]]);
+    }
+
+    #[test]
+    fn step_stays_within_the_tabstops() {
+        let mut t = tabstops();
+
+        assert_eq!(t.step(false), Some((11, 14)));
+        assert!(!t.on_last());
+        assert_eq!(t.step(true), Some((15, 15)));
+        assert!(t.on_last());
+        assert_eq!(t.step(true), Some((15, 15)));
+    }
+}
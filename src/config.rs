@@ -1,6 +1,6 @@
 //! A minimal config file format for ad
-use crate::{key::Input, mode::normal_mode, term::Color};
-use std::{collections::BTreeMap, env, fs, io};
+use crate::{key::Input, mode::normal_mode, regex::Syntax, term::Color};
+use std::{collections::BTreeMap, env, fs, io, path::Path};
 
 /// Editor level configuration
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -12,9 +12,14 @@ pub struct Config {
     pub(crate) status_timeout: u64,
     pub(crate) double_click_ms: u128,
     pub(crate) minibuffer_lines: usize,
+    /// The Normal mode key used to repeat the last edit
+    pub(crate) repeat_key: char,
     pub(crate) find_command: String,
+    pub(crate) completion_command: String,
+    pub(crate) regex_syntax: Syntax,
     pub(crate) colorscheme: ColorScheme,
     pub(crate) bindings: BTreeMap<Vec<Input>, String>,
+    pub(crate) lsp_servers: Vec<LspServerConfig>,
 }
 
 impl Default for Config {
@@ -27,13 +32,27 @@ impl Default for Config {
             status_timeout: 3,
             double_click_ms: 200,
             minibuffer_lines: 8,
+            repeat_key: '.',
             find_command: "fd -t f".to_string(),
+            completion_command: String::new(),
+            regex_syntax: Syntax::Sam,
             colorscheme: ColorScheme::default(),
             bindings: BTreeMap::new(),
+            lsp_servers: Vec::new(),
         }
     }
 }
 
+/// A language server to run for files with one of the given extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LspServerConfig {
+    /// The language identifier sent to the server when opening files
+    pub(crate) lang: String,
+    pub(crate) extensions: Vec<String>,
+    /// The command (and arguments) used to start the server
+    pub(crate) command: String,
+}
+
 /// A colorscheme for the terminal UI
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColorScheme {
@@ -43,6 +62,7 @@ pub struct ColorScheme {
     pub(crate) dot_bg: Color,
     pub(crate) load_bg: Color,
     pub(crate) exec_bg: Color,
+    pub(crate) search_bg: Color,
     pub(crate) bar_bg: Color,
     pub(crate) signcol_fg: Color,
     pub(crate) minibuffer_hl: Color,
@@ -53,6 +73,10 @@ pub struct ColorScheme {
     pub(crate) definition: Color,
     pub(crate) punctuation: Color,
     pub(crate) string: Color,
+    // diagnostics
+    pub(crate) error: Color,
+    pub(crate) warning: Color,
+    pub(crate) info: Color,
 }
 
 impl Default for ColorScheme {
@@ -64,6 +88,7 @@ impl Default for ColorScheme {
             dot_bg: "#336677".try_into().unwrap(),
             load_bg: "#957FB8".try_into().unwrap(),
             exec_bg: "#Bf616A".try_into().unwrap(),
+            search_bg: "#625372".try_into().unwrap(),
             bar_bg: "#4E415C".try_into().unwrap(),
             signcol_fg: "#544863".try_into().unwrap(),
             minibuffer_hl: "#3E3549".try_into().unwrap(),
@@ -74,6 +99,10 @@ impl Default for ColorScheme {
             definition: "#957FB8".try_into().unwrap(),
             punctuation: "#DCA561".try_into().unwrap(),
             string: "#61DCA5".try_into().unwrap(),
+            // diagnostics
+            error: "#E82424".try_into().unwrap(),
+            warning: "#FF9E3B".try_into().unwrap(),
+            info: "#7FB4CA".try_into().unwrap(),
         }
     }
 }
@@ -111,23 +140,23 @@ impl Config {
                 continue;
             }
 
-            match line.strip_prefix("set ") {
-                Some(line) => self.try_set_prop(line)?,
-                None => match line.strip_prefix("map ") {
-                    Some(line) => self.try_add_mapping(line)?,
-                    None => {
-                        return Err(format!(
-                            "'{line}' should be 'set prop=val' or 'map ... => prog'"
-                        ))
-                    }
-                },
+            if let Some(line) = line.strip_prefix("set ") {
+                self.try_set_prop(line)?;
+            } else if let Some(line) = line.strip_prefix("map ") {
+                self.try_add_mapping(line)?;
+            } else if let Some(line) = line.strip_prefix("lsp ") {
+                self.try_add_lsp_server(line)?;
+            } else {
+                return Err(format!(
+                    "'{line}' should be 'set prop=val', 'map ... => prog' or 'lsp lang ext... => cmd'"
+                ));
             }
         }
 
         if !self.bindings.is_empty() {
             // Make sure that none of the user provided bindings clash with Normal mode
             // bindings as that will mean they never get run
-            let nm = normal_mode();
+            let nm = normal_mode(self.repeat_key);
             for keys in self.bindings.keys() {
                 if nm.keymap.contains_key_or_prefix(keys) {
                     let mut s = String::new();
@@ -153,6 +182,9 @@ impl Config {
         match prop {
             // Strings
             "find-command" => self.find_command = val.trim().to_string(),
+            "completion-command" => self.completion_command = val.trim().to_string(),
+            "regex-syntax" => self.regex_syntax = val.trim().parse()?,
+            "repeat-key" => self.repeat_key = parse_repeat_key(prop, val)?,
 
             // Numbers
             "tabstop" => self.tabstop = parse_usize(prop, val)?,
@@ -171,6 +203,7 @@ impl Config {
             "dot-bg-color" => self.colorscheme.dot_bg = parse_color(prop, val)?,
             "load-bg-color" => self.colorscheme.load_bg = parse_color(prop, val)?,
             "exec-bg-color" => self.colorscheme.exec_bg = parse_color(prop, val)?,
+            "search-bg-color" => self.colorscheme.search_bg = parse_color(prop, val)?,
             "bar-bg-color" => self.colorscheme.bar_bg = parse_color(prop, val)?,
             "signcol-fg-color" => self.colorscheme.signcol_fg = parse_color(prop, val)?,
             "minibuffer-hl-color" => self.colorscheme.minibuffer_hl = parse_color(prop, val)?,
@@ -180,6 +213,9 @@ impl Config {
             "definition-color" => self.colorscheme.definition = parse_color(prop, val)?,
             "punctuation-color" => self.colorscheme.punctuation = parse_color(prop, val)?,
             "string-color" => self.colorscheme.string = parse_color(prop, val)?,
+            "error-color" => self.colorscheme.error = parse_color(prop, val)?,
+            "warning-color" => self.colorscheme.warning = parse_color(prop, val)?,
+            "info-color" => self.colorscheme.info = parse_color(prop, val)?,
 
             _ => return Err(format!("'{prop}' is not a known config property")),
         }
@@ -217,6 +253,51 @@ impl Config {
     }
 }
 
+impl Config {
+    pub(crate) fn try_add_lsp_server(&mut self, input: &str) -> Result<(), String> {
+        let (lhs, command) = input
+            .split_once("=>")
+            .ok_or_else(|| format!("'{input}' is not a 'lsp lang ext... => cmd' statement"))?;
+
+        let mut fields = lhs.split_whitespace();
+        let lang = fields
+            .next()
+            .ok_or_else(|| format!("no language provided for '{input}'"))?
+            .to_string();
+        let extensions: Vec<String> = fields
+            .map(|ext| ext.trim_start_matches('.').into())
+            .collect();
+        let command = command.trim().to_string();
+
+        if extensions.is_empty() {
+            return Err(format!("no file extensions provided for '{lang}'"));
+        } else if command.is_empty() {
+            return Err(format!("no command provided for '{lang}'"));
+        }
+
+        self.lsp_servers.retain(|s| s.lang != lang);
+        self.lsp_servers.push(LspServerConfig {
+            lang,
+            extensions,
+            command,
+        });
+
+        Ok(())
+    }
+}
+
+/// The language server configured for files at the given path, if there is one.
+pub(crate) fn lsp_server_for_path<'a>(
+    servers: &'a [LspServerConfig],
+    path: &Path,
+) -> Option<&'a LspServerConfig> {
+    let ext = path.extension()?.to_str()?;
+
+    servers
+        .iter()
+        .find(|s| s.extensions.iter().any(|e| e == ext))
+}
+
 fn parse_usize(prop: &str, val: &str) -> Result<usize, String> {
     match val.parse() {
         Ok(num) => Ok(num),
@@ -234,6 +315,30 @@ fn parse_bool(prop: &str, val: &str) -> Result<bool, String> {
     }
 }
 
+/// The repeat key replaces whatever would otherwise be bound to it in Normal mode (other than
+/// '.' which moves to Alt-.) so it needs to be a key that is free for us to use.
+fn parse_repeat_key(prop: &str, val: &str) -> Result<char, String> {
+    let mut chars = val.trim().chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => {
+            return Err(format!(
+                "expected a single character for '{prop}' but found '{val}'"
+            ))
+        }
+    };
+
+    if c != '.'
+        && normal_mode('.')
+            .keymap
+            .contains_key_or_prefix(&[Input::Char(c)])
+    {
+        return Err(format!("{prop} '{c}' collides with a Normal mode mapping"));
+    }
+
+    Ok(c)
+}
+
 fn parse_color(prop: &str, val: &str) -> Result<Color, String> {
     Color::try_from(val)
         .map_err(|_| format!("expected #RRGGBB string for '{prop}' but found '{val}'"))
@@ -255,6 +360,9 @@ set expand-tab=false
 set match-indent=false
 
 map G G => my-prog
+
+lsp rust rs => rust-analyzer
+lsp python py pyi => pylsp --verbose
 ";
 
     // This should be our default so we are just verifying that we have not diverged from
@@ -292,9 +400,53 @@ map G G => my-prog
             )]
             .into_iter()
             .collect(),
+            lsp_servers: vec![
+                LspServerConfig {
+                    lang: "rust".to_string(),
+                    extensions: vec!["rs".to_string()],
+                    command: "rust-analyzer".to_string(),
+                },
+                LspServerConfig {
+                    lang: "python".to_string(),
+                    extensions: vec!["py".to_string(), "pyi".to_string()],
+                    command: "pylsp --verbose".to_string(),
+                },
+            ],
             ..Default::default()
         };
 
         assert_eq!(cfg, expected);
     }
+
+    #[test]
+    fn repeat_key_can_be_changed() {
+        let err = Config::parse("set repeat-key=,").unwrap_err();
+        assert_eq!(err, "repeat-key ',' collides with a Normal mode mapping");
+        assert!(Config::parse("set repeat-key=ab").is_err());
+        assert!(Config::parse("set repeat-key=Z\nmap Z => prog").is_err());
+
+        let cfg = Config::parse("set repeat-key=Z").unwrap();
+        assert_eq!(cfg.repeat_key, 'Z');
+    }
+
+    #[test]
+    fn invalid_lsp_servers_are_rejected() {
+        let mut cfg = Config::default();
+
+        assert!(cfg.try_add_lsp_server("rust rs").is_err());
+        assert!(cfg.try_add_lsp_server("rust => rust-analyzer").is_err());
+        assert!(cfg.try_add_lsp_server("rust rs =>").is_err());
+    }
+
+    #[test]
+    fn lsp_server_for_path_works() {
+        let cfg = Config::parse(CUSTOM_CONFIG).unwrap();
+        let lang =
+            |p: &str| lsp_server_for_path(&cfg.lsp_servers, Path::new(p)).map(|s| s.lang.as_str());
+
+        assert_eq!(lang("src/main.rs"), Some("rust"));
+        assert_eq!(lang("stubs/foo.pyi"), Some("python"));
+        assert_eq!(lang("README.md"), None);
+        assert_eq!(lang("Makefile"), None);
+    }
 }
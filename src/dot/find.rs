@@ -5,6 +5,7 @@ use crate::{
     buffer::Buffer,
     dot::{Cur, Dot, Range},
     exec::IterBoundedChars,
+    regex::{self, Regex, Syntax},
 };
 
 /// A Find is able to locate its next occurance within an indexed character stream and return
@@ -64,10 +65,10 @@ pub fn find_backward_start<F: Find>(f: &F, cur: Cur, b: &Buffer) -> Cur {
     find_backward(f, cur, b).unwrap_or_default().first_cur()
 }
 
-// pub fn find_backward_wrapping<F: Find>(f: &F, b: &Buffer) -> Option<Dot> {
-//     rev_find_between(f, b.dot.first_cur().idx, 0, b)
-//         .or_else(|| rev_find_between(f, b.txt.len_chars(), b.dot.first_cur().idx, b))
-// }
+pub fn find_backward_wrapping<F: Find>(f: &F, b: &Buffer) -> Option<Dot> {
+    rev_find_between(f, b.dot.first_cur().idx, 0, b)
+        .or_else(|| rev_find_between(f, b.txt.len_chars(), b.dot.first_cur().idx, b))
+}
 
 fn match_to_dot(m: Option<(usize, usize)>) -> Option<Dot> {
     match m {
@@ -130,7 +131,7 @@ impl Find for char {
 }
 
 // Strings need to locate themselves character by character.
-impl<'a> Find for &'a str {
+impl Find for &str {
     type Reversed = String;
 
     fn try_find<I>(&self, it: I) -> Option<(usize, usize)>
@@ -184,6 +185,62 @@ impl Find for String {
     }
 }
 
+/// A [Regex] paired with its reversed form so that it can be used to search in either direction
+/// through a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexFind {
+    fwd: Regex,
+    rev: Regex,
+    reversed: bool,
+}
+
+impl RegexFind {
+    /// Compile the forward and reverse forms of the given regex.
+    pub fn try_new(re: &str, syntax: Syntax) -> Result<Self, regex::Error> {
+        Ok(Self {
+            fwd: Regex::compile_with_syntax(re, syntax)?,
+            rev: Regex::compile_reverse_with_syntax(re, syntax)?,
+            reversed: false,
+        })
+    }
+
+    /// The character ranges of all non-empty matches within the given string.
+    pub fn match_ranges(&mut self, s: &str) -> Vec<(usize, usize)> {
+        self.fwd
+            .match_str_all(s)
+            .map(|m| m.loc())
+            .filter(|(start, end)| start < end)
+            .collect()
+    }
+}
+
+// Regex matches are half open so need converting to the inclusive ranges used by Find.
+// Empty matches are returned as a single cursor position.
+impl Find for RegexFind {
+    type Reversed = RegexFind;
+
+    fn try_find<I>(&self, it: I) -> Option<(usize, usize)>
+    where
+        I: Iterator<Item = (usize, char)>,
+    {
+        let mut it = it.peekable();
+        let (i, _) = *it.peek()?;
+        let sp = if self.reversed { i + 1 } else { i };
+        let m = self.fwd.clone().match_iter(&mut it, sp)?;
+        let (start, end) = m.loc();
+
+        Some((start, end.saturating_sub(1).max(start)))
+    }
+
+    fn reversed(&self) -> Self::Reversed {
+        Self {
+            fwd: self.rev.clone(),
+            rev: self.fwd.clone(),
+            reversed: !self.reversed,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,4 +259,25 @@ mod tests {
 
         assert_eq!(matched_text, s);
     }
+
+    #[test_case(0, true, "fo"; "forward from start")]
+    #[test_case(1, true, "fooo"; "forward")]
+    #[test_case(4, true, "fo+"; "forward from within match")]
+    #[test_case(16, true, "fo"; "forward wrapping")]
+    #[test_case(16, false, "fo+"; "backward")]
+    #[test_case(0, false, "fo+"; "backward wrapping")]
+    #[test]
+    fn find_regex_wrapping(idx: usize, forward: bool, expected: &str) {
+        let mut b = Buffer::new_virtual(0, "test", "fo fooo bar fo+ baz");
+        b.dot = Cur { idx }.into();
+        let f = RegexFind::try_new("fo+\\+?", Syntax::Sam).unwrap();
+        let dot = if forward {
+            find_forward_wrapping(&f, &b)
+        } else {
+            find_backward_wrapping(&f, &b)
+        }
+        .expect("to find a match");
+
+        assert_eq!(dot.content(&b), expected);
+    }
 }
@@ -1,14 +1,18 @@
 //! Editor actions in response to user input
 use crate::{
-    buffer::BufferKind,
+    buffer::{ActionOutcome, BufferKind},
     config::Config,
     config_handle, die,
     dot::{Cur, Dot, Range, TextObject},
-    editor::{Editor, MiniBufferSelection},
+    editor::{
+        commands::{parse_buffer_ctl, BufferCtl},
+        replace::ReplaceHit,
+        Editor, MiniBufferSelection,
+    },
     exec::{Addr, Address, Program},
     fsys::LogEvent,
     key::Input,
-    mode::Mode,
+    mode::{normal_mode, Mode},
     plumb::{MatchOutcome, PlumbingMessage},
     replace_config,
     system::System,
@@ -45,56 +49,159 @@ pub enum ViewPort {
 /// Supported actions for interacting with the editor state
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
-    AppendToOutputBuffer { bufid: usize, content: String },
-    ChangeDirectory { path: Option<String> },
+    AppendToOutputBuffer {
+        bufid: usize,
+        content: String,
+    },
+    AppendToReplaceBuffer {
+        gen: usize,
+        name: String,
+        hits: Vec<ReplaceHit>,
+    },
+    AppendToSearchBuffer {
+        gen: usize,
+        content: String,
+    },
+    ChangeDirectory {
+        path: Option<String>,
+    },
+    ClearSearchHighlight,
     CommandMode,
+    Complete,
     Delete,
-    DeleteBuffer { force: bool },
+    DeleteBuffer {
+        force: bool,
+    },
+    DiagnosticNext,
+    DiagnosticPrevious,
     DotCollapseFirst,
     DotCollapseLast,
     DotExtendBackward(TextObject, usize),
     DotExtendForward(TextObject, usize),
     DotFlip,
     DotSet(TextObject, usize),
-    EditCommand { cmd: String },
+    EditCommand {
+        cmd: String,
+    },
     ExecuteDot,
-    Exit { force: bool },
+    Exit {
+        force: bool,
+    },
     ExpandDot,
     FindFile,
     FindRepoFile,
-    FocusBuffer { id: usize },
-    InsertChar { c: char },
-    InsertString { s: String },
+    FocusBuffer {
+        id: usize,
+    },
+    Grep {
+        pattern: String,
+    },
+    GrepRefresh,
+    InsertChar {
+        c: char,
+    },
+    InsertString {
+        s: String,
+    },
     JumpListForward,
     JumpListBack,
+    JumpToMark {
+        name: char,
+    },
+    ListDiagnostics,
     LoadDot,
-    MarkClean { bufid: usize },
+    LspDefinition,
+    LspHover,
+    LspReferences,
+    LspRename {
+        name: Option<String>,
+    },
+    LspStart,
+    LspStop,
+    MacroEdit,
+    MacroLoad {
+        reg: char,
+        name: String,
+    },
+    MacroPlay {
+        reg: char,
+    },
+    MacroRecord {
+        reg: char,
+    },
+    MacroSave {
+        reg: char,
+        name: String,
+    },
+    MacrosApply,
+    MarkClean {
+        bufid: usize,
+    },
     NewEditLogTransaction,
     NextBuffer,
-    OpenFile { path: String },
+    OpenFile {
+        path: String,
+    },
     Paste,
     PreviousBuffer,
-    RawInput { i: Input },
+    RawInput {
+        i: Input,
+    },
     Redo,
     ReloadActiveBuffer,
-    ReloadBuffer { id: usize },
+    ReloadBuffer {
+        id: usize,
+    },
     ReloadConfig,
+    Repeat,
+    ReplaceInFiles {
+        input: String,
+    },
+    ReplaceInFilesApply,
     RunMode,
     SamMode,
-    SaveBuffer { force: bool },
-    SaveBufferAs { path: String, force: bool },
+    SaveBuffer {
+        force: bool,
+    },
+    SaveBufferAs {
+        path: String,
+        force: bool,
+    },
+    SearchIncremental,
     SearchInCurrentBuffer,
+    SearchNext,
+    SearchPrevious,
     SelectBuffer,
+    SelectSnippet,
     SetViewPort(ViewPort),
-    SetMode { m: &'static str },
-    SetStatusMessage { message: String },
-    ShellPipe { cmd: String },
-    ShellReplace { cmd: String },
-    ShellRun { cmd: String },
-    ShellSend { cmd: String },
+    SnippetNext,
+    SnippetPrevious,
+    SetMark {
+        name: char,
+    },
+    SetMode {
+        m: &'static str,
+    },
+    SetStatusMessage {
+        message: String,
+    },
+    ShellPipe {
+        cmd: String,
+    },
+    ShellReplace {
+        cmd: String,
+    },
+    ShellRun {
+        cmd: String,
+    },
+    ShellSend {
+        cmd: String,
+    },
     ShowHelp,
     Undo,
-    UpdateConfig { input: String },
+    UpdateConfig {
+        input: String,
+    },
     ViewLogs,
     Yank,
 
@@ -315,6 +422,8 @@ where
         let msg = match Config::try_load() {
             Ok(config) => {
                 replace_config(config);
+                self.refresh_normal_mode();
+                self.snippets.clear();
                 "config reloaded".to_string()
             }
             Err(s) => s,
@@ -334,6 +443,15 @@ where
         if let Err(msg) = update_config(input) {
             self.set_status_message(&msg);
         }
+        self.refresh_normal_mode();
+    }
+
+    /// Rebuild NORMAL mode so that it picks up any change to the repeat key.
+    fn refresh_normal_mode(&mut self) {
+        let repeat_key = config_handle!().repeat_key;
+        if let Some(m) = self.modes.iter_mut().find(|m| m.name == "NORMAL") {
+            *m = normal_mode(repeat_key);
+        }
     }
 
     pub(super) fn set_cursor_shape_for_active_mode(&mut self) {
@@ -534,7 +652,8 @@ where
             _ => {
                 self.open_file(data);
                 if let Some(s) = attrs.get("addr") {
-                    match Addr::parse(&mut s.chars().peekable()) {
+                    let syntax = config_handle!().regex_syntax;
+                    match Addr::parse(&mut s.chars().peekable(), syntax) {
                         Ok(mut addr) => {
                             let b = self.buffers.active_mut();
                             b.dot = b.map_addr(&mut addr);
@@ -556,7 +675,10 @@ where
             Some(idx) => {
                 let (s, addr) = s.split_at(idx);
                 let (_, addr) = addr.split_at(1);
-                match Addr::parse(&mut addr.chars().peekable()) {
+                // Allow for the trailing colon used in grep style "file:line:col: text" output
+                let addr = addr.strip_suffix(':').unwrap_or(addr);
+                let syntax = config_handle!().regex_syntax;
+                match Addr::parse(&mut addr.chars().peekable(), syntax) {
                     Ok(expr) => (s, Some(expr)),
                     Err(_) => (s, None),
                 }
@@ -637,14 +759,21 @@ where
 
     pub(super) fn execute_edit_command(&mut self, cmd: &str) {
         debug!(%cmd, "executing edit command");
-        let mut prog = match Program::try_parse(cmd) {
-            Ok(prog) => prog,
+        let syntax = config_handle!().regex_syntax;
+        match Program::try_parse_with_syntax(cmd, syntax) {
+            Ok(prog) => self.run_edit_program(prog),
             Err(error) => {
                 warn!(?error, "invalid edit command");
-                self.set_status_message(&format!("Invalid edit command: {error:?}"));
-                return;
+                self.set_status_message(&format!("Invalid edit command: {error}"));
             }
-        };
+        }
+    }
+
+    fn run_edit_program(&mut self, mut prog: Program) {
+        if self.buffers.active().read_only && prog.modifies_text() {
+            self.set_status_message("Buffer is read-only");
+            return;
+        }
 
         let mut buf = Vec::new();
         let fname = self.buffers.active().full_name().to_string();
@@ -654,7 +783,7 @@ where
                 self.buffers.active_mut().dot = new_dot;
             }
 
-            Err(e) => self.set_status_message(&format!("Error running edit command: {e:?}")),
+            Err(e) => self.set_status_message(&format!("Error running edit command: {e}")),
         }
 
         if !buf.is_empty() {
@@ -664,6 +793,131 @@ where
         }
     }
 
+    /// Apply a command written to the ctl file of the given buffer without changing focus.
+    pub(super) fn handle_buffer_ctl(&mut self, id: usize, msg: &str) -> Result<(), String> {
+        use BufferCtl::*;
+
+        // Clients send a zero length write to mark the end of their data
+        if msg.trim().is_empty() {
+            return Ok(());
+        }
+
+        let cmd = parse_buffer_ctl(msg)?;
+        let (screen_rows, screen_cols) = (self.screen_rows, self.screen_cols);
+        let b = match self.buffers.with_id_mut(id) {
+            Some(b) => b,
+            None => {
+                _ = self.tx_fsys.send(LogEvent::Close(id));
+                return Err("unknown buffer".to_string());
+            }
+        };
+
+        match cmd {
+            Clean => b.dirty = false,
+            Dirty => b.dirty = true,
+            ReadOnly(read_only) => b.read_only = read_only,
+            Show => b.set_view_port(ViewPort::Center, screen_rows, screen_cols),
+
+            Scroll(n) => {
+                // Scrolling by more than the number of lines in the buffer has no further effect
+                let steps = n.unsigned_abs().min(b.txt.len_lines());
+                if n < 0 {
+                    (0..steps).for_each(|_| b.scroll_up(screen_rows));
+                } else {
+                    (0..steps).for_each(|_| b.scroll_down());
+                }
+            }
+
+            Undo | Redo => {
+                let a = if cmd == Undo {
+                    Action::Undo
+                } else {
+                    Action::Redo
+                };
+                if let Some(ActionOutcome::SetStatusMessage(msg)) = b.handle_action(a, Source::Fsys)
+                {
+                    return Err(msg);
+                }
+            }
+
+            Name(name) => {
+                b.kind = match &b.kind {
+                    // Names of the form "+name" are in-memory buffers in the style of +output
+                    BufferKind::Unnamed | BufferKind::Output(_) if name.starts_with('+') => {
+                        BufferKind::Output(self.cwd.join(name).display().to_string())
+                    }
+                    BufferKind::File(_) | BufferKind::Unnamed => {
+                        BufferKind::File(self.cwd.join(name))
+                    }
+                    BufferKind::Virtual(_) => BufferKind::Virtual(name),
+                    BufferKind::Output(_) => BufferKind::Output(name),
+                    BufferKind::Directory(_) | BufferKind::MiniBuffer => {
+                        return Err("unable to rename this buffer".to_string())
+                    }
+                };
+            }
+
+            Get => {
+                if !(b.kind.is_file() || b.kind.is_dir()) {
+                    return Err("Buffer is not backed by a file on disk".to_string());
+                }
+                let msg = b.reload_from_disk();
+                self.set_status_message(&msg);
+            }
+
+            Save => {
+                let path = match &b.kind {
+                    BufferKind::File(p) => p.clone(),
+                    _ => return Err("Buffer is not backed by a file on disk".to_string()),
+                };
+                let msg = b.save_to_disk_at(path, false);
+                if b.dirty {
+                    return Err(msg);
+                }
+                self.set_status_message(&msg);
+                _ = self.tx_fsys.send(LogEvent::Save(id));
+            }
+
+            Delete { force } => {
+                if b.dirty && !force {
+                    return Err("No write since last change".to_string());
+                }
+                self.delete_buffer(id, force);
+            }
+
+            Edit(cmd) => {
+                let syntax = config_handle!().regex_syntax;
+                let mut prog = Program::try_parse_with_syntax(&cmd, syntax)
+                    .map_err(|e| format!("Invalid edit command: {e}"))?;
+                if b.read_only && prog.modifies_text() {
+                    return Err("Buffer is read-only".to_string());
+                }
+                let fname = b.full_name().to_string();
+                let mut buf = Vec::new();
+                let res = prog.execute(b, &fname, &mut buf);
+
+                if !buf.is_empty() {
+                    self.buffers.write_output_for_buffer(
+                        id,
+                        String::from_utf8(buf).unwrap(),
+                        &self.cwd,
+                    );
+                }
+
+                match res {
+                    Ok(new_dot) => {
+                        if let Some(b) = self.buffers.with_id_mut(id) {
+                            b.dot = new_dot;
+                        }
+                    }
+                    Err(e) => return Err(format!("Error running edit command: {e}")),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub(super) fn command_mode(&mut self) {
         self.modes.insert(0, Mode::ephemeral_mode("COMMAND"));
 
@@ -686,11 +940,36 @@ where
     }
 
     pub(super) fn sam_mode(&mut self) {
+        const PROMPT: &str = "Edit> ";
         self.modes.insert(0, Mode::ephemeral_mode("EDIT"));
 
-        if let Some(input) = self.minibuffer_prompt("Edit> ") {
-            self.execute_edit_command(&input);
-        };
+        // Invalid programs are returned to the user for correction with a caret marking the
+        // position of the error.
+        let mut input = String::new();
+        let mut hint_lines = Vec::new();
+
+        while let Some(cmd) = self.minibuffer_prompt_with_hint(PROMPT, input, hint_lines) {
+            debug!(%cmd, "executing edit command");
+            let syntax = config_handle!().regex_syntax;
+            match Program::try_parse_with_syntax(&cmd, syntax) {
+                Ok(prog) => {
+                    self.run_edit_program(prog);
+                    break;
+                }
+                Err(error) => {
+                    warn!(?error, "invalid edit command");
+                    let padding = " ".repeat(PROMPT.len());
+                    hint_lines = match error.annotate(&cmd) {
+                        Some((line, caret)) => vec![
+                            format!("{padding}{line}"),
+                            format!("{padding}{caret} {}", error.kind),
+                        ],
+                        None => vec![format!("{padding}{error}")],
+                    };
+                    input = cmd;
+                }
+            }
+        }
 
         self.modes.remove(0);
     }
@@ -736,8 +1015,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{editor::EditorMode, LogBuffer, PlumbingRules};
+    use crate::{
+        editor::test_utils::headless_editor,
+        fsys::{Message, Req},
+    };
     use simple_test_case::test_case;
+    use std::sync::mpsc::channel;
 
     macro_rules! assert_recv {
         ($brx:expr, $msg:ident, $expected:expr) => {
@@ -760,12 +1043,7 @@ recv {}({})",
 
     #[test]
     fn opening_a_file_sends_the_correct_fsys_messages() {
-        let mut ed = Editor::new(
-            Config::default(),
-            PlumbingRules::default(),
-            EditorMode::Headless,
-            LogBuffer::default(),
-        );
+        let mut ed = headless_editor();
         let brx = ed.rx_fsys.take().expect("to have fsys channels");
 
         ed.open_file("foo");
@@ -790,12 +1068,7 @@ recv {}({})",
     #[test_case(&["foo", "bar"], &[1, 2]; "two files")]
     #[test]
     fn ensure_correct_fsys_state_works(files: &[&str], expected_ids: &[usize]) {
-        let mut ed = Editor::new(
-            Config::default(),
-            PlumbingRules::default(),
-            EditorMode::Headless,
-            LogBuffer::default(),
-        );
+        let mut ed = headless_editor();
         let brx = ed.rx_fsys.take().expect("to have fsys channels");
 
         for file in files {
@@ -813,4 +1086,131 @@ recv {}({})",
             assert_recv!(brx, Focus, expected);
         }
     }
+
+    #[test]
+    fn buffer_ctl_does_not_change_focus() {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        ed.open_file("bar");
+
+        ed.handle_buffer_ctl(1, "edit i/hello/").unwrap();
+        ed.handle_buffer_ctl(1, "readonly").unwrap();
+        let res = ed.handle_buffer_ctl(1, "undo");
+
+        assert_eq!(res, Err("Buffer is read-only".to_string()));
+        assert_eq!(ed.active_buffer_id(), 2);
+
+        let b = ed.buffers.with_id(1).unwrap();
+        assert_eq!(b.str_contents(), "hello\n");
+        assert!(b.metadata().contains("dirty\ttrue\n"));
+        assert!(b.metadata().contains("readonly\ttrue\n"));
+    }
+
+    #[test]
+    fn buffer_ctl_delete_requires_force_for_dirty_buffers() {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        ed.open_file("bar");
+        ed.handle_buffer_ctl(1, "dirty").unwrap();
+        ed.handle_buffer_ctl(1, "").unwrap();
+
+        assert!(ed.handle_buffer_ctl(1, "delete").is_err());
+        ed.handle_buffer_ctl(1, "delete!").unwrap();
+        assert!(ed.buffers.with_id(1).is_none());
+        assert_eq!(ed.active_buffer_id(), 2);
+    }
+
+    #[test_case(isize::MAX, 3; "down")]
+    #[test_case(isize::MIN, 0; "up")]
+    #[test]
+    fn scrolling_is_clamped_to_the_buffer_size(n: isize, row_off: usize) {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        ed.handle_buffer_ctl(1, "edit i/a\nb\n/").unwrap();
+        ed.handle_buffer_ctl(1, &format!("scroll {n}")).unwrap();
+
+        assert_eq!(ed.buffers.with_id(1).unwrap().row_off, row_off);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("\n"; "newline")]
+    #[test]
+    fn empty_addr_writes_leave_dot_unchanged(s: &str) {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        let id = ed.active_buffer_id();
+        let send = |ed: &mut Editor<_>, req: Req| {
+            let (tx, rx) = channel();
+            ed.handle_message(Message { req, tx });
+            rx.recv().unwrap()
+        };
+        ed.handle_buffer_ctl(id, "edit i/one\ntwo\n/").unwrap();
+
+        let res = send(&mut ed, Req::SetBufferAddr { id, s: "2".into() });
+        assert!(res.is_ok(), "{res:?}");
+        let dot = ed.buffers.with_id(id).unwrap().dot;
+
+        let res = send(&mut ed, Req::SetBufferAddr { id, s: s.into() });
+        assert!(res.is_ok(), "{res:?}");
+        let res = send(&mut ed, Req::SetBufferXAddr { id, s: s.into() });
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(ed.buffers.with_id(id).unwrap().dot, dot);
+    }
+
+    #[test_case(Req::SetBufferDot { id: 1, s: "x".into() }; "dot")]
+    #[test_case(Req::SetBufferXDot { id: 1, s: "x".into() }; "xdot")]
+    #[test_case(Req::ClearBufferBody { id: 1 }; "clear body")]
+    #[test_case(Req::AppendBufferBody { id: 1, s: "x".into() }; "append body")]
+    #[test_case(Req::BufferControlMessage { id: 1, msg: "edit ,d".into() }; "edit")]
+    #[test]
+    fn fsys_edits_to_read_only_buffers_are_an_error(req: Req) {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        ed.handle_buffer_ctl(1, "edit i/hello/").unwrap();
+        ed.handle_buffer_ctl(1, "readonly").unwrap();
+        let dot = ed.buffers.with_id(1).unwrap().dot;
+
+        let (tx, rx) = channel();
+        ed.handle_message(Message { req, tx });
+
+        assert_eq!(rx.recv().unwrap(), Err("Buffer is read-only".to_string()));
+        let b = ed.buffers.with_id(1).unwrap();
+        assert_eq!(b.str_contents(), "hello\n");
+        assert_eq!(b.dot, dot);
+    }
+
+    #[test]
+    fn edit_commands_that_only_print_are_allowed_in_read_only_buffers() {
+        let mut ed = headless_editor();
+        ed.open_file("foo");
+        ed.handle_buffer_ctl(1, "edit i/hello/").unwrap();
+        ed.handle_buffer_ctl(1, "readonly").unwrap();
+
+        assert_eq!(ed.handle_buffer_ctl(1, "edit ,x/l+/ =#"), Ok(()));
+    }
+
+    #[test]
+    fn new_buffers_are_created_without_changing_focus() {
+        let mut ed = headless_editor();
+        let brx = ed.rx_fsys.take().expect("to have fsys channels");
+        ed.open_file("foo");
+        assert_recv!(brx, Close, 0);
+        assert_recv!(brx, Open, 1);
+        assert_recv!(brx, Focus, 1);
+
+        let (tx, rx) = channel();
+        ed.handle_message(Message {
+            req: Req::NewBuffer,
+            tx,
+        });
+
+        assert_eq!(rx.recv().unwrap(), Ok("2".to_string()));
+        assert_recv!(brx, Open, 2);
+        assert_eq!(ed.active_buffer_id(), 1);
+
+        ed.handle_buffer_ctl(2, "name +cargo-test").unwrap();
+        let b = ed.buffers.with_id(2).unwrap();
+        let expected = ed.cwd.join("+cargo-test").display().to_string();
+        assert_eq!(b.kind, BufferKind::Output(expected));
+    }
 }
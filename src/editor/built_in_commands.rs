@@ -30,6 +30,18 @@ pub fn built_in_commands() -> Vec<(Vec<&'static str>, &'static str)> {
             vec!["db!", "delete-buffer!"],
             "delete the active buffer discarding all pending changes",
         ),
+        (
+            vec!["diagnostics"],
+            "list the diagnostics set for all open buffers in +diagnostics",
+        ),
+        (
+            vec!["diagnostic-next"],
+            "move dot to the next diagnostic in the current buffer, showing its message",
+        ),
+        (
+            vec!["diagnostic-prev"],
+            "move dot to the previous diagnostic in the current buffer, showing its message",
+        ),
         (
             vec!["echo"],
             "display the given string in the status line ('echo hello, world!')",
@@ -42,7 +54,59 @@ pub fn built_in_commands() -> Vec<(Vec<&'static str>, &'static str)> {
             vec!["expand-dot"],
             "smart expand the current cursor position into a range",
         ),
+        (
+            vec!["grep"],
+            "search files under the working directory for a regex, listing matches in +search ('grep fn \\w+')",
+        ),
+        (
+            vec!["grep-refresh"],
+            "re-run the most recent grep, replacing the contents of its +search buffer",
+        ),
         (vec!["help"], "display this help file"),
+        (
+            vec!["lsp-start"],
+            "start (or restart) the language server configured for the current file",
+        ),
+        (
+            vec!["lsp-stop"],
+            "stop the language server for the current file",
+        ),
+        (
+            vec!["lsp-definition"],
+            "jump to the definition of the symbol under the cursor",
+        ),
+        (
+            vec!["lsp-hover"],
+            "show hover information for the symbol under the cursor in the minibuffer",
+        ),
+        (
+            vec!["lsp-references"],
+            "list references to the symbol under the cursor in +references",
+        ),
+        (
+            vec!["lsp-rename"],
+            "rename the symbol under the cursor, prompting for a name if none is given ('lsp-rename new_name')",
+        ),
+        (
+            vec!["macros"],
+            "view and edit recorded macros in +macros",
+        ),
+        (
+            vec!["macros-apply"],
+            "replace the contents of the macro registers with the macros in +macros",
+        ),
+        (
+            vec!["macro-save"],
+            "save a register to ~/.ad/macros, optionally with a name ('macro-save a indent')",
+        ),
+        (
+            vec!["macro-load"],
+            "load a named macro from ~/.ad/macros into a register ('macro-load a indent')",
+        ),
+        (
+            vec!["mark"],
+            "set the named mark to the current dot for use in addresses ('mark a')",
+        ),
         (
             vec!["mark-clean"],
             "mark the current buffer as being clean to prevent saving changes",
@@ -66,7 +130,19 @@ pub fn built_in_commands() -> Vec<(Vec<&'static str>, &'static str)> {
         ),
         (
             vec!["reload-config"],
-            "reload the editor config file located at ~/.ad/init.conf",
+            "reload the editor config file located at ~/.ad/init.conf along with any snippets",
+        ),
+        (
+            vec!["replace"],
+            "collect replacements across files for review in +replace ('replace /foo/bar/ *.rs')",
+        ),
+        (
+            vec!["replace-apply"],
+            "apply the replacements remaining in +replace to open buffers and files on disk",
+        ),
+        (
+            vec!["snippet"],
+            "select a snippet for the current buffer from ~/.ad/snippets to insert at the cursor",
         ),
         (
             vec!["set"],
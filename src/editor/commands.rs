@@ -1,6 +1,8 @@
 //! Command mode commands for ad
 use crate::{
+    buffer::is_valid_mark_name,
     editor::{
+        macros::is_valid_register_name,
         Action::*,
         Actions::{self, *},
         Editor, ViewPort,
@@ -38,6 +40,15 @@ fn parse_command(input: &str, active_buffer_id: usize, cwd: &Path) -> Result<Act
             }
         }
 
+        "mark" => {
+            let mut chars = args.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), None) if is_valid_mark_name(name) => Ok(Single(SetMark { name })),
+                (None, _) => Err("No mark name provided".to_string()),
+                _ => Err(format!("'{args}' is not a valid mark name")),
+            }
+        }
+
         "mark-clean" => {
             let bufid = if args.is_empty() {
                 active_buffer_id
@@ -54,6 +65,10 @@ fn parse_command(input: &str, active_buffer_id: usize, cwd: &Path) -> Result<Act
         "db" | "delete-buffer" => Ok(Single(DeleteBuffer { force: false })),
         "db!" | "delete-buffer!" => Ok(Single(DeleteBuffer { force: true })),
 
+        "diagnostics" => Ok(Single(ListDiagnostics)),
+        "diagnostic-next" => Ok(Single(DiagnosticNext)),
+        "diagnostic-prev" => Ok(Single(DiagnosticPrevious)),
+
         "echo" => Ok(Single(SetStatusMessage {
             message: args.to_string(),
         })),
@@ -70,8 +85,50 @@ fn parse_command(input: &str, active_buffer_id: usize, cwd: &Path) -> Result<Act
             }
         }
 
+        "grep" => {
+            if args.is_empty() {
+                Err("No regex provided".to_string())
+            } else {
+                Ok(Single(Grep {
+                    pattern: args.to_string(),
+                }))
+            }
+        }
+        "grep-refresh" => Ok(Single(GrepRefresh)),
+
         "help" => Ok(Single(ShowHelp)),
 
+        "lsp-start" => Ok(Single(LspStart)),
+        "lsp-stop" => Ok(Single(LspStop)),
+        "lsp-definition" => Ok(Single(LspDefinition)),
+        "lsp-hover" => Ok(Single(LspHover)),
+        "lsp-references" => Ok(Single(LspReferences)),
+        "lsp-rename" => Ok(Single(LspRename {
+            name: (!args.is_empty()).then(|| args.to_string()),
+        })),
+
+        "macros" => Ok(Single(MacroEdit)),
+        "macros-apply" => Ok(Single(MacrosApply)),
+        "macro-save" | "macro-load" => {
+            let (reg, name) = match args.split_once(' ') {
+                Some((reg, name)) => (reg, name.trim()),
+                None => (args, args),
+            };
+            let mut chars = reg.chars();
+            let reg = match (chars.next(), chars.next()) {
+                (Some(reg), None) if is_valid_register_name(reg) => reg,
+                (None, _) => return Err("No register provided".to_string()),
+                _ => return Err(format!("'{reg}' is not a valid register")),
+            };
+            let name = name.to_string();
+
+            if command == "macro-save" {
+                Ok(Single(MacroSave { reg, name }))
+            } else {
+                Ok(Single(MacroLoad { reg, name }))
+            }
+        }
+
         "o" | "open" => {
             if args.is_empty() {
                 Err("No filename provided".to_string())
@@ -90,6 +147,16 @@ fn parse_command(input: &str, active_buffer_id: usize, cwd: &Path) -> Result<Act
         "q!" | "quit!" | "Exit!" => Ok(Single(Exit { force: true })),
 
         "reload-config" => Ok(Single(ReloadConfig)),
+        "replace" => {
+            if args.is_empty() {
+                Err("No replacement provided".to_string())
+            } else {
+                Ok(Single(ReplaceInFiles {
+                    input: args.to_string(),
+                }))
+            }
+        }
+        "replace-apply" => Ok(Single(ReplaceInFilesApply)),
         "reload-buffer" | "Get" => {
             if args.is_empty() {
                 Ok(Single(ReloadActiveBuffer))
@@ -101,6 +168,8 @@ fn parse_command(input: &str, active_buffer_id: usize, cwd: &Path) -> Result<Act
             }
         }
 
+        "snippet" => Ok(Single(SelectSnippet)),
+
         "set" => Ok(Single(UpdateConfig {
             input: input.to_string(),
         })),
@@ -163,6 +232,67 @@ where
     }
 }
 
+/// Commands accepted by the ctl file of an individual buffer in fsys.
+///
+/// These are applied directly to the target buffer without changing focus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BufferCtl {
+    Clean,
+    Dirty,
+    Save,
+    Get,
+    Name(String),
+    Show,
+    Delete { force: bool },
+    Undo,
+    Redo,
+    Scroll(isize),
+    ReadOnly(bool),
+    Edit(String),
+}
+
+pub(crate) fn parse_buffer_ctl(input: &str) -> Result<BufferCtl, String> {
+    use BufferCtl::*;
+
+    let input = input.trim();
+    let (command, args) = match input.split_once(' ') {
+        Some((command, args)) => (command, args.trim()),
+        None => (input, ""),
+    };
+
+    let no_args = |cmd: BufferCtl| {
+        if args.is_empty() {
+            Ok(cmd)
+        } else {
+            Err(format!("'{command}' does not take arguments"))
+        }
+    };
+
+    match command {
+        "clean" => no_args(Clean),
+        "dirty" => no_args(Dirty),
+        "save" => no_args(Save),
+        "get" => no_args(Get),
+        "name" if args.is_empty() => Err("No name provided".to_string()),
+        "name" => Ok(Name(args.to_string())),
+        "show" => no_args(Show),
+        "delete" => no_args(Delete { force: false }),
+        "delete!" => no_args(Delete { force: true }),
+        "undo" => no_args(Undo),
+        "redo" => no_args(Redo),
+        "scroll" => match args.parse::<isize>() {
+            Ok(n) => Ok(Scroll(n)),
+            Err(_) => Err(format!("'{args}' is not a valid number of lines")),
+        },
+        "readonly" => no_args(ReadOnly(true)),
+        "readwrite" => no_args(ReadOnly(false)),
+        "edit" | "Edit" if args.is_empty() => Err("No edit program provided".to_string()),
+        "edit" | "Edit" => Ok(Edit(args.to_string())),
+        "" => Err("No command provided".to_string()),
+        _ => Err(format!("Not a buffer command: {command}")),
+    }
+}
+
 fn try_parse_single_char_command(input: &str) -> Option<Actions> {
     match input.chars().next() {
         Some('!') => Some(Single(ShellRun {
@@ -186,6 +316,7 @@ fn try_parse_single_char_command(input: &str) -> Option<Actions> {
 mod tests {
     use super::*;
     use crate::editor::built_in_commands::built_in_commands;
+    use simple_test_case::test_case;
     use std::path::PathBuf;
 
     // The current behaviour of the command parser ignores additional input rather than erroring
@@ -210,4 +341,19 @@ mod tests {
             }
         }
     }
+
+    #[test_case("clean", Ok(BufferCtl::Clean); "clean")]
+    #[test_case("save\n", Ok(BufferCtl::Save); "trailing newline")]
+    #[test_case("name +cargo-test", Ok(BufferCtl::Name("+cargo-test".to_string())); "name")]
+    #[test_case("delete!", Ok(BufferCtl::Delete { force: true }); "forced delete")]
+    #[test_case("scroll -3", Ok(BufferCtl::Scroll(-3)); "scroll up")]
+    #[test_case("edit x/foo/ d", Ok(BufferCtl::Edit("x/foo/ d".to_string())); "edit")]
+    #[test_case("scroll up", Err("'up' is not a valid number of lines".to_string()); "invalid scroll")]
+    #[test_case("undo 2", Err("'undo' does not take arguments".to_string()); "unexpected args")]
+    #[test_case("name", Err("No name provided".to_string()); "missing name")]
+    #[test_case("focus", Err("Not a buffer command: focus".to_string()); "unknown")]
+    #[test]
+    fn parse_buffer_ctl_works(input: &str, expected: Result<BufferCtl, String>) {
+        assert_eq!(parse_buffer_ctl(input), expected);
+    }
 }
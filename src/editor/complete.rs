@@ -0,0 +1,301 @@
+//! Completing the word or file path before the cursor in INSERT mode.
+//!
+//! Candidates are collected from the words in all open buffers (starting with the active
+//! buffer), from file paths relative to the directory of the active buffer and from the output
+//! of the user's "completion-command" if one is set. The command is run with the prefix being
+//! completed as its final argument and each line of output is treated as a candidate.
+use crate::{
+    buffer::{Buffer, GapBuffer},
+    config_handle,
+    dot::Dot,
+    editor::{Action, Editor, MiniBufferSelection},
+    system::System,
+};
+use ad_event::Source;
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Complete the prefix before the cursor in the active buffer, inserting the candidate
+    /// directly if there is only one and otherwise prompting for a selection in the minibuffer.
+    pub(super) fn complete(&mut self) {
+        let b = self.buffers.active();
+        let cur = b.dot.active_cur().idx;
+        let prefix = match Prefix::before(&b.txt, cur) {
+            Some(prefix) => prefix,
+            None => {
+                self.set_status_message("nothing to complete");
+                return;
+            }
+        };
+
+        let dir = b.dir().unwrap_or(&self.cwd).to_path_buf();
+        let mut candidates = match prefix.kind {
+            Kind::Word => word_candidates(&prefix.s, self.buffers.iter()),
+            Kind::Path => path_candidates(&prefix.s, &dir),
+        };
+
+        let cmd = config_handle!().completion_command.clone();
+        if !cmd.is_empty() {
+            let mut args: Vec<&str> = cmd.split_whitespace().collect();
+            let prog = args.remove(0);
+            args.push(&prefix.s);
+
+            match self
+                .system
+                .run_command_blocking(prog, args, &dir, self.active_buffer_id())
+            {
+                Ok(s) => {
+                    let mut seen: HashSet<String> = candidates.iter().cloned().collect();
+                    candidates.extend(
+                        s.lines()
+                            .map(String::from)
+                            .filter(|c| !c.is_empty() && *c != prefix.s && seen.insert(c.clone())),
+                    );
+                }
+                Err(e) => {
+                    self.set_status_message(&format!("unable to run completion command: {e}"))
+                }
+            }
+        }
+
+        let candidate = match candidates.len() {
+            0 => {
+                self.set_status_message("no completions");
+                return;
+            }
+            1 => candidates.remove(0),
+            _ => match self.minibuffer_select_from("complete> ", candidates) {
+                MiniBufferSelection::Line { line, .. } => line,
+                _ => return,
+            },
+        };
+
+        self.insert_completion(&prefix, cur, candidate);
+    }
+
+    fn insert_completion(&mut self, prefix: &Prefix, cur: usize, candidate: String) {
+        let b = self.buffers.active_mut();
+        let s = match candidate.strip_prefix(&prefix.s) {
+            Some(suffix) => suffix.to_string(),
+            None => {
+                b.dot = Dot::from_char_indices(prefix.start, cur - 1).collapse_null_range();
+                b.handle_action(Action::Delete, Source::Keyboard);
+                candidate
+            }
+        };
+
+        if !s.is_empty() {
+            b.handle_action(Action::InsertString { s }, Source::Keyboard);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Path,
+}
+
+/// The text before the cursor that we are attempting to complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Prefix {
+    start: usize,
+    s: String,
+    kind: Kind,
+}
+
+impl Prefix {
+    /// Paths are preferred over words if the text before the cursor contains a '/' so that
+    /// completing "src/ed" offers "src/editor/" rather than words starting with "ed".
+    fn before(txt: &GapBuffer, idx: usize) -> Option<Self> {
+        let scan_back = |is_valid: fn(char) -> bool| {
+            let mut start = idx;
+            while start > 0 && is_valid(txt.char(start - 1)) {
+                start -= 1;
+            }
+            start
+        };
+
+        let start = scan_back(is_path_char);
+        let s = txt.slice(start, idx).to_string();
+        if s.contains('/') {
+            return Some(Self {
+                start,
+                s,
+                kind: Kind::Path,
+            });
+        }
+
+        let start = scan_back(is_word_char);
+        if start == idx {
+            return None;
+        }
+
+        Some(Self {
+            start,
+            s: txt.slice(start, idx).to_string(),
+            kind: Kind::Word,
+        })
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_path_char(ch: char) -> bool {
+    !ch.is_whitespace() && !"\"'`()[]{}<>,;".contains(ch)
+}
+
+/// Words from the given buffers that extend `prefix`, in the order they are first seen.
+fn word_candidates<'a>(prefix: &str, buffers: impl Iterator<Item = &'a Buffer>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+
+    for b in buffers {
+        let content = b.txt.to_string();
+        for word in content.split(|ch: char| !is_word_char(ch)) {
+            if word.len() > prefix.len() && word.starts_with(prefix) && !seen.contains(word) {
+                seen.insert(word.to_string());
+                candidates.push(word.to_string());
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Directory entries that extend `prefix` when it is read as a path relative to `dir`.
+/// Directories have a trailing '/' so that completion can continue into them and hidden files
+/// are only included if the file name being completed starts with a '.'.
+fn path_candidates(prefix: &str, dir: &Path) -> Vec<String> {
+    let (dir_part, name_part) = match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    };
+
+    let search_dir = match dir_part.strip_prefix("~/") {
+        Some(rest) => match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(rest),
+            Err(_) => return Vec::new(),
+        },
+        None => dir.join(dir_part),
+    };
+
+    let entries = match fs::read_dir(search_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(name_part)
+                || (name.starts_with('.') && !name_part.starts_with('.'))
+            {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+
+            Some(format!("{dir_part}{name}{suffix}"))
+        })
+        .filter(|c| c != prefix)
+        .collect();
+    candidates.sort();
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::test_utils::editor_with_content;
+    use simple_test_case::test_case;
+
+    #[test_case("foo ba", 6, Some((4, "ba", Kind::Word)); "word")]
+    #[test_case("foo(bar_b", 9, Some((4, "bar_b", Kind::Word)); "word after paren")]
+    #[test_case("foo.ba", 6, Some((4, "ba", Kind::Word)); "field access")]
+    #[test_case("cat src/ed", 10, Some((4, "src/ed", Kind::Path)); "relative path")]
+    #[test_case("(\"/usr/li", 9, Some((2, "/usr/li", Kind::Path)); "quoted absolute path")]
+    #[test_case("see ./", 6, Some((4, "./", Kind::Path)); "directory")]
+    #[test_case("foo ", 4, None; "after whitespace")]
+    #[test_case("", 0, None; "empty buffer")]
+    #[test]
+    fn prefix_before_works(s: &str, idx: usize, expected: Option<(usize, &str, Kind)>) {
+        let txt = GapBuffer::from(s);
+        let prefix = Prefix::before(&txt, idx);
+        let expected = expected.map(|(start, s, kind)| Prefix {
+            start,
+            s: s.to_string(),
+            kind,
+        });
+
+        assert_eq!(prefix, expected);
+    }
+
+    #[test]
+    fn word_candidates_come_from_all_buffers_without_duplicates() {
+        let buffers = [
+            Buffer::new_unnamed(0, "let completion = complete(comp);\n"),
+            Buffer::new_unnamed(1, "fn complete() {}\nfn compare() {}\n"),
+        ];
+
+        let candidates = word_candidates("comp", buffers.iter());
+
+        assert_eq!(candidates, vec!["completion", "complete", "compare"]);
+    }
+
+    #[test_case("src/ed", &["src/editor/"]; "directory")]
+    #[test_case("src/editor/comp", &["src/editor/complete.rs"]; "file")]
+    #[test_case("data/init", &["data/init.conf"]; "file in directory")]
+    #[test_case("src/nope", &[]; "no match")]
+    #[test_case("missing/", &[]; "missing directory")]
+    #[test]
+    fn path_candidates_works(prefix: &str, expected: &[&str]) {
+        let candidates = path_candidates(prefix, Path::new(env!("CARGO_MANIFEST_DIR")));
+
+        assert_eq!(candidates, expected);
+    }
+
+    #[test]
+    fn hidden_files_need_a_leading_dot() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+        assert!(!path_candidates("./", dir).iter().any(|c| c == "./.git/"));
+        assert!(path_candidates("./.g", dir).iter().any(|c| c == "./.git/"));
+    }
+
+    #[test]
+    fn a_single_candidate_is_inserted_directly() {
+        let mut ed = editor_with_content("let completion_source = 1;\nlet x = comp");
+
+        ed.complete();
+
+        assert_eq!(
+            ed.buffers.active().txt.to_string(),
+            "let completion_source = 1;\nlet x = completion_source"
+        );
+    }
+
+    #[test_case("let x = c", "total", "let x = total"; "single character prefix")]
+    #[test_case("let x = cou", "amount", "let x = amount"; "longer prefix")]
+    #[test]
+    fn candidates_without_the_prefix_replace_it(content: &str, candidate: &str, expected: &str) {
+        let mut ed = editor_with_content(content);
+        let b = ed.buffers.active();
+        let cur = b.dot.active_cur().idx;
+        let prefix = Prefix::before(&b.txt, cur).unwrap();
+
+        ed.insert_completion(&prefix, cur, candidate.to_string());
+
+        assert_eq!(ed.buffers.active().txt.to_string(), expected);
+    }
+}
@@ -0,0 +1,59 @@
+//! Navigating the diagnostics that external programs have attached to open buffers.
+use crate::{
+    buffer::DIAGNOSTICS_BUFFER,
+    editor::{Editor, ViewPort},
+    system::System,
+};
+use ad_event::Source;
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Move dot to the next (or previous) diagnostic in the active buffer, wrapping around the
+    /// buffer, and show its message in the status line.
+    pub(super) fn diagnostic_next(&mut self, forward: bool) {
+        let dot = self.buffers.active().dot;
+        let msg = match self.buffers.active_mut().goto_next_diagnostic(forward) {
+            Some(msg) => msg,
+            None => {
+                self.set_status_message("no diagnostics");
+                return;
+            }
+        };
+
+        // Record where we started from so that the jump list can return us there
+        let found = self.buffers.active().dot;
+        self.buffers.active_mut().dot = dot;
+        self.buffers.record_jump_position();
+        let b = self.buffers.active_mut();
+        b.dot = found;
+        b.set_view_port(ViewPort::Center, self.screen_rows, self.screen_cols);
+        self.set_status_message(&msg);
+    }
+
+    /// List the diagnostics for all open buffers in a +diagnostics buffer so that they can be
+    /// loaded to jump to them.
+    pub(super) fn list_diagnostics(&mut self) {
+        let lines: Vec<String> = self
+            .buffers
+            .iter()
+            .flat_map(|b| b.diagnostics_listing())
+            .collect();
+
+        if lines.is_empty() {
+            self.set_status_message("no diagnostics");
+            return;
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        let id = self
+            .buffers
+            .reset_output_buffer(format!("{}/{DIAGNOSTICS_BUFFER}", self.cwd.display()));
+        if let Some(b) = self.buffers.with_id_mut(id) {
+            b.append(content, Source::Fsys);
+            b.dot = Default::default();
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Making requests to running language servers and acting on their responses.
+use crate::{
+    buffer::{Buffer, BufferKind, DiagnosticCmd, GapBuffer, REFERENCES_BUFFER},
+    config::lsp_server_for_path,
+    config_handle,
+    dot::{Cur, Dot},
+    editor::{Editor, ViewPort},
+    exec::Edit,
+    grep::read_text_file,
+    lsp::{Location, LspDiagnostic, LspEvent, LspManager, LspResponse, PositionEncoding, TextEdit},
+    system::System,
+};
+use ad_event::Source;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Start (or restart) the language server configured for the active buffer.
+    pub(super) fn lsp_start(&mut self) {
+        let path = match &self.buffers.active().kind {
+            BufferKind::File(path) => path.clone(),
+            _ => {
+                self.set_status_message("not a file buffer");
+                return;
+            }
+        };
+
+        let cfg = lsp_server_for_path(&config_handle!().lsp_servers, &path).cloned();
+        match cfg {
+            Some(cfg) => match self.lsp.start(&cfg, &self.cwd) {
+                Ok(()) => {
+                    self.set_status_message(&format!("starting {} language server", cfg.lang))
+                }
+                Err(e) => self.set_status_message(&e),
+            },
+            None => self.set_status_message("no language server configured for this file"),
+        }
+    }
+
+    /// Stop the language server responsible for the active buffer.
+    pub(super) fn lsp_stop(&mut self) {
+        let lang = self
+            .lsp
+            .lang_for_buffer(self.active_buffer_id())
+            .map(String::from);
+
+        match lang {
+            Some(lang) => {
+                self.lsp.stop(&lang);
+                self.set_status_message(&format!("stopped {lang} language server"));
+            }
+            None => self.set_status_message("no language server is running for this buffer"),
+        }
+    }
+
+    fn lsp_request<F>(&mut self, req: F)
+    where
+        F: FnOnce(&mut LspManager, &Buffer) -> Result<(), String>,
+    {
+        // Make sure that the server has seen any edits made while handling the current event
+        // so that the position we send refers to the correct text.
+        self.publish_observed_changes();
+        if let Err(e) = req(&mut self.lsp, self.buffers.active()) {
+            self.set_status_message(&e);
+        }
+    }
+
+    pub(super) fn lsp_goto_definition(&mut self) {
+        self.lsp_request(LspManager::goto_definition);
+    }
+
+    pub(super) fn lsp_hover(&mut self) {
+        self.lsp_request(LspManager::hover);
+    }
+
+    pub(super) fn lsp_references(&mut self) {
+        self.lsp_request(LspManager::references);
+    }
+
+    /// Rename the symbol under the cursor, prompting for the new name if one is not provided.
+    pub(super) fn lsp_rename(&mut self, name: Option<String>) {
+        let name = match name.or_else(|| self.minibuffer_prompt("Rename to: ")) {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => return,
+        };
+
+        self.lsp_request(|lsp, b| lsp.rename(b, &name));
+    }
+
+    pub(super) fn handle_lsp_event(&mut self, evt: LspEvent) {
+        let (enc, resp) = match self.lsp.handle_event(evt) {
+            Some(resp) => resp,
+            None => return,
+        };
+
+        match resp {
+            LspResponse::Definition(locs) => self.lsp_jump_to(locs, enc),
+            LspResponse::Hover(text) => self.lsp_show_hover(text),
+            LspResponse::References(locs) => self.lsp_show_references(locs, enc),
+            LspResponse::Rename(edits) => self.lsp_apply_rename(edits, enc),
+            LspResponse::Diagnostics { path, diagnostics } => {
+                self.lsp_set_diagnostics(&path, diagnostics, enc)
+            }
+            LspResponse::Message(msg) => self.set_status_message(&msg),
+        }
+    }
+
+    /// Open the first location (opening the file if needed) with the jump list recording where
+    /// we came from.
+    fn lsp_jump_to(&mut self, locs: Vec<Location>, enc: PositionEncoding) {
+        let loc = match locs.into_iter().next() {
+            Some(loc) => loc,
+            None => {
+                self.set_status_message("no definition found");
+                return;
+            }
+        };
+
+        let path = canonical(&loc.path);
+        self.open_file(&path);
+        let b = self.buffers.active_mut();
+        if !matches!(&b.kind, BufferKind::File(p) if *p == path) {
+            return; // the file could not be opened
+        }
+
+        let (idx, _) = loc.range.to_char_indices(&b.txt, enc);
+        b.dot = Dot::Cur { c: Cur { idx } };
+        b.set_view_port(ViewPort::Center, self.screen_rows, self.screen_cols);
+    }
+
+    fn lsp_show_hover(&mut self, text: String) {
+        if text.is_empty() {
+            self.set_status_message("no hover information");
+            return;
+        }
+
+        self.minibuffer_select_from("hover> ", text.lines().map(String::from).collect());
+    }
+
+    /// Write the references found by the server to a +references buffer as "name:line:col: text"
+    /// lines so that they can be loaded to jump to them.
+    fn lsp_show_references(&mut self, locs: Vec<Location>, enc: PositionEncoding) {
+        if locs.is_empty() {
+            self.set_status_message("no references found");
+            return;
+        }
+
+        let mut unopened: HashMap<PathBuf, GapBuffer> = HashMap::new();
+        let mut lines = Vec::with_capacity(locs.len());
+
+        for loc in locs.into_iter() {
+            let path = canonical(&loc.path);
+            let txt = match self.buffers.with_path(&path) {
+                Some(b) => &b.txt,
+                None => {
+                    if !unopened.contains_key(&path) {
+                        let s = read_text_file(&path).unwrap_or_default();
+                        unopened.insert(path.clone(), GapBuffer::from(s));
+                    }
+                    &unopened[&path]
+                }
+            };
+
+            let (idx, _) = loc.range.to_char_indices(txt, enc);
+            let line = txt.char_to_line(idx);
+            let col = idx - txt.line_to_char(line);
+            let text = txt.line(line).to_string();
+            let name = path.strip_prefix(&self.cwd).unwrap_or(&path);
+
+            lines.push(format!(
+                "{}:{}:{}: {}",
+                name.display(),
+                line + 1,
+                col + 1,
+                text.trim_end()
+            ));
+        }
+
+        let n = lines.len();
+        let mut content = lines.join("\n");
+        content.push('\n');
+        let id = self
+            .buffers
+            .reset_output_buffer(format!("{}/{REFERENCES_BUFFER}", self.cwd.display()));
+        if let Some(b) = self.buffers.with_id_mut(id) {
+            b.append(content, Source::Fsys);
+            b.dot = Default::default();
+        }
+        self.set_status_message(&format!("{n} references"));
+    }
+
+    /// Apply the edits for a rename to open buffers (as a single undo transaction per buffer)
+    /// or directly to files on disk for those that are not open.
+    fn lsp_apply_rename(&mut self, files: Vec<(PathBuf, Vec<TextEdit>)>, enc: PositionEncoding) {
+        if files.is_empty() {
+            self.set_status_message("nothing to rename");
+            return;
+        }
+
+        let (mut n_edits, mut n_files, mut read_only) = (0, 0, 0);
+        let mut errors = Vec::new();
+
+        for (path, edits) in files.into_iter() {
+            let path = canonical(&path);
+            match self.buffers.with_path_mut(&path) {
+                Some(b) if b.read_only => {
+                    read_only += edits.len();
+                    continue;
+                }
+                Some(b) => {
+                    let dot = b.dot;
+                    let edits = char_edits(&b.txt, &edits, enc);
+                    apply_edits(b, &edits);
+                    b.dot = dot;
+                    b.dot.clamp_idx(b.txt.len_chars());
+                    n_edits += edits.len();
+                }
+
+                None => match apply_edits_to_file(&path, &edits, enc) {
+                    Ok(n) => n_edits += n,
+                    Err(e) => {
+                        errors.push(format!("{}: {e}", path.display()));
+                        continue;
+                    }
+                },
+            }
+            n_files += 1;
+        }
+
+        let mut msg = format!("renamed {n_edits} occurrences in {n_files} files");
+        if read_only > 0 {
+            msg.push_str(&format!(
+                ", skipped {read_only} occurrences in read-only buffers"
+            ));
+        }
+        if !errors.is_empty() {
+            msg.push_str(&format!(", failed to write: {}", errors.join(", ")));
+        }
+        self.set_status_message(&msg);
+    }
+
+    /// Replace the diagnostics for an open buffer with those published by its language server.
+    fn lsp_set_diagnostics(
+        &mut self,
+        path: &Path,
+        diagnostics: Vec<LspDiagnostic>,
+        enc: PositionEncoding,
+    ) {
+        let b = match self.buffers.with_path_mut(&canonical(path)) {
+            Some(b) => b,
+            None => return,
+        };
+
+        let pos = |idx: usize| {
+            let line = b.txt.char_to_line(idx);
+            (line + 1, idx - b.txt.line_to_char(line) + 1)
+        };
+
+        let mut cmds = vec![DiagnosticCmd::Clear];
+        for d in diagnostics.into_iter() {
+            let (from, to) = d.range.to_char_indices(&b.txt, enc);
+            cmds.push(DiagnosticCmd::Add {
+                start: pos(from),
+                end: pos(to),
+                severity: d.severity,
+                message: d.message,
+            });
+        }
+
+        b.apply_diagnostics(cmds);
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Convert edits to character offsets within `txt`, ordered from the end of the text so that
+/// applying them in turn leaves the offsets of the remaining edits valid.
+fn char_edits<'a>(
+    txt: &GapBuffer,
+    edits: &'a [TextEdit],
+    enc: PositionEncoding,
+) -> Vec<(usize, usize, &'a str)> {
+    let mut char_edits: Vec<_> = edits
+        .iter()
+        .map(|e| {
+            let (from, to) = e.range.to_char_indices(txt, enc);
+            (from, to, e.new_text.as_str())
+        })
+        .collect();
+    char_edits.sort_by_key(|&(from, _, _)| Reverse(from));
+
+    char_edits
+}
+
+fn apply_edits<E: Edit>(e: &mut E, edits: &[(usize, usize, &str)]) {
+    e.begin_edit_transaction();
+    for &(from, to, s) in edits.iter() {
+        if from < to {
+            e.remove(from, to);
+        }
+        if !s.is_empty() {
+            e.insert(from, s);
+        }
+    }
+    e.end_edit_transaction();
+}
+
+fn apply_edits_to_file(
+    path: &Path,
+    edits: &[TextEdit],
+    enc: PositionEncoding,
+) -> Result<usize, String> {
+    let s = read_text_file(path).ok_or_else(|| "unable to read file".to_string())?;
+    let mut txt = GapBuffer::from(s);
+    let edits = char_edits(&txt, edits, enc);
+    apply_edits(&mut txt, &edits);
+    fs::write(path, txt.to_string()).map_err(|e| e.to_string())?;
+
+    Ok(edits.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        editor::{test_utils::headless_editor, Action},
+        lsp::types::{Position, Range},
+    };
+    use std::{env, fs, process};
+
+    fn edit(line: usize, from: usize, to: usize, s: &str) -> TextEdit {
+        let pos = |character| Position { line, character };
+
+        TextEdit {
+            range: Range {
+                start: pos(from),
+                end: pos(to),
+            },
+            new_text: s.to_string(),
+        }
+    }
+
+    #[test]
+    fn rename_edits_are_a_single_undo_transaction() {
+        let content = "fn main() {\n    main();\n}\n";
+        let mut b = Buffer::new_unnamed(0, content);
+        let edits = [edit(0, 3, 7, "start"), edit(1, 4, 8, "start")];
+
+        let edits = char_edits(&b.txt, &edits, PositionEncoding::Utf16);
+        apply_edits(&mut b, &edits);
+        assert_eq!(b.txt.to_string(), "fn start() {\n    start();\n}\n");
+
+        b.handle_action(Action::Undo, Source::Keyboard);
+        assert_eq!(b.txt.to_string(), content);
+    }
+
+    #[test]
+    fn read_only_buffers_are_skipped_when_renaming() {
+        let dir = env::temp_dir().join(format!("ad-rename-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        fs::write(dir.join("a.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("b.rs"), "fn main() {}\n").unwrap();
+
+        let mut ed = headless_editor();
+        ed.open_file(dir.join("a.rs"));
+        let id = ed.active_buffer_id();
+        ed.handle_buffer_ctl(id, "readonly").unwrap();
+
+        let files = vec![
+            (dir.join("a.rs"), vec![edit(0, 3, 7, "start")]),
+            (dir.join("b.rs"), vec![edit(0, 3, 7, "start")]),
+        ];
+        ed.lsp_apply_rename(files, PositionEncoding::Utf16);
+
+        let a = ed.buffers.with_id(id).unwrap().str_contents();
+        let b = fs::read_to_string(dir.join("b.rs")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(a, "fn main() {}\n");
+        assert_eq!(b, "fn start() {}\n");
+        assert_eq!(
+            ed.status_message,
+            "renamed 1 occurrences in 1 files, skipped 1 occurrences in read-only buffers"
+        );
+    }
+}
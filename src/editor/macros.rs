@@ -0,0 +1,317 @@
+//! Recording and replaying keyboard macros.
+//!
+//! Pressing "q" followed by a register name in NORMAL mode starts recording the keys that are
+//! typed into that register and pressing "q" again in NORMAL mode stops the recording. Playing
+//! a macro back feeds its keys through the same input handling as if they had been typed, so
+//! prompts opened by the macro read their input from it as well.
+//!
+//! Macros are shown in the +macros buffer and saved in ~/.ad/macros one per line as a name
+//! followed by the text form of its keys (see [crate::key]).
+use crate::{
+    buffer::MACROS_BUFFER,
+    editor::Editor,
+    key::{parse_keys, render_keys, Input},
+    system::System,
+};
+use ad_event::Source;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Recorded macros along with the state of any recording or playback in progress.
+#[derive(Debug, Default)]
+pub(super) struct Macros {
+    registers: BTreeMap<char, Vec<Input>>,
+    recording: Option<(char, Vec<Input>)>,
+    queue: VecDeque<Input>,
+    playing: bool,
+    /// The id of the +macros buffer if it has been opened
+    bufid: Option<usize>,
+}
+
+pub(super) fn is_valid_register_name(ch: char) -> bool {
+    ch.is_alphanumeric()
+}
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    pub(super) fn record_macro(&mut self, reg: char) {
+        if self.macros.playing {
+            return;
+        }
+        self.macros.recording = Some((reg, Vec::new()));
+        self.set_status_message(&format!("recording macro '{reg}'"));
+    }
+
+    /// Stop recording if `input` is the "q" that ends the current recording, otherwise add it
+    /// to the recording (if there is one). Returns true if recording was stopped.
+    pub(super) fn try_stop_recording_macro(&mut self, input: Input) -> bool {
+        if self.macros.playing {
+            return false;
+        }
+        let keys = match self.macros.recording.as_mut() {
+            Some((_, keys)) => keys,
+            None => return false,
+        };
+
+        if input == Input::Char('q')
+            && self.modes[0].name == "NORMAL"
+            && self.pending_keys.is_empty()
+            && self.count.is_none()
+        {
+            let (reg, keys) = self.macros.recording.take().unwrap();
+            self.macros.registers.insert(reg, keys);
+            self.set_status_message(&format!("recorded macro '{reg}'"));
+            return true;
+        }
+
+        // Mouse events depend on the layout of the screen at the time they were recorded
+        if !matches!(input, Input::Mouse(_)) {
+            keys.push(input);
+        }
+
+        false
+    }
+
+    /// Play the macro in register `reg`, repeating it as many times as the count prefix.
+    pub(super) fn play_macro(&mut self, reg: char) {
+        let count = self.count.take().unwrap_or(1);
+        if self.macros.playing {
+            self.set_status_message("macros can not be played from inside of a macro");
+            return;
+        }
+        let keys = match self.macros.registers.get(&reg) {
+            Some(keys) => keys,
+            None => {
+                self.set_status_message(&format!("no macro in register '{reg}'"));
+                return;
+            }
+        };
+
+        for _ in 0..count {
+            self.macros.queue.extend(keys.iter().copied());
+        }
+
+        self.macros.playing = true;
+        while let Some(input) = self.macros.queue.pop_front() {
+            self.handle_input(input);
+            if !self.running {
+                break;
+            }
+        }
+        self.macros.queue.clear();
+        self.macros.playing = false;
+    }
+
+    /// The next key from the macro currently being played, if there is one.
+    pub(super) fn next_macro_input(&mut self) -> Option<Input> {
+        self.macros.queue.pop_front()
+    }
+
+    /// Open the +macros buffer to view and edit the contents of each register.
+    pub(super) fn edit_macros(&mut self) {
+        let content: String = self
+            .macros
+            .registers
+            .iter()
+            .map(|(reg, keys)| format!("{reg} {}\n", render_keys(keys)))
+            .collect();
+
+        let bufid = self.buffers.reset_output_buffer(MACROS_BUFFER.to_string());
+        self.buffers.active_mut().append(content, Source::Fsys);
+        self.macros.bufid = Some(bufid);
+    }
+
+    /// Replace the contents of the registers with those in the +macros buffer.
+    pub(super) fn apply_macros(&mut self) {
+        let content = match self.macros.bufid.and_then(|id| self.buffers.with_id(id)) {
+            Some(b) => b.txt.to_string(),
+            None => {
+                self.set_status_message("the +macros buffer is not open");
+                return;
+            }
+        };
+
+        match parse_macros(&content) {
+            Ok(macros) => {
+                let mut registers = BTreeMap::new();
+                for (name, keys) in macros.into_iter() {
+                    match register_from_name(&name) {
+                        Some(reg) => _ = registers.insert(reg, keys),
+                        None => {
+                            self.set_status_message(&format!("'{name}' is not a valid register"));
+                            return;
+                        }
+                    }
+                }
+                self.macros.registers = registers;
+                self.set_status_message("macros updated");
+            }
+            Err(e) => self.set_status_message(&e),
+        }
+    }
+
+    /// Save the macro in register `reg` to ~/.ad/macros under the given name.
+    pub(super) fn save_macro(&mut self, reg: char, name: String) {
+        let keys = match self.macros.registers.get(&reg) {
+            Some(keys) => keys,
+            None => {
+                self.set_status_message(&format!("no macro in register '{reg}'"));
+                return;
+            }
+        };
+
+        let msg = match save_macro_to(&macros_path(), &name, keys) {
+            Ok(()) => format!("saved macro '{name}'"),
+            Err(e) => e,
+        };
+        self.set_status_message(&msg);
+    }
+
+    /// Load the macro with the given name from ~/.ad/macros into register `reg`.
+    pub(super) fn load_macro(&mut self, reg: char, name: String) {
+        let msg = match load_macros_from(&macros_path()) {
+            Ok(mut macros) => match macros.remove(&name) {
+                Some(keys) => {
+                    self.macros.registers.insert(reg, keys);
+                    format!("loaded macro '{name}' into register '{reg}'")
+                }
+                None => format!("no saved macro named '{name}'"),
+            },
+            Err(e) => e,
+        };
+        self.set_status_message(&msg);
+    }
+}
+
+fn register_from_name(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if is_valid_register_name(ch) => Some(ch),
+        _ => None,
+    }
+}
+
+fn macros_path() -> PathBuf {
+    let home = env::var("HOME").unwrap();
+
+    Path::new(&home).join(".ad/macros")
+}
+
+/// Parse lines of "name keys..." ignoring blank lines and comments.
+fn parse_macros(s: &str) -> Result<BTreeMap<String, Vec<Input>>, String> {
+    let mut macros = BTreeMap::new();
+
+    for line in s.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, keys) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let keys = parse_keys(keys).map_err(|e| format!("invalid macro '{name}': {e}"))?;
+        macros.insert(name.to_string(), keys);
+    }
+
+    Ok(macros)
+}
+
+fn load_macros_from(path: &Path) -> Result<BTreeMap<String, Vec<Input>>, String> {
+    match fs::read_to_string(path) {
+        Ok(s) => parse_macros(&s),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("Unable to load macros: {e}")),
+    }
+}
+
+/// Add the given macro to the file at `path`, replacing any existing macro with the same name.
+fn save_macro_to(path: &Path, name: &str, keys: &[Input]) -> Result<(), String> {
+    if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with('#') {
+        return Err(format!("'{name}' is not a valid macro name"));
+    }
+
+    let mut macros = load_macros_from(path)?;
+    macros.insert(name.to_string(), keys.to_vec());
+
+    let content: String = macros
+        .iter()
+        .map(|(name, keys)| format!("{name} {}\n", render_keys(keys)))
+        .collect();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Unable to save macro: {e}"))?;
+    }
+    fs::write(path, content).map_err(|e| format!("Unable to save macro: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{
+        test_utils::{editor_with_content, type_keys},
+        Action,
+    };
+
+    #[test]
+    fn recorded_macros_can_be_played_with_a_count() {
+        let mut ed = editor_with_content("a\nb\nc\nd\n");
+        type_keys(&mut ed, "ggqaI-<20><esc>jq");
+
+        assert_eq!(
+            ed.macros.registers[&'a'],
+            parse_keys("I-<20><esc>j").unwrap()
+        );
+        assert_eq!(ed.buffers.active().txt.to_string(), "- a\nb\nc\nd\n");
+
+        type_keys(&mut ed, "2Qa");
+        assert_eq!(ed.buffers.active().txt.to_string(), "- a\n- b\n- c\nd\n");
+    }
+
+    #[test]
+    fn q_only_stops_recording_in_normal_mode() {
+        let mut ed = editor_with_content("");
+        type_keys(&mut ed, "qbiq<esc>q");
+
+        assert_eq!(ed.macros.recording, None);
+        assert_eq!(ed.macros.registers[&'b'], parse_keys("iq<esc>").unwrap());
+    }
+
+    #[test]
+    fn macros_can_be_edited_in_the_macros_buffer() {
+        let mut ed = editor_with_content("x\n");
+        type_keys(&mut ed, "qaAy<esc>q");
+        ed.edit_macros();
+        assert_eq!(ed.buffers.active().txt.to_string(), "a Ay<esc>\n");
+
+        // Switching away from the +macros buffer leaves it open for applying later
+        type_keys(&mut ed, "%d");
+        let s = "a Az<esc>\nb ^[^x\n".to_string();
+        ed.handle_action(Action::InsertString { s }, Source::Keyboard);
+        ed.handle_action(Action::PreviousBuffer, Source::Keyboard);
+        ed.apply_macros();
+
+        assert_eq!(ed.macros.registers[&'a'], parse_keys("Az<esc>").unwrap());
+        assert_eq!(ed.macros.registers[&'b'], vec![Input::CtrlAlt('x')]);
+    }
+
+    #[test]
+    fn saved_macros_replace_existing_ones_with_the_same_name() {
+        let path = env::temp_dir().join(format!("ad-macros-test-{}", std::process::id()));
+        let keys = parse_keys("dd").unwrap();
+
+        save_macro_to(&path, "delete", &parse_keys("d").unwrap()).unwrap();
+        save_macro_to(&path, "comment", &parse_keys("I#<20><esc>").unwrap()).unwrap();
+        save_macro_to(&path, "delete", &keys).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let macros = load_macros_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(content, "comment I#<20><esc>\ndelete dd\n");
+        assert_eq!(macros["delete"], keys);
+        assert!(save_macro_to(&path, "two words", &keys).is_err());
+    }
+}
@@ -43,6 +43,7 @@ where
     prompt: String,
     input: String,
     initial_lines: Vec<String>,
+    hint_lines: Vec<String>,
     line_indices: Vec<usize>,
     b: Buffer,
     max_height: usize,
@@ -79,6 +80,7 @@ where
             prompt,
             input: String::new(),
             initial_lines: lines,
+            hint_lines: Vec::new(),
             line_indices,
             b: Buffer::new_minibuffer(),
             max_height,
@@ -92,6 +94,20 @@ where
         }
     }
 
+    /// Pre-populate the user input for this minibuffer
+    fn with_input(mut self, input: String) -> Self {
+        self.x = input.len();
+        self.input = input;
+        self
+    }
+
+    /// Display the given lines above the prompt regardless of the current user input. Hint
+    /// lines are not selectable and are only shown if there are no other lines to select from.
+    fn with_hint_lines(mut self, hint_lines: Vec<String>) -> Self {
+        self.hint_lines = hint_lines;
+        self
+    }
+
     /// Force the cursor to be a single Cur and ensure that its y offset is in bounds
     #[inline]
     fn handle_on_change(&mut self) {
@@ -124,6 +140,10 @@ where
             }
         }
 
+        if self.initial_lines.is_empty() {
+            visible_lines.extend(self.hint_lines.iter().cloned());
+        }
+
         self.b.txt = GapBuffer::from(visible_lines.join("\n"));
         self.b.dot.clamp_idx(self.b.txt.len_chars());
 
@@ -173,12 +193,12 @@ where
                 self.x += 1;
                 self.handle_on_change();
             }
-            Input::Ctrl('h') | Input::Backspace | Input::Del => {
-                if self.x > 0 && self.x <= self.input.len() {
-                    self.input.remove(self.x - 1);
-                    self.x = self.x.saturating_sub(1);
-                    self.handle_on_change();
-                }
+            Input::Ctrl('h') | Input::Backspace | Input::Del
+                if self.x > 0 && self.x <= self.input.len() =>
+            {
+                self.input.remove(self.x - 1);
+                self.x = self.x.saturating_sub(1);
+                self.handle_on_change();
             }
 
             Input::Esc => return Some(MiniBufferSelection::Cancelled),
@@ -260,6 +280,87 @@ where
         }
     }
 
+    /// Use the minibuffer to prompt for user input, pre-populating the input and displaying
+    /// the given hint lines above the prompt. This is used to allow the user to correct a
+    /// previous input that was invalid.
+    pub(crate) fn minibuffer_prompt_with_hint(
+        &mut self,
+        prompt: &str,
+        input: String,
+        hint_lines: Vec<String>,
+    ) -> Option<String> {
+        trace!(%prompt, "opening mini-buffer with hint");
+        let mut mb = MiniBuffer::new(
+            prompt.to_string(),
+            vec![],
+            config_handle!().minibuffer_lines,
+            |_| None,
+        )
+        .with_input(input)
+        .with_hint_lines(hint_lines);
+
+        loop {
+            mb.update_state();
+            self.refresh_screen_w_minibuffer(Some(mb.current_state(self.screen_rows)));
+            let input = self.block_for_input();
+            match mb.handle_input(input) {
+                Some(MiniBufferSelection::UserInput { input }) => return Some(input),
+                Some(_) => return None,
+                None => (),
+            }
+        }
+    }
+
+    /// Use the minibuffer to prompt for user input, calling `on_change` each time the input is
+    /// modified so that the editor state can be updated while the user is typing. If `on_change`
+    /// returns a message then it is displayed above the prompt. The up and down arrow keys can
+    /// be used to cycle through the provided history of previous inputs.
+    pub(crate) fn minibuffer_prompt_interactive<F>(
+        &mut self,
+        prompt: &str,
+        history: &[String],
+        mut on_change: F,
+    ) -> Option<String>
+    where
+        F: FnMut(&mut Self, &str) -> Option<String>,
+    {
+        trace!(%prompt, "opening interactive mini-buffer");
+        let mut mb = MiniBuffer::new(
+            prompt.to_string(),
+            vec![],
+            config_handle!().minibuffer_lines,
+            |_| None,
+        );
+        let mut history_idx = history.len();
+        let mut prev_input = String::new();
+
+        loop {
+            mb.update_state();
+            self.refresh_screen_w_minibuffer(Some(mb.current_state(self.screen_rows)));
+
+            match self.block_for_input() {
+                Input::Alt('k') | Input::Arrow(Arrow::Up) if history_idx > 0 => {
+                    history_idx -= 1;
+                    mb = mb.with_input(history[history_idx].clone());
+                }
+                Input::Alt('j') | Input::Arrow(Arrow::Down) if history_idx < history.len() => {
+                    history_idx += 1;
+                    mb = mb.with_input(history.get(history_idx).cloned().unwrap_or_default());
+                }
+                input => match mb.handle_input(input) {
+                    Some(MiniBufferSelection::UserInput { input }) => return Some(input),
+                    Some(_) => return None,
+                    None => (),
+                },
+            }
+
+            if mb.input != prev_input {
+                prev_input.clone_from(&mb.input);
+                mb.hint_lines = on_change(self, &mb.input).into_iter().collect();
+            }
+        }
+    }
+
     /// Append ", continue? [y/n]: " to the prompt and return true if the user enters one of
     /// y, Y, yes, YES, Yes (otherwise return false)
     pub(crate) fn minibuffer_confirm(&mut self, prompt: &str) -> bool {
@@ -1,25 +1,29 @@
 //! The main control flow and functionality of the `ad` editor.
 use crate::{
-    buffer::{ActionOutcome, Buffer, Buffers},
+    buffer::{parse_diagnostics, parse_highlights, ActionOutcome, Buffer, Buffers},
     config::Config,
-    die,
-    dot::TextObject,
+    config_handle, die,
+    dot::{find::RegexFind, TextObject},
     exec::{Addr, Address},
-    fsys::{AdFs, InputFilter, LogEvent, Message, Req},
+    fsys::{claim_default_socket, AdFs, InputFilter, LogEvent, Message, Observed, Observers, Req},
     input::{Event, StdinInput},
     key::{Arrow, Input},
+    lsp::LspManager,
     mode::{modes, Mode},
     plumb::PlumbingRules,
     restore_terminal_state, set_config,
+    snippets::Snippet,
     system::{DefaultSystem, System},
     term::{
-        clear_screen, enable_alternate_screen, enable_mouse_support, enable_raw_mode, get_termios,
-        get_termsize, register_signal_handler,
+        clear_screen, enable_alternate_screen, enable_focus_reporting, enable_mouse_support,
+        enable_raw_mode, get_termios, get_termsize, register_signal_handler,
     },
     LogBuffer, ORIGINAL_TERMIOS,
 };
 use ad_event::Source;
+use ninep::namespace::remove_sockets;
 use std::{
+    collections::HashMap,
     env,
     io::{self, Stdout, Write},
     panic,
@@ -27,20 +31,32 @@ use std::{
     sync::mpsc::{channel, Receiver, Sender},
     time::Instant,
 };
-use tracing::{debug, trace, warn};
+use tracing::{debug, error, trace, warn};
 
 mod actions;
 mod built_in_commands;
 mod commands;
+mod complete;
+mod diagnostics;
+mod lsp;
+mod macros;
 mod minibuffer;
 mod mouse;
 mod render;
+mod repeat;
+mod replace;
+mod search;
+mod snippets;
 
 pub(crate) use actions::{Action, Actions, ViewPort};
 pub(crate) use built_in_commands::built_in_commands;
 pub(crate) use minibuffer::{MiniBufferSelection, MiniBufferState};
 
+use macros::Macros;
 use mouse::Click;
+use repeat::Repeat;
+use replace::PendingReplace;
+use search::{GrepGeneration, LastGrep};
 
 /// The mode that the [Editor] will run in following a call to [Editor::run].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -67,17 +83,35 @@ where
     status_time: Instant,
     modes: Vec<Mode>,
     pending_keys: Vec<Input>,
+    /// A count prefix typed in NORMAL mode
+    count: Option<usize>,
+    repeat: Repeat,
+    macros: Macros,
     buffers: Buffers,
     tx_events: Sender<Event>,
     rx_events: Receiver<Event>,
     tx_fsys: Sender<LogEvent>,
     rx_fsys: Option<Receiver<LogEvent>>,
+    observers: Observers,
+    /// The active buffer and mode as last reported to observers
+    observed_focus: usize,
+    observed_mode: String,
     mode: EditorMode,
     log_buffer: LogBuffer,
     plumbing_rules: PlumbingRules,
+    /// Snippets loaded for each file extension, cleared when the config is reloaded
+    snippets: HashMap<Option<String>, Vec<Snippet>>,
     held_click: Option<Click>,
     last_click_was_left: bool,
     last_click_time: Instant,
+    search: Option<RegexFind>,
+    search_hl: bool,
+    search_history: Vec<String>,
+    last_grep: Option<LastGrep>,
+    grep_gen: GrepGeneration,
+    replace_gen: GrepGeneration,
+    pending_replace: Option<PendingReplace>,
+    lsp: LspManager,
 }
 
 impl<S> Drop for Editor<S>
@@ -122,8 +156,11 @@ where
         let stdout = io::stdout();
         let (tx_events, rx_events) = channel();
         let (tx_fsys, rx_fsys) = channel();
+        let lsp = LspManager::new(tx_events.clone());
 
         set_config(cfg);
+        let modes = modes();
+        let observed_mode = modes[0].name.clone();
 
         Self {
             system,
@@ -134,19 +171,34 @@ where
             running: true,
             status_message: String::new(),
             status_time: Instant::now(),
-            modes: modes(),
+            modes,
             pending_keys: Vec::new(),
+            count: None,
+            repeat: Repeat::default(),
+            macros: Macros::default(),
             buffers: Buffers::new(),
             tx_events,
             rx_events,
             tx_fsys,
             rx_fsys: Some(rx_fsys),
+            observers: Observers::default(),
+            observed_focus: 0,
+            observed_mode,
             mode,
             log_buffer,
             plumbing_rules,
+            snippets: HashMap::new(),
             held_click: None,
             last_click_was_left: false,
             last_click_time: Instant::now(),
+            search: None,
+            search_hl: false,
+            search_history: Vec::new(),
+            last_grep: None,
+            grep_gen: GrepGeneration::default(),
+            replace_gen: GrepGeneration::default(),
+            pending_replace: None,
+            lsp,
         }
     }
 
@@ -176,13 +228,18 @@ where
     /// Initialise any UI state required for our [EditorMode] and run the main event loop.
     pub fn run(mut self) {
         let rx_fsys = self.rx_fsys.take().expect("to have fsys channels");
-        AdFs::new(self.tx_events.clone(), rx_fsys).run_threaded();
+        let fs = AdFs::new(self.tx_events.clone(), rx_fsys, self.observers.clone());
+        if let Err(e) = fs.run_threaded() {
+            error!("unable to start the filesystem interface: {e}");
+        }
         self.ensure_correct_fsys_state();
 
         match self.mode {
             EditorMode::Terminal => self.run_event_loop_with_screen_refresh(self.tx_events.clone()),
             EditorMode::Headless => self.run_event_loop(),
         }
+
+        remove_sockets();
     }
 
     #[inline]
@@ -191,7 +248,42 @@ where
             Event::Input(i) => self.handle_input(i),
             Event::Action(a) => self.handle_action(a, Source::Fsys),
             Event::Message(msg) => self.handle_message(msg),
+            Event::Lsp(evt) => self.handle_lsp_event(evt),
             Event::WinsizeChanged => self.update_window_size(),
+            Event::FocusGained => claim_default_socket(),
+        }
+
+        self.buffers.active_mut().sync_tabstops();
+        self.publish_observed_changes();
+    }
+
+    /// Send any changes made while handling the last event to readers of the events files and
+    /// to any running language servers.
+    fn publish_observed_changes(&mut self) {
+        let servers = config_handle!().lsp_servers.clone();
+        let mut errors = Vec::new();
+        self.lsp.close_stale(&self.buffers);
+
+        for b in self.buffers.iter_mut() {
+            let changes = b.pending_changes.take(b.dot);
+            errors.extend(self.lsp.sync_buffer(b, &changes, &servers, &self.cwd));
+            self.observers.publish(b.id, &changes);
+        }
+
+        if let Some(msg) = errors.pop() {
+            self.set_status_message(&msg);
+        }
+
+        let id = self.active_buffer_id();
+        if self.modes[0].name != self.observed_mode {
+            self.observed_mode = self.modes[0].name.clone();
+            let mode = Observed::Mode(self.observed_mode.clone());
+            self.observers.publish(id, &[mode]);
+        }
+
+        if id != self.observed_focus {
+            self.observed_focus = id;
+            self.observers.publish(id, &[Observed::Focus]);
         }
     }
 
@@ -238,6 +330,7 @@ where
         }));
 
         enable_mouse_support(&mut self.stdout);
+        enable_focus_reporting(&mut self.stdout);
         enable_alternate_screen(&mut self.stdout);
 
         // SAFETY: we only register our signal handler once
@@ -257,12 +350,18 @@ where
     }
 
     pub(crate) fn block_for_input(&mut self) -> Input {
+        if let Some(input) = self.next_macro_input() {
+            return input;
+        }
+
         loop {
             match self.rx_events.recv().unwrap() {
                 Event::Input(k) => return k,
                 Event::Action(a) => self.handle_action(a, Source::Fsys),
                 Event::Message(msg) => self.handle_message(msg),
+                Event::Lsp(evt) => self.handle_lsp_event(evt),
                 Event::WinsizeChanged => self.update_window_size(),
+                Event::FocusGained => claim_default_socket(),
             }
         }
     }
@@ -287,11 +386,11 @@ where
         }
     }
 
-    fn handle_buffer_mutation<F: FnOnce(&mut Buffer, String)>(
+    fn handle_buffer_mutation<T, F: FnOnce(&mut Buffer, T)>(
         &mut self,
         id: usize,
         tx: Sender<Result<String, String>>,
-        s: String,
+        s: T,
         f: F,
     ) {
         match self.buffers.with_id_mut(id) {
@@ -307,6 +406,23 @@ where
         }
     }
 
+    /// As [Editor::handle_buffer_mutation] but for requests that modify the text of the buffer,
+    /// which are rejected if the buffer is read-only.
+    fn handle_buffer_text_mutation<T, F: FnOnce(&mut Buffer, T)>(
+        &mut self,
+        id: usize,
+        tx: Sender<Result<String, String>>,
+        s: T,
+        f: F,
+    ) {
+        if self.buffers.with_id(id).is_some_and(|b| b.read_only) {
+            _ = tx.send(Err("Buffer is read-only".to_string()));
+            return;
+        }
+
+        self.handle_buffer_mutation(id, tx, s, f);
+    }
+
     fn handle_message(&mut self, Message { req, tx }: Message) {
         use Req::*;
 
@@ -324,27 +440,44 @@ where
                 default_handled();
             }
 
+            NewBuffer => {
+                let id = self.buffers.open_unnamed();
+                _ = self.tx_fsys.send(LogEvent::Open(id));
+                _ = tx.send(Ok(id.to_string()));
+            }
+
             ReadBufferName { id } => self.send_buffer_resp(id, tx, |b| b.full_name().to_string()),
             ReadBufferAddr { id } => self.send_buffer_resp(id, tx, |b| b.addr()),
             ReadBufferDot { id } => self.send_buffer_resp(id, tx, |b| b.dot_contents()),
             ReadBufferXAddr { id } => self.send_buffer_resp(id, tx, |b| b.xaddr()),
             ReadBufferXDot { id } => self.send_buffer_resp(id, tx, |b| b.xdot_contents()),
             ReadBufferBody { id } => self.send_buffer_resp(id, tx, |b| b.str_contents()),
+            ReadBufferCtl { id } => self.send_buffer_resp(id, tx, |b| b.metadata()),
+            ReadBufferHighlights { id } => {
+                self.send_buffer_resp(id, tx, |b| b.highlights.as_file_content())
+            }
+            ReadBufferDiagnostics { id } => {
+                self.send_buffer_resp(id, tx, |b| b.diagnostics_file_content())
+            }
 
-            SetBufferAddr { id, s } => self.handle_buffer_mutation(id, tx, s, |b, s| {
-                if let Ok(mut expr) = Addr::parse(&mut s.trim_end().chars().peekable()) {
+            SetBufferAddr { id, s } => match parse_addr(&s) {
+                Ok(Some(expr)) => self.handle_buffer_mutation(id, tx, expr, |b, mut expr| {
                     b.dot = b.map_addr(&mut expr);
-                };
-            }),
-            SetBufferDot { id, s } => self.handle_buffer_mutation(id, tx, s, |b, s| {
+                }),
+                Ok(None) => default_handled(),
+                Err(e) => _ = tx.send(Err(e)),
+            },
+            SetBufferDot { id, s } => self.handle_buffer_text_mutation(id, tx, s, |b, s| {
                 b.handle_action(Action::InsertString { s }, Source::Fsys);
             }),
-            SetBufferXAddr { id, s } => self.handle_buffer_mutation(id, tx, s, |b, s| {
-                if let Ok(mut expr) = Addr::parse(&mut s.trim_end().chars().peekable()) {
+            SetBufferXAddr { id, s } => match parse_addr(&s) {
+                Ok(Some(expr)) => self.handle_buffer_mutation(id, tx, expr, |b, mut expr| {
                     b.xdot = b.map_addr(&mut expr);
-                };
-            }),
-            SetBufferXDot { id, s } => self.handle_buffer_mutation(id, tx, s, |b, s| {
+                }),
+                Ok(None) => default_handled(),
+                Err(e) => _ = tx.send(Err(e)),
+            },
+            SetBufferXDot { id, s } => self.handle_buffer_text_mutation(id, tx, s, |b, s| {
                 let dot = b.dot;
                 b.dot = b.xdot;
                 b.handle_action(Action::InsertString { s }, Source::Fsys);
@@ -352,16 +485,32 @@ where
                 b.dot.clamp_idx(b.txt.len_chars()); // xdot already clamped as part of the insert
             }),
 
-            ClearBufferBody { id } => self.handle_buffer_mutation(id, tx, String::new(), |b, _| {
-                b.handle_action(Action::DotSet(TextObject::BufferStart, 1), Source::Fsys);
-                b.handle_action(
-                    Action::DotExtendForward(TextObject::BufferEnd, 1),
-                    Source::Fsys,
-                );
-                b.handle_action(Action::Delete, Source::Fsys);
-            }),
+            SetBufferHighlights { id, s } => match parse_highlights(&s) {
+                Ok(cmds) => self.handle_buffer_mutation(id, tx, cmds, |b, cmds| {
+                    b.highlights.apply(cmds);
+                }),
+                Err(e) => _ = tx.send(Err(e)),
+            },
+
+            SetBufferDiagnostics { id, s } => match parse_diagnostics(&s) {
+                Ok(cmds) => self.handle_buffer_mutation(id, tx, cmds, |b, cmds| {
+                    b.apply_diagnostics(cmds);
+                }),
+                Err(e) => _ = tx.send(Err(e)),
+            },
+
+            ClearBufferBody { id } => {
+                self.handle_buffer_text_mutation(id, tx, String::new(), |b, _| {
+                    b.handle_action(Action::DotSet(TextObject::BufferStart, 1), Source::Fsys);
+                    b.handle_action(
+                        Action::DotExtendForward(TextObject::BufferEnd, 1),
+                        Source::Fsys,
+                    );
+                    b.handle_action(Action::Delete, Source::Fsys);
+                })
+            }
 
-            AppendBufferBody { id, s } => self.handle_buffer_mutation(id, tx, s, |b, s| {
+            AppendBufferBody { id, s } => self.handle_buffer_text_mutation(id, tx, s, |b, s| {
                 b.append(s, Source::Fsys);
             }),
 
@@ -370,6 +519,13 @@ where
                 default_handled();
             }
 
+            BufferControlMessage { id, msg } => {
+                _ = tx.send(
+                    self.handle_buffer_ctl(id, &msg)
+                        .map(|_| "handled".to_string()),
+                );
+            }
+
             AddInputEventFilter { id, filter } => {
                 let resp = if self.try_set_input_filter(id, filter) {
                     Ok("handled".to_string())
@@ -397,10 +553,20 @@ where
     }
 
     fn handle_input(&mut self, input: Input) {
+        if self.try_stop_recording_macro(input) || self.try_push_count(input) {
+            return;
+        }
         self.pending_keys.push(input);
 
         if let Some(actions) = self.modes[0].handle_keys(&mut self.pending_keys) {
-            self.handle_actions(actions, Source::Keyboard);
+            let actions = self.apply_count(actions);
+            let mode = self.modes[0].name.clone();
+            self.handle_actions(actions.clone(), Source::Keyboard);
+            self.record_for_repeat(&mode, actions);
+        }
+
+        if self.pending_keys.is_empty() {
+            self.count = None;
         }
     }
 
@@ -425,8 +591,16 @@ where
             AppendToOutputBuffer { bufid, content } => self
                 .buffers
                 .write_output_for_buffer(bufid, content, &self.cwd),
+            AppendToReplaceBuffer { gen, name, hits } => {
+                self.append_to_replace_buffer(gen, name, hits)
+            }
+            AppendToSearchBuffer { gen, content } => self.append_to_search_buffer(gen, content),
             ChangeDirectory { path } => self.change_directory(path),
+            ClearSearchHighlight => self.search_hl = false,
             CommandMode => self.command_mode(),
+            Complete => self.complete(),
+            DiagnosticNext => self.diagnostic_next(true),
+            DiagnosticPrevious => self.diagnostic_next(false),
             DeleteBuffer { force } => self.delete_buffer(self.buffers.active().id, force),
             EditCommand { cmd } => self.execute_edit_command(&cmd),
             ExecuteDot => self.default_execute_dot(None, source),
@@ -435,8 +609,24 @@ where
             FindFile => self.find_file(),
             FindRepoFile => self.find_repo_file(),
             FocusBuffer { id } => self.focus_buffer(id),
+            Grep { pattern } => self.grep(pattern),
+            GrepRefresh => self.grep_refresh(),
             JumpListForward => self.jump_forward(),
+            ListDiagnostics => self.list_diagnostics(),
+            LspDefinition => self.lsp_goto_definition(),
+            LspHover => self.lsp_hover(),
+            LspReferences => self.lsp_references(),
+            LspRename { name } => self.lsp_rename(name),
+            LspStart => self.lsp_start(),
+            LspStop => self.lsp_stop(),
+            MacroEdit => self.edit_macros(),
+            MacroLoad { reg, name } => self.load_macro(reg, name),
+            MacroPlay { reg } => self.play_macro(reg),
+            MacroRecord { reg } => self.record_macro(reg),
+            MacroSave { reg, name } => self.save_macro(reg, name),
+            MacrosApply => self.apply_macros(),
             JumpListBack => self.jump_backward(),
+            JumpToMark { name } => self.jump_to_mark(name),
             LoadDot => self.default_load_dot(source),
             MarkClean { bufid } => self.mark_clean(bufid),
             NewEditLogTransaction => self.buffers.active_mut().new_edit_log_transaction(),
@@ -455,12 +645,23 @@ where
             ReloadActiveBuffer => self.reload_active_buffer(),
             ReloadBuffer { id } => self.reload_buffer(id),
             ReloadConfig => self.reload_config(),
+            Repeat => self.repeat_last_edit(),
+            ReplaceInFiles { input } => self.replace_in_files(&input),
+            ReplaceInFilesApply => self.replace_in_files_apply(),
             RunMode => self.run_mode(),
             SamMode => self.sam_mode(),
             SaveBufferAs { path, force } => self.save_current_buffer(Some(path), force),
             SaveBuffer { force } => self.save_current_buffer(None, force),
+            SearchIncremental => self.search_incremental(),
             SearchInCurrentBuffer => self.search_in_current_buffer(),
+            SearchNext => self.search_next(true),
+            SearchPrevious => self.search_next(false),
             SelectBuffer => self.select_buffer(),
+            SelectSnippet => self.select_snippet(),
+            SetMark { name } => {
+                let b = self.buffers.active_mut();
+                b.set_mark(name, b.dot);
+            }
             SetMode { m } => self.set_mode(m),
             SetStatusMessage { message } => self.set_status_message(&message),
             SetViewPort(vp) => {
@@ -472,6 +673,8 @@ where
             ShellReplace { cmd } => self.replace_dot_with_shell_cmd(&cmd),
             ShellRun { cmd } => self.run_shell_cmd(&cmd),
             ShowHelp => self.show_help(),
+            SnippetNext => self.snippet_next(source),
+            SnippetPrevious => self.snippet_previous(),
             UpdateConfig { input } => self.update_config(&input),
             ViewLogs => self.view_logs(),
             Yank => self.set_clipboard(self.buffers.active().dot_contents()),
@@ -517,6 +720,22 @@ where
         }
     }
 
+    fn jump_to_mark(&mut self, name: char) {
+        let mut dot = match self.buffers.active().mark(name) {
+            Some(dot) => dot,
+            None => {
+                self.set_status_message(&format!("mark '{name}' is not set"));
+                return;
+            }
+        };
+
+        self.buffers.record_jump_position();
+        let b = self.buffers.active_mut();
+        dot.clamp_idx(b.txt.len_chars());
+        b.dot = dot;
+        b.set_view_port(ViewPort::Center, self.screen_rows, self.screen_cols);
+    }
+
     fn forward_action_to_active_buffer(&mut self, a: Action, source: Source) {
         if let Some(o) = self.buffers.active_mut().handle_action(a, source) {
             match o {
@@ -550,3 +769,53 @@ where
         }
     }
 }
+
+/// Parse an address written to a buffer's addr or xaddr file so that invalid addresses can be
+/// reported back to the client rather than being silently ignored.
+///
+/// Empty writes (such as the zero length write that clients send to mark the end of their data)
+/// leave the address unchanged.
+fn parse_addr(s: &str) -> Result<Option<Addr>, String> {
+    let s = s.trim_end();
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    let syntax = config_handle!().regex_syntax;
+    Addr::parse(&mut s.chars().peekable(), syntax)
+        .map(Some)
+        .map_err(|e| format!("invalid address {s:?}: {e:?}"))
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use crate::{key::parse_keys, LogBuffer};
+
+    /// A headless editor using the default config.
+    pub(crate) fn headless_editor() -> Editor<DefaultSystem> {
+        Editor::new(
+            Config::default(),
+            PlumbingRules::default(),
+            EditorMode::Headless,
+            LogBuffer::default(),
+        )
+    }
+
+    /// A headless editor whose active buffer contains `s` with the cursor at the end of it.
+    pub(crate) fn editor_with_content(s: &str) -> Editor<DefaultSystem> {
+        let mut ed = headless_editor();
+        let s = s.to_string();
+        ed.handle_action(Action::InsertString { s }, Source::Keyboard);
+
+        ed
+    }
+
+    /// Send keys to the editor as if they had been typed, using the text form of key sequences
+    /// described in [crate::key].
+    pub(crate) fn type_keys<S: System>(ed: &mut Editor<S>, keys: &str) {
+        for i in parse_keys(keys).expect("valid keys") {
+            ed.handle_event(Event::Input(i));
+        }
+    }
+}
@@ -1,11 +1,11 @@
 //! Rendering the user interface
 use crate::{
-    buffer::Buffer,
+    buffer::{Buffer, Severity},
     config::ColorScheme,
     config_handle, die,
     dot::Range,
-    editor::{Editor, MiniBufferState},
-    key::{Input, MouseButton},
+    editor::{Editor, EditorMode, MiniBufferState},
+    key::{render_keys, Input, MouseButton},
     system::System,
     term::{Cursor, Style},
     VERSION,
@@ -26,6 +26,11 @@ where
     // FIXME: This has an implicit "only one buffer is active at a time" assumption which needs to
     // be reworked to support multiple buffers open at once.
     pub(crate) fn refresh_screen_w_minibuffer(&mut self, mb: Option<MiniBufferState<'_>>) {
+        // There is no screen to draw to when running headless
+        if self.mode == EditorMode::Headless {
+            return;
+        }
+
         let w_minibuffer = mb.is_some();
         let MiniBufferState {
             cx,
@@ -102,6 +107,8 @@ where
         cs: &ColorScheme,
     ) {
         let is_empty_scratch = self.buffers.is_empty_scratch();
+        // A single regex is reused for every line rather than being cloned for each one
+        let mut search = self.search.as_mut().filter(|_| self.search_hl);
         let b = self.buffers.active_mut();
 
         // Sort out dimensions of the sign/number column
@@ -134,10 +141,26 @@ where
                     }
                 }
             } else {
-                // +2 for the leading space and vline chars
+                // +2 for the leading sign and vline chars
                 let padding = w_lnum + 2;
+                let sign = match b.diagnostic_sign(file_row) {
+                    Some(sev) => {
+                        let color = match sev {
+                            Severity::Error => cs.error,
+                            Severity::Warning => cs.warning,
+                            Severity::Info => cs.info,
+                        };
+                        format!(
+                            "{}{}{}",
+                            Style::Fg(color),
+                            sev.glyph(),
+                            Style::Fg(cs.signcol_fg)
+                        )
+                    }
+                    None => " ".to_string(),
+                };
                 buf.push_str(&format!(
-                    "{}{} {:>width$}{VLINE}{}{}",
+                    "{}{}{sign}{:>width$}{VLINE}{}{}",
                     Style::Fg(cs.signcol_fg),
                     Style::Bg(cs.bg),
                     file_row + 1,
@@ -147,6 +170,7 @@ where
                         padding,
                         self.screen_cols,
                         load_exec_range,
+                        search.as_deref_mut(),
                         cs
                     ),
                     width = w_lnum
@@ -184,7 +208,10 @@ where
         let mut msg = self.status_message.clone();
         msg.truncate(self.screen_cols.saturating_sub(10));
 
-        let pending = render_pending(&self.pending_keys);
+        let pending = match self.count {
+            Some(n) => format!("{n}{}", render_pending(&self.pending_keys)),
+            None => render_pending(&self.pending_keys),
+        };
         let delta = (Instant::now() - self.status_time).as_secs();
 
         if !msg.is_empty() && delta < status_timeout {
@@ -251,30 +278,7 @@ where
 }
 
 fn render_pending(keys: &[Input]) -> String {
-    let mut s = String::new();
-    for k in keys {
-        match k {
-            Input::Char(c) if c.is_ascii_whitespace() => s.push_str(&format!("<{:x}>", *c as u8)),
-            Input::Char(c) => s.push(*c),
-            Input::Ctrl(c) => {
-                s.push('^');
-                s.push(*c);
-            }
-            Input::Alt(c) => {
-                s.push('^');
-                s.push('[');
-                s.push(*c);
-            }
-            Input::CtrlAlt(c) => {
-                s.push('^');
-                s.push('[');
-                s.push('^');
-                s.push(*c);
-            }
-
-            _ => (),
-        }
-    }
+    let mut s = render_keys(keys);
 
     if s.len() > 10 {
         s = s.split_off(s.len() - 10);
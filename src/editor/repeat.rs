@@ -0,0 +1,297 @@
+//! Repeating the last edit made from NORMAL mode.
+//!
+//! An edit is a NORMAL mode key binding that modifies the buffer or enters INSERT mode. When it
+//! enters INSERT mode the edit continues until we return to NORMAL mode and the text typed in
+//! the meantime is recorded along with it. If the INSERT session only added text at the cursor
+//! then that text is replayed directly (covering completions and snippets) and otherwise the
+//! actions run by each key are replayed in turn.
+use crate::{
+    config_handle,
+    editor::{Action, Actions, Editor},
+    key::Input,
+    system::System,
+};
+use ad_event::Source;
+
+/// The last completed edit along with the one currently being recorded (if any).
+#[derive(Debug, Default)]
+pub(super) struct Repeat {
+    last: Vec<Action>,
+    pending: Option<PendingEdit>,
+}
+
+/// An edit that is waiting on the INSERT session it started to finish.
+#[derive(Debug)]
+struct PendingEdit {
+    actions: Vec<Action>,
+    inserted: Vec<Action>,
+    bufid: usize,
+    len: usize,
+    start: usize,
+    end: usize,
+    min: usize,
+}
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Accumulate a count prefix for the next NORMAL mode key binding, returning false if
+    /// `input` is not part of a count. Digits that start a user provided mapping are left
+    /// alone so that the mapping can still be run.
+    pub(super) fn try_push_count(&mut self, input: Input) -> bool {
+        if self.modes[0].name != "NORMAL" || !self.pending_keys.is_empty() {
+            return false;
+        }
+
+        let d = match input {
+            Input::Char(c) => match c.to_digit(10) {
+                Some(0) if self.count.is_none() => return false,
+                Some(d) => d as usize,
+                None => return false,
+            },
+            _ => return false,
+        };
+
+        if config_handle!()
+            .bindings
+            .keys()
+            .any(|keys| keys.first() == Some(&input))
+        {
+            return false;
+        }
+
+        let n = self.count.unwrap_or(0);
+        self.count = Some(n.saturating_mul(10).saturating_add(d));
+
+        true
+    }
+
+    /// Apply a pending count prefix to the actions run by a key binding. Bindings made up of
+    /// only motions move that many times, repeat and macro playback read the count themselves
+    /// and for anything else the count is ignored.
+    pub(super) fn apply_count(&mut self, actions: Actions) -> Actions {
+        let n = match self.count {
+            Some(n) => n,
+            None => return actions,
+        };
+        let mut actions = match actions {
+            Actions::Single(action) => vec![action],
+            Actions::Multi(actions) => actions,
+        };
+
+        if actions.iter().all(is_motion) {
+            self.count = None;
+            for action in actions.iter_mut() {
+                if let Action::DotSet(_, m)
+                | Action::DotExtendForward(_, m)
+                | Action::DotExtendBackward(_, m) = action
+                {
+                    *m = m.saturating_mul(n);
+                }
+            }
+        } else if !actions
+            .iter()
+            .any(|a| matches!(a, Action::Repeat | Action::MacroPlay { .. }))
+        {
+            self.count = None;
+            self.set_status_message(&format!(
+                "count {n} ignored: only motions, repeat and macros use a count"
+            ));
+        }
+
+        Actions::Multi(actions)
+    }
+
+    /// Record the actions run by a key binding from `mode` so that completed edits can be
+    /// repeated.
+    pub(super) fn record_for_repeat(&mut self, mode: &str, actions: Actions) {
+        let actions = match actions {
+            Actions::Single(action) => vec![action],
+            Actions::Multi(actions) => actions,
+        };
+        let in_insert = self.modes[0].name == "INSERT";
+
+        if mode == "NORMAL" && actions.iter().any(is_edit) {
+            if in_insert {
+                let b = self.buffers.active();
+                let idx = b.dot.active_cur().idx;
+                self.repeat.pending = Some(PendingEdit {
+                    actions,
+                    inserted: Vec::new(),
+                    bufid: b.id,
+                    len: b.txt.len_chars(),
+                    start: idx,
+                    end: idx,
+                    min: idx,
+                });
+            } else {
+                self.repeat.pending = None;
+                self.repeat.last = actions;
+            }
+        } else if mode == "INSERT" {
+            let mut p = match self.repeat.pending.take() {
+                Some(p) => p,
+                None => return,
+            };
+
+            if in_insert {
+                let idx = self.buffers.active().dot.active_cur().idx;
+                p.end = idx;
+                p.min = p.min.min(idx);
+                p.inserted.extend(actions);
+                self.repeat.pending = Some(p);
+            } else {
+                self.repeat.last = self.finish_pending_edit(p);
+            }
+        }
+    }
+
+    fn finish_pending_edit(&self, p: PendingEdit) -> Vec<Action> {
+        let b = self.buffers.active();
+        let only_typed =
+            b.id == p.bufid && p.min >= p.start && b.txt.len_chars() == p.len + (p.end - p.start);
+
+        let mut actions = p.actions;
+        if only_typed {
+            if p.end > p.start {
+                let s = b.txt.slice(p.start, p.end).to_string();
+                actions.push(Action::InsertString { s });
+            }
+        } else {
+            actions.extend(p.inserted.into_iter().filter(|a| !is_interactive(a)));
+        }
+        actions.push(Action::SetMode { m: "NORMAL" });
+
+        actions
+    }
+
+    /// Replay the last edit at the current dot, repeating it as many times as the count
+    /// prefix (if there was one) as a single edit log transaction.
+    pub(super) fn repeat_last_edit(&mut self) {
+        let count = self.count.take().unwrap_or(1);
+        if self.repeat.last.is_empty() {
+            self.set_status_message("nothing to repeat");
+            return;
+        }
+
+        let actions: Vec<Action> = self
+            .repeat
+            .last
+            .iter()
+            .filter(|a| **a != Action::NewEditLogTransaction)
+            .cloned()
+            .collect();
+
+        self.buffers.active_mut().new_edit_log_transaction();
+        for _ in 0..count {
+            self.handle_actions(Actions::Multi(actions.clone()), Source::Keyboard);
+        }
+        self.buffers.active_mut().new_edit_log_transaction();
+    }
+}
+
+fn is_edit(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::Delete
+            | Action::InsertChar { .. }
+            | Action::InsertString { .. }
+            | Action::Paste
+            | Action::SetMode { m: "INSERT" }
+    )
+}
+
+fn is_motion(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::DotSet(..)
+            | Action::DotExtendForward(..)
+            | Action::DotExtendBackward(..)
+            | Action::DotCollapseFirst
+            | Action::DotCollapseLast
+    )
+}
+
+/// Actions that prompt the user and so can't be replayed as part of an edit.
+fn is_interactive(action: &Action) -> bool {
+    matches!(action, Action::Complete | Action::SelectSnippet)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dot::{Cur, Dot},
+        editor::test_utils::{editor_with_content, type_keys},
+    };
+    use simple_test_case::test_case;
+
+    #[test_case("xd", 4, "one\nix\n"; "delete")]
+    #[test_case("xcnew<esc>", 0, "newne\nnewsix\n"; "change")]
+    #[test_case("I><20><esc>", 10, "one\n> two\n> six\n"; "insert")]
+    #[test_case("Ia<bs>b<esc>", 9, "one\nbtwo\nbsix\n"; "insert with backspace")]
+    #[test_case("I<bs>!<esc>", 8, "one!two!six\n"; "backspace past the start of the insert")]
+    #[test]
+    fn repeat_replays_the_last_edit(edit: &str, repeat_at: usize, expected: &str) {
+        let mut ed = editor_with_content("one\ntwo\nsix\n");
+        ed.buffers.active_mut().dot = Dot::Cur { c: Cur { idx: 4 } };
+        type_keys(&mut ed, edit);
+        ed.buffers.active_mut().dot = Dot::Cur {
+            c: Cur { idx: repeat_at },
+        };
+        type_keys(&mut ed, ".");
+
+        assert_eq!(ed.buffers.active().txt.to_string(), expected);
+        assert_eq!(ed.modes[0].name, "NORMAL");
+    }
+
+    #[test]
+    fn repeat_uses_the_count_prefix() {
+        let mut ed = editor_with_content("a\n");
+        type_keys(&mut ed, "ggA!<esc>12.");
+
+        assert_eq!(ed.buffers.active().txt.to_string(), "a!!!!!!!!!!!!!\n");
+        assert_eq!(ed.count, None);
+
+        // The whole repeat is undone in one go
+        type_keys(&mut ed, "u");
+        assert_eq!(ed.buffers.active().txt.to_string(), "a!\n");
+    }
+
+    #[test_case("3j", "jjj"; "down")]
+    #[test_case("2w", "ww"; "words")]
+    #[test_case("2J", "JJ"; "extend")]
+    #[test_case("40l", "ggge"; "clamped to the buffer")]
+    #[test]
+    fn motions_use_the_count_prefix(counted: &str, repeated: &str) {
+        let content = "one two\nthree\nfour five\nsix\n";
+        let mut ed = editor_with_content(content);
+        type_keys(&mut ed, "gg");
+        type_keys(&mut ed, counted);
+
+        let mut expected = editor_with_content(content);
+        type_keys(&mut expected, "gg");
+        type_keys(&mut expected, repeated);
+
+        assert_eq!(ed.buffers.active().dot, expected.buffers.active().dot);
+        assert_eq!(ed.count, None);
+    }
+
+    #[test]
+    fn counts_are_ignored_for_other_bindings() {
+        let mut ed = editor_with_content("abc\n");
+        type_keys(&mut ed, "gg2d");
+
+        assert_eq!(ed.buffers.active().txt.to_string(), "bc\n");
+        assert_eq!(ed.count, None);
+        assert!(ed.status_message.contains("count 2 ignored"));
+    }
+
+    #[test]
+    fn motions_are_not_recorded() {
+        let mut ed = editor_with_content("abcd\n");
+        type_keys(&mut ed, "ggdll.");
+
+        assert_eq!(ed.buffers.active().txt.to_string(), "bc\n");
+    }
+}
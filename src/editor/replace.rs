@@ -0,0 +1,589 @@
+//! Project wide search and replace.
+//!
+//! Running `replace /re/template/ [glob]` collects every line under the working directory that
+//! matches the regex and writes each one into a +replace buffer for review as a pair of lines:
+//! the original prefixed with '-' and the result of applying the replacement prefixed with '+'.
+//! Hits can be deleted or edited in place before running `replace-apply` to write the remaining
+//! replacements back to open buffers (as a single undo transaction per buffer) or directly to
+//! files on disk. Only the '+' lines are used when applying, so deleting a hit requires
+//! deleting its '+' line.
+use crate::{
+    buffer::{Buffer, BufferKind, REPLACE_BUFFER},
+    config_handle,
+    editor::{Action, Editor},
+    exec::Edit,
+    grep::{glob_matches, read_text_file, replace_matches, walk_files},
+    input::Event,
+    regex::Regex,
+    system::System,
+};
+use ad_event::Source;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    thread::spawn,
+};
+
+/// Line numbers (1-based) mapped to the original and replacement text for that line.
+type LineEdits = BTreeMap<usize, (String, String)>;
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Collect all replacements for the given "/re/template/ [glob]" input into a +replace
+    /// buffer for review. Files are searched on a background thread with the results being
+    /// streamed into the buffer as they are found.
+    pub(super) fn replace_in_files(&mut self, input: &str) {
+        let (pattern, template, glob) = match parse_replace_args(input) {
+            Ok(args) => args,
+            Err(msg) => {
+                self.set_status_message(&msg);
+                return;
+            }
+        };
+
+        let syntax = config_handle!().regex_syntax;
+        if let Err(e) = Regex::compile_with_syntax(&pattern, syntax) {
+            self.set_status_message(&format!("invalid regex: {e}"));
+            return;
+        }
+
+        // Open buffers may have changes that are yet to be saved so we match against them
+        // rather than the file on disk.
+        let open: HashMap<PathBuf, Vec<String>> = self
+            .buffers
+            .iter()
+            .filter_map(|b| match &b.kind {
+                BufferKind::File(path) => Some((path.clone(), b.string_lines())),
+                _ => None,
+            })
+            .collect();
+
+        // Bumping the generation stops any search that is still running from writing its results
+        let gen = self.replace_gen.fetch_add(1, Ordering::Relaxed) + 1;
+        let dir = self.cwd.clone();
+        let bufid = self
+            .buffers
+            .reset_output_buffer(format!("{}/{REPLACE_BUFFER}", dir.display()));
+        self.pending_replace = Some(PendingReplace {
+            dir: dir.clone(),
+            bufid,
+            originals: BTreeMap::new(),
+        });
+        self.set_status_message(&format!("replace: searching for '{pattern}'..."));
+
+        let current_gen = self.replace_gen.clone();
+        let tx = self.tx_events.clone();
+
+        spawn(move || {
+            // Regex is not Send so we need to compile it again on this thread
+            let mut re = match Regex::compile_with_syntax(&pattern, syntax) {
+                Ok(re) => re,
+                Err(_) => return,
+            };
+            let is_current = || current_gen.load(Ordering::Relaxed) == gen;
+            let (mut n_hits, mut n_files) = (0, 0);
+            let mut err = None;
+
+            walk_files(&dir, |path| {
+                if !is_current() {
+                    return false;
+                }
+
+                let name = path
+                    .strip_prefix(&dir)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string();
+                if let Some(glob) = &glob {
+                    if !glob_matches(glob, &name) {
+                        return true;
+                    }
+                }
+
+                let lines = match open.get(path) {
+                    Some(lines) => lines.clone(),
+                    None => match read_text_file(path) {
+                        Some(s) => s.lines().map(|l| l.to_string()).collect(),
+                        None => return true,
+                    },
+                };
+
+                let mut hits = Vec::new();
+                for (i, line) in lines.into_iter().enumerate() {
+                    let col = match re.match_str(&line) {
+                        Some(m) => m.loc().0,
+                        None => continue,
+                    };
+                    let replacement = match replace_matches(&mut re, &line, &template, &name) {
+                        Ok(Some(replacement)) => replacement,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            err = Some(e);
+                            return false;
+                        }
+                    };
+
+                    hits.push(ReplaceHit {
+                        line: i + 1,
+                        col: col + 1,
+                        original: line,
+                        replacement,
+                    });
+                }
+
+                if hits.is_empty() {
+                    return true;
+                }
+                n_hits += hits.len();
+                n_files += 1;
+
+                tx.send(Event::Action(Action::AppendToReplaceBuffer {
+                    gen,
+                    name,
+                    hits,
+                }))
+                .is_ok()
+            });
+
+            if !is_current() {
+                return;
+            }
+            let message = match err {
+                Some(e) => format!("invalid replacement: {e}"),
+                None if n_hits == 0 => format!("replace: no matches for '{pattern}'"),
+                None => format!(
+                    "replace: {n_hits} hits in {n_files} files, review and then run 'replace-apply'"
+                ),
+            };
+            _ = tx.send(Event::Action(Action::SetStatusMessage { message }));
+        });
+    }
+
+    /// Append hits from a running replace to its +replace buffer so long as it is still the
+    /// most recent replace and has not yet been applied.
+    pub(super) fn append_to_replace_buffer(
+        &mut self,
+        gen: usize,
+        name: String,
+        hits: Vec<ReplaceHit>,
+    ) {
+        if gen != self.replace_gen.load(Ordering::Relaxed) {
+            return;
+        }
+        let pending = match self.pending_replace.as_mut() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let b = match self.buffers.with_id_mut(pending.bufid) {
+            Some(b) => b,
+            None => return,
+        };
+
+        let mut content = String::new();
+        let originals = pending.originals.entry(name.clone()).or_default();
+        for hit in hits.into_iter() {
+            let loc = format!("{name}:{}:{}:", hit.line, hit.col);
+            content.push_str(&format!("{loc} - {}\n", hit.original));
+            content.push_str(&format!("{loc} + {}\n", hit.replacement));
+            originals.insert(hit.line, hit.original);
+        }
+        b.append(content, Source::Fsys);
+    }
+
+    /// Apply the replacements remaining in the +replace buffer of the most recent call to
+    /// [Editor::replace_in_files].
+    pub(super) fn replace_in_files_apply(&mut self) {
+        let PendingReplace {
+            dir,
+            bufid,
+            originals,
+        } = match self.pending_replace.take() {
+            Some(pending) => pending,
+            None => {
+                self.set_status_message("no pending replacements");
+                return;
+            }
+        };
+        // Stop any search that is still running from adding further hits
+        self.replace_gen.fetch_add(1, Ordering::Relaxed);
+
+        let lines = match self.buffers.with_id(bufid) {
+            Some(b) => b.string_lines(),
+            None => {
+                self.set_status_message("the +replace buffer has been closed");
+                return;
+            }
+        };
+
+        let mut conflicts = 0;
+        let mut edits: BTreeMap<String, LineEdits> = BTreeMap::new();
+        for line in lines.iter().filter(|l| !l.is_empty()) {
+            let is_name = |name: &str| originals.contains_key(name);
+            let original = match parse_review_line(line, is_name) {
+                Some((_, _, ReviewText::Original(_))) => continue,
+                Some((name, n, ReviewText::Replacement(replacement))) => originals
+                    .get(name)
+                    .and_then(|lines| lines.get(&n))
+                    .map(|original| (name, n, original, replacement)),
+                None => None,
+            };
+
+            match original {
+                Some((name, n, original, replacement)) => {
+                    edits
+                        .entry(name.to_string())
+                        .or_default()
+                        .insert(n, (original.clone(), replacement.to_string()));
+                }
+                None => conflicts += 1,
+            }
+        }
+
+        let (mut n_lines, mut n_files, mut read_only) = (0, 0, 0);
+        let mut errors = Vec::new();
+        for (name, line_edits) in edits.into_iter() {
+            let path = dir.join(&name);
+            let applied = match self.buffers.with_path_mut(&path) {
+                Some(b) if b.read_only => {
+                    read_only += line_edits.len();
+                    continue;
+                }
+                Some(b) => apply_to_buffer(b, &line_edits),
+                None => match apply_to_file(&path, &line_edits) {
+                    Ok(applied) => applied,
+                    Err(e) => {
+                        errors.push(format!("{name}: {e}"));
+                        continue;
+                    }
+                },
+            };
+
+            conflicts += line_edits.len() - applied;
+            n_lines += applied;
+            if applied > 0 {
+                n_files += 1;
+            }
+        }
+
+        let mut msg = format!("replaced {n_lines} lines in {n_files} files");
+        if conflicts > 0 {
+            msg.push_str(&format!(", skipped {conflicts} lines that no longer match"));
+        }
+        if read_only > 0 {
+            msg.push_str(&format!(", skipped {read_only} lines in read-only buffers"));
+        }
+        if !errors.is_empty() {
+            msg.push_str(&format!(", failed to write: {}", errors.join(", ")));
+        }
+        self.set_status_message(&msg);
+    }
+}
+
+/// A set of replacements that are awaiting review in a +replace buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PendingReplace {
+    dir: PathBuf,
+    bufid: usize,
+    /// The original content of each line containing a hit, keyed by file name and line number
+    /// so that lines that have changed since the hits were collected can be skipped.
+    originals: BTreeMap<String, BTreeMap<usize, String>>,
+}
+
+/// A line containing a match that was found while collecting replacements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaceHit {
+    line: usize,
+    col: usize,
+    original: String,
+    replacement: String,
+}
+
+/// Parse "/re/template/ [glob]" where the first character is used as the delimiter and can be
+/// escaped with a backslash in order to include it in either the regex or the template.
+fn parse_replace_args(input: &str) -> Result<(String, String, Option<String>), String> {
+    let mut it = input.trim_start().chars();
+    let delim = match it.next() {
+        Some(ch) if !ch.is_alphanumeric() && !ch.is_whitespace() => ch,
+        _ => return Err("expected a delimited regex and replacement ('/re/template/')".into()),
+    };
+
+    let mut parts = [String::new(), String::new()];
+    for part in parts.iter_mut() {
+        let mut closed = false;
+        while let Some(ch) = it.next() {
+            match ch {
+                ch if ch == delim => {
+                    closed = true;
+                    break;
+                }
+                '\\' => match it.next() {
+                    Some(ch) if ch == delim => part.push(ch),
+                    Some(ch) => {
+                        part.push('\\');
+                        part.push(ch);
+                    }
+                    None => part.push('\\'),
+                },
+                ch => part.push(ch),
+            }
+        }
+
+        if !closed {
+            return Err(format!("unclosed delimiter '{delim}'"));
+        }
+    }
+
+    let [pattern, template] = parts;
+    if pattern.is_empty() {
+        return Err("empty regex".to_string());
+    }
+    let glob = it.as_str().trim();
+    let glob = (!glob.is_empty()).then(|| glob.to_string());
+
+    Ok((pattern, template, glob))
+}
+
+/// The text of a hit in a +replace buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReviewText<'a> {
+    Original(&'a str),
+    Replacement(&'a str),
+}
+
+/// Split a "name:line:col: - original" or "name:line:col: + replacement" line from a +replace
+/// buffer into its file name, line number and text. File names may themselves contain ':' so
+/// only prefixes accepted by `is_name` are considered.
+fn parse_review_line(
+    line: &str,
+    is_name: impl Fn(&str) -> bool,
+) -> Option<(&str, usize, ReviewText<'_>)> {
+    line.match_indices(':').find_map(|(i, _)| {
+        let name = &line[..i];
+        if !is_name(name) {
+            return None;
+        }
+        let (n, rest) = line[i + 1..].split_once(':')?;
+        let (col, text) = rest.split_once(':')?;
+        col.parse::<usize>().ok()?;
+        let n = n.parse().ok().filter(|&n| n > 0)?;
+
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        let (marker, text) = text.split_at_checked(1)?;
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        let text = match marker {
+            "-" => ReviewText::Original(text),
+            "+" => ReviewText::Replacement(text),
+            _ => return None,
+        };
+
+        Some((name, n, text))
+    })
+}
+
+/// Apply edits to an open buffer as a single transaction in the edit log, returning the number
+/// of lines that were replaced. Lines that no longer match their original content are skipped.
+fn apply_to_buffer(b: &mut Buffer, edits: &LineEdits) -> usize {
+    let lines = b.string_lines();
+    let dot = b.dot;
+    let mut applied = 0;
+
+    b.begin_edit_transaction();
+    // Working from the end of the buffer means that earlier line offsets remain valid
+    for (&n, (original, replacement)) in edits.iter().rev() {
+        if lines.get(n - 1) != Some(original) {
+            continue;
+        }
+
+        let from = b.txt.line_to_char(n - 1);
+        b.remove(from, from + original.chars().count());
+        if !replacement.is_empty() {
+            b.insert(from, replacement);
+        }
+        applied += 1;
+    }
+    b.end_edit_transaction();
+
+    b.dot = dot;
+    b.dot.clamp_idx(b.txt.len_chars());
+
+    applied
+}
+
+/// Apply edits to a file on disk, returning the number of lines that were replaced. Lines that
+/// no longer match their original content are skipped.
+fn apply_to_file(path: &Path, edits: &LineEdits) -> Result<usize, String> {
+    let s = read_text_file(path).ok_or_else(|| "unable to read file".to_string())?;
+    let (updated, applied) = replace_lines(&s, edits);
+    if applied > 0 {
+        fs::write(path, updated).map_err(|e| e.to_string())?;
+    }
+
+    Ok(applied)
+}
+
+/// Replace the given lines within `s`, preserving existing line endings.
+fn replace_lines(s: &str, edits: &LineEdits) -> (String, usize) {
+    let mut output = String::with_capacity(s.len());
+    let mut applied = 0;
+
+    for (i, raw) in s.split_inclusive('\n').enumerate() {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        match edits.get(&(i + 1)) {
+            Some((original, replacement)) if original == line => {
+                output.push_str(replacement);
+                output.push_str(&raw[line.len()..]);
+                applied += 1;
+            }
+            _ => output.push_str(raw),
+        }
+    }
+
+    (output, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::test_utils::headless_editor;
+    use simple_test_case::test_case;
+    use std::{env, process, time::Duration};
+    use ReviewText::*;
+
+    fn edits(raw: &[(usize, &str, &str)]) -> LineEdits {
+        raw.iter()
+            .map(|&(n, o, r)| (n, (o.to_string(), r.to_string())))
+            .collect()
+    }
+
+    #[test_case("/foo/bar/", Ok(("foo", "bar", None)); "no glob")]
+    #[test_case("/foo/bar/ *.rs", Ok(("foo", "bar", Some("*.rs"))); "glob")]
+    #[test_case("/foo//", Ok(("foo", "", None)); "empty template")]
+    #[test_case("#a/b#c#", Ok(("a/b", "c", None)); "alternate delimiter")]
+    #[test_case("/a\\/b/c\\/d/", Ok(("a/b", "c/d", None)); "escaped delimiter")]
+    #[test_case("/\\w+/$0/", Ok(("\\w+", "$0", None)); "other escapes are kept")]
+    #[test_case("/foo/bar", Err("unclosed delimiter '/'"); "unclosed")]
+    #[test_case("//bar/", Err("empty regex"); "empty regex")]
+    #[test]
+    fn parse_replace_args_works(input: &str, expected: Result<(&str, &str, Option<&str>), &str>) {
+        let expected = expected
+            .map(|(p, t, g)| (p.to_string(), t.to_string(), g.map(|g| g.to_string())))
+            .map_err(|e| e.to_string());
+
+        assert_eq!(parse_replace_args(input), expected);
+    }
+
+    #[test_case("src/main.rs:12:5: + let x = 1;", Some(("src/main.rs", 12, Replacement("let x = 1;"))); "hit")]
+    #[test_case("src/main.rs:12:5: - let y = 1;", Some(("src/main.rs", 12, Original("let y = 1;"))); "original")]
+    #[test_case("b:c.txt:2:1: + x", Some(("b:c.txt", 2, Replacement("x"))); "colon in name")]
+    #[test_case("unknown.txt:2:1: + x", None; "unknown name")]
+    #[test_case("a.txt:3:1: + a: b", Some(("a.txt", 3, Replacement("a: b"))); "colon in text")]
+    #[test_case("a.txt:3:1: + ", Some(("a.txt", 3, Replacement(""))); "empty replacement")]
+    #[test_case("a.txt:3:1:+", Some(("a.txt", 3, Replacement(""))); "trimmed empty replacement")]
+    #[test_case("a.txt:3:1: x", None; "missing marker")]
+    #[test_case("a.txt:3:1:", None; "missing text")]
+    #[test_case("a.txt:x:1: + b", None; "invalid line number")]
+    #[test_case("a.txt:0:1: + b", None; "zero line number")]
+    #[test_case("just some text", None; "not a hit")]
+    #[test]
+    fn parse_review_line_works(line: &str, expected: Option<(&str, usize, ReviewText<'_>)>) {
+        let names = ["src/main.rs", "a.txt", "b:c.txt"];
+
+        assert_eq!(parse_review_line(line, |n| names.contains(&n)), expected);
+    }
+
+    #[test_case("a\nfoo\nb\n", &[(2, "foo", "bar")], "a\nbar\nb\n", 1; "single line")]
+    #[test_case("a\r\nfoo\r\n", &[(2, "foo", "bar")], "a\r\nbar\r\n", 1; "crlf")]
+    #[test_case("foo", &[(1, "foo", "bar")], "bar", 1; "no trailing newline")]
+    #[test_case("a\nchanged\n", &[(2, "foo", "bar")], "a\nchanged\n", 0; "conflict")]
+    #[test_case("a\n", &[(5, "foo", "bar")], "a\n", 0; "line out of range")]
+    #[test]
+    fn replace_lines_works(s: &str, raw: &[(usize, &str, &str)], expected: &str, n: usize) {
+        assert_eq!(replace_lines(s, &edits(raw)), (expected.to_string(), n));
+    }
+
+    #[test]
+    fn apply_to_buffer_is_a_single_undo_transaction() {
+        let content = "foo a\nb\nfoo c\nfoo d\n";
+        let mut b = Buffer::new_unnamed(0, content);
+        let edits = edits(&[
+            (1, "foo a", "bar a"),
+            (3, "foo c", ""),
+            (4, "changed", "bar d"),
+        ]);
+
+        let applied = apply_to_buffer(&mut b, &edits);
+        assert_eq!(applied, 2);
+        assert_eq!(b.txt.to_string(), "bar a\nb\n\nfoo d\n");
+
+        b.handle_action(Action::Undo, Source::Keyboard);
+        assert_eq!(b.txt.to_string(), content);
+    }
+
+    #[test]
+    fn review_lines_show_the_original_and_replacement() {
+        let mut ed = headless_editor();
+        let bufid = ed.buffers.reset_output_buffer(REPLACE_BUFFER.to_string());
+        ed.pending_replace = Some(PendingReplace {
+            dir: ed.cwd.clone(),
+            bufid,
+            originals: BTreeMap::new(),
+        });
+        let gen = ed.replace_gen.load(Ordering::Relaxed);
+        let hit = ReplaceHit {
+            line: 3,
+            col: 5,
+            original: "let foo = 1;".to_string(),
+            replacement: "let bar = 1;".to_string(),
+        };
+        ed.append_to_replace_buffer(gen, "a.rs".to_string(), vec![hit]);
+
+        let lines = ed.buffers.with_id(bufid).unwrap().string_lines();
+        assert_eq!(
+            &lines[..2],
+            ["a.rs:3:5: - let foo = 1;", "a.rs:3:5: + let bar = 1;"]
+        );
+    }
+
+    #[test]
+    fn read_only_buffers_are_skipped() {
+        let dir = env::temp_dir().join(format!("ad-replace-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        fs::write(dir.join("a.txt"), "foo\n").unwrap();
+        fs::write(dir.join("b.txt"), "foo\n").unwrap();
+
+        let mut ed = headless_editor();
+        ed.cwd = dir.clone();
+        ed.open_file(dir.join("a.txt"));
+        let id = ed.active_buffer_id();
+        ed.handle_buffer_ctl(id, "readonly").unwrap();
+
+        ed.replace_in_files("/foo/bar/");
+        // Hits are collected on a background thread which finishes by setting the status
+        while let Ok(event) = ed.rx_events.recv_timeout(Duration::from_secs(5)) {
+            let done = matches!(event, Event::Action(Action::SetStatusMessage { .. }));
+            ed.handle_event(event);
+            if done {
+                break;
+            }
+        }
+        ed.replace_in_files_apply();
+
+        let a = ed.buffers.with_id(id).unwrap().str_contents();
+        let b = fs::read_to_string(dir.join("b.txt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(a, "foo\n");
+        assert_eq!(b, "bar\n");
+        assert_eq!(
+            ed.status_message,
+            "replaced 1 lines in 1 files, skipped 1 lines in read-only buffers"
+        );
+    }
+}
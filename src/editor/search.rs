@@ -0,0 +1,246 @@
+//! Searching with regular expressions: incremental search within the active buffer and a
+//! project wide grep that streams its results into a +search buffer.
+use crate::{
+    buffer::SEARCH_BUFFER,
+    config_handle,
+    dot::find::RegexFind,
+    editor::{Action, Editor},
+    grep::{grep_file, walk_files},
+    input::Event,
+    regex::Regex,
+    system::System,
+};
+use ad_event::Source;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::spawn,
+};
+
+/// The maximum number of previous search patterns that are retained.
+const MAX_SEARCH_HISTORY: usize = 100;
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Prompt for a regex to search for within the active buffer, moving dot to the next match
+    /// and highlighting all visible matches as the pattern is typed. Cancelling the search
+    /// restores the original dot and viewport.
+    pub(super) fn search_incremental(&mut self) {
+        let b = self.buffers.active();
+        let (dot, row_off, col_off) = (b.dot, b.row_off, b.col_off);
+        let prev_search = self.search.take();
+        let history = self.search_history.clone();
+        self.search_hl = true;
+
+        let input = self.minibuffer_prompt_interactive("/", &history, |ed, input| {
+            ed.buffers.active_mut().dot = dot;
+            ed.update_search(input)
+        });
+
+        match input {
+            Some(input) if self.search.is_some() => {
+                self.push_search_history(input);
+
+                // Record where we started from so that the jump list can return us there
+                let found = self.buffers.active().dot;
+                self.buffers.active_mut().dot = dot;
+                self.buffers.record_jump_position();
+                self.buffers.active_mut().dot = found;
+            }
+
+            _ => {
+                self.search = prev_search;
+                self.search_hl = false;
+                let b = self.buffers.active_mut();
+                b.dot = dot;
+                b.row_off = row_off;
+                b.col_off = col_off;
+            }
+        }
+    }
+
+    /// Compile the current search pattern and move dot to the next match, returning a message
+    /// to show to the user if the pattern is invalid or has no matches.
+    fn update_search(&mut self, input: &str) -> Option<String> {
+        if input.is_empty() {
+            self.search = None;
+            return None;
+        }
+
+        let syntax = config_handle!().regex_syntax;
+        let f = match RegexFind::try_new(input, syntax) {
+            Ok(f) => f,
+            Err(e) => {
+                self.search = None;
+                return Some(e.to_string());
+            }
+        };
+
+        let found = self.buffers.active_mut().find_next_regex_match(&f, true);
+        self.search = Some(f);
+
+        if found {
+            None
+        } else {
+            Some("no matches".to_string())
+        }
+    }
+
+    fn push_search_history(&mut self, input: String) {
+        self.search_history.retain(|s| s != &input);
+        self.search_history.push(input);
+        if self.search_history.len() > MAX_SEARCH_HISTORY {
+            self.search_history.remove(0);
+        }
+    }
+
+    /// Move dot to the next (or previous) match of the last search, wrapping around the buffer.
+    pub(super) fn search_next(&mut self, forward: bool) {
+        let found = match &self.search {
+            Some(f) => self.buffers.active_mut().find_next_regex_match(f, forward),
+            None => {
+                self.set_status_message("no previous search");
+                return;
+            }
+        };
+
+        self.search_hl = true;
+        if !found {
+            self.set_status_message("no matches");
+        }
+    }
+
+    /// Search all files under the current working directory for lines matching the given regex,
+    /// streaming the results into a +search buffer.
+    pub(super) fn grep(&mut self, pattern: String) {
+        let dir = self.cwd.clone();
+        self.run_grep(pattern, dir);
+    }
+
+    /// Re-run the most recent grep, replacing the contents of its +search buffer.
+    pub(super) fn grep_refresh(&mut self) {
+        match self.last_grep.clone() {
+            Some(LastGrep { pattern, dir, .. }) => self.run_grep(pattern, dir),
+            None => self.set_status_message("no previous grep to refresh"),
+        }
+    }
+
+    fn run_grep(&mut self, pattern: String, dir: PathBuf) {
+        let syntax = config_handle!().regex_syntax;
+        if let Err(e) = Regex::compile_with_syntax(&pattern, syntax) {
+            self.set_status_message(&format!("invalid regex: {e}"));
+            return;
+        }
+
+        // Bumping the generation stops any grep that is still running from writing its results
+        let gen = self.grep_gen.fetch_add(1, Ordering::Relaxed) + 1;
+        let bufid = self
+            .buffers
+            .reset_output_buffer(format!("{}/{SEARCH_BUFFER}", dir.display()));
+        self.last_grep = Some(LastGrep {
+            pattern: pattern.clone(),
+            dir: dir.clone(),
+            bufid,
+        });
+        self.set_status_message(&format!("searching for '{pattern}'..."));
+
+        let current_gen = self.grep_gen.clone();
+        let tx = self.tx_events.clone();
+
+        spawn(move || {
+            // Regex is not Send so we need to compile it again on this thread
+            let mut re = match Regex::compile_with_syntax(&pattern, syntax) {
+                Ok(re) => re,
+                Err(_) => return,
+            };
+            let is_current = || current_gen.load(Ordering::Relaxed) == gen;
+            let mut n_matches = 0;
+
+            walk_files(&dir, |path| {
+                if !is_current() {
+                    return false;
+                }
+
+                let name = path
+                    .strip_prefix(&dir)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string();
+                let lines = grep_file(&mut re, path, &name);
+                if lines.is_empty() {
+                    return true;
+                }
+                n_matches += lines.len();
+                let mut content = lines.join("\n");
+                content.push('\n');
+
+                tx.send(Event::Action(Action::AppendToSearchBuffer { gen, content }))
+                    .is_ok()
+            });
+
+            if is_current() {
+                _ = tx.send(Event::Action(Action::SetStatusMessage {
+                    message: format!("grep: {n_matches} matches for '{pattern}'"),
+                }));
+            }
+        });
+    }
+
+    /// Append results from a running grep to its +search buffer so long as it is still the
+    /// most recent grep and the buffer is still open.
+    pub(super) fn append_to_search_buffer(&mut self, gen: usize, content: String) {
+        if gen != self.grep_gen.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let bufid = match &self.last_grep {
+            Some(LastGrep { bufid, .. }) => *bufid,
+            None => return,
+        };
+
+        if let Some(b) = self.buffers.with_id_mut(bufid) {
+            b.append(content, Source::Fsys);
+        }
+    }
+}
+
+/// The most recently run grep so that it can be refreshed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct LastGrep {
+    pattern: String,
+    dir: PathBuf,
+    bufid: usize,
+}
+
+/// Used to identify the most recently run grep so that stale results can be discarded.
+pub(super) type GrepGeneration = Arc<AtomicUsize>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dot::Dot, editor::test_utils::editor_with_content, key::parse_keys};
+    use simple_test_case::test_case;
+
+    #[test_case("ba<ret>", true, Some(4); "confirmed")]
+    #[test_case("ba<esc>", false, None; "cancelled")]
+    #[test]
+    fn search_highlight_is_cleared_on_cancel(keys: &str, hl: bool, idx: Option<usize>) {
+        let mut ed = editor_with_content("foo bar\n");
+        ed.buffers.active_mut().dot = Dot::default();
+        for i in parse_keys(keys).unwrap() {
+            ed.tx_events.send(Event::Input(i)).unwrap();
+        }
+
+        ed.search_incremental();
+
+        assert_eq!(ed.search_hl, hl);
+        assert_eq!(ed.search.is_some(), hl);
+        let expected = idx.map_or(Dot::default(), |i| Dot::from_char_indices(i, i + 1));
+        assert_eq!(ed.buffers.active().dot, expected);
+    }
+}
@@ -0,0 +1,264 @@
+//! Expanding snippets and moving between their tabstops.
+use crate::{
+    buffer::{BufferKind, GapBuffer},
+    config_handle,
+    dot::{Cur, Dot},
+    editor::{Action, Editor, MiniBufferSelection},
+    key::Input,
+    snippets::{load_snippets, Snippet},
+    system::System,
+};
+use ad_event::Source;
+
+impl<S> Editor<S>
+where
+    S: System,
+{
+    /// Move to the next tabstop of the snippet being filled in, or expand the snippet whose
+    /// trigger is before the cursor, falling back to inserting a tab if there is neither.
+    pub(super) fn snippet_next(&mut self, source: Source) {
+        if self.buffers.active_mut().goto_tabstop(true) {
+            return;
+        }
+
+        if !self.try_expand_snippet() {
+            self.handle_action(Action::RawInput { i: Input::Tab }, source);
+        }
+    }
+
+    pub(super) fn snippet_previous(&mut self) {
+        self.buffers.active_mut().goto_tabstop(false);
+    }
+
+    /// Select one of the snippets available for the active buffer in the minibuffer and expand
+    /// it at the cursor.
+    pub(super) fn select_snippet(&mut self) {
+        let snippets = match self.snippets_for_active_buffer() {
+            Some(snippets) if !snippets.is_empty() => snippets,
+            Some(_) => {
+                self.set_status_message("no snippets for this buffer");
+                return;
+            }
+            None => return,
+        };
+
+        let lines = snippets
+            .iter()
+            .map(|s| {
+                format!("{} {}", s.trigger, s.description)
+                    .trim()
+                    .to_string()
+            })
+            .collect();
+
+        let trigger = match self.minibuffer_select_from("snippet> ", lines) {
+            MiniBufferSelection::Line { line, .. } => line,
+            _ => return,
+        };
+        let trigger = trigger.split_whitespace().next().unwrap_or_default();
+
+        if let Some(snippet) = snippets.iter().find(|s| s.trigger == trigger) {
+            let idx = self.buffers.active().dot.first_cur().idx;
+            self.expand_snippet(snippet, idx, idx);
+        }
+    }
+
+    fn snippets_for_active_buffer(&mut self) -> Option<Vec<Snippet>> {
+        let ext = match &self.buffers.active().kind {
+            BufferKind::File(path) => path.extension().and_then(|e| e.to_str()).map(String::from),
+            _ => None,
+        };
+
+        if let Some(snippets) = self.snippets.get(&ext) {
+            return Some(snippets.clone());
+        }
+
+        match load_snippets(ext.as_deref()) {
+            Ok(snippets) => {
+                self.snippets.insert(ext, snippets.clone());
+                Some(snippets)
+            }
+            Err(e) => {
+                self.set_status_message(&e);
+                None
+            }
+        }
+    }
+
+    /// Expand the snippet whose trigger is immediately before the cursor, returning false if
+    /// there is no such snippet.
+    fn try_expand_snippet(&mut self) -> bool {
+        let b = self.buffers.active();
+        let idx = match b.dot {
+            Dot::Cur { c } => c.idx,
+            Dot::Range { .. } => return false,
+        };
+
+        let starts = trigger_starts(&b.txt, idx);
+        if starts.is_empty() {
+            return false;
+        }
+        let triggers: Vec<(usize, String)> = starts
+            .into_iter()
+            .map(|start| (start, b.txt.slice(start, idx).to_string()))
+            .collect();
+
+        let snippets = match self.snippets_for_active_buffer() {
+            Some(snippets) => snippets,
+            None => return false,
+        };
+
+        for (start, trigger) in triggers.into_iter() {
+            if let Some(snippet) = snippets.iter().find(|s| s.trigger == trigger) {
+                self.expand_snippet(snippet, start, idx);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Replace the characters `from..to` in the active buffer with the expansion of `snippet`
+    /// and start filling in its tabstops.
+    fn expand_snippet(&mut self, snippet: &Snippet, from: usize, to: usize) {
+        let indent_unit = {
+            let conf = config_handle!();
+            if conf.expand_tab {
+                " ".repeat(conf.tabstop)
+            } else {
+                "\t".to_string()
+            }
+        };
+
+        let b = self.buffers.active_mut();
+        let line_start = b.txt.line_to_char(b.txt.char_to_line(from));
+        let indent: String = b
+            .txt
+            .slice(line_start, from)
+            .chars()
+            .take_while(|&ch| ch == ' ' || ch == '\t')
+            .collect();
+        let exp = snippet.expand(b.full_name(), &indent, &indent_unit);
+
+        if from < to {
+            b.dot = Dot::from_char_indices(from, to - 1).collapse_null_range();
+            b.handle_action(Action::Delete, Source::Keyboard);
+        } else {
+            b.dot = Dot::Cur {
+                c: Cur { idx: from },
+            };
+        }
+        b.handle_action(Action::InsertString { s: exp.text }, Source::Keyboard);
+        b.start_tabstops(exp.stops, from);
+    }
+}
+
+/// The possible starting positions of a snippet trigger ending at `idx`, longest first: the
+/// non-whitespace characters before `idx` and the word at the end of them.
+fn trigger_starts(txt: &GapBuffer, idx: usize) -> Vec<usize> {
+    let scan_back = |is_valid: fn(char) -> bool| {
+        let mut start = idx;
+        while start > 0 && is_valid(txt.char(start - 1)) {
+            start -= 1;
+        }
+        start
+    };
+
+    let mut starts = vec![scan_back(|ch| !ch.is_whitespace())];
+    let word_start = scan_back(|ch| ch.is_alphanumeric() || ch == '_');
+    if word_start != starts[0] {
+        starts.push(word_start);
+    }
+    starts.retain(|&start| start < idx);
+
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dot::TextObject,
+        editor::test_utils::{editor_with_content, type_keys},
+        input::Event,
+        snippets::parse_snippets,
+    };
+    use simple_test_case::test_case;
+
+    #[test_case("fn foo(te", 9, &[3, 7]; "word after punctuation")]
+    #[test_case("    test", 8, &[4]; "indented word")]
+    #[test_case("x #[t", 5, &[2, 4]; "non word trigger")]
+    #[test_case("test ", 5, &[]; "after whitespace")]
+    #[test]
+    fn trigger_starts_works(s: &str, idx: usize, expected: &[usize]) {
+        let txt = GapBuffer::from(s);
+
+        assert_eq!(trigger_starts(&txt, idx), expected);
+    }
+
+    #[test]
+    fn tabstops_are_visited_and_mirrors_are_updated() {
+        let snippets = parse_snippets("snippet tag\n\t<${1:div}>$2</$1>$0").unwrap();
+        let mut ed = editor_with_content("  tag");
+        ed.set_mode("INSERT");
+        ed.expand_snippet(&snippets[0], 2, 5);
+
+        let b = ed.buffers.active();
+        assert_eq!(b.txt.to_string(), "  <div></div>");
+        assert_eq!(b.dot, Dot::from_char_indices(3, 5));
+
+        type_keys(&mut ed, "span");
+        assert_eq!(ed.buffers.active().txt.to_string(), "  <span></span>");
+
+        ed.snippet_next(Source::Keyboard);
+        type_keys(&mut ed, "hi");
+        assert_eq!(ed.buffers.active().txt.to_string(), "  <span>hi</span>");
+
+        // Moving to $0 ends the snippet so the next tab is inserted as normal
+        ed.snippet_next(Source::Keyboard);
+        assert_eq!(ed.buffers.active().dot, Dot::Cur { c: Cur { idx: 17 } });
+        ed.snippet_next(Source::Keyboard);
+        assert_eq!(ed.buffers.active().txt.to_string(), "  <span>hi</span>    ");
+    }
+
+    #[test]
+    fn cached_snippets_are_used_for_expansion() {
+        let snippets = parse_snippets("snippet hi\n\thello, world").unwrap();
+        let mut ed = editor_with_content("hi");
+        ed.snippets.insert(None, snippets);
+        ed.set_mode("INSERT");
+        ed.buffers.active_mut().dot = Dot::Cur { c: Cur { idx: 2 } };
+        ed.snippet_next(Source::Keyboard);
+
+        assert_eq!(ed.buffers.active().txt.to_string(), "hello, world");
+    }
+
+    #[test]
+    fn snippet_bodies_are_indented_to_match() {
+        let snippets = parse_snippets("snippet if\n\tif $1 {\n\t\t$0\n\t}").unwrap();
+        let mut ed = editor_with_content("fn main() {\n    if");
+        ed.set_mode("INSERT");
+        ed.expand_snippet(&snippets[0], 16, 18);
+
+        type_keys(&mut ed, "x");
+        ed.snippet_next(Source::Keyboard);
+        type_keys(&mut ed, "y();");
+
+        assert_eq!(
+            ed.buffers.active().txt.to_string(),
+            "fn main() {\n    if x {\n        y();\n    }"
+        );
+    }
+
+    #[test]
+    fn moving_out_of_a_tabstop_ends_the_snippet() {
+        let snippets = parse_snippets("snippet p\n\tprint($1, $2)").unwrap();
+        let mut ed = editor_with_content("p");
+        ed.expand_snippet(&snippets[0], 0, 1);
+
+        ed.handle_event(Event::Action(Action::DotSet(TextObject::BufferStart, 1)));
+        ed.snippet_next(Source::Keyboard);
+
+        assert_eq!(ed.buffers.active().txt.to_string(), "    print(, )");
+    }
+}
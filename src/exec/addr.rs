@@ -16,12 +16,13 @@
 //! e1     => set dot to e1
 //! e1,    => set dot to e1_start..=EOF
 //! e1,e2  => set dot to e1_start..=e2_end
+//! 'a     => set dot to the position of mark 'a'
 //! ```
 use crate::{
-    buffer::{Buffer, GapBuffer},
+    buffer::{is_valid_mark_name, Buffer, GapBuffer},
     dot::{Cur, Dot, Range},
     exec::char_iter::IterBoundedChars,
-    regex::{self, Regex},
+    regex::{self, Regex, Syntax},
     util::parse_num,
 };
 use std::{iter::Peekable, str::Chars};
@@ -51,9 +52,10 @@ impl Addr {
         Addr::Compound(AddrBase::Bof.into(), AddrBase::Eof.into())
     }
 
-    /// Attempt to parse a valid dot expression from a character stream
-    pub fn parse(it: &mut Peekable<Chars<'_>>) -> Result<Self, ParseError> {
-        let start = match SimpleAddr::parse(it) {
+    /// Attempt to parse a valid dot expression from a character stream, compiling any regular
+    /// expressions using the given [Syntax].
+    pub fn parse(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<Self, ParseError> {
+        let start = match SimpleAddr::parse(it, syntax) {
             Ok(exp) => Some(exp),
             // If the following char is a ',' we substitute BOF for a missing start
             Err(ParseError::NotAnAddress) => None,
@@ -69,7 +71,7 @@ impl Addr {
             Some(',') => {
                 it.next();
                 let start = start.unwrap_or(AddrBase::Bof.into());
-                let end = match SimpleAddr::parse(it) {
+                let end = match SimpleAddr::parse(it, syntax) {
                     Ok(exp) => exp,
                     Err(ParseError::NotAnAddress) => AddrBase::Eof.into(),
                     Err(e) => return Err(e),
@@ -90,12 +92,12 @@ pub struct SimpleAddr {
 }
 
 impl SimpleAddr {
-    fn parse(it: &mut Peekable<Chars<'_>>) -> Result<Self, ParseError> {
-        let base = AddrBase::parse(it)?;
+    fn parse(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<Self, ParseError> {
+        let base = AddrBase::parse(it, syntax)?;
         let mut suffixes = Vec::new();
 
         while let Some('-' | '+') = it.peek() {
-            let a = AddrBase::parse(it)?;
+            let a = AddrBase::parse(it, syntax)?;
             if !a.is_valid_suffix() {
                 return Err(ParseError::InvalidSuffix);
             }
@@ -135,6 +137,8 @@ pub enum AddrBase {
     Regex(Regex),
     /// -/re/
     RegexBack(Regex),
+    /// 'a
+    Mark(char),
 }
 
 impl From<AddrBase> for SimpleAddr {
@@ -160,7 +164,7 @@ impl AddrBase {
         )
     }
 
-    pub(crate) fn parse(it: &mut Peekable<Chars<'_>>) -> Result<Self, ParseError> {
+    pub(crate) fn parse(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<Self, ParseError> {
         let dir = match it.peek() {
             Some('-') => {
                 it.next();
@@ -240,7 +244,16 @@ impl AddrBase {
 
             (Some('/'), dir) => {
                 it.next();
-                parse_delimited_regex(it, dir.unwrap_or(Dir::Fwd))
+                parse_delimited_regex(it, dir.unwrap_or(Dir::Fwd), syntax)
+            }
+
+            (Some('\''), None) => {
+                it.next();
+                match it.next() {
+                    Some(c) if is_valid_mark_name(c) => Ok(Self::Mark(c)),
+                    Some(c) => Err(ParseError::UnexpectedCharacter(c)),
+                    None => Err(ParseError::NotAnAddress),
+                }
             }
 
             (_, Some(Dir::Fwd)) => Ok(Self::Eol),
@@ -251,7 +264,11 @@ impl AddrBase {
     }
 }
 
-fn parse_delimited_regex(it: &mut Peekable<Chars<'_>>, dir: Dir) -> Result<AddrBase, ParseError> {
+fn parse_delimited_regex(
+    it: &mut Peekable<Chars<'_>>,
+    dir: Dir,
+    syntax: Syntax,
+) -> Result<AddrBase, ParseError> {
     let mut s = String::new();
     let mut prev = '/';
 
@@ -259,10 +276,11 @@ fn parse_delimited_regex(it: &mut Peekable<Chars<'_>>, dir: Dir) -> Result<AddrB
         if ch == '/' && prev != '\\' {
             return match dir {
                 Dir::Fwd => Ok(AddrBase::Regex(
-                    Regex::compile(&s).map_err(ParseError::InvalidRegex)?,
+                    Regex::compile_with_syntax(&s, syntax).map_err(ParseError::InvalidRegex)?,
                 )),
                 Dir::Bck => Ok(AddrBase::RegexBack(
-                    Regex::compile_reverse(&s).map_err(ParseError::InvalidRegex)?,
+                    Regex::compile_reverse_with_syntax(&s, syntax)
+                        .map_err(ParseError::InvalidRegex)?,
                 )),
             };
         }
@@ -285,6 +303,11 @@ pub trait Address: IterBoundedChars {
     fn char_to_line_end(&self, char_idx: usize) -> Option<usize>;
     fn char_to_line_start(&self, char_idx: usize) -> Option<usize>;
 
+    /// The current position of a named mark. Only buffers within the editor support marks.
+    fn mark(&self, _name: char) -> Option<Dot> {
+        None
+    }
+
     fn max_iter(&self) -> usize {
         self.len_chars()
     }
@@ -369,6 +392,8 @@ pub trait Address: IterBoundedChars {
                 let (from, to) = m.loc();
                 Dot::from_char_indices(from, to.saturating_sub(1))
             }
+
+            Mark(name) => self.mark(*name)?,
         };
 
         Some(dot)
@@ -453,13 +478,18 @@ impl Address for Buffer {
         let line_idx = self.txt.try_char_to_line(char_idx)?;
         Some(self.txt.line_to_char(line_idx))
     }
+
+    fn mark(&self, name: char) -> Option<Dot> {
+        Buffer::mark(self, name)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use super::{Addr::*, AddrBase::*};
-    use crate::regex::Regex;
+    use crate::{editor::Action, regex::Regex};
+    use ad_event::Source;
     use simple_test_case::test_case;
 
     fn re(s: &str) -> Regex {
@@ -488,6 +518,7 @@ mod tests {
     #[test_case("/foo/", Simple(Regex(re("foo")).into()); "regex")]
     #[test_case("+/baz/", Simple(Regex(re("baz")).into()); "regex explicit forward")]
     #[test_case("-/bar/", Simple(RegexBack(Regex::compile_reverse("bar").unwrap()).into()); "regex back")]
+    #[test_case("'a", Simple(Mark('a').into()); "mark")]
     // Simple with suffix
     #[test_case(
         "#5+",
@@ -516,6 +547,7 @@ mod tests {
     #[test_case("5,9", Compound(Line(4).into(), Line(8).into()); "from n to m")]
     #[test_case("25,90", Compound(Line(24).into(), Line(89).into()); "from n to m multi digit")]
     #[test_case("/foo/,/bar/", Compound(Regex(re("foo")).into(), Regex(re("bar")).into()); "regex range")]
+    #[test_case("'a,'b", Compound(Mark('a').into(), Mark('b').into()); "mark range")]
     // Compound with suffix
     #[test_case(
         "-/\\s/+#1,/\\s/-#1",
@@ -527,7 +559,7 @@ mod tests {
     )]
     #[test]
     fn parse_works(s: &str, expected: Addr) {
-        let addr = Addr::parse(&mut s.chars().peekable()).expect("valid input");
+        let addr = Addr::parse(&mut s.chars().peekable(), Syntax::Sam).expect("valid input");
         assert_eq!(addr, expected);
     }
 
@@ -539,15 +571,31 @@ mod tests {
     #[test_case("-/his/", Dot::from_char_indices(1, 3), "his"; "regex back 2")]
     #[test_case("-/a/,/a/", Dot::from_char_indices(15, 19), "and a"; "regex range")]
     #[test_case("-/\\s/+#1,/\\s/-#1", Dot::from_char_indices(15, 17), "and"; "regex range boundaries")]
+    #[test_case("'m", Dot::from_char_indices(19, 25), "another"; "mark")]
+    #[test_case("'m,$", Dot::from_char_indices(19, 49), "another\n- [ ] something to do\n"; "mark to eof")]
     #[test]
     fn map_addr_works(s: &str, expected: Dot, expected_contents: &str) {
         let mut b = Buffer::new_unnamed(0, "this is a line\nand another\n- [ ] something to do\n");
+        b.set_mark('m', Dot::from_char_indices(19, 25));
         b.dot = Cur::new(16).into();
 
-        let mut addr = Addr::parse(&mut s.chars().peekable()).expect("valid addr");
+        let mut addr = Addr::parse(&mut s.chars().peekable(), Syntax::Sam).expect("valid addr");
         b.dot = b.map_addr(&mut addr);
 
         assert_eq!(b.dot, expected, ">{}<", b.dot_contents());
         assert_eq!(b.dot_contents(), expected_contents);
     }
+
+    #[test]
+    fn mark_addr_tracks_edits() {
+        let mut b = Buffer::new_unnamed(0, "foo bar baz");
+        b.set_mark('a', Dot::from_char_indices(4, 6));
+        b.dot = Cur::new(0).into();
+        b.handle_action(Action::InsertString { s: "a ".into() }, Source::Keyboard);
+
+        let mut addr = Addr::parse(&mut "'a".chars().peekable(), Syntax::Sam).expect("valid addr");
+        b.dot = b.map_addr(&mut addr);
+
+        assert_eq!(b.dot_contents(), "bar");
+    }
 }
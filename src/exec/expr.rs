@@ -1,5 +1,8 @@
-use super::{consume_whitespace, Error};
-use crate::regex::Regex;
+use super::{consume_whitespace, ErrorKind};
+use crate::{
+    buffer::is_valid_mark_name,
+    regex::{Regex, Syntax},
+};
 use std::{iter::Peekable, str::Chars};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -15,7 +18,10 @@ pub(super) enum Expr {
     Change(String),
     Sub(Regex, String),
     Print(String),
+    PrintAddr,
+    PrintCharAddr,
     Delete,
+    SetMark(char),
 
     Group(Vec<Vec<Expr>>),
 }
@@ -27,56 +33,95 @@ pub(super) enum ParseOutput {
 }
 
 impl Expr {
-    pub(super) fn try_parse(it: &mut Peekable<Chars<'_>>) -> Result<ParseOutput, Error> {
+    pub(super) fn try_parse(
+        it: &mut Peekable<Chars<'_>>,
+        syntax: Syntax,
+    ) -> Result<ParseOutput, ErrorKind> {
         use Expr::*;
         use ParseOutput::*;
 
         match it.next() {
-            Some('x') => Ok(Single(LoopMatches(parse_delimited_regex(it, "x")?))),
+            Some('x') => Ok(Single(LoopMatches(parse_delimited_regex(it, "x", syntax)?))),
             Some('X') => Ok(Single(LoopMatches(Regex::compile(".*")?))),
 
-            Some('y') => Ok(Single(LoopBetweenMatches(parse_delimited_regex(it, "y")?))),
+            Some('y') => Ok(Single(LoopBetweenMatches(parse_delimited_regex(
+                it, "y", syntax,
+            )?))),
             Some('Y') => Ok(Single(LoopBetweenMatches(Regex::compile(".*")?))),
 
-            Some('g') => Ok(Single(IfContains(parse_delimited_regex(it, "g")?))),
-            Some('v') => Ok(Single(IfNotContains(parse_delimited_regex(it, "v")?))),
+            Some('g') => Ok(Single(IfContains(parse_delimited_regex(it, "g", syntax)?))),
+            Some('v') => Ok(Single(IfNotContains(parse_delimited_regex(
+                it, "v", syntax,
+            )?))),
 
             Some('i') => Ok(Single(Insert(parse_delimited_str(it, "i")?))),
             Some('a') => Ok(Single(Append(parse_delimited_str(it, "a")?))),
             Some('c') => Ok(Single(Change(parse_delimited_str(it, "c")?))),
-            Some('s') => parse_sub(it),
+            Some('s') => parse_sub(it, syntax),
             Some('d') => Ok(Single(Delete)),
 
             Some('p') => Ok(Single(Print(parse_delimited_str(it, "p")?))),
             Some('P') => Ok(Single(Print("$0\n".to_string()))),
+            Some('=') => {
+                if it.peek() == Some(&'#') {
+                    it.next();
+                    Ok(Single(PrintCharAddr))
+                } else {
+                    Ok(Single(PrintAddr))
+                }
+            }
 
-            Some('{') => Ok(Single(Group(parse_group(it)?))),
+            Some('k') => match it.next() {
+                Some(ch) if is_valid_mark_name(ch) => Ok(Single(SetMark(ch))),
+                Some(ch) => Err(ErrorKind::InvalidMarkName(ch)),
+                None => Err(ErrorKind::MissingMarkName),
+            },
+
+            Some('{') => Ok(Single(Group(parse_group(it, syntax)?))),
 
             // Comments run until the end of the current line
             Some('#') => loop {
                 match it.next() {
                     Some('\n') => {
                         consume_whitespace(it);
-                        return Expr::try_parse(it);
+                        return Expr::try_parse(it, syntax);
                     }
-                    None => return Err(Error::Eof),
+                    None => return Err(ErrorKind::Eof),
                     _ => (),
                 }
             },
 
-            Some(ch) => Err(Error::UnexpectedCharacter(ch)),
-            None => Err(Error::Eof),
+            Some(ch) => Err(ErrorKind::UnexpectedCharacter(ch)),
+            None => Err(ErrorKind::Eof),
+        }
+    }
+
+    /// Whether or not running this expression is able to modify the text it is run against.
+    pub(super) fn modifies_text(&self) -> bool {
+        use Expr::*;
+
+        match self {
+            Insert(_) | Append(_) | Change(_) | Sub(_, _) | Delete => true,
+            Group(branches) => branches.iter().flatten().any(|e| e.modifies_text()),
+            _ => false,
         }
     }
 }
 
-fn parse_delimited_regex(it: &mut Peekable<Chars<'_>>, kind: &'static str) -> Result<Regex, Error> {
+fn parse_delimited_regex(
+    it: &mut Peekable<Chars<'_>>,
+    kind: &'static str,
+    syntax: Syntax,
+) -> Result<Regex, ErrorKind> {
     let s = parse_delimited_str(it, kind)?;
-    Ok(Regex::compile(&s)?)
+    Ok(Regex::compile_with_syntax(&s, syntax)?)
 }
 
-fn parse_delimited_str(it: &mut Peekable<Chars<'_>>, kind: &'static str) -> Result<String, Error> {
-    let delim = it.next().ok_or(Error::MissingDelimiter(kind))?;
+fn parse_delimited_str(
+    it: &mut Peekable<Chars<'_>>,
+    kind: &'static str,
+) -> Result<String, ErrorKind> {
+    let delim = it.next().ok_or(ErrorKind::MissingDelimiter(kind))?;
     read_until(delim, it, kind)
 }
 
@@ -84,7 +129,7 @@ fn read_until(
     delim: char,
     it: &mut Peekable<Chars<'_>>,
     kind: &'static str,
-) -> Result<String, Error> {
+) -> Result<String, ErrorKind> {
     let mut s = String::new();
     let mut prev = delim;
 
@@ -96,12 +141,12 @@ fn read_until(
         prev = ch;
     }
 
-    Err(Error::UnclosedDelimiter(kind, delim))
+    Err(ErrorKind::UnclosedDelimiter(kind, delim))
 }
 
-fn parse_sub(it: &mut Peekable<Chars<'_>>) -> Result<ParseOutput, Error> {
-    let delim = it.next().ok_or(Error::MissingDelimiter("s"))?;
-    let re = Regex::compile(&read_until(delim, it, "s")?)?;
+fn parse_sub(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<ParseOutput, ErrorKind> {
+    let delim = it.next().ok_or(ErrorKind::MissingDelimiter("s"))?;
+    let re = Regex::compile_with_syntax(&read_until(delim, it, "s")?, syntax)?;
     let s = read_until(delim, it, "s")?;
     if let Some('g') = it.peek() {
         it.next();
@@ -111,7 +156,7 @@ fn parse_sub(it: &mut Peekable<Chars<'_>>) -> Result<ParseOutput, Error> {
     }
 }
 
-fn parse_group(it: &mut Peekable<Chars<'_>>) -> Result<Vec<Vec<Expr>>, Error> {
+fn parse_group(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<Vec<Vec<Expr>>, ErrorKind> {
     let mut group = Vec::new();
     let mut branch = Vec::new();
     loop {
@@ -120,7 +165,7 @@ fn parse_group(it: &mut Peekable<Chars<'_>>) -> Result<Vec<Vec<Expr>>, Error> {
             Some(';' | '\n') => {
                 it.next();
                 if branch.is_empty() {
-                    return Err(Error::EmptyExpressionGroupBranch);
+                    return Err(ErrorKind::EmptyExpressionGroupBranch);
                 }
                 group.push(branch);
                 branch = Vec::new();
@@ -128,9 +173,9 @@ fn parse_group(it: &mut Peekable<Chars<'_>>) -> Result<Vec<Vec<Expr>>, Error> {
             Some('}') => {
                 it.next();
                 return if group.is_empty() {
-                    Err(Error::EmptyExpressionGroup)
+                    Err(ErrorKind::EmptyExpressionGroup)
                 } else if !branch.is_empty() {
-                    Err(Error::UnclosedExpressionGroupBranch)
+                    Err(ErrorKind::UnclosedExpressionGroupBranch)
                 } else {
                     Ok(group)
                 };
@@ -140,16 +185,16 @@ fn parse_group(it: &mut Peekable<Chars<'_>>) -> Result<Vec<Vec<Expr>>, Error> {
             Some('#') => loop {
                 match it.next() {
                     Some('\n') => break,
-                    None => return Err(Error::Eof),
+                    None => return Err(ErrorKind::Eof),
                     _ => (),
                 }
             },
 
-            Some(_) => match Expr::try_parse(it)? {
+            Some(_) => match Expr::try_parse(it, syntax)? {
                 ParseOutput::Single(e) => branch.push(e),
                 ParseOutput::Pair(e1, e2) => branch.extend([e1, e2]),
             },
-            None => return Err(Error::UnclosedExpressionGroup),
+            None => return Err(ErrorKind::UnclosedExpressionGroup),
         }
     }
 }
@@ -184,6 +229,9 @@ mod tests {
     #[test_case("p/$0/", s(Print("$0".to_string())); "print")]
     #[test_case("P", s(Print("$0\n".to_string())); "print full match")]
     #[test_case("d", s(Delete); "delete")]
+    #[test_case("=", s(PrintAddr); "print addr")]
+    #[test_case("=#", s(PrintCharAddr); "print char addr")]
+    #[test_case("ka", s(SetMark('a')); "set mark")]
     #[test_case(
         "{P; g/bar/ a/foo/;}",
         s(Group(vec![
@@ -194,7 +242,7 @@ mod tests {
     )]
     #[test]
     fn parse_expr_works(input: &str, expected: ParseOutput) {
-        let a = Expr::try_parse(&mut input.chars().peekable()).expect("valid input");
+        let a = Expr::try_parse(&mut input.chars().peekable(), Syntax::Sam).expect("valid input");
         assert_eq!(a, expected);
     }
 }
@@ -3,10 +3,11 @@ use crate::{
     buffer::{Buffer, GapBuffer},
     dot::{Cur, Dot},
     editor::Action,
-    regex::{self, Match},
+    regex::{self, Match, Syntax},
+    util::caret_lines,
 };
 use ad_event::Source;
-use std::{cmp::min, io::Write, iter::Peekable, str::Chars};
+use std::{cmp::min, fmt, io::Write, iter::Peekable, str::Chars};
 
 mod addr;
 mod cached_stdin;
@@ -21,11 +22,47 @@ use expr::{Expr, ParseOutput};
 
 /// Variable usable in templates for injecting the current filename.
 /// (Following the naming convention used in Awk)
-const FNAME_VAR: &str = "$FILENAME";
+pub(crate) const FNAME_VAR: &str = "$FILENAME";
 
-/// Errors that can be returned by the exec engine
+/// An error encountered while parsing or running a [Program].
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Error {
+pub struct Error {
+    /// What went wrong
+    pub kind: ErrorKind,
+    /// The character offset into the program text at which the error was detected. Errors
+    /// raised while executing a program are located at the start of the expression that
+    /// raised them.
+    pub offset: Option<usize>,
+}
+
+impl Error {
+    /// Render the line of the given program text containing this error along with a second
+    /// line placing a caret beneath the offending character.
+    pub fn annotate(&self, prog: &str) -> Option<(String, String)> {
+        self.offset.map(|offset| caret_lines(prog, offset))
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, offset: None }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} (at char {offset})", self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The kinds of error that can be returned by the exec engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
     /// Empty expression group
     EmptyExpressionGroup,
     /// Empty branch for an expression group
@@ -38,12 +75,16 @@ pub enum Error {
     InvalidRegex(regex::Error),
     /// Invalid substitution
     InvalidSubstitution(usize),
+    /// Invalid mark name
+    InvalidMarkName(char),
     /// Invalid suffix
     InvalidSuffix,
     /// Missing action
     MissingAction,
     /// Missing delimiter
     MissingDelimiter(&'static str),
+    /// Missing mark name
+    MissingMarkName,
     /// Unclosed delimiter
     UnclosedDelimiter(&'static str, char),
     /// Unclosed expression group
@@ -54,9 +95,33 @@ pub enum Error {
     UnexpectedCharacter(char),
 }
 
-impl From<regex::Error> for Error {
+impl From<regex::Error> for ErrorKind {
     fn from(err: regex::Error) -> Self {
-        Error::InvalidRegex(err)
+        ErrorKind::InvalidRegex(err)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyExpressionGroup => write!(f, "empty expression group"),
+            Self::EmptyExpressionGroupBranch => write!(f, "empty expression group branch"),
+            Self::EmptyProgram => write!(f, "empty program"),
+            Self::Eof => write!(f, "unexpected end of input"),
+            Self::InvalidRegex(e) => write!(f, "invalid regex: {}", e.kind),
+            Self::InvalidSubstitution(n) => write!(f, "no submatch for ${n}"),
+            Self::InvalidMarkName(ch) => write!(f, "invalid mark name '{ch}'"),
+            Self::InvalidSuffix => write!(f, "invalid address suffix"),
+            Self::MissingAction => write!(f, "program must end with an action"),
+            Self::MissingDelimiter(kind) => write!(f, "missing delimiter for '{kind}'"),
+            Self::MissingMarkName => write!(f, "missing mark name for 'k'"),
+            Self::UnclosedDelimiter(kind, delim) => {
+                write!(f, "unclosed delimiter '{delim}' for '{kind}'")
+            }
+            Self::UnclosedExpressionGroup => write!(f, "unclosed expression group"),
+            Self::UnclosedExpressionGroupBranch => write!(f, "unclosed expression group branch"),
+            Self::UnexpectedCharacter(ch) => write!(f, "unexpected character '{ch}'"),
+        }
     }
 }
 
@@ -74,6 +139,9 @@ pub trait Edit: Address {
     /// Remove all characters from (from..to)
     fn remove(&mut self, from: usize, to: usize);
 
+    /// Record the given dot under a named mark
+    fn set_mark(&mut self, _name: char, _dot: Dot) {}
+
     /// Mark the start of an edit transaction
     fn begin_edit_transaction(&mut self) {}
 
@@ -105,6 +173,10 @@ impl Edit for Buffer {
         self.handle_action(Action::Delete, Source::Fsys);
     }
 
+    fn set_mark(&mut self, name: char, dot: Dot) {
+        Buffer::set_mark(self, name, dot)
+    }
+
     fn begin_edit_transaction(&mut self) {
         self.new_edit_log_transaction()
     }
@@ -119,65 +191,117 @@ impl Edit for Buffer {
 pub struct Program {
     initial_dot: Addr,
     exprs: Vec<Expr>,
+    /// The character offset into the program text of each expression in exprs, used for
+    /// locating errors raised while executing the program
+    offsets: Vec<usize>,
 }
 
 impl Program {
-    /// Attempt to parse a given program input
+    /// Attempt to parse a given program input using the default regex [Syntax].
     pub fn try_parse(s: &str) -> Result<Self, Error> {
+        Self::try_parse_with_syntax(s, Syntax::default())
+    }
+
+    /// Attempt to parse a given program input using the specified regex [Syntax].
+    pub fn try_parse_with_syntax(s: &str, syntax: Syntax) -> Result<Self, Error> {
+        let trimmed = s.trim_start();
+        let leading = s.chars().count() - trimmed.chars().count();
+        let input = trimmed.trim_end();
+        let mut it = input.chars().peekable();
+
+        match Self::parse_from(input, &mut it, leading, syntax) {
+            Ok(prog) => Ok(prog),
+            Err(kind) => {
+                let consumed = input.chars().count() - it.count();
+                let offset = error_offset(input, consumed, &kind);
+
+                Err(Error {
+                    kind,
+                    offset: Some(leading + offset),
+                })
+            }
+        }
+    }
+
+    fn parse_from<'a>(
+        s: &'a str,
+        it: &mut Peekable<Chars<'a>>,
+        leading: usize,
+        syntax: Syntax,
+    ) -> Result<Self, ErrorKind> {
         let mut exprs = vec![];
-        let mut it = s.trim().chars().peekable();
+        let mut offsets = vec![];
+        let n_chars = s.chars().count();
 
         if it.peek().is_none() {
-            return Err(Error::EmptyProgram);
+            return Err(ErrorKind::EmptyProgram);
         }
 
-        let initial_dot = match Addr::parse(&mut it) {
+        let initial_dot = match Addr::parse(it, syntax) {
             Ok(dot_expr) => dot_expr,
 
             // If the start of input is not an address we default to Full and attempt to parse the
             // rest of the program. We need to reconstruct the iterator here as we may have
             // advanced through the string while we attempt to parse the initial address.
             Err(ParseError::NotAnAddress) => {
-                it = s.trim().chars().peekable();
+                *it = s.chars().peekable();
                 Addr::full()
             }
 
-            Err(ParseError::InvalidRegex(e)) => return Err(Error::InvalidRegex(e)),
+            Err(ParseError::InvalidRegex(e)) => return Err(ErrorKind::InvalidRegex(e)),
             Err(ParseError::UnclosedDelimiter) => {
-                return Err(Error::UnclosedDelimiter("dot expr regex", '/'))
+                return Err(ErrorKind::UnclosedDelimiter("dot expr regex", '/'))
+            }
+            Err(ParseError::UnexpectedCharacter(c)) => {
+                return Err(ErrorKind::UnexpectedCharacter(c))
             }
-            Err(ParseError::UnexpectedCharacter(c)) => return Err(Error::UnexpectedCharacter(c)),
-            Err(ParseError::InvalidSuffix) => return Err(Error::InvalidSuffix),
+            Err(ParseError::InvalidSuffix) => return Err(ErrorKind::InvalidSuffix),
         };
 
-        consume_whitespace(&mut it);
+        consume_whitespace(it);
 
         loop {
             if it.peek().is_none() {
                 break;
             }
 
-            match Expr::try_parse(&mut it) {
+            let offset = leading + n_chars - it.clone().count();
+            match Expr::try_parse(it, syntax) {
                 Ok(ParseOutput::Single(expr)) => {
                     exprs.push(expr);
-                    consume_whitespace(&mut it);
+                    offsets.push(offset);
+                    consume_whitespace(it);
                 }
                 Ok(ParseOutput::Pair(e1, e2)) => {
                     exprs.extend([e1, e2]);
-                    consume_whitespace(&mut it);
+                    offsets.extend([offset, offset]);
+                    consume_whitespace(it);
                 }
-                Err(Error::Eof) => break,
+                Err(ErrorKind::Eof) => break,
                 Err(e) => return Err(e),
             }
         }
 
         if exprs.is_empty() {
-            return Ok(Self { initial_dot, exprs });
+            return Ok(Self {
+                initial_dot,
+                exprs,
+                offsets,
+            });
         }
 
         validate(&exprs)?;
 
-        Ok(Self { initial_dot, exprs })
+        Ok(Self {
+            initial_dot,
+            exprs,
+            offsets,
+        })
+    }
+
+    /// Whether or not executing this program is able to modify the text it is run against.
+    pub fn modifies_text(&self) -> bool {
+        self.exprs.iter().any(|e| e.modifies_text())
     }
 
     /// Execute this program against a given Edit
@@ -209,6 +333,8 @@ impl Program {
         Ok(Dot::from_char_indices(min(from, ix_max), min(to, ix_max)))
     }
 
+    /// Run the expression at pc, locating any error it raises that has not already been
+    /// located by a nested expression.
     fn step<E, W>(
         &mut self,
         ed: &mut E,
@@ -217,6 +343,26 @@ impl Program {
         fname: &str,
         out: &mut W,
     ) -> Result<Dot, Error>
+    where
+        E: Edit,
+        W: Write,
+    {
+        self.step_expr(ed, m, pc, fname, out).map_err(|mut e| {
+            if e.offset.is_none() {
+                e.offset = self.offsets.get(pc).copied();
+            }
+            e
+        })
+    }
+
+    fn step_expr<E, W>(
+        &mut self,
+        ed: &mut E,
+        m: &Match,
+        pc: usize,
+        fname: &str,
+        out: &mut W,
+    ) -> Result<Dot, Error>
     where
         E: Edit,
         W: Write,
@@ -227,9 +373,11 @@ impl Program {
             Expr::Group(g) => {
                 let mut dot = Dot::from_char_indices(from, to);
                 for exprs in g {
+                    // Errors from within the group are located at the group itself
                     let mut p = Program {
                         initial_dot: Addr::Explicit(dot),
                         exprs: exprs.clone(),
+                        offsets: Vec::new(),
                     };
                     dot = p.step(ed, m, 0, fname, out)?;
                 }
@@ -322,6 +470,30 @@ impl Program {
                 Ok(Dot::from_char_indices(from, from + s.chars().count()))
             }
 
+            Expr::PrintAddr => {
+                let s = line_and_column_addr(ed, from, to);
+                writeln!(out, "{s}").expect("to be able to write");
+                Ok(Dot::from_char_indices(from, to))
+            }
+
+            Expr::PrintCharAddr => {
+                let s = match inclusive_end(from, to) {
+                    Some(end) => format!("#{from},#{end}"),
+                    None => format!("#{from}"),
+                };
+                writeln!(out, "{s}").expect("to be able to write");
+                Ok(Dot::from_char_indices(from, to))
+            }
+
+            Expr::SetMark(name) => {
+                let dot = match inclusive_end(from, to) {
+                    Some(end) => Dot::from_char_indices(from, end),
+                    None => Cur { idx: from }.into(),
+                };
+                ed.set_mark(name, dot);
+                Ok(Dot::from_char_indices(from, to))
+            }
+
             Expr::Delete => {
                 ed.remove(from, to);
                 Ok(Dot::from_char_indices(from, from))
@@ -376,6 +548,33 @@ impl Program {
     }
 }
 
+/// The inclusive end of the match `from..to` if it spans more than a single character.
+fn inclusive_end(from: usize, to: usize) -> Option<usize> {
+    if to > from + 1 {
+        Some(to - 1)
+    } else {
+        None
+    }
+}
+
+/// Render the match `from..to` as a 1-based "line:col" address that can be used to set dot.
+fn line_and_column_addr<A: Address>(a: &A, from: usize, to: usize) -> String {
+    let line_col = |idx: usize| {
+        let line = a.char_to_line(idx)?;
+        let col = idx - a.line_to_char(line)?;
+        Some(format!("{}:{}", line + 1, col + 1))
+    };
+
+    let addr = match inclusive_end(from, to) {
+        Some(end) => line_col(from)
+            .zip(line_col(end))
+            .map(|(s, e)| format!("{s},{e}")),
+        None => line_col(from),
+    };
+
+    addr.unwrap_or_else(|| format!("#{from}"))
+}
+
 fn consume_whitespace(it: &mut Peekable<Chars<'_>>) {
     loop {
         match it.peek() {
@@ -387,11 +586,41 @@ fn consume_whitespace(it: &mut Peekable<Chars<'_>>) {
     }
 }
 
-fn validate(exprs: &[Expr]) -> Result<(), Error> {
+/// Locate the character offset within a program that a parse error should be reported at, given
+/// the number of characters the parser had consumed when it failed.
+fn error_offset(s: &str, consumed: usize, kind: &ErrorKind) -> usize {
+    let last = consumed.saturating_sub(1);
+
+    match kind {
+        // Regexes are compiled once their closing delimiter has been consumed so we need to
+        // locate the opening delimiter in order to offset the position reported by the regex
+        // parser, which is relative to the start of the pattern.
+        ErrorKind::InvalidRegex(e) => {
+            let chars: Vec<char> = s.chars().take(consumed).collect();
+            let delim = match chars.last() {
+                Some(&delim) => delim,
+                None => return e.offset,
+            };
+            let mut start = last;
+            while start > 0 {
+                start -= 1;
+                if chars[start] == delim && (start == 0 || chars[start - 1] != '\\') {
+                    break;
+                }
+            }
+
+            start + 1 + e.offset
+        }
+
+        _ => last,
+    }
+}
+
+fn validate(exprs: &[Expr]) -> Result<(), ErrorKind> {
     use Expr::*;
 
     if exprs.is_empty() {
-        return Err(Error::EmptyProgram);
+        return Err(ErrorKind::EmptyProgram);
     }
 
     // Groups branches must be valid sub-programs
@@ -406,9 +635,18 @@ fn validate(exprs: &[Expr]) -> Result<(), Error> {
     // Must end with an action
     if !matches!(
         exprs[exprs.len() - 1],
-        Group(_) | Insert(_) | Append(_) | Change(_) | Sub(_, _) | Print(_) | Delete
+        Group(_)
+            | Insert(_)
+            | Append(_)
+            | Change(_)
+            | Sub(_, _)
+            | Print(_)
+            | PrintAddr
+            | PrintCharAddr
+            | Delete
+            | SetMark(_)
     ) {
-        return Err(Error::MissingAction);
+        return Err(ErrorKind::MissingAction);
     }
 
     Ok(())
@@ -416,7 +654,7 @@ fn validate(exprs: &[Expr]) -> Result<(), Error> {
 
 // FIXME: if a previous sub-match replacement injects a valid var name for a subsequent one
 // then we end up attempting to template THAT in a later iteration of the loop.
-fn template_match<E>(s: &str, m: &Match, ed: &E, fname: &str) -> Result<String, Error>
+pub(crate) fn template_match<E>(s: &str, m: &Match, ed: &E, fname: &str) -> Result<String, Error>
 where
     E: Edit,
 {
@@ -436,7 +674,7 @@ where
         }
         match ed.submatch(m, n) {
             Some(sm) => output = output.replace(var, &sm.to_string()),
-            None => return Err(Error::InvalidSubstitution(n)),
+            None => return Err(ErrorKind::InvalidSubstitution(n).into()),
         }
     }
 
@@ -460,21 +698,52 @@ mod tests {
     #[test]
     fn parse_program_works(s: &str, expected: Vec<Expr>) {
         let p = Program::try_parse(s).expect("valid input");
-        assert_eq!(
-            p,
-            Program {
-                initial_dot: Addr::full(),
-                exprs: expected
-            }
-        );
+        assert_eq!(p.initial_dot, Addr::full());
+        assert_eq!(p.exprs, expected);
+    }
+
+    #[test_case(", x/a\\%/ d"; "program regex")]
+    #[test_case("/a\\%/ d"; "address regex")]
+    #[test_case("-/a\\%/ d"; "reverse address regex")]
+    #[test]
+    fn regex_syntax_is_used_when_parsing(s: &str) {
+        assert!(Program::try_parse_with_syntax(s, Syntax::Sam).is_err());
+        assert!(Program::try_parse_with_syntax(s, Syntax::Compat).is_ok());
     }
 
-    #[test_case("", Error::EmptyProgram; "empty program")]
-    #[test_case(", x/.*/", Error::MissingAction; "missing action")]
+    #[test_case(", p/$0/", false; "print")]
+    #[test_case(", x/foo/ =#", false; "loop print")]
+    #[test_case(", x/foo/ d", true; "loop delete")]
+    #[test_case(", s/foo/bar/", true; "sub")]
+    #[test_case(", x/foo/ { p/$0/; c/bar/; }", true; "group")]
     #[test]
-    fn parse_program_errors_correctly(s: &str, expected: Error) {
+    fn modifies_text_works(s: &str, expected: bool) {
+        let p = Program::try_parse(s).expect("valid input");
+        assert_eq!(p.modifies_text(), expected);
+    }
+
+    fn re_err(kind: regex::ErrorKind, offset: usize) -> ErrorKind {
+        ErrorKind::InvalidRegex(regex::Error { kind, offset })
+    }
+
+    #[test_case("", ErrorKind::EmptyProgram, 0; "empty program")]
+    #[test_case(", x/.*/", ErrorKind::MissingAction, 6; "missing action")]
+    #[test_case(", x/.*/ z", ErrorKind::UnexpectedCharacter('z'), 8; "unexpected character")]
+    #[test_case("  , x/.*/ z", ErrorKind::UnexpectedCharacter('z'), 10; "leading whitespace")]
+    #[test_case(", x/a(b/ d", re_err(regex::ErrorKind::UnbalancedParens, 3), 7; "regex error")]
+    #[test_case(", x/a[/ d", re_err(regex::ErrorKind::InvalidClass, 2), 6; "regex error at delimiter")]
+    #[test_case(", s/a\\/(/b/", re_err(regex::ErrorKind::InvalidEscape('/'), 2), 6; "escaped delimiter")]
+    #[test_case("/a(/ d", re_err(regex::ErrorKind::UnbalancedParens, 2), 3; "address regex error")]
+    #[test]
+    fn parse_program_errors_correctly(s: &str, kind: ErrorKind, offset: usize) {
         let res = Program::try_parse(s);
-        assert_eq!(res, Err(expected));
+        assert_eq!(
+            res,
+            Err(Error {
+                kind,
+                offset: Some(offset)
+            })
+        );
     }
 
     #[test_case(vec![Insert("X".to_string())], "Xfoo foo foo", (0, 12); "insert")]
@@ -491,6 +760,7 @@ mod tests {
         let mut prog = Program {
             initial_dot: Addr::full(),
             exprs,
+            offsets: Vec::new(),
         };
         let mut b = Buffer::new_unnamed(0, "foo foo foo");
         let dot = prog
@@ -513,6 +783,22 @@ mod tests {
         assert_eq!(&b.txt.to_string(), expected);
     }
 
+    #[test_case(", x/(t.)/ c/$2/", 10; "top level")]
+    #[test_case("  , x/t./ p/$1/", 10; "leading whitespace")]
+    #[test_case(", x/(t.)/ { p/$1/; c/$3/; }", 10; "group")]
+    #[test_case(", s/(t.)/$2/g", 2; "sub all")]
+    #[test]
+    fn runtime_errors_are_located(s: &str, offset: usize) {
+        let mut prog = Program::try_parse(s).unwrap();
+        let mut b = Buffer::new_unnamed(0, "this is a test string");
+        let res = prog.execute(&mut b, "test", &mut vec![]);
+
+        assert!(
+            matches!(res, Err(Error { kind: ErrorKind::InvalidSubstitution(_), offset: Some(o) }) if o == offset),
+            "{res:?}"
+        );
+    }
+
     #[test]
     fn loop_between_generates_the_correct_blocks() {
         let mut prog = Program::try_parse(", y/ / p/>$0<\n/").unwrap();
@@ -559,6 +845,32 @@ mod tests {
         assert_eq!(&b.txt.to_string(), expected, "buffer");
     }
 
+    #[test_case("2 =", "2:1,2:12\n"; "line")]
+    #[test_case("2 =#", "#15,#26\n"; "line as chars")]
+    #[test_case("#3 =", "1:4\n"; "single char")]
+    #[test_case("#3 =#", "#3\n"; "single char as chars")]
+    #[test_case(", x/line|another/ =", "1:11,1:14\n2:5,2:11\n"; "each match")]
+    #[test]
+    fn print_addr_works(s: &str, expected: &str) {
+        let mut prog = Program::try_parse(s).unwrap();
+        let mut b = Buffer::new_unnamed(0, "this is a line\nand another\n");
+        let mut output = Vec::new();
+        prog.execute(&mut b, "test", &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test_case("/another/ ka", Dot::from_char_indices(19, 25); "range")]
+    #[test_case("#3 ka", Cur::new(3).into(); "single char")]
+    #[test]
+    fn set_mark_works(s: &str, expected: Dot) {
+        let mut prog = Program::try_parse(s).unwrap();
+        let mut b = Buffer::new_unnamed(0, "this is a line\nand another\n");
+        prog.execute(&mut b, "test", &mut vec![]).unwrap();
+
+        assert_eq!(b.mark('a'), Some(expected));
+    }
+
     #[test]
     fn multiline_file_dot_star_works() {
         let mut prog = Program::try_parse(", x/.*/ c/foo/").unwrap();
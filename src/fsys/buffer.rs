@@ -5,14 +5,19 @@ use crate::{
         event::{run_threaded_input_listener, send_event_to_editor, InputFilter, InputRequest},
         log::{Log, LogEvent},
         InternalRead, Message, Req, Result, BUFFERS_DIR, BUFFERS_QID, CURRENT_BUFFER,
-        CURRENT_BUFFER_QID, E_UNKNOWN_FILE, INDEX_BUFFER, INDEX_BUFFER_QID, QID_OFFSET,
+        CURRENT_BUFFER_QID, E_UNKNOWN_FILE, INDEX_BUFFER, INDEX_BUFFER_QID, LAST_FIXED_QID,
+        NEW_BUFFER, NEW_BUFFER_QID, QID_OFFSET,
     },
     input::Event,
 };
-use ninep::{fs::Stat, server::ReadOutcome};
+use ninep::{
+    fs::Stat,
+    server::{ClientId, ReadOutcome, ReadSender},
+};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     sync::mpsc::{channel, Receiver, Sender},
+    thread::spawn,
     time::SystemTime,
 };
 use tracing::{debug, error, trace};
@@ -25,6 +30,10 @@ const XADDR: &str = "xaddr";
 const BODY: &str = "body";
 const EVENT: &str = "event";
 const OUTPUT: &str = "output";
+const CTL: &str = "ctl";
+const EVENTS: &str = "events";
+const HIGHLIGHTS: &str = "highlights";
+const DIAGNOSTICS: &str = "diagnostics";
 
 pub(super) const BUFFER_FILES: [(u64, &str); QID_OFFSET as usize - 1] = [
     (1, FILENAME),
@@ -35,12 +44,16 @@ pub(super) const BUFFER_FILES: [(u64, &str); QID_OFFSET as usize - 1] = [
     (6, BODY),
     (7, EVENT),
     (8, OUTPUT),
+    (9, CTL),
+    (10, EVENTS),
+    (11, HIGHLIGHTS),
+    (12, DIAGNOSTICS),
 ];
 
 fn parent_and_fname(qid: u64) -> (u64, &'static str) {
-    assert!(qid > CURRENT_BUFFER_QID, "invalid buffer file qid");
+    assert!(qid > LAST_FIXED_QID, "invalid buffer file qid");
 
-    let (cur, off) = (CURRENT_BUFFER_QID, QID_OFFSET);
+    let (cur, off) = (LAST_FIXED_QID, QID_OFFSET);
     let parent = cur + 1 + ((qid - cur - 1) / off) * off;
     let fname = BUFFER_FILES[((qid - cur - 2) % off) as usize].1;
 
@@ -51,9 +64,22 @@ fn parent_and_fname(qid: u64) -> (u64, &'static str) {
 pub(super) enum QidCheck {
     Unknown,
     EventFile { buf_qid: u64 },
+    EventsFile { id: usize },
     OtherFile,
 }
 
+/// A buffer created for a client that has the new file open
+#[derive(Debug)]
+enum NewBuffer {
+    /// The editor has yet to reply with the id of the buffer so reads are handed off to the
+    /// thread waiting on it
+    Pending {
+        req: usize,
+        read_tx: Sender<(ReadSender, usize, usize)>,
+    },
+    Created(usize),
+}
+
 #[derive(Debug)]
 pub(super) struct BufferNodes {
     pub(super) known: BTreeMap<u64, BufferNode>,
@@ -63,6 +89,12 @@ pub(super) struct BufferNodes {
     stat: Stat,
     current_buff_stat: Stat,
     index_stat: Stat,
+    new_buff_stat: Stat,
+    /// The buffers created by each client that currently has the new file open
+    new_buffers: HashMap<ClientId, NewBuffer>,
+    next_new_req: usize,
+    created_tx: Sender<(usize, usize)>,
+    created_rx: Receiver<(usize, usize)>,
     tx: Sender<Event>,
     brx: Receiver<LogEvent>,
 }
@@ -71,16 +103,23 @@ impl BufferNodes {
     pub(super) fn new(
         tx: Sender<Event>,
         brx: Receiver<LogEvent>,
-        log_tx: Sender<Sender<Vec<u8>>>,
+        log_tx: Sender<ReadSender>,
     ) -> Self {
+        let (created_tx, created_rx) = channel();
+
         Self {
             known: BTreeMap::default(),
             log: Log::new(log_tx),
-            next_qid: CURRENT_BUFFER_QID + 1,
+            next_qid: LAST_FIXED_QID + 1,
             current_buffid: 1,
             stat: empty_dir_stat(BUFFERS_QID, BUFFERS_DIR),
             current_buff_stat: empty_file_stat(CURRENT_BUFFER_QID, CURRENT_BUFFER),
             index_stat: empty_file_stat(INDEX_BUFFER_QID, INDEX_BUFFER),
+            new_buff_stat: empty_file_stat(NEW_BUFFER_QID, NEW_BUFFER),
+            new_buffers: HashMap::default(),
+            next_new_req: 0,
+            created_tx,
+            created_rx,
             tx,
             brx,
         }
@@ -94,6 +133,7 @@ impl BufferNodes {
         let mut stats: Vec<Stat> = self.known.values().map(|b| b.stat.clone()).collect();
         stats.push(self.current_buff_stat.clone());
         stats.push(self.index_stat.clone());
+        stats.push(self.new_buff_stat.clone());
 
         stats
     }
@@ -114,7 +154,7 @@ impl BufferNodes {
                 match bn.check_if_known_qid(qid) {
                     QidCheck::Unknown => (),
                     QidCheck::EventFile { .. } => return QidCheck::EventFile { buf_qid },
-                    QidCheck::OtherFile => return QidCheck::OtherFile,
+                    check => return check,
                 }
             }
 
@@ -139,6 +179,7 @@ impl BufferNodes {
         match (parent, name) {
             (BUFFERS_QID, CURRENT_BUFFER) => Some(self.current_buff_stat.clone()),
             (BUFFERS_QID, INDEX_BUFFER) => Some(self.index_stat.clone()),
+            (BUFFERS_QID, NEW_BUFFER) => Some(self.new_buff_stat.clone()),
             (BUFFERS_QID, _) => self
                 .known
                 .values()
@@ -160,6 +201,8 @@ impl BufferNodes {
             return Some(self.current_buff_stat.clone());
         } else if qid == INDEX_BUFFER_QID {
             return Some(self.index_stat.clone());
+        } else if qid == NEW_BUFFER_QID {
+            return Some(self.new_buff_stat.clone());
         } else if let Some(b) = self.known.get(&qid) {
             return Some(b.stat());
         }
@@ -210,7 +253,7 @@ impl BufferNodes {
     }
 
     pub(super) fn truncate(&mut self, qid: u64) {
-        if qid == CURRENT_BUFFER_QID || qid == INDEX_BUFFER_QID {
+        if qid == CURRENT_BUFFER_QID || qid == INDEX_BUFFER_QID || qid == NEW_BUFFER_QID {
             return;
         }
 
@@ -245,6 +288,9 @@ impl BufferNodes {
             XDOT => Req::SetBufferXDot { id, s },
             XADDR => Req::SetBufferXAddr { id, s },
             OUTPUT => Req::AppendOutput { id, s },
+            CTL => Req::BufferControlMessage { id, msg: s },
+            HIGHLIGHTS => Req::SetBufferHighlights { id, s },
+            DIAGNOSTICS => Req::SetBufferDiagnostics { id, s },
             EVENT => return send_event_to_editor(id, &s, &self.tx),
             FILENAME => return Err(E_UNKNOWN_FILE.to_string()),
             _ => return Err(E_UNKNOWN_FILE.to_string()),
@@ -256,34 +302,143 @@ impl BufferNodes {
         }
     }
 
+    /// Ask the editor to create a new buffer for the given client.
+    ///
+    /// The request is made from a separate thread so that we are not blocked waiting on the
+    /// editor: reads of the new file made before the editor has replied are answered once the id
+    /// of the new buffer is known.
+    pub(super) fn create_new_buffer(&mut self, cid: ClientId) {
+        let req = self.next_new_req;
+        self.next_new_req += 1;
+
+        let (read_tx, read_rx) = channel();
+        spawn_new_buffer_request(req, self.tx.clone(), self.created_tx.clone(), read_rx);
+        self.new_buffers
+            .insert(cid, NewBuffer::Pending { req, read_tx });
+    }
+
+    pub(super) fn forget_new_buffer(&mut self, cid: ClientId) {
+        self.new_buffers.remove(&cid);
+    }
+
+    pub(super) fn new_buffer_id(
+        &mut self,
+        cid: ClientId,
+        offset: usize,
+        count: usize,
+    ) -> ReadOutcome {
+        let read_tx = match self.new_buffers.get(&cid) {
+            Some(NewBuffer::Created(id)) => return new_buffer_content(*id, offset, count),
+            Some(NewBuffer::Pending { read_tx, .. }) => read_tx,
+            None => return ReadOutcome::Immediate(Vec::new()),
+        };
+
+        let (tx, outcome) = ReadOutcome::blocked();
+        if read_tx.send((tx, offset, count)).is_ok() {
+            return outcome;
+        }
+
+        // The request thread only exits early if the editor failed to create the buffer
+        error!("unable to create new buffer");
+        self.new_buffers.remove(&cid);
+
+        ReadOutcome::Immediate(Vec::new())
+    }
+
     /// Process any pending updates from the main thread for changes to the buffer set
     pub(super) fn update(&mut self) {
-        for bid in self.brx.try_iter() {
-            self.log.push(bid);
-            match bid {
-                LogEvent::Open(id) => {
-                    debug!(%id, "adding buffer to fsys state");
-                    let qid = self.next_qid;
-                    self.next_qid += QID_OFFSET;
-                    self.known.insert(qid, BufferNode::new(id, qid));
-                }
+        while let Ok(evt) = self.brx.try_recv() {
+            self.handle_log_event(evt);
+        }
 
-                // TODO: handle closing defered reads of files associated with this buffer
-                LogEvent::Close(id) => {
-                    debug!(%id, "removing buffer from fsys state");
-                    self.known.retain(|_, v| v.id != id);
-                }
+        while let Ok((req, id)) = self.created_rx.try_recv() {
+            self.handle_created_buffer(req, id);
+        }
+    }
 
-                LogEvent::Focus(id) => {
-                    debug!(%id, "setting current buffer in fsys state");
-                    self.current_buffid = id;
-                    self.current_buff_stat.n_bytes = id.to_string().len() as u64;
-                }
+    fn handle_created_buffer(&mut self, req: usize, id: usize) {
+        // The editor notifies us of the new buffer before replying with its id so the event is
+        // already on its way from the log listener. We need to wait for it to arrive in order for
+        // the buffer to be usable as soon as the client has its id.
+        while !self.known.values().any(|b| b.id == id) {
+            match self.brx.recv() {
+                Ok(evt) => self.handle_log_event(evt),
+                Err(_) => return,
+            }
+        }
 
-                LogEvent::Save(_) => (), // only used in the log
-            };
+        let nb = self
+            .new_buffers
+            .values_mut()
+            .find(|nb| matches!(nb, NewBuffer::Pending { req: r, .. } if *r == req));
+
+        if let Some(nb) = nb {
+            // Dropping the pending read sender allows the request thread to exit
+            *nb = NewBuffer::Created(id);
+            let stat = &mut self.new_buff_stat;
+            stat.n_bytes = id.to_string().len() as u64;
+            stat.last_modified = SystemTime::now();
         }
     }
+
+    fn handle_log_event(&mut self, bid: LogEvent) {
+        self.log.push(bid);
+        match bid {
+            LogEvent::Open(id) => {
+                debug!(%id, "adding buffer to fsys state");
+                let qid = self.next_qid;
+                self.next_qid += QID_OFFSET;
+                self.known.insert(qid, BufferNode::new(id, qid));
+            }
+
+            // TODO: handle closing defered reads of files associated with this buffer
+            LogEvent::Close(id) => {
+                debug!(%id, "removing buffer from fsys state");
+                self.known.retain(|_, v| v.id != id);
+            }
+
+            LogEvent::Focus(id) => {
+                debug!(%id, "setting current buffer in fsys state");
+                self.current_buffid = id;
+                self.current_buff_stat.n_bytes = id.to_string().len() as u64;
+            }
+
+            LogEvent::Save(_) => (), // only used in the log
+        };
+    }
+}
+
+fn new_buffer_content(id: usize, offset: usize, count: usize) -> ReadOutcome {
+    ReadOutcome::Immediate(apply_offset(format!("{id}\n").as_bytes(), offset, count))
+}
+
+/// Ask the editor for a new buffer and then answer reads of the new file once we have its id.
+///
+/// The id is reported back to the filesystem thread before any reads are answered so that the
+/// buffer is known by the time the client sees the id. Reads continue to be answered until the
+/// filesystem thread drops its end of the read channel.
+fn spawn_new_buffer_request(
+    req: usize,
+    tx: Sender<Event>,
+    created_tx: Sender<(usize, usize)>,
+    read_rx: Receiver<(ReadSender, usize, usize)>,
+) {
+    spawn(move || {
+        let id: usize = match Message::send(Req::NewBuffer, &tx).map(|s| s.parse()) {
+            Ok(Ok(id)) => id,
+            Ok(Err(e)) => return error!("invalid buffer id: {e}"),
+            Err(e) => return error!("unable to create new buffer: {e}"),
+        };
+
+        if created_tx.send((req, id)).is_err() {
+            return; // filesystem thread has exited
+        }
+
+        let content = format!("{id}\n");
+        for (read_tx, offset, count) in read_rx.iter() {
+            _ = read_tx.send(apply_offset(content.as_bytes(), offset, count));
+        }
+    });
 }
 
 /// A BufferNode in the filesystem is a directory containing a fixed
@@ -326,11 +481,11 @@ impl BufferNode {
     fn check_if_known_qid(&self, qid: u64) -> QidCheck {
         for (&fname, s) in self.file_stats.iter() {
             if s.fm.qid == qid {
-                return if fname == EVENT {
+                return match fname {
                     // replaced with the correct qid by BufferNodes
-                    QidCheck::EventFile { buf_qid: 0 }
-                } else {
-                    QidCheck::OtherFile
+                    EVENT => QidCheck::EventFile { buf_qid: 0 },
+                    EVENTS => QidCheck::EventsFile { id: self.id },
+                    _ => QidCheck::OtherFile,
                 };
             }
         }
@@ -359,14 +514,14 @@ impl BufferNode {
     }
 
     fn refreshed_file_stat(&mut self, fname: &str, tx: &Sender<Event>) -> Option<Stat> {
-        if fname == OUTPUT || fname == EVENT {
+        if fname == OUTPUT || fname == EVENT || fname == EVENTS {
             return self.file_stats.get(fname).cloned();
         }
 
         trace!(id=%self.id, %fname, "refreshing file stat");
         let content = self.current_file_content_as_string(fname, tx)?;
         let stat = self.file_stats.get_mut(fname)?;
-        stat.n_bytes = content.as_bytes().len() as u64;
+        stat.n_bytes = content.len() as u64;
 
         Some(stat.clone())
     }
@@ -384,6 +539,9 @@ impl BufferNode {
             BODY => Req::ReadBufferBody { id: self.id },
             XDOT => Req::ReadBufferXDot { id: self.id },
             XADDR => Req::ReadBufferXAddr { id: self.id },
+            CTL => Req::ReadBufferCtl { id: self.id },
+            HIGHLIGHTS => Req::ReadBufferHighlights { id: self.id },
+            DIAGNOSTICS => Req::ReadBufferDiagnostics { id: self.id },
             OUTPUT => return Some(String::new()),
             _ => return None, // can hit this as part of walk for unknown files
         };
@@ -411,6 +569,9 @@ impl BufferNode {
             BODY => Req::ReadBufferBody { id: self.id },
             XDOT => Req::ReadBufferXDot { id: self.id },
             XADDR => Req::ReadBufferXAddr { id: self.id },
+            CTL => Req::ReadBufferCtl { id: self.id },
+            HIGHLIGHTS => Req::ReadBufferHighlights { id: self.id },
+            DIAGNOSTICS => Req::ReadBufferDiagnostics { id: self.id },
             OUTPUT => return InternalRead::Immediate(Vec::new()),
             EVENT => {
                 // ignoring offset
@@ -452,7 +613,7 @@ impl BufferNode {
 
         match read_rx.recv() {
             Ok(ReadOutcome::Immediate(data)) => Ok(InternalRead::Immediate(data)),
-            Ok(ReadOutcome::Blocked(rx)) => Ok(InternalRead::Blocked(rx)),
+            Ok(ReadOutcome::Blocked(blocked)) => Ok(InternalRead::Blocked(blocked)),
             Err(_) => Err("failed to read".to_string()),
         }
     }
@@ -473,9 +634,13 @@ mod tests {
     use super::*;
     use simple_test_case::test_case;
 
-    #[test_case(CURRENT_BUFFER_QID + 1 + 1, CURRENT_BUFFER_QID + 1, FILENAME; "filename first buffer")]
-    #[test_case(9, 7, DOT; "dot second buffer")]
-    #[test_case(22, 16, BODY; "body second buffer")]
+    #[test_case(LAST_FIXED_QID + 1 + 1, LAST_FIXED_QID + 1, FILENAME; "filename first buffer")]
+    #[test_case(11, 9, DOT; "dot first buffer")]
+    #[test_case(28, 22, BODY; "body second buffer")]
+    #[test_case(18, 9, CTL; "ctl first buffer")]
+    #[test_case(32, 22, EVENTS; "events second buffer")]
+    #[test_case(20, 9, HIGHLIGHTS; "highlights first buffer")]
+    #[test_case(34, 22, DIAGNOSTICS; "diagnostics second buffer")]
     #[test]
     fn parent_and_fname_works(qid: u64, parent: u64, fname: &str) {
         let (p, f) = parent_and_fname(qid);
@@ -483,4 +648,38 @@ mod tests {
         assert_eq!(p, parent);
         assert_eq!(f, fname);
     }
+
+    #[test]
+    fn reads_of_new_buffers_are_answered_once_the_editor_replies() {
+        let (tx, rx) = channel();
+        let (created_tx, created_rx) = channel();
+        let (read_tx, read_rx) = channel();
+        spawn_new_buffer_request(7, tx, created_tx, read_rx);
+
+        // Reads made before the editor has replied are blocked rather than blocking us
+        let (rtx, outcome) = ReadOutcome::blocked();
+        read_tx.send((rtx, 0, 100)).unwrap();
+
+        match rx.recv().unwrap() {
+            Event::Message(Message {
+                req: Req::NewBuffer,
+                tx,
+            }) => tx.send(Ok("3".to_string())).unwrap(),
+            evt => panic!("unexpected event: {evt:?}"),
+        }
+
+        assert_eq!(created_rx.recv(), Ok((7, 3)));
+        match outcome {
+            ReadOutcome::Blocked(blocked) => assert_eq!(blocked.recv(), Some(b"3\n".to_vec())),
+            outcome => panic!("unexpected outcome: {outcome:?}"),
+        }
+
+        // Reads after the id is known are still answered until the channel is dropped
+        let (rtx, outcome) = ReadOutcome::blocked();
+        read_tx.send((rtx, 1, 100)).unwrap();
+        match outcome {
+            ReadOutcome::Blocked(blocked) => assert_eq!(blocked.recv(), Some(b"\n".to_vec())),
+            outcome => panic!("unexpected outcome: {outcome:?}"),
+        }
+    }
 }
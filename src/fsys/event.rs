@@ -93,8 +93,8 @@ pub fn run_threaded_input_listener(event_rx: Receiver<FsysEvent>) -> Sender<Inpu
         }
 
         // Otherwise return a blocked read and wait for the next event to come through
-        let (read_tx, read_rx) = channel();
-        _ = tx.send(ReadOutcome::Blocked(read_rx));
+        let (read_tx, outcome) = ReadOutcome::blocked();
+        _ = tx.send(outcome);
         let data = match event_rx.recv() {
             Ok(evt) => evt.as_event_file_line().into_bytes(),
             Err(_) => return,
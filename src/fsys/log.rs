@@ -1,8 +1,8 @@
-use ninep::server::{ClientId, ReadOutcome};
+use ninep::server::{ClientId, ReadOutcome, ReadSender};
 use std::{
     collections::HashMap,
     mem::swap,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::mpsc::{Receiver, Sender},
     thread::spawn,
 };
 use tracing::{debug, error};
@@ -12,7 +12,7 @@ use tracing::{debug, error};
 pub(super) fn spawn_log_listener(
     log_event_rx: Receiver<LogEvent>,
     listener_tx: Sender<LogEvent>,
-    pending_rx: Receiver<Sender<Vec<u8>>>,
+    pending_rx: Receiver<ReadSender>,
 ) {
     spawn(move || {
         for event in log_event_rx.iter() {
@@ -69,11 +69,11 @@ impl ClientLog {
 #[derive(Debug)]
 pub(super) struct Log {
     events: HashMap<ClientId, ClientLog>,
-    tx: Sender<Sender<Vec<u8>>>,
+    tx: Sender<ReadSender>,
 }
 
 impl Log {
-    pub(super) fn new(tx: Sender<Sender<Vec<u8>>>) -> Self {
+    pub(super) fn new(tx: Sender<ReadSender>) -> Self {
         Self {
             events: HashMap::default(),
             tx,
@@ -108,7 +108,7 @@ impl Log {
     pub(super) fn events_since_last_read(&mut self, cid: ClientId) -> ReadOutcome {
         match self.events.get_mut(&cid) {
             Some(cl) if cl.is_empty_events() => {
-                let (tx, rx) = channel();
+                let (tx, outcome) = ReadOutcome::blocked();
                 if self.tx.send(tx).is_err() {
                     error!("log listener died");
                     return ReadOutcome::Immediate(Vec::new());
@@ -116,7 +116,7 @@ impl Log {
 
                 *cl = ClientLog::Pending;
 
-                ReadOutcome::Blocked(rx)
+                outcome
             }
 
             Some(ClientLog::Events(events)) => {
@@ -41,6 +41,7 @@ pub enum Req {
         lines: String,
         tx: Sender<String>,
     },
+    NewBuffer,
     ReadBufferName {
         id: usize,
     },
@@ -59,6 +60,15 @@ pub enum Req {
     ReadBufferBody {
         id: usize,
     },
+    ReadBufferCtl {
+        id: usize,
+    },
+    ReadBufferHighlights {
+        id: usize,
+    },
+    ReadBufferDiagnostics {
+        id: usize,
+    },
     SetBufferDot {
         id: usize,
         s: String,
@@ -75,6 +85,14 @@ pub enum Req {
         id: usize,
         s: String,
     },
+    SetBufferHighlights {
+        id: usize,
+        s: String,
+    },
+    SetBufferDiagnostics {
+        id: usize,
+        s: String,
+    },
     ClearBufferBody {
         id: usize,
     },
@@ -86,6 +104,10 @@ pub enum Req {
         id: usize,
         s: String,
     },
+    BufferControlMessage {
+        id: usize,
+        msg: String,
+    },
     AddInputEventFilter {
         id: usize,
         filter: InputFilter,
@@ -19,6 +19,7 @@
 //!   ctl
 //!   minibuffer
 //!   log
+//!   events
 //!   buffers/
 //!     [n]/
 //!       filename
@@ -26,34 +27,43 @@
 //!       addr
 //!       body
 //!       event
+//!       output
+//!       ctl
+//!       events
+//!       highlights
+//!       diagnostics
 //! ```
 use crate::{config_handle, input::Event};
 use ninep::{
     fs::{FileMeta, IoUnit, Mode, Perm, Stat},
-    server::{socket_path, ClientId, ReadOutcome, Serve9p, Server},
+    namespace::{alias_socket, socket_path},
+    server::{BlockedRead, ClientId, ReadOutcome, ReadSender, Serve9p, Server},
     Result,
 };
 use std::{
     collections::HashMap,
     env,
     fs::create_dir_all,
+    io,
     mem::take,
     path::Path,
-    process::Command,
+    process::{self, Command},
     sync::mpsc::{channel, Receiver, Sender},
     thread::{spawn, JoinHandle},
     time::SystemTime,
 };
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 
 mod buffer;
 mod event;
 mod log;
 mod message;
+mod observer;
 
 pub(crate) use event::InputFilter;
 pub(crate) use log::LogEvent;
 pub(crate) use message::{Message, Req};
+pub(crate) use observer::{Observed, Observers, PendingChanges};
 
 use buffer::{BufferNodes, QidCheck};
 use log::spawn_log_listener;
@@ -83,6 +93,15 @@ const INDEX_BUFFER: &str = "index";
 //    6      /current   -> the fsys filename of the current buffer
 const CURRENT_BUFFER_QID: u64 = 6;
 const CURRENT_BUFFER: &str = "current";
+//    7      /new       -> opening creates a new buffer, reading returns its id
+const NEW_BUFFER_QID: u64 = 7;
+const NEW_BUFFER: &str = "new";
+///   8    /events      -> read only stream of changes to all buffers
+const EVENTS_FILE_QID: u64 = 8;
+const EVENTS_FILE: &str = "events";
+
+/// Buffer directories are allocated qids following on from the last of the fixed qids above
+const LAST_FIXED_QID: u64 = EVENTS_FILE_QID;
 
 /// The number of qids required to serve both the directory and contents
 /// of a buffer node (used to generate qid values for buffers):
@@ -96,9 +115,13 @@ const CURRENT_BUFFER: &str = "current";
 ///   7.   body         -> The full body of the buffer
 ///   8.   event        -> Contol file for intercepting input events for the buffer
 ///   9.   output       -> Write only output connected to stdout/err of commands run within the buffer
-const QID_OFFSET: u64 = 9;
+///  10.   ctl          -> Control file for issuing commands scoped to the buffer and reading its state
+///  11.   events       -> Read only stream of changes to the buffer
+///  12.   highlights   -> Style spans for the buffer provided by external programs
+///  13.   diagnostics  -> Diagnostic messages for the buffer provided by external programs
+const QID_OFFSET: u64 = 13;
 
-const TOP_LEVEL_QIDS: [u64; 7] = [
+const TOP_LEVEL_QIDS: [u64; 9] = [
     MOUNT_ROOT_QID,
     CONTROL_FILE_QID,
     MINIBUFFER_QID,
@@ -106,6 +129,8 @@ const TOP_LEVEL_QIDS: [u64; 7] = [
     BUFFERS_QID,
     INDEX_BUFFER_QID,
     CURRENT_BUFFER_QID,
+    NEW_BUFFER_QID,
+    EVENTS_FILE_QID,
 ];
 
 const E_UNKNOWN_FILE: &str = "unknown file";
@@ -113,7 +138,7 @@ const E_NOT_ALLOWED: &str = "not allowed";
 
 enum InternalRead {
     Immediate(Vec<u8>),
-    Blocked(Receiver<Vec<u8>>),
+    Blocked(BlockedRead),
     Unknown,
 }
 
@@ -134,11 +159,26 @@ impl FsHandle {
     }
 }
 
+/// The name of the socket used to serve the filesystem for this editor.
+fn socket_name() -> String {
+    format!("{DEFAULT_SOCKET_NAME}.{}", process::id())
+}
+
+/// Point the default socket name at this editor's filesystem.
+pub(crate) fn claim_default_socket() {
+    if let Err(e) = alias_socket(&socket_name(), DEFAULT_SOCKET_NAME) {
+        warn!(
+            "unable to alias {DEFAULT_SOCKET_NAME} to {}: {e}",
+            socket_name()
+        );
+    }
+}
+
 #[derive(Debug)]
 enum MiniBufferContent {
     Buffering(Vec<u8>),
     Data(Vec<u8>),
-    Pending(Sender<Sender<Vec<u8>>>, Receiver<Vec<u8>>),
+    Pending(Sender<ReadSender>, Receiver<Vec<u8>>),
 }
 
 /// The filesystem interface for ad
@@ -146,6 +186,7 @@ enum MiniBufferContent {
 pub(crate) struct AdFs {
     tx: Sender<Event>,
     buffer_nodes: BufferNodes,
+    observers: Observers,
     minibuffer_content: MiniBufferContent,
     minibuffer_prompt: Option<String>,
     /// map of qids to client IDs with that qid open
@@ -155,6 +196,7 @@ pub(crate) struct AdFs {
     control_file_stat: Stat,
     minibuffer_stat: Stat,
     log_file_stat: Stat,
+    events_file_stat: Stat,
     mount_path: String,
     auto_mount: bool,
 }
@@ -175,7 +217,7 @@ impl Drop for AdFs {
 
 impl AdFs {
     /// Construct a new filesystem interface using channels held by the editor.
-    pub fn new(tx: Sender<Event>, brx: Receiver<LogEvent>) -> Self {
+    pub fn new(tx: Sender<Event>, brx: Receiver<LogEvent>, observers: Observers) -> Self {
         let home = env::var("HOME").expect("$HOME to be set");
         let mount_path = format!("{home}/{MOUNT_DIR}");
 
@@ -193,6 +235,7 @@ impl AdFs {
         Self {
             tx,
             buffer_nodes,
+            observers,
             open_cids: HashMap::new(),
             minibuffer_content: MiniBufferContent::Data(Vec::new()),
             minibuffer_prompt: None,
@@ -200,19 +243,25 @@ impl AdFs {
             control_file_stat: empty_file_stat(CONTROL_FILE_QID, CONTROL_FILE),
             minibuffer_stat: empty_file_stat(MINIBUFFER_QID, MINIBUFFER),
             log_file_stat: empty_file_stat(LOG_FILE_QID, LOG_FILE),
+            events_file_stat: empty_file_stat(EVENTS_FILE_QID, EVENTS_FILE),
             mount_path,
             auto_mount,
         }
     }
 
-    /// Spawn a thread for running this filesystem and return a handle to it
-    pub fn run_threaded(self) -> FsHandle {
+    /// Spawn a thread for running this filesystem and return a handle to it.
+    ///
+    /// Each editor serves its filesystem using a socket named for its pid and the default socket
+    /// name is an alias for the editor that most recently gained focus.
+    pub fn run_threaded(self) -> io::Result<FsHandle> {
         let auto_mount = self.auto_mount;
         let mount_path = self.mount_path.clone();
-        let socket_path = socket_path(DEFAULT_SOCKET_NAME);
+        let name = socket_name();
+        let socket_path = socket_path(&name)?;
 
         let s = Server::new(self);
-        let handle = FsHandle(s.serve_socket(DEFAULT_SOCKET_NAME.to_string()));
+        let handle = FsHandle(s.serve_socket(&name)?);
+        claim_default_socket();
 
         if auto_mount {
             let res = Command::new("9pfuse")
@@ -224,7 +273,7 @@ impl AdFs {
             }
         }
 
-        handle
+        Ok(handle)
     }
 
     fn add_open_cid(&mut self, qid: u64, cid: ClientId) {
@@ -288,7 +337,7 @@ impl AdFs {
                 self.minibuffer_stat.last_modified = SystemTime::now();
                 spawn_minibuffer_listener(data_rx, fsys_tx, sub_rx);
 
-                let (tx, rx) = channel();
+                let (tx, outcome) = ReadOutcome::blocked();
                 _ = sub_tx.send(tx);
                 self.minibuffer_content = MiniBufferContent::Pending(sub_tx, fsys_rx);
 
@@ -300,7 +349,7 @@ impl AdFs {
                     },
                     &self.tx,
                 ) {
-                    Ok(_) => ReadOutcome::Blocked(rx),
+                    Ok(_) => outcome,
                     Err(e) => {
                         error!("unable to open minibuffer: {e}");
                         self.minibuffer_content = MiniBufferContent::Buffering(Vec::new());
@@ -320,9 +369,9 @@ impl AdFs {
                     ReadOutcome::Immediate(apply_offset(&data, offset, count))
                 }
                 _ => {
-                    let (tx, rx) = channel();
+                    let (tx, outcome) = ReadOutcome::blocked();
                     _ = sub_tx.send(tx);
-                    ReadOutcome::Blocked(rx)
+                    outcome
                 }
             },
         }
@@ -333,7 +382,7 @@ impl AdFs {
 fn spawn_minibuffer_listener(
     data_rx: Receiver<String>,
     fsys_tx: Sender<Vec<u8>>,
-    sub_rx: Receiver<Sender<Vec<u8>>>,
+    sub_rx: Receiver<ReadSender>,
 ) {
     spawn(move || {
         let data = match data_rx.recv() {
@@ -364,6 +413,7 @@ impl Serve9p for AdFs {
             CONTROL_FILE_QID => Ok(self.control_file_stat.clone()),
             MINIBUFFER_QID => Ok(self.minibuffer_stat.clone()),
             LOG_FILE_QID => Ok(self.log_file_stat.clone()),
+            EVENTS_FILE_QID => Ok(self.events_file_stat.clone()),
             BUFFERS_QID => Ok(self.buffer_nodes.stat().clone()),
             qid => match self.buffer_nodes.get_stat_for_qid(qid) {
                 Some(stat) => Ok(stat.clone()),
@@ -379,7 +429,8 @@ impl Serve9p for AdFs {
         if stat.n_bytes == 0 {
             trace!(%qid, %uname, "stat n_bytes=0, truncating file");
             match qid {
-                MOUNT_ROOT_QID | CONTROL_FILE_QID | MINIBUFFER_QID | LOG_FILE_QID => (),
+                MOUNT_ROOT_QID | CONTROL_FILE_QID | MINIBUFFER_QID | LOG_FILE_QID
+                | NEW_BUFFER_QID | EVENTS_FILE_QID => (),
                 qid => self.buffer_nodes.truncate(qid),
             }
         }
@@ -402,6 +453,7 @@ impl Serve9p for AdFs {
                 CONTROL_FILE => Ok(self.control_file_stat.fm.clone()),
                 MINIBUFFER => Ok(self.minibuffer_stat.fm.clone()),
                 LOG_FILE => Ok(self.log_file_stat.fm.clone()),
+                EVENTS_FILE => Ok(self.events_file_stat.fm.clone()),
                 BUFFERS_DIR => Ok(self.buffer_nodes.stat().fm.clone()),
                 _ => match self.buffer_nodes.lookup_file_stat(parent_qid, child) {
                     Some(stat) => Ok(stat.fm.clone()),
@@ -420,15 +472,28 @@ impl Serve9p for AdFs {
         }
     }
 
-    fn open(&mut self, cid: ClientId, qid: u64, mode: Mode, uname: &str) -> Result<IoUnit> {
-        trace!(?cid, %qid, %uname, ?mode, "handling open request");
+    fn open(
+        &mut self,
+        cid: ClientId,
+        fid: u32,
+        qid: u64,
+        mode: Mode,
+        uname: &str,
+    ) -> Result<IoUnit> {
+        trace!(?cid, %fid, %qid, %uname, ?mode, "handling open request");
         self.buffer_nodes.update();
 
         if qid == LOG_FILE_QID {
             self.buffer_nodes.log.add_client(cid);
+        } else if qid == EVENTS_FILE_QID {
+            self.observers.subscribe(cid, fid, None);
+        } else if qid == NEW_BUFFER_QID {
+            self.buffer_nodes.create_new_buffer(cid);
         } else if !TOP_LEVEL_QIDS.contains(&qid) {
-            if let QidCheck::Unknown = self.buffer_nodes.check_if_known_qid(qid) {
-                return Err(format!("{E_UNKNOWN_FILE}: {qid}"));
+            match self.buffer_nodes.check_if_known_qid(qid) {
+                QidCheck::Unknown => return Err(format!("{E_UNKNOWN_FILE}: {qid}")),
+                QidCheck::EventsFile { id } => self.observers.subscribe(cid, fid, Some(id)),
+                _ => (),
             }
         }
 
@@ -437,14 +502,22 @@ impl Serve9p for AdFs {
         Ok(IO_UNIT)
     }
 
-    fn clunk(&mut self, cid: ClientId, qid: u64) {
-        trace!(?cid, %qid, "handling clunk request");
+    fn clunk(&mut self, cid: ClientId, fid: u32, qid: u64) {
+        trace!(?cid, %fid, %qid, "handling clunk request");
 
         if qid == LOG_FILE_QID {
             self.buffer_nodes.log.remove_client(cid);
-        } else if let QidCheck::EventFile { buf_qid } = self.buffer_nodes.check_if_known_qid(qid) {
-            if self.readlocked_cid(qid) == Some(cid) {
-                self.buffer_nodes.clear_input_filter(buf_qid);
+        } else if qid == EVENTS_FILE_QID {
+            self.observers.unsubscribe(cid, fid);
+        } else if qid == NEW_BUFFER_QID {
+            self.buffer_nodes.forget_new_buffer(cid);
+        } else {
+            match self.buffer_nodes.check_if_known_qid(qid) {
+                QidCheck::EventFile { buf_qid } if self.readlocked_cid(qid) == Some(cid) => {
+                    self.buffer_nodes.clear_input_filter(buf_qid);
+                }
+                QidCheck::EventsFile { .. } => self.observers.unsubscribe(cid, fid),
+                _ => (),
             }
         }
         self.remove_open_cid(qid, cid); // also handles clearing the read lock
@@ -453,12 +526,13 @@ impl Serve9p for AdFs {
     fn read(
         &mut self,
         cid: ClientId,
+        fid: u32,
         qid: u64,
         offset: usize,
         count: usize,
         uname: &str,
     ) -> Result<ReadOutcome> {
-        trace!(?cid, %qid, %offset, %count, %uname, "handling read request");
+        trace!(?cid, %fid, %qid, %offset, %count, %uname, "handling read request");
         self.buffer_nodes.update();
 
         if qid == CONTROL_FILE_QID {
@@ -467,10 +541,14 @@ impl Serve9p for AdFs {
             return Ok(self.minibuffer_read(offset, count));
         } else if qid == LOG_FILE_QID {
             return Ok(self.buffer_nodes.log.events_since_last_read(cid));
+        } else if qid == EVENTS_FILE_QID {
+            return self.observers.read(cid, fid, count);
+        } else if qid == NEW_BUFFER_QID {
+            return Ok(self.buffer_nodes.new_buffer_id(cid, offset, count));
         }
 
-        if let QidCheck::EventFile { buf_qid } = self.buffer_nodes.check_if_known_qid(qid) {
-            match self.readlocked_cid(qid) {
+        match self.buffer_nodes.check_if_known_qid(qid) {
+            QidCheck::EventFile { buf_qid } => match self.readlocked_cid(qid) {
                 Some(id) if id == cid => (),
                 Some(_) => return Ok(ReadOutcome::Immediate(Vec::new())),
                 None => {
@@ -478,13 +556,15 @@ impl Serve9p for AdFs {
                     self.buffer_nodes.attach_input_filter(buf_qid)?;
                     self.lock_qid_for_reading(qid, cid)?;
                 }
-            }
+            },
+            QidCheck::EventsFile { .. } => return self.observers.read(cid, fid, count),
+            _ => (),
         }
 
         match self.buffer_nodes.get_file_content(qid, offset, count) {
             InternalRead::Unknown => Err(format!("{E_UNKNOWN_FILE}: {qid}")),
             InternalRead::Immediate(content) => Ok(ReadOutcome::Immediate(content)),
-            InternalRead::Blocked(tx) => Ok(ReadOutcome::Blocked(tx)),
+            InternalRead::Blocked(blocked) => Ok(ReadOutcome::Blocked(blocked)),
         }
     }
 
@@ -495,6 +575,7 @@ impl Serve9p for AdFs {
         match qid {
             MOUNT_ROOT_QID => Ok(vec![
                 self.log_file_stat.clone(),
+                self.events_file_stat.clone(),
                 self.minibuffer_stat.clone(),
                 self.control_file_stat.clone(),
                 self.buffer_nodes.stat().clone(),
@@ -510,12 +591,13 @@ impl Serve9p for AdFs {
     fn write(
         &mut self,
         cid: ClientId,
+        fid: u32,
         qid: u64,
         offset: usize,
         data: Vec<u8>,
         uname: &str,
     ) -> Result<usize> {
-        trace!(?cid, %qid, %offset, n_bytes=%data.len(), %uname, "handling write request");
+        trace!(?cid, %fid, %qid, %offset, n_bytes=%data.len(), %uname, "handling write request");
         self.buffer_nodes.update();
 
         let n_bytes = data.len();
@@ -541,9 +623,22 @@ impl Serve9p for AdFs {
 
             MINIBUFFER_QID => self.minibuffer_write(s),
 
-            CURRENT_BUFFER_QID | LOG_FILE_QID | INDEX_BUFFER_QID => Err(E_NOT_ALLOWED.to_string()),
+            EVENTS_FILE_QID => {
+                self.observers.set_kinds(cid, fid, None, &s)?;
+                Ok(n_bytes)
+            }
+
+            CURRENT_BUFFER_QID | NEW_BUFFER_QID | LOG_FILE_QID | INDEX_BUFFER_QID => {
+                Err(E_NOT_ALLOWED.to_string())
+            }
 
-            qid => self.buffer_nodes.write(qid, s, offset),
+            qid => match self.buffer_nodes.check_if_known_qid(qid) {
+                QidCheck::EventsFile { id } => {
+                    self.observers.set_kinds(cid, fid, Some(id), &s)?;
+                    Ok(n_bytes)
+                }
+                _ => self.buffer_nodes.write(qid, s, offset),
+            },
         }
     }
 
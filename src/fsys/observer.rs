@@ -0,0 +1,539 @@
+//! Read only streams of editor events for passive observers
+//!
+//! Unlike the per-buffer event file, any number of clients are able to read from the events
+//! files at the same time without taking control of how input is handled. Each open fid has its
+//! own queue of events which is drained on read, with reads blocking until a new event arrives
+//! if the queue is empty. Reads return whole lines where the requested count allows and queues
+//! are capped in size, dropping the oldest events if a client stops reading.
+use crate::dot::Dot;
+use ninep::server::{ClientId, ReadOutcome, ReadSender};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    hash::Hash,
+    mem::take,
+    sync::{mpsc::SendError, Arc, Mutex, MutexGuard},
+};
+use tracing::{debug, error, warn};
+
+/// The kinds of event that can be subscribed to by observers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ObservedKind {
+    Insert,
+    Delete,
+    Dot,
+    Save,
+    Mode,
+    Focus,
+}
+
+impl ObservedKind {
+    const ALL: [Self; 6] = [
+        Self::Insert,
+        Self::Delete,
+        Self::Dot,
+        Self::Save,
+        Self::Mode,
+        Self::Focus,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn try_from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "insert" => Ok(Self::Insert),
+            "delete" => Ok(Self::Delete),
+            "dot" => Ok(Self::Dot),
+            "save" => Ok(Self::Save),
+            "mode" => Ok(Self::Mode),
+            "focus" => Ok(Self::Focus),
+            _ => Err(format!("unknown event kind: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for ObservedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+            Self::Dot => "dot",
+            Self::Save => "save",
+            Self::Mode => "mode",
+            Self::Focus => "focus",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+/// A change within the editor that is reported to observers.
+///
+/// Character offsets for inserts and deletes are the half open range of characters that were
+/// modified while those for dot are the (inclusive) start and end of the new dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Observed {
+    Insert { from: usize, to: usize, txt: String },
+    Delete { from: usize, to: usize },
+    Dot { from: usize, to: usize },
+    Save,
+    Mode(String),
+    Focus,
+}
+
+impl Observed {
+    fn kind(&self) -> ObservedKind {
+        match self {
+            Self::Insert { .. } => ObservedKind::Insert,
+            Self::Delete { .. } => ObservedKind::Delete,
+            Self::Dot { .. } => ObservedKind::Dot,
+            Self::Save => ObservedKind::Save,
+            Self::Mode(_) => ObservedKind::Mode,
+            Self::Focus => ObservedKind::Focus,
+        }
+    }
+
+    /// Render this event as a line in an events file.
+    ///
+    /// Inserted text has backslashes and newlines escaped so that each event is a single line.
+    pub(crate) fn as_events_line(&self, id: usize) -> String {
+        let kind = self.kind();
+        match self {
+            Self::Insert { from, to, txt } => {
+                let txt = txt.replace('\\', "\\\\").replace('\n', "\\n");
+                format!("{id} {kind} {from} {to} {txt}\n")
+            }
+            Self::Delete { from, to } | Self::Dot { from, to } => {
+                format!("{id} {kind} {from} {to}\n")
+            }
+            Self::Mode(name) => format!("{id} {kind} {name}\n"),
+            Self::Save | Self::Focus => format!("{id} {kind}\n"),
+        }
+    }
+}
+
+/// Changes made to a buffer that have not yet been published to observers.
+#[derive(Debug, Default)]
+pub(crate) struct PendingChanges {
+    changes: Vec<Observed>,
+    dot: Dot,
+}
+
+impl PendingChanges {
+    pub(crate) fn push(&mut self, change: Observed) {
+        self.changes.push(change);
+    }
+
+    /// Take the pending changes, adding a dot change if the given dot differs from the one seen
+    /// the last time changes were taken.
+    pub(crate) fn take(&mut self, dot: Dot) -> Vec<Observed> {
+        let mut changes = take(&mut self.changes);
+        if dot != self.dot {
+            let (from, to) = dot.as_char_indices();
+            changes.push(Observed::Dot { from, to });
+            self.dot = dot;
+        }
+
+        changes
+    }
+}
+
+/// The maximum number of bytes of events held for a subscriber that is not reading them. Once
+/// this is exceeded the oldest events are dropped.
+const MAX_QUEUED_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug)]
+struct Subscriber {
+    buffer: Option<usize>,
+    kinds: u8,
+    lines: VecDeque<Vec<u8>>,
+    n_bytes: usize,
+    /// A blocked read along with the maximum number of bytes it is able to accept
+    pending: Option<(ReadSender, usize)>,
+}
+
+impl Subscriber {
+    fn new(buffer: Option<usize>) -> Self {
+        Self {
+            buffer,
+            kinds: ObservedKind::ALL.iter().fold(0, |k, kind| k | kind.bit()),
+            lines: VecDeque::new(),
+            n_bytes: 0,
+            pending: None,
+        }
+    }
+
+    fn wants(&self, id: usize, kind: ObservedKind) -> bool {
+        self.buffer.is_none_or(|b| b == id) && self.kinds & kind.bit() != 0
+    }
+
+    fn set_kinds(&mut self, s: &str) -> Result<(), String> {
+        let mut kinds = 0;
+        for k in s.split_whitespace() {
+            kinds |= ObservedKind::try_from_str(k)?.bit();
+        }
+        self.kinds = kinds;
+
+        Ok(())
+    }
+
+    fn push(&mut self, line: &str) {
+        self.lines.push_back(line.as_bytes().to_vec());
+        self.n_bytes += line.len();
+
+        let mut dropped = 0;
+        while self.n_bytes > MAX_QUEUED_BYTES && self.lines.len() > 1 {
+            let line = self.lines.pop_front().expect("len > 1");
+            self.n_bytes -= line.len();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!("events queue is full: dropped {dropped} events");
+        }
+
+        if let Some((tx, count)) = self.pending.take() {
+            let data = self.take_upto(count);
+            if let Err(SendError(data)) = tx.send(data) {
+                debug!("blocked events reader went away: requeueing events");
+                self.n_bytes += data.len();
+                self.lines.push_front(data);
+            }
+        }
+    }
+
+    /// Remove at most `count` bytes of queued events, ending on a line boundary unless the first
+    /// queued line is longer than `count` in which case it is split.
+    fn take_upto(&mut self, count: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(line) = self.lines.front_mut() {
+            if data.len() + line.len() <= count {
+                data.append(line);
+                self.lines.pop_front();
+                continue;
+            }
+
+            if data.is_empty() {
+                data = line.drain(..split_point(line, count)).collect();
+            }
+            break;
+        }
+        self.n_bytes -= data.len();
+
+        data
+    }
+
+    fn read(&mut self, count: usize) -> Result<ReadOutcome, String> {
+        if self.pending.is_some() {
+            return Err("events file already has a blocked read".to_string());
+        } else if count == 0 {
+            return Ok(ReadOutcome::Immediate(Vec::new()));
+        } else if !self.lines.is_empty() {
+            return Ok(ReadOutcome::Immediate(self.take_upto(count)));
+        }
+
+        let (tx, outcome) = ReadOutcome::blocked();
+        self.pending = Some((tx, count));
+
+        Ok(outcome)
+    }
+}
+
+/// Where to split a line that is longer than `count` bytes: after the last newline within the
+/// first `count` bytes if there is one, otherwise on the last char boundary.
+fn split_point(line: &[u8], count: usize) -> usize {
+    if let Some(i) = line[..count].iter().rposition(|&b| b == b'\n') {
+        return i + 1;
+    }
+
+    // continuation bytes in UTF-8 are of the form 0b10xxxxxx
+    (1..=count)
+        .rev()
+        .find(|&i| line[i] & 0b1100_0000 != 0b1000_0000)
+        .unwrap_or(count)
+}
+
+fn publish_to<'a>(
+    subscribers: impl Iterator<Item = &'a mut Subscriber>,
+    id: usize,
+    changes: &[Observed],
+) {
+    let lines: Vec<(ObservedKind, String)> = changes
+        .iter()
+        .map(|c| (c.kind(), c.as_events_line(id)))
+        .collect();
+
+    for sub in subscribers {
+        for (kind, line) in lines.iter() {
+            if sub.wants(id, *kind) {
+                sub.push(line);
+            }
+        }
+    }
+}
+
+/// The set of clients reading from an events file.
+///
+/// This is shared between the editor (which publishes events) and fsys (which manages
+/// subscriptions and handles reads) so that blocked reads are able to return as soon as an event
+/// is published.
+///
+/// Subscriptions are keyed by client and fid. The client type is only generic so that
+/// subscriptions can be tested without a running 9p server.
+#[derive(Debug, Clone)]
+pub(crate) struct Observers<C = ClientId> {
+    subscribers: Arc<Mutex<HashMap<(C, u32), Subscriber>>>,
+}
+
+impl<C> Default for Observers<C> {
+    fn default() -> Self {
+        Self {
+            subscribers: Default::default(),
+        }
+    }
+}
+
+impl<C> Observers<C>
+where
+    C: fmt::Debug + Copy + Eq + Hash,
+{
+    fn lock(&self) -> MutexGuard<'_, HashMap<(C, u32), Subscriber>> {
+        match self.subscribers.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Send the given changes to the buffer with the given id to any interested subscribers.
+    pub(crate) fn publish(&self, id: usize, changes: &[Observed]) {
+        if !changes.is_empty() {
+            publish_to(self.lock().values_mut(), id, changes);
+        }
+    }
+
+    /// Start tracking events for a client that has opened an events file using the given fid,
+    /// optionally restricted to a single buffer. Existing subscriptions are left unchanged.
+    ///
+    /// Each open fid has its own subscription so that a client is able to read multiple event
+    /// streams over a single connection.
+    pub(super) fn subscribe(&self, cid: C, fid: u32, buffer: Option<usize>) {
+        self.lock()
+            .entry((cid, fid))
+            .or_insert_with(|| Subscriber::new(buffer));
+    }
+
+    pub(super) fn unsubscribe(&self, cid: C, fid: u32) {
+        self.lock().remove(&(cid, fid));
+    }
+
+    /// Restrict the events sent to a client to the space separated kinds listed in `s`.
+    ///
+    /// Empty writes leave the current subscription unchanged.
+    pub(super) fn set_kinds(
+        &self,
+        cid: C,
+        fid: u32,
+        buffer: Option<usize>,
+        s: &str,
+    ) -> Result<(), String> {
+        if s.trim().is_empty() {
+            return Ok(());
+        }
+
+        self.lock()
+            .entry((cid, fid))
+            .or_insert_with(|| Subscriber::new(buffer))
+            .set_kinds(s)
+    }
+
+    /// Return up to `count` bytes of the events queued for the given fid, blocking until the next
+    /// event is published if there are none.
+    ///
+    /// Only one read may be blocked on each fid at a time.
+    pub(super) fn read(&self, cid: C, fid: u32, count: usize) -> Result<ReadOutcome, String> {
+        match self.lock().get_mut(&(cid, fid)) {
+            Some(sub) => sub.read(count),
+            None => {
+                error!("got events read from {cid:?} without subscribing");
+                Ok(ReadOutcome::Immediate(Vec::new()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case(Observed::Insert { from: 3, to: 5, txt: "a\nb\\".to_string() }, "1 insert 3 5 a\\nb\\\\\n"; "insert")]
+    #[test_case(Observed::Delete { from: 3, to: 5 }, "1 delete 3 5\n"; "delete")]
+    #[test_case(Observed::Dot { from: 4, to: 4 }, "1 dot 4 4\n"; "dot")]
+    #[test_case(Observed::Mode("INSERT".to_string()), "1 mode INSERT\n"; "mode")]
+    #[test_case(Observed::Focus, "1 focus\n"; "focus")]
+    #[test]
+    fn as_events_line_works(evt: Observed, expected: &str) {
+        assert_eq!(evt.as_events_line(1), expected);
+    }
+
+    fn read_immediate(sub: &mut Subscriber) -> String {
+        read_n(sub, 1024)
+    }
+
+    fn read_n(sub: &mut Subscriber, count: usize) -> String {
+        match sub.read(count).unwrap() {
+            ReadOutcome::Immediate(data) => String::from_utf8(data).unwrap(),
+            ReadOutcome::Blocked(_) => panic!("read blocked"),
+        }
+    }
+
+    #[test]
+    fn subscribers_only_see_events_they_asked_for() {
+        let mut all = Subscriber::new(None);
+        let mut saves = Subscriber::new(None);
+        saves.set_kinds("save focus\n").unwrap();
+        let mut buf = Subscriber::new(Some(2));
+
+        let mut subs = [&mut all, &mut saves, &mut buf];
+        let changes = [Observed::Delete { from: 0, to: 1 }, Observed::Save];
+        publish_to(subs.iter_mut().map(|s| &mut **s), 1, &changes);
+        publish_to(subs.iter_mut().map(|s| &mut **s), 2, &[Observed::Focus]);
+
+        assert_eq!(read_immediate(&mut all), "1 delete 0 1\n1 save\n2 focus\n");
+        assert_eq!(read_immediate(&mut saves), "1 save\n2 focus\n");
+        assert_eq!(read_immediate(&mut buf), "2 focus\n");
+    }
+
+    #[test]
+    fn unknown_kinds_are_an_error() {
+        let mut sub = Subscriber::new(None);
+        assert!(sub.set_kinds("insert explode").is_err());
+    }
+
+    #[test]
+    fn blocked_reads_are_sent_the_next_event() {
+        let mut sub = Subscriber::new(None);
+        let rx = match sub.read(1024).unwrap() {
+            ReadOutcome::Blocked(rx) => rx,
+            ReadOutcome::Immediate(_) => panic!("read did not block"),
+        };
+        publish_to(
+            [&mut sub].into_iter(),
+            3,
+            &[Observed::Save, Observed::Focus],
+        );
+
+        assert_eq!(rx.recv().unwrap(), b"3 save\n");
+        assert_eq!(read_immediate(&mut sub), "3 focus\n");
+    }
+
+    #[test]
+    fn streams_on_the_same_client_are_independent() {
+        let obs: Observers<u8> = Observers::default();
+        obs.subscribe(1, 10, None);
+        obs.subscribe(1, 11, None);
+        obs.set_kinds(1, 11, None, "save").unwrap();
+
+        let rx = match obs.read(1, 10, 1024).unwrap() {
+            ReadOutcome::Blocked(rx) => rx,
+            ReadOutcome::Immediate(_) => panic!("read did not block"),
+        };
+        assert!(matches!(
+            obs.read(1, 11, 1024).unwrap(),
+            ReadOutcome::Blocked(_)
+        ));
+
+        obs.publish(1, &[Observed::Focus, Observed::Save]);
+        assert_eq!(rx.recv().unwrap(), b"1 focus\n");
+
+        obs.unsubscribe(1, 11);
+        obs.publish(2, &[Observed::Focus]);
+        match obs.read(1, 10, 1024).unwrap() {
+            ReadOutcome::Immediate(data) => assert_eq!(data, b"1 save\n2 focus\n"),
+            ReadOutcome::Blocked(_) => panic!("read blocked"),
+        }
+    }
+
+    #[test]
+    fn a_second_blocked_read_is_an_error() {
+        let mut sub = Subscriber::new(None);
+        let _outcome = sub.read(1024).unwrap();
+
+        assert!(sub.read(1024).is_err());
+    }
+
+    #[test]
+    fn blocked_reads_respect_count() {
+        let mut sub = Subscriber::new(None);
+        let rx = match sub.read(5).unwrap() {
+            ReadOutcome::Blocked(rx) => rx,
+            ReadOutcome::Immediate(_) => panic!("read did not block"),
+        };
+        publish_to([&mut sub].into_iter(), 3, &[Observed::Save]);
+
+        assert_eq!(rx.recv().unwrap(), b"3 sav");
+        assert_eq!(read_immediate(&mut sub), "e\n");
+    }
+
+    #[test_case(100, &["1 save\n1 focus\n1 dot 0 0\n"]; "everything fits")]
+    #[test_case(16, &["1 save\n1 focus\n", "1 dot 0 0\n"]; "split on line boundaries")]
+    #[test_case(4, &["1 sa", "ve\n", "1 fo", "cus\n", "1 do", "t 0 ", "0\n"]; "long lines are split")]
+    #[test]
+    fn reads_are_limited_to_count(count: usize, expected: &[&str]) {
+        let mut sub = Subscriber::new(None);
+        let changes = [
+            Observed::Save,
+            Observed::Focus,
+            Observed::Dot { from: 0, to: 0 },
+        ];
+        publish_to([&mut sub].into_iter(), 1, &changes);
+
+        let chunks: Vec<String> = expected.iter().map(|_| read_n(&mut sub, count)).collect();
+        assert_eq!(chunks, expected);
+        assert!(sub.lines.is_empty());
+        assert_eq!(sub.n_bytes, 0);
+    }
+
+    #[test]
+    fn long_lines_are_split_on_char_boundaries() {
+        let mut sub = Subscriber::new(None);
+        let change = Observed::Insert {
+            from: 0,
+            to: 2,
+            txt: "🦀🦀".to_string(),
+        };
+        publish_to([&mut sub].into_iter(), 1, &[change]);
+
+        assert_eq!(read_n(&mut sub, 16), "1 insert 0 2 ");
+        assert_eq!(read_n(&mut sub, 6), "🦀");
+        assert_eq!(read_n(&mut sub, 16), "🦀\n");
+    }
+
+    #[test]
+    fn the_oldest_events_are_dropped_when_the_queue_is_full() {
+        let mut sub = Subscriber::new(None);
+        let txt = "a".repeat(MAX_QUEUED_BYTES / 2);
+        let changes = [
+            Observed::Save,
+            Observed::Insert {
+                from: 0,
+                to: 1,
+                txt: txt.clone(),
+            },
+            Observed::Insert {
+                from: 0,
+                to: 1,
+                txt: txt.clone(),
+            },
+        ];
+        publish_to([&mut sub].into_iter(), 1, &changes);
+
+        assert_eq!(sub.lines.len(), 1);
+        assert_eq!(sub.n_bytes, sub.lines[0].len());
+        assert_eq!(
+            read_n(&mut sub, MAX_QUEUED_BYTES),
+            format!("1 insert 0 1 {txt}\n")
+        );
+    }
+}
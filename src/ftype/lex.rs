@@ -7,6 +7,7 @@ pub(crate) enum TokenType {
     Dot,
     Load,
     Execute,
+    Search,
     Default,
     Comment,
     String,
@@ -14,6 +15,9 @@ pub(crate) enum TokenType {
     ControlFlow,
     Definition,
     Punctuation,
+    Error,
+    Warning,
+    Info,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,6 +32,7 @@ impl<'a> Token<'a> {
             TokenType::Dot => format!("{}{}", Style::Bg(cs.dot_bg), self.s),
             TokenType::Load => format!("{}{}", Style::Bg(cs.load_bg), self.s),
             TokenType::Execute => format!("{}{}", Style::Bg(cs.exec_bg), self.s),
+            TokenType::Search => format!("{}{}", Style::Bg(cs.search_bg), self.s),
             TokenType::Default => format!("{}{}{}", Style::Bg(cs.bg), Style::Fg(cs.fg), self.s),
             TokenType::Comment => format!(
                 "{}{}{}{}{}",
@@ -70,6 +75,21 @@ impl<'a> Token<'a> {
                 Style::Fg(cs.punctuation),
                 self.s
             ),
+            TokenType::Error | TokenType::Warning | TokenType::Info => {
+                let fg = match self.ty {
+                    TokenType::Error => cs.error,
+                    TokenType::Warning => cs.warning,
+                    _ => cs.info,
+                };
+                format!(
+                    "{}{}{}{}{}",
+                    Style::Underline,
+                    Style::Bg(cs.bg),
+                    Style::Fg(fg),
+                    self.s,
+                    Style::NoUnderline
+                )
+            }
         }
     }
 
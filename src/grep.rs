@@ -0,0 +1,392 @@
+//! A built in, project wide grep using ad's own regex engine.
+//!
+//! Directories are walked recursively, skipping hidden files and directories along with anything
+//! that is ignored by a .gitignore file found along the way.
+use crate::{
+    buffer::GapBuffer,
+    exec::{self, template_match},
+    regex::Regex,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The number of bytes checked for a NUL byte when determining if a file is binary.
+const BINARY_CHECK_LEN: usize = 8192;
+
+/// A single pattern parsed from a .gitignore file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Pattern {
+    fn try_parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        // A separator anywhere other than the end anchors the pattern to the .gitignore directory
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            glob: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, rel: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let target = if self.anchored {
+            rel
+        } else {
+            rel.rsplit('/').next().unwrap_or(rel)
+        };
+
+        glob_match(&self.glob, &target.chars().collect::<Vec<_>>())
+    }
+}
+
+/// Match a gitignore style glob against a path: '*' and '?' do not match '/', '**' matches
+/// anything (including '/') and "[...]" matches a class of characters.
+fn glob_match(glob: &[char], s: &[char]) -> bool {
+    match glob {
+        [] => s.is_empty(),
+
+        ['*', '*', '/', rest @ ..] => {
+            glob_match(rest, s)
+                || (0..s.len()).any(|i| s[i] == '/' && glob_match(rest, &s[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        ['*', rest @ ..] => (0..=s.len())
+            .take_while(|&i| i == 0 || s[i - 1] != '/')
+            .any(|i| glob_match(rest, &s[i..])),
+
+        ['?', rest @ ..] => matches!(s, [ch, ..] if *ch != '/') && glob_match(rest, &s[1..]),
+
+        ['[', rest @ ..] => match (s.first(), rest.iter().skip(1).position(|&ch| ch == ']')) {
+            (Some(&ch), Some(end)) if ch != '/' => {
+                let (class, rest) = (&rest[..end + 1], &rest[end + 2..]);
+                class_matches(class, ch) && glob_match(rest, &s[1..])
+            }
+            (_, None) => matches!(s, ['[', ..]) && glob_match(rest, &s[1..]),
+            _ => false,
+        },
+
+        ['\\', ch, rest @ ..] | [ch, rest @ ..] => {
+            matches!(s, [c, ..] if c == ch) && glob_match(rest, &s[1..])
+        }
+    }
+}
+
+fn class_matches(class: &[char], ch: char) -> bool {
+    let (negated, class) = match class {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= ch && ch <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == ch;
+            i += 1;
+        }
+    }
+
+    matched != negated
+}
+
+/// The patterns from a single .gitignore file along with the directory containing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gitignore {
+    root: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl Gitignore {
+    /// Parse the contents of a .gitignore file found in the directory `root`.
+    pub fn parse(root: impl Into<PathBuf>, contents: &str) -> Self {
+        Self {
+            root: root.into(),
+            patterns: contents.lines().filter_map(Pattern::try_parse).collect(),
+        }
+    }
+
+    /// Whether or not the given path is ignored by this file. None is returned if no patterns
+    /// match and Some(false) if the path is explicitly un-ignored by a negated pattern.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = path.strip_prefix(&self.root).ok()?.to_str()?;
+
+        // Later patterns take precedence over earlier ones
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.matches(rel, is_dir))
+            .map(|p| !p.negated)
+    }
+}
+
+/// Recursively walk `dir` calling `f` on each file that is not hidden or ignored by a .gitignore
+/// file. Directory entries are visited in sorted order and symlinks are not followed. If `f`
+/// returns false then the walk is stopped.
+pub fn walk_files<F>(dir: &Path, mut f: F)
+where
+    F: FnMut(&Path) -> bool,
+{
+    walk(dir, &mut Vec::new(), &mut f);
+}
+
+fn walk<F>(dir: &Path, ignores: &mut Vec<Gitignore>, f: &mut F) -> bool
+where
+    F: FnMut(&Path) -> bool,
+{
+    let has_gitignore = match fs::read_to_string(dir.join(".gitignore")) {
+        Ok(s) => {
+            ignores.push(Gitignore::parse(dir, &s));
+            true
+        }
+        Err(_) => false,
+    };
+
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(it) => it
+            .flatten()
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| Some((e.path(), e.file_type().ok()?)))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut keep_going = true;
+    for (path, ty) in entries {
+        if !(ty.is_dir() || ty.is_file()) {
+            continue;
+        }
+        let ignored = ignores
+            .iter()
+            .rev()
+            .find_map(|g| g.is_ignored(&path, ty.is_dir()))
+            .unwrap_or(false);
+        if ignored {
+            continue;
+        }
+
+        keep_going = if ty.is_dir() {
+            walk(&path, ignores, f)
+        } else {
+            f(&path)
+        };
+        if !keep_going {
+            break;
+        }
+    }
+
+    if has_gitignore {
+        ignores.pop();
+    }
+
+    keep_going
+}
+
+/// Whether or not the given path (relative to the root of a search) matches a glob. As with
+/// .gitignore patterns, globs that do not contain a '/' are matched against the file name only.
+pub fn glob_matches(glob: &str, rel: &str) -> bool {
+    match Pattern::try_parse(glob) {
+        Some(p) => !p.negated && p.matches(rel, false),
+        None => false,
+    }
+}
+
+/// Read the contents of a text file, returning None if the file is binary or not valid utf8.
+pub fn read_text_file(path: &Path) -> Option<String> {
+    let raw = fs::read(path).ok()?;
+    if raw.iter().take(BINARY_CHECK_LEN).any(|&b| b == 0) {
+        return None;
+    }
+
+    String::from_utf8(raw).ok()
+}
+
+/// Search the file at `path` for lines matching `re`, returning one "name:line:col: text" line
+/// per matching line. Binary and non-utf8 files are skipped.
+pub fn grep_file(re: &mut Regex, path: &Path, name: &str) -> Vec<String> {
+    let s = match read_text_file(path) {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+
+    let mut results = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        if let Some(m) = re.match_str(line) {
+            let (col, _) = m.loc();
+            results.push(format!("{name}:{}:{}: {line}", i + 1, col + 1));
+        }
+    }
+
+    results
+}
+
+/// Replace every match of `re` within `line` using a template in the form supported by the `s`
+/// command in Edit programs. Returns None if there were no matches.
+pub fn replace_matches(
+    re: &mut Regex,
+    line: &str,
+    template: &str,
+    fname: &str,
+) -> Result<Option<String>, exec::Error> {
+    let gb = GapBuffer::from(line);
+    let chars: Vec<char> = line.chars().collect();
+    let mut output = String::new();
+    let mut last = None;
+
+    for m in re.match_str_all(line) {
+        let (from, to) = m.loc();
+        output.extend(&chars[last.unwrap_or(0)..from]);
+        output.push_str(&template_match(template, &m, &gb, fname)?);
+        last = Some(to);
+    }
+
+    match last {
+        Some(to) => {
+            output.extend(&chars[to..]);
+            Ok(Some(output))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+    use std::process;
+
+    #[test_case("target", "target", true, Some(true); "plain name")]
+    #[test_case("target", "crates/foo/target", true, Some(true); "plain name nested")]
+    #[test_case("target/", "crates/foo/target", false, None; "dir only pattern against file")]
+    #[test_case("/target", "crates/foo/target", true, None; "anchored pattern nested")]
+    #[test_case("/target", "target", true, Some(true); "anchored pattern at root")]
+    #[test_case("*.log", "logs/a.log", false, Some(true); "star extension")]
+    #[test_case("*.log\n!keep.log", "logs/keep.log", false, Some(false); "negated pattern")]
+    #[test_case("docs/*.md", "docs/a/b.md", false, None; "star does not cross separator")]
+    #[test_case("docs/**/*.md", "docs/a/b.md", false, Some(true); "double star")]
+    #[test_case("docs/**/*.md", "docs/b.md", false, Some(true); "double star zero dirs")]
+    #[test_case("**/build", "a/b/build", true, Some(true); "leading double star")]
+    #[test_case("out/**", "out/a/b.txt", false, Some(true); "trailing double star")]
+    #[test_case("file?.[ch]", "file1.c", false, Some(true); "question mark and class")]
+    #[test_case("file[!0-9].rs", "file1.rs", false, None; "negated class range")]
+    #[test_case("# comment\n\n\\#hash", "#hash", false, Some(true); "comments and escapes")]
+    #[test]
+    fn gitignore_matching(contents: &str, path: &str, is_dir: bool, expected: Option<bool>) {
+        let g = Gitignore::parse("/repo", contents);
+        let p = Path::new("/repo").join(path);
+
+        assert_eq!(g.is_ignored(&p, is_dir), expected);
+    }
+
+    #[test_case("*.rs", "src/main.rs", true; "extension")]
+    #[test_case("src/*.rs", "src/main.rs", true; "anchored")]
+    #[test_case("src/*.rs", "src/editor/mod.rs", false; "anchored star is not recursive")]
+    #[test_case("src/**/*.rs", "src/editor/mod.rs", true; "anchored double star")]
+    #[test_case("*.md", "src/main.rs", false; "no match")]
+    #[test]
+    fn glob_matches_works(glob: &str, rel: &str, expected: bool) {
+        assert_eq!(glob_matches(glob, rel), expected);
+    }
+
+    #[test_case("foo", "bar", "a foo b foo", Some("a bar b bar"); "all matches")]
+    #[test_case("(\\w+)=(\\w+)", "$2=$1", "x a=b", Some("x b=a"); "submatches")]
+    #[test_case("^", "// ", "code", Some("// code"); "empty match")]
+    #[test_case("foo", "bar", "nothing here", None; "no matches")]
+    #[test]
+    fn replace_matches_works(re: &str, template: &str, line: &str, expected: Option<&str>) {
+        let mut re = Regex::compile(re).unwrap();
+        let res = replace_matches(&mut re, line, template, "test").unwrap();
+
+        assert_eq!(res.as_deref(), expected);
+    }
+
+    /// A temporary directory containing the given files that is removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("ad-grep-test.{}.{name}", process::id()));
+            for (rel, contents) in files {
+                let path = dir.join(rel);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+
+            Self(dir)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn grep_file_reports_line_and_column() {
+        let dir = Fixture::new("grep", &[("init.conf", "# settings\nset tabstop=4\n")]);
+        let mut re = Regex::compile("tabstop=\\d").unwrap();
+        let results = grep_file(&mut re, &dir.0.join("init.conf"), "init.conf");
+
+        assert_eq!(results, vec!["init.conf:2:5: set tabstop=4"]);
+    }
+
+    #[test]
+    fn walk_files_skips_hidden_and_ignored_files() {
+        let dir = Fixture::new(
+            "walk",
+            &[
+                (".gitignore", "target/\n*.log\n"),
+                (".hidden", "hidden file"),
+                (".git/config", "hidden directory"),
+                ("debug.log", "ignored file"),
+                ("target/out.txt", "ignored directory"),
+                ("notes.txt", "notes"),
+                ("src/main.rs", "fn main() {}"),
+                ("src/.gitignore", "generated.rs\n"),
+                ("src/generated.rs", "nested ignore"),
+            ],
+        );
+
+        let mut files = Vec::new();
+        walk_files(&dir.0, |p| {
+            files.push(p.strip_prefix(&dir.0).unwrap().display().to_string());
+            true
+        });
+
+        assert_eq!(files, vec!["notes.txt", "src/main.rs"]);
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     editor::Action,
     fsys::Message,
     key::{Input, MouseEvent},
+    lsp::LspEvent,
     term::win_size_changed,
 };
 use std::{
@@ -16,12 +17,16 @@ use std::{
 pub enum Event {
     /// A [Message] received from the virtual filesystem interface
     Message(Message),
+    /// An [LspEvent] from a running language server
+    Lsp(LspEvent),
     /// An [Input] from the user
     Input(Input),
     /// An [Action] for the event loop to handle
     Action(Action),
     /// A signal that our window size has changed
     WinsizeChanged,
+    /// A signal that the terminal we are running in has gained focus
+    FocusGained,
 }
 
 /// A tui input handle that parses stdin and emits [Event]s to the main editor event loop.
@@ -78,6 +83,16 @@ impl StdinInput {
             return Some(key);
         }
 
+        // xterm focus reporting: "^[[I" for focus in and "^[[O" for focus out
+        match (c2, c3) {
+            ('[', 'I') => {
+                _ = self.tx.send(Event::FocusGained);
+                return None;
+            }
+            ('[', 'O') => return None,
+            _ => (),
+        }
+
         if c2 == '[' && c3.is_ascii_digit() {
             if let Some('~') = self.try_read_char() {
                 if let Some(key) = Input::try_from_bracket_tilde(c3) {
@@ -1,4 +1,10 @@
 //! Keypresses and related user interactions.
+//!
+//! Sequences of keys can be written out as text using the same notation that is used to show
+//! pending keys in the status bar: Ctrl-x is "^x", Alt-x is "^[x" and Ctrl-Alt-x is "^[^x".
+//! Other keys are written inside of angle brackets, either by name ("<esc>", "<ret>") or as the
+//! hex code of the character they insert ("<20>" for a space). Whitespace in the text form is
+//! ignored so that long sequences can be split over multiple lines.
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Arrow {
@@ -127,3 +133,118 @@ impl MouseEvent {
         }
     }
 }
+
+/// Names used for keys that are not characters in the text form of a key sequence.
+const KEY_NAMES: [(Input, &str); 14] = [
+    (Input::Tab, "tab"),
+    (Input::BackTab, "backtab"),
+    (Input::Return, "ret"),
+    (Input::Backspace, "bs"),
+    (Input::Del, "del"),
+    (Input::Home, "home"),
+    (Input::End, "end"),
+    (Input::PageUp, "pgup"),
+    (Input::PageDown, "pgdn"),
+    (Input::Esc, "esc"),
+    (Input::Arrow(Arrow::Up), "up"),
+    (Input::Arrow(Arrow::Down), "down"),
+    (Input::Arrow(Arrow::Left), "left"),
+    (Input::Arrow(Arrow::Right), "right"),
+];
+
+/// Render a sequence of keys in its text form. Mouse events have no text form and are skipped.
+pub(crate) fn render_keys(keys: &[Input]) -> String {
+    let mut s = String::new();
+    for k in keys {
+        match k {
+            Input::Char(c) if c.is_whitespace() || *c == '^' || *c == '<' => {
+                s.push_str(&format!("<{:x}>", *c as u32))
+            }
+            Input::Char(c) => s.push(*c),
+            Input::Ctrl(c) => {
+                s.push('^');
+                s.push(*c);
+            }
+            Input::Alt(c) => {
+                s.push('^');
+                s.push('[');
+                s.push(*c);
+            }
+            Input::CtrlAlt(c) => {
+                s.push('^');
+                s.push('[');
+                s.push('^');
+                s.push(*c);
+            }
+            Input::Mouse(_) => (),
+            k => {
+                if let Some((_, name)) = KEY_NAMES.iter().find(|(i, _)| i == k) {
+                    s.push('<');
+                    s.push_str(name);
+                    s.push('>');
+                }
+            }
+        }
+    }
+
+    s
+}
+
+/// Parse the text form of a sequence of keys as produced by [render_keys].
+pub(crate) fn parse_keys(s: &str) -> Result<Vec<Input>, String> {
+    let mut chars = s.chars().filter(|c| !c.is_whitespace());
+    let mut keys = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let k = match c {
+            '^' => match chars.next() {
+                Some('[') => match chars.next() {
+                    Some('^') => Input::CtrlAlt(chars.next().ok_or("missing key after '^[^'")?),
+                    Some(c) => Input::Alt(c),
+                    None => return Err("missing key after '^['".to_string()),
+                },
+                Some(c) => Input::Ctrl(c),
+                None => return Err("missing key after '^'".to_string()),
+            },
+            '<' => {
+                let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+                match KEY_NAMES.iter().find(|(_, n)| *n == name) {
+                    Some((k, _)) => *k,
+                    None => u32::from_str_radix(&name, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .map(Input::Char)
+                        .ok_or_else(|| format!("unknown key '<{name}>'"))?,
+                }
+            }
+            c => Input::Char(c),
+        };
+        keys.push(k);
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    #[test_case("ab", vec![Input::Char('a'), Input::Char('b')]; "chars")]
+    #[test_case("^a^[b^[^c", vec![Input::Ctrl('a'), Input::Alt('b'), Input::CtrlAlt('c')]; "modifiers")]
+    #[test_case("<20><3c><5e>", vec![Input::Char(' '), Input::Char('<'), Input::Char('^')]; "escaped chars")]
+    #[test_case("<esc><ret><up>", vec![Input::Esc, Input::Return, Input::Arrow(Arrow::Up)]; "named keys")]
+    #[test]
+    fn render_and_parse_keys_round_trip(s: &str, keys: Vec<Input>) {
+        assert_eq!(render_keys(&keys), s);
+        assert_eq!(parse_keys(s), Ok(keys));
+    }
+
+    #[test_case("a b\n\tc", Ok(vec![Input::Char('a'), Input::Char('b'), Input::Char('c')]); "whitespace is ignored")]
+    #[test_case("<nope>", Err("unknown key '<nope>'".to_string()); "unknown key name")]
+    #[test_case("^", Err("missing key after '^'".to_string()); "trailing ctrl")]
+    #[test]
+    fn parse_keys_works(s: &str, expected: Result<Vec<Input>, String>) {
+        assert_eq!(parse_keys(s), expected);
+    }
+}
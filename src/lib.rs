@@ -24,12 +24,15 @@ pub mod editor;
 pub mod exec;
 pub mod fsys;
 pub mod ftype;
+pub mod grep;
 pub mod input;
 pub mod key;
 pub mod log;
+pub mod lsp;
 pub mod mode;
 pub mod plumb;
 pub mod regex;
+pub mod snippets;
 pub mod system;
 pub mod term;
 pub mod trie;
@@ -42,7 +45,7 @@ pub use exec::{CachedStdin, Edit, Program};
 pub use log::LogBuffer;
 pub use plumb::PlumbingRules;
 
-use term::{disable_alternate_screen, disable_mouse_support, set_termios};
+use term::{disable_alternate_screen, disable_focus_reporting, disable_mouse_support, set_termios};
 
 /// The environment variable to set to control logging within ad
 pub const LOG_LEVEL_ENV_VAR: &str = "AD_LOG";
@@ -105,6 +108,7 @@ macro_rules! die {
 pub(crate) fn restore_terminal_state(so: &mut Stdout) {
     disable_alternate_screen(so);
     disable_mouse_support(so);
+    disable_focus_reporting(so);
     let t = match ORIGINAL_TERMIOS.get() {
         Some(t) => t,
         None => return,
@@ -0,0 +1,526 @@
+//! A connection to a single running language server.
+use crate::{
+    buffer::{Buffer, GapBuffer},
+    config::LspServerConfig,
+    fsys::Observed,
+    input::Event,
+    lsp::{
+        rpc::{
+            error_response, notification, read_message, request, response, write_message, Message,
+        },
+        types::{
+            parse_hover, parse_workspace_edit, path_to_uri, uri_to_path, Location, LspDiagnostic,
+            Position, PositionEncoding, Range,
+        },
+        LspEvent, LspResponse,
+    },
+    VERSION,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::Sender,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+use tracing::{debug, error, warn};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The requests we have sent to the server that are awaiting a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Pending {
+    Initialize,
+    Shutdown,
+    Definition,
+    Hover,
+    References,
+    Rename,
+}
+
+/// How the server would like to be sent changes to open documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncKind {
+    None,
+    Full,
+    Incremental,
+}
+
+/// An open buffer along with its text as last sent to the server.
+///
+/// The text is needed to convert the character offsets of changes into positions within the
+/// document as the server currently has it (see the module docs for why this is not taken from
+/// the edit log of the buffer).
+#[derive(Debug)]
+struct Doc {
+    path: PathBuf,
+    uri: String,
+    version: i64,
+    txt: GapBuffer,
+}
+
+pub(super) struct LspClient {
+    pub(super) id: usize,
+    pub(super) lang: String,
+    pub(super) enc: PositionEncoding,
+    child: Option<Child>,
+    w: Box<dyn Write + Send>,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    initialized: bool,
+    capabilities: Value,
+    sync: SyncKind,
+    /// Whether or not the server wants to be notified when documents are saved, and if so
+    /// whether it wants the text of the document as well
+    save: Option<bool>,
+    docs: HashMap<usize, Doc>,
+}
+
+impl fmt::Debug for LspClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LspClient")
+            .field("id", &self.id)
+            .field("lang", &self.lang)
+            .field("initialized", &self.initialized)
+            .field("docs", &self.docs.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl LspClient {
+    /// Spawn the configured server command as a child process, communicating over its stdio.
+    pub(super) fn spawn(
+        id: usize,
+        cfg: &LspServerConfig,
+        root: &Path,
+        tx: Sender<Event>,
+    ) -> io::Result<Self> {
+        let mut args = cfg.command.split_whitespace();
+        let prog = args
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "empty command"))?;
+
+        let mut child = Command::new(prog)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("stdin to be piped");
+        let stdout = child.stdout.take().expect("stdout to be piped");
+        let stderr = child.stderr.take().expect("stderr to be piped");
+
+        let lang = cfg.lang.clone();
+        spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                debug!(%lang, "language server stderr: {line}");
+            }
+        });
+
+        Ok(Self::new(
+            id,
+            &cfg.lang,
+            Some(child),
+            Box::new(stdin),
+            stdout,
+            tx,
+        ))
+    }
+
+    /// Construct a new client writing messages to `w` and reading them from `r`. Messages from
+    /// the server are sent to the main event loop from a background thread until `r` is closed.
+    pub(super) fn new<R>(
+        id: usize,
+        lang: &str,
+        child: Option<Child>,
+        w: Box<dyn Write + Send>,
+        r: R,
+        tx: Sender<Event>,
+    ) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let l = lang.to_string();
+        spawn(move || {
+            let mut r = BufReader::new(r);
+            loop {
+                match read_message(&mut r) {
+                    Ok(Some(v)) => match Message::try_from_value(v) {
+                        Ok(msg) => {
+                            let evt = LspEvent {
+                                server: id,
+                                msg: Some(msg),
+                            };
+                            if tx.send(Event::Lsp(evt)).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => warn!(lang=%l, "invalid message from language server: {e}"),
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!(lang=%l, "error reading from language server: {e}");
+                        break;
+                    }
+                }
+            }
+
+            _ = tx.send(Event::Lsp(LspEvent {
+                server: id,
+                msg: None,
+            }));
+        });
+
+        Self {
+            id,
+            lang: lang.to_string(),
+            enc: PositionEncoding::default(),
+            child,
+            w,
+            next_id: 1,
+            pending: HashMap::new(),
+            initialized: false,
+            capabilities: Value::Null,
+            sync: SyncKind::None,
+            save: None,
+            docs: HashMap::new(),
+        }
+    }
+
+    fn send(&mut self, msg: &Value) {
+        if let Err(e) = write_message(&mut self.w, msg) {
+            warn!(lang=%self.lang, "unable to write to language server: {e}");
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value, pending: Pending) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, pending);
+        self.send(&request(id, method, params));
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(&notification(method, params));
+    }
+
+    pub(super) fn initialize(&mut self, root: &Path) {
+        let uri = path_to_uri(root);
+        let name = root
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let params = json!({
+            "processId": std::process::id(),
+            "clientInfo": { "name": "ad", "version": VERSION },
+            "rootPath": root,
+            "rootUri": uri,
+            "workspaceFolders": [{ "uri": uri, "name": name }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-32", "utf-16"] },
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": { "linkSupport": true },
+                    "references": {},
+                    "rename": {},
+                    "publishDiagnostics": {},
+                },
+                "workspace": {
+                    "configuration": true,
+                    "workspaceFolders": true,
+                    "workspaceEdit": { "documentChanges": true },
+                },
+            },
+        });
+
+        self.request("initialize", params, Pending::Initialize);
+    }
+
+    /// Ask the server to exit, killing it if it has not done so shortly afterwards.
+    fn shutdown(&mut self) {
+        self.request("shutdown", Value::Null, Pending::Shutdown);
+        self.notify("exit", Value::Null);
+
+        if let Some(mut child) = self.child.take() {
+            for _ in 0..10 {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                sleep(Duration::from_millis(20));
+            }
+            _ = child.kill();
+            _ = child.wait();
+        }
+    }
+
+    pub(super) fn has_doc(&self, id: usize) -> bool {
+        self.docs.contains_key(&id)
+    }
+
+    /// Whether the server advertised the given provider in its capabilities.
+    pub(super) fn supports(&self, provider: &str) -> bool {
+        !matches!(
+            self.capabilities.get(provider),
+            None | Some(Value::Null) | Some(Value::Bool(false))
+        )
+    }
+
+    /// Bring the server's view of the buffer up to date, opening it if this is the first time
+    /// that we have seen it. Changes made before the server has finished initializing are
+    /// dropped as the full text is sent when the buffer is opened.
+    pub(super) fn sync(&mut self, b: &Buffer, path: &Path, changes: &[Observed]) {
+        if !self.initialized {
+            return;
+        }
+
+        let enc = self.enc;
+        let doc = match self.docs.get_mut(&b.id) {
+            Some(doc) => doc,
+            None => return self.open(b, path),
+        };
+
+        let mut content_changes = Vec::new();
+        let mut saved = false;
+        for change in changes.iter() {
+            match change {
+                Observed::Insert { from, txt, .. } if *from <= doc.txt.len_chars() => {
+                    let pos = Position::from_char_idx(&doc.txt, *from, enc).to_value();
+                    content_changes.push(json!({
+                        "range": { "start": pos, "end": pos },
+                        "text": txt,
+                    }));
+                    doc.txt.insert_str(*from, txt);
+                }
+                Observed::Delete { from, to } if from < to && *to <= doc.txt.len_chars() => {
+                    let range = Range::from_char_indices(&doc.txt, *from, *to, enc);
+                    content_changes.push(json!({ "range": range.to_value(), "text": "" }));
+                    doc.txt.remove_range(*from, *to);
+                }
+                Observed::Save => saved = true,
+                _ => (),
+            }
+        }
+
+        // If we have somehow got out of step with the buffer then we fall back to resending
+        // the full text rather than leaving the server with the wrong content
+        let in_sync = doc.txt.len_chars() == b.txt.len_chars();
+        if !in_sync || (self.sync == SyncKind::Full && !content_changes.is_empty()) {
+            let text = b.txt.to_string();
+            doc.txt = GapBuffer::from(text.as_str());
+            content_changes = vec![json!({ "text": text })];
+        }
+
+        let uri = doc.uri.clone();
+        if !content_changes.is_empty() {
+            doc.version += 1;
+            let params = json!({
+                "textDocument": { "uri": uri, "version": doc.version },
+                "contentChanges": content_changes,
+            });
+            if self.sync != SyncKind::None {
+                self.notify("textDocument/didChange", params);
+            }
+        }
+
+        if let (true, Some(include_text)) = (saved, self.save) {
+            let mut params = json!({ "textDocument": { "uri": uri } });
+            if include_text {
+                params["text"] = b.txt.to_string().into();
+            }
+            self.notify("textDocument/didSave", params);
+        }
+    }
+
+    fn open(&mut self, b: &Buffer, path: &Path) {
+        let uri = path_to_uri(path);
+        let text = b.txt.to_string();
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": self.lang,
+                    "version": 0,
+                    "text": text,
+                }
+            }),
+        );
+
+        self.docs.insert(
+            b.id,
+            Doc {
+                path: path.to_path_buf(),
+                uri,
+                version: 0,
+                txt: GapBuffer::from(text),
+            },
+        );
+    }
+
+    /// Close any documents for buffers that are no longer open at the path we opened them with.
+    pub(super) fn close_stale(&mut self, is_current: impl Fn(usize, &Path) -> bool) {
+        let stale: Vec<usize> = self
+            .docs
+            .iter()
+            .filter(|(&id, doc)| !is_current(id, &doc.path))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in stale.into_iter() {
+            if let Some(doc) = self.docs.remove(&id) {
+                self.notify(
+                    "textDocument/didClose",
+                    json!({ "textDocument": { "uri": doc.uri } }),
+                );
+            }
+        }
+    }
+
+    /// Send a request for the position of the cursor in the given buffer, merging `extra` into
+    /// the request parameters.
+    pub(super) fn request_at_cursor(
+        &mut self,
+        b: &Buffer,
+        method: &str,
+        pending: Pending,
+        extra: Value,
+    ) -> Result<(), String> {
+        let doc = self
+            .docs
+            .get(&b.id)
+            .ok_or_else(|| "buffer is not open in the language server".to_string())?;
+        let pos = Position::from_char_idx(&doc.txt, b.dot.active_cur().idx, self.enc);
+        let mut params = json!({
+            "textDocument": { "uri": doc.uri },
+            "position": pos.to_value(),
+        });
+        if let (Value::Object(params), Value::Object(extra)) = (&mut params, extra) {
+            params.extend(extra);
+        }
+
+        self.request(method, params, pending);
+
+        Ok(())
+    }
+
+    pub(super) fn handle_message(&mut self, msg: Message) -> Option<LspResponse> {
+        match msg {
+            Message::Response { id, result } => {
+                let pending = self.pending.remove(&id)?;
+                match result {
+                    Ok(v) => self.handle_response(pending, v),
+                    Err(e) => Some(LspResponse::Message(format!("{}: {e}", self.lang))),
+                }
+            }
+
+            Message::Request { id, method, params } => {
+                self.handle_request(id, &method, &params);
+                None
+            }
+
+            Message::Notification { method, params } => self.handle_notification(&method, &params),
+        }
+    }
+
+    fn handle_response(&mut self, pending: Pending, v: Value) -> Option<LspResponse> {
+        match pending {
+            Pending::Initialize => {
+                self.capabilities = v.get("capabilities").cloned().unwrap_or_default();
+                self.enc = self.capabilities["positionEncoding"]
+                    .as_str()
+                    .and_then(PositionEncoding::try_from_str)
+                    .unwrap_or_default();
+
+                let sync = &self.capabilities["textDocumentSync"];
+                self.sync = match sync.as_u64().or_else(|| sync["change"].as_u64()) {
+                    Some(1) => SyncKind::Full,
+                    Some(2) => SyncKind::Incremental,
+                    _ => SyncKind::None,
+                };
+                self.save = match &sync["save"] {
+                    Value::Bool(true) => Some(false),
+                    Value::Object(opts) => {
+                        Some(opts.get("includeText") == Some(&Value::Bool(true)))
+                    }
+                    _ => None,
+                };
+
+                self.initialized = true;
+                self.notify("initialized", json!({}));
+
+                Some(LspResponse::Message(format!(
+                    "{} language server ready",
+                    self.lang
+                )))
+            }
+
+            Pending::Shutdown => None,
+            Pending::Definition => Some(LspResponse::Definition(Location::parse_all(&v))),
+            Pending::Hover => Some(LspResponse::Hover(parse_hover(&v))),
+            Pending::References => Some(LspResponse::References(Location::parse_all(&v))),
+            Pending::Rename => Some(LspResponse::Rename(parse_workspace_edit(&v))),
+        }
+    }
+
+    fn handle_request(&mut self, id: Value, method: &str, params: &Value) {
+        let resp = match method {
+            "workspace/configuration" => {
+                let n = params["items"].as_array().map_or(0, Vec::len);
+                response(id, Value::Array(vec![Value::Null; n]))
+            }
+
+            "client/registerCapability"
+            | "client/unregisterCapability"
+            | "window/workDoneProgress/create" => response(id, Value::Null),
+
+            _ => error_response(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unsupported method: {method}"),
+            ),
+        };
+
+        self.send(&resp);
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Option<LspResponse> {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let path = uri_to_path(params["uri"].as_str()?)?;
+                let diagnostics = params["diagnostics"]
+                    .as_array()?
+                    .iter()
+                    .flat_map(LspDiagnostic::from_value)
+                    .collect();
+
+                Some(LspResponse::Diagnostics { path, diagnostics })
+            }
+
+            "window/showMessage" => {
+                let msg = params["message"].as_str()?;
+                Some(LspResponse::Message(format!("{}: {msg}", self.lang)))
+            }
+
+            "window/logMessage" => {
+                debug!(lang=%self.lang, "language server log: {}", params["message"]);
+                None
+            }
+
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! A built in client for the language server protocol.
+//!
+//! Servers are configured per language in the config file with a line of the form
+//! `lsp lang ext... => cmd` and are started the first time that a file with one of the given
+//! extensions is opened. Communication is JSON-RPC over the stdio of the server process with
+//! messages from the server being passed to the main event loop as [Event]s by a background
+//! thread for each server.
+//!
+//! Open buffers are kept in sync with the server by converting the changes recorded for them
+//! while handling each event into incremental `textDocument/didChange` notifications.
+//!
+//! These changes are taken from the same per-event stream that is published to the fsys events
+//! files rather than from the `EditLog` of the buffer. The edit log is an undo history rather
+//! than a record of what has changed: it is paused while undo and redo are applied, merges
+//! adjacent edits into a single entry and is reset when a buffer is reloaded or cleared, so it
+//! can not tell us what has changed since the server was last updated.
+//!
+//! The server expects each change as a line and character range within the document as it was
+//! before the change was applied but the changes we record only hold character offsets, and
+//! deletions do not hold the text that was removed. Each client therefore keeps a copy of the
+//! text of each document it has open as the server last saw it, which is used to convert offsets
+//! into positions and to detect when the two have got out of step. This copy is only held for
+//! buffers that have a running server.
+use crate::{
+    buffer::{Buffer, BufferKind, Buffers},
+    config::{lsp_server_for_path, LspServerConfig},
+    fsys::Observed,
+    input::Event,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
+
+mod client;
+mod rpc;
+pub(crate) mod types;
+
+pub(crate) use types::{Location, LspDiagnostic, PositionEncoding, TextEdit};
+
+use client::{LspClient, Pending};
+use rpc::Message;
+
+/// A message received from a running language server, or notice that the server has exited
+/// if there is no message.
+#[derive(Debug)]
+pub struct LspEvent {
+    pub(crate) server: usize,
+    pub(crate) msg: Option<Message>,
+}
+
+/// The results of requests to language servers, along with the notifications from them that
+/// the editor needs to act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LspResponse {
+    Definition(Vec<Location>),
+    Hover(String),
+    References(Vec<Location>),
+    Rename(Vec<(PathBuf, Vec<TextEdit>)>),
+    Diagnostics {
+        path: PathBuf,
+        diagnostics: Vec<LspDiagnostic>,
+    },
+    Message(String),
+}
+
+/// The set of running language servers.
+#[derive(Debug)]
+pub(crate) struct LspManager {
+    clients: Vec<LspClient>,
+    /// Languages whose server failed to start or exited unexpectedly. These are not restarted
+    /// automatically so that a broken server is not respawned on every event.
+    failed: HashSet<String>,
+    next_id: usize,
+    tx: Sender<Event>,
+}
+
+impl LspManager {
+    pub(crate) fn new(tx: Sender<Event>) -> Self {
+        Self {
+            clients: Vec::new(),
+            failed: HashSet::new(),
+            next_id: 0,
+            tx,
+        }
+    }
+
+    /// Start (or restart) the server for the given language.
+    pub(crate) fn start(&mut self, cfg: &LspServerConfig, root: &Path) -> Result<(), String> {
+        self.stop(&cfg.lang);
+        self.failed.remove(&cfg.lang);
+        let id = self.next_id;
+        self.next_id += 1;
+
+        match LspClient::spawn(id, cfg, root, self.tx.clone()) {
+            Ok(client) => {
+                self.add_client(client, root);
+                Ok(())
+            }
+            Err(e) => {
+                self.failed.insert(cfg.lang.clone());
+                Err(format!("unable to start {} language server: {e}", cfg.lang))
+            }
+        }
+    }
+
+    fn add_client(&mut self, mut client: LspClient, root: &Path) {
+        client.initialize(root);
+        self.clients.push(client);
+    }
+
+    /// Shut down the server for the given language, returning false if it was not running.
+    pub(crate) fn stop(&mut self, lang: &str) -> bool {
+        let n = self.clients.len();
+        self.clients.retain(|c| c.lang != lang);
+
+        self.clients.len() != n
+    }
+
+    /// The language of the server responsible for the given buffer, if there is one.
+    pub(crate) fn lang_for_buffer(&self, id: usize) -> Option<&str> {
+        self.clients
+            .iter()
+            .find(|c| c.has_doc(id))
+            .map(|c| c.lang.as_str())
+    }
+
+    /// Pass on the changes made to a buffer while handling the last event to the server for
+    /// its language, starting the server if it is not already running. Returns an error message
+    /// if the server could not be started.
+    pub(crate) fn sync_buffer(
+        &mut self,
+        b: &Buffer,
+        changes: &[Observed],
+        servers: &[LspServerConfig],
+        root: &Path,
+    ) -> Option<String> {
+        let path = match &b.kind {
+            BufferKind::File(path) => path,
+            _ => return None,
+        };
+        let cfg = lsp_server_for_path(servers, path)?;
+
+        if !self.clients.iter().any(|c| c.lang == cfg.lang) {
+            if self.failed.contains(&cfg.lang) {
+                return None;
+            }
+            if let Err(e) = self.start(cfg, root) {
+                return Some(e);
+            }
+        }
+
+        let client = self.clients.iter_mut().find(|c| c.lang == cfg.lang)?;
+        client.sync(b, path, changes);
+
+        None
+    }
+
+    /// Close documents for buffers that have been closed or renamed since they were opened.
+    pub(crate) fn close_stale(&mut self, buffers: &Buffers) {
+        for client in self.clients.iter_mut() {
+            client.close_stale(|id, path| {
+                matches!(buffers.with_id(id), Some(b) if b.kind == BufferKind::File(path.to_path_buf()))
+            });
+        }
+    }
+
+    fn client_for(
+        &mut self,
+        b: &Buffer,
+        provider: &str,
+        feature: &str,
+    ) -> Result<&mut LspClient, String> {
+        let client = self
+            .clients
+            .iter_mut()
+            .find(|c| c.has_doc(b.id))
+            .ok_or_else(|| "no language server is running for this buffer".to_string())?;
+
+        if !client.supports(provider) {
+            return Err(format!(
+                "the {} language server does not support {feature}",
+                client.lang
+            ));
+        }
+
+        Ok(client)
+    }
+
+    pub(crate) fn goto_definition(&mut self, b: &Buffer) -> Result<(), String> {
+        self.client_for(b, "definitionProvider", "go to definition")?
+            .request_at_cursor(
+                b,
+                "textDocument/definition",
+                Pending::Definition,
+                Value::Null,
+            )
+    }
+
+    pub(crate) fn hover(&mut self, b: &Buffer) -> Result<(), String> {
+        self.client_for(b, "hoverProvider", "hover")?
+            .request_at_cursor(b, "textDocument/hover", Pending::Hover, Value::Null)
+    }
+
+    pub(crate) fn references(&mut self, b: &Buffer) -> Result<(), String> {
+        self.client_for(b, "referencesProvider", "references")?
+            .request_at_cursor(
+                b,
+                "textDocument/references",
+                Pending::References,
+                json!({ "context": { "includeDeclaration": true } }),
+            )
+    }
+
+    pub(crate) fn rename(&mut self, b: &Buffer, new_name: &str) -> Result<(), String> {
+        self.client_for(b, "renameProvider", "rename")?
+            .request_at_cursor(
+                b,
+                "textDocument/rename",
+                Pending::Rename,
+                json!({ "newName": new_name }),
+            )
+    }
+
+    /// Process a message from one of our servers, returning anything that the editor needs to
+    /// act on along with the position encoding required to interpret it.
+    pub(crate) fn handle_event(
+        &mut self,
+        LspEvent { server, msg }: LspEvent,
+    ) -> Option<(PositionEncoding, LspResponse)> {
+        // Events from servers that have since been stopped are ignored
+        let i = self.clients.iter().position(|c| c.id == server)?;
+
+        match msg {
+            Some(msg) => {
+                let client = &mut self.clients[i];
+                let resp = client.handle_message(msg)?;
+                Some((client.enc, resp))
+            }
+
+            None => {
+                let client = self.clients.remove(i);
+                self.failed.insert(client.lang.clone());
+                let msg = format!("{} language server exited", client.lang);
+                Some((client.enc, LspResponse::Message(msg)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rpc::{notification, read_message, response, write_message},
+        types::{Position, Range},
+        *,
+    };
+    use crate::exec::Edit;
+    use std::{
+        io::BufReader,
+        os::unix::net::UnixStream,
+        sync::mpsc::{channel, Receiver},
+        thread::spawn,
+        time::Duration,
+    };
+
+    const ROOT: &str = "/tmp/ad-lsp";
+    const PATH: &str = "/tmp/ad-lsp/main.rs";
+    const URI: &str = "file:///tmp/ad-lsp/main.rs";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn range(l1: usize, c1: usize, l2: usize, c2: usize) -> Range {
+        Range {
+            start: Position {
+                line: l1,
+                character: c1,
+            },
+            end: Position {
+                line: l2,
+                character: c2,
+            },
+        }
+    }
+
+    /// The scripted responses of our fake language server
+    fn reply(msg: &Value) -> Vec<Value> {
+        let id = msg["id"].clone();
+
+        match msg["method"].as_str().unwrap_or_default() {
+            "initialize" => vec![response(
+                id,
+                json!({
+                    "capabilities": {
+                        "positionEncoding": "utf-32",
+                        "textDocumentSync": { "openClose": true, "change": 2, "save": true },
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "renameProvider": { "prepareProvider": false },
+                    }
+                }),
+            )],
+
+            "initialized" => vec![json!({
+                "jsonrpc": "2.0",
+                "id": "cfg",
+                "method": "workspace/configuration",
+                "params": { "items": [{ "section": "a" }, { "section": "b" }] },
+            })],
+
+            "textDocument/didOpen" => vec![notification(
+                "textDocument/publishDiagnostics",
+                json!({
+                    "uri": URI,
+                    "diagnostics": [{
+                        "range": range(0, 3, 0, 7).to_value(),
+                        "severity": 2,
+                        "message": "function is never used\n`#[warn(dead_code)]` on by default",
+                    }],
+                }),
+            )],
+
+            "textDocument/definition" => vec![response(
+                id,
+                json!({ "uri": URI, "range": range(1, 4, 1, 8).to_value() }),
+            )],
+
+            "textDocument/rename" => vec![response(
+                id,
+                json!({
+                    "changes": {
+                        URI: [
+                            { "range": range(0, 3, 0, 7).to_value(), "newText": "start" },
+                            { "range": range(1, 4, 1, 8).to_value(), "newText": "start" },
+                        ]
+                    }
+                }),
+            )],
+
+            _ => vec![],
+        }
+    }
+
+    /// A manager connected to an in process fake server running [reply] along with the events
+    /// sent to the editor and the messages received by the server.
+    fn fake_lsp() -> (LspManager, Receiver<Event>, Receiver<Value>) {
+        let (tx, rx_events) = channel();
+        let (tx_seen, rx_seen) = channel();
+        let (client_end, server_end) = UnixStream::pair().unwrap();
+
+        spawn(move || {
+            let mut r = BufReader::new(server_end.try_clone().unwrap());
+            let mut w = server_end;
+            while let Ok(Some(msg)) = read_message(&mut r) {
+                for resp in reply(&msg).iter() {
+                    write_message(&mut w, resp).unwrap();
+                }
+                if tx_seen.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lsp = LspManager::new(tx.clone());
+        let w = Box::new(client_end.try_clone().unwrap());
+        let client = LspClient::new(0, "rust", None, w, client_end, tx);
+        lsp.add_client(client, Path::new(ROOT));
+
+        (lsp, rx_events, rx_seen)
+    }
+
+    fn servers() -> Vec<LspServerConfig> {
+        vec![LspServerConfig {
+            lang: "rust".to_string(),
+            extensions: vec!["rs".to_string()],
+            command: "not-used".to_string(),
+        }]
+    }
+
+    fn buffer(content: &str) -> Buffer {
+        let mut b = Buffer::new_unnamed(1, content);
+        b.kind = BufferKind::File(PathBuf::from(PATH));
+        b
+    }
+
+    fn next_response(
+        lsp: &mut LspManager,
+        rx: &Receiver<Event>,
+    ) -> (PositionEncoding, LspResponse) {
+        loop {
+            match rx.recv_timeout(TIMEOUT).expect("event from server") {
+                Event::Lsp(evt) => {
+                    if let Some(resp) = lsp.handle_event(evt) {
+                        return resp;
+                    }
+                }
+                evt => panic!("unexpected event: {evt:?}"),
+            }
+        }
+    }
+
+    fn next_seen(rx: &Receiver<Value>, method: &str) -> Value {
+        loop {
+            let msg = rx.recv_timeout(TIMEOUT).expect("message for server");
+            if msg["method"] == method {
+                return msg;
+            }
+        }
+    }
+
+    /// Start the fake server and open a buffer with it, returning the buffer
+    fn initialized_lsp(content: &str) -> (LspManager, Receiver<Event>, Receiver<Value>, Buffer) {
+        let (mut lsp, rx_events, rx_seen) = fake_lsp();
+        let (enc, resp) = next_response(&mut lsp, &rx_events);
+        assert_eq!(enc, PositionEncoding::Utf32);
+        assert_eq!(
+            resp,
+            LspResponse::Message("rust language server ready".to_string())
+        );
+
+        let b = buffer(content);
+        assert_eq!(lsp.sync_buffer(&b, &[], &servers(), Path::new(ROOT)), None);
+        assert_eq!(lsp.lang_for_buffer(b.id), Some("rust"));
+
+        (lsp, rx_events, rx_seen, b)
+    }
+
+    #[test]
+    fn changes_are_synced_incrementally() {
+        let (mut lsp, _rx_events, rx_seen, mut b) = initialized_lsp("fn main() {}\n");
+
+        let open = next_seen(&rx_seen, "textDocument/didOpen");
+        assert_eq!(
+            open["params"]["textDocument"],
+            json!({ "uri": URI, "languageId": "rust", "version": 0, "text": "fn main() {}\n" })
+        );
+
+        b.insert(11, "🦀 x ");
+        b.remove(0, 3);
+        let mut changes = b.pending_changes.take(b.dot);
+        changes.push(Observed::Save);
+        lsp.sync_buffer(&b, &changes, &servers(), Path::new(ROOT));
+
+        let change = next_seen(&rx_seen, "textDocument/didChange");
+        assert_eq!(
+            change["params"],
+            json!({
+                "textDocument": { "uri": URI, "version": 1 },
+                "contentChanges": [
+                    { "range": range(0, 11, 0, 11).to_value(), "text": "🦀 x " },
+                    { "range": range(0, 0, 0, 3).to_value(), "text": "" },
+                ],
+            })
+        );
+
+        let save = next_seen(&rx_seen, "textDocument/didSave");
+        assert_eq!(save["params"], json!({ "textDocument": { "uri": URI } }));
+    }
+
+    #[test]
+    fn servers_that_fail_to_start_are_only_reported_once() {
+        let (tx, _rx) = channel();
+        let mut lsp = LspManager::new(tx);
+        let mut servers = servers();
+        servers[0].command = "ad-test-no-such-language-server".to_string();
+        let b = buffer("fn main() {}\n");
+
+        let res = lsp.sync_buffer(&b, &[], &servers, Path::new("."));
+        assert!(res.is_some_and(|e| e.starts_with("unable to start rust language server")));
+        assert_eq!(lsp.sync_buffer(&b, &[], &servers, Path::new(".")), None);
+    }
+
+    #[test]
+    fn closed_buffers_are_closed_in_the_server() {
+        let (mut lsp, _rx_events, rx_seen, _b) = initialized_lsp("fn main() {}\n");
+        lsp.close_stale(&Buffers::new());
+
+        let close = next_seen(&rx_seen, "textDocument/didClose");
+        assert_eq!(close["params"], json!({ "textDocument": { "uri": URI } }));
+        assert_eq!(lsp.lang_for_buffer(1), None);
+    }
+
+    #[test]
+    fn server_requests_are_answered() {
+        let (mut lsp, rx_events, rx_seen, _b) = initialized_lsp("fn main() {}\n");
+
+        // The request is answered while handling events from the server, which here ends with
+        // the diagnostics published after the buffer is opened
+        let (_, resp) = next_response(&mut lsp, &rx_events);
+        assert!(matches!(resp, LspResponse::Diagnostics { .. }));
+
+        let resp = loop {
+            let msg = rx_seen.recv_timeout(TIMEOUT).expect("message for server");
+            if msg["id"] == "cfg" {
+                break msg;
+            }
+        };
+
+        assert_eq!(resp["result"], json!([null, null]));
+    }
+
+    #[test]
+    fn responses_from_the_server_are_parsed() {
+        let (mut lsp, rx_events, rx_seen, mut b) = initialized_lsp("fn main() {\n    main();\n}\n");
+        let path = PathBuf::from(PATH);
+
+        let (_, diagnostics) = next_response(&mut lsp, &rx_events);
+        assert_eq!(
+            diagnostics,
+            LspResponse::Diagnostics {
+                path: path.clone(),
+                diagnostics: vec![LspDiagnostic {
+                    range: range(0, 3, 0, 7),
+                    severity: crate::buffer::Severity::Warning,
+                    message: "function is never used".to_string(),
+                }]
+            }
+        );
+
+        b.dot = crate::dot::Dot::from_char_indices(17, 17);
+        lsp.goto_definition(&b).unwrap();
+        let def = next_seen(&rx_seen, "textDocument/definition");
+        assert_eq!(
+            def["params"]["position"],
+            json!({ "line": 1, "character": 5 })
+        );
+        assert_eq!(
+            next_response(&mut lsp, &rx_events).1,
+            LspResponse::Definition(vec![Location {
+                path: path.clone(),
+                range: range(1, 4, 1, 8)
+            }])
+        );
+
+        lsp.rename(&b, "start").unwrap();
+        let rename = next_seen(&rx_seen, "textDocument/rename");
+        assert_eq!(rename["params"]["newName"], "start");
+        let edit = |range| TextEdit {
+            range,
+            new_text: "start".to_string(),
+        };
+        assert_eq!(
+            next_response(&mut lsp, &rx_events).1,
+            LspResponse::Rename(vec![(
+                path,
+                vec![edit(range(0, 3, 0, 7)), edit(range(1, 4, 1, 8))]
+            )])
+        );
+
+        assert_eq!(
+            lsp.references(&b),
+            Err("the rust language server does not support references".to_string())
+        );
+    }
+}
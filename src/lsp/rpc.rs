@@ -0,0 +1,182 @@
+//! JSON-RPC messages as they are framed over the stdio of a language server.
+//!
+//! Each message is a JSON object preceded by a `Content-Length` header giving the length of the
+//! body in bytes, followed by a blank line.
+use serde_json::{json, Value};
+use std::io::{self, BufRead, ErrorKind, Write};
+
+/// A message received from a language server.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    /// A request from the server that requires a response
+    Request {
+        id: Value,
+        method: String,
+        params: Value,
+    },
+    /// The response to one of our requests, holding the error message if it failed
+    Response {
+        id: u64,
+        result: Result<Value, String>,
+    },
+    /// A notification from the server that does not expect a response
+    Notification { method: String, params: Value },
+}
+
+impl Message {
+    pub(crate) fn try_from_value(mut v: Value) -> Result<Self, String> {
+        let method = v.get("method").and_then(Value::as_str).map(String::from);
+        let id = v.get_mut("id").map(Value::take);
+        let params = v.get_mut("params").map(Value::take).unwrap_or_default();
+
+        match (id, method) {
+            (Some(id), Some(method)) => Ok(Self::Request { id, method, params }),
+            (None, Some(method)) => Ok(Self::Notification { method, params }),
+            (Some(id), None) => {
+                let id = id
+                    .as_u64()
+                    .ok_or_else(|| format!("unexpected response id: {id}"))?;
+                let result = match v.get("error") {
+                    Some(err) => Err(err
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown error")
+                        .to_string()),
+                    None => Ok(v.get_mut("result").map(Value::take).unwrap_or_default()),
+                };
+
+                Ok(Self::Response { id, result })
+            }
+            (None, None) => Err(format!("invalid message: {v}")),
+        }
+    }
+}
+
+pub(crate) fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+pub(crate) fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+pub(crate) fn response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub(crate) fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+pub(crate) fn write_message<W: Write>(w: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    w.flush()
+}
+
+/// Read the next message from `r`, returning `None` once the stream has been closed.
+pub(crate) fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    let invalid = |e: String| io::Error::new(ErrorKind::InvalidData, e);
+    let mut len = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = Some(
+                    val.trim()
+                        .parse::<usize>()
+                        .map_err(|e| invalid(e.to_string()))?,
+                );
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| invalid("missing Content-Length header".to_string()))?;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+
+    serde_json::from_slice(&buf)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+    use std::io::Cursor;
+
+    #[test]
+    fn messages_round_trip() {
+        let msgs = [
+            request(1, "initialize", json!({ "rootUri": null })),
+            notification("initialized", json!({})),
+            response(json!(7), json!([1, "two"])),
+        ];
+
+        let mut buf = Vec::new();
+        for msg in msgs.iter() {
+            write_message(&mut buf, msg).unwrap();
+        }
+
+        let mut r = Cursor::new(buf);
+        for msg in msgs.iter() {
+            assert_eq!(read_message(&mut r).unwrap().as_ref(), Some(msg));
+        }
+        assert_eq!(read_message(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn extra_headers_are_ignored() {
+        let body = r#"{"jsonrpc":"2.0","method":"exit","params":{}}"#;
+        let raw = format!(
+            "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        let msg = read_message(&mut Cursor::new(raw)).unwrap();
+
+        assert_eq!(msg, Some(notification("exit", json!({}))));
+    }
+
+    #[test]
+    fn missing_content_length_is_an_error() {
+        let res = read_message(&mut Cursor::new("Content-Type: foo\r\n\r\n{}"));
+        assert!(res.is_err());
+    }
+
+    #[test_case(
+        request(3, "workspace/configuration", json!({ "items": [] })),
+        Message::Request { id: json!(3), method: "workspace/configuration".into(), params: json!({ "items": [] }) };
+        "from server request"
+    )]
+    #[test_case(
+        notification("window/showMessage", json!({ "message": "hi" })),
+        Message::Notification { method: "window/showMessage".into(), params: json!({ "message": "hi" }) };
+        "from server notification"
+    )]
+    #[test_case(
+        response(json!(4), json!({ "contents": "docs" })),
+        Message::Response { id: 4, result: Ok(json!({ "contents": "docs" })) };
+        "successful response"
+    )]
+    #[test_case(
+        error_response(json!(5), -32601, "method not found"),
+        Message::Response { id: 5, result: Err("method not found".into()) };
+        "failed response"
+    )]
+    #[test]
+    fn message_from_value_works(v: Value, expected: Message) {
+        assert_eq!(Message::try_from_value(v), Ok(expected));
+    }
+}
@@ -0,0 +1,430 @@
+//! Conversions between buffer state and the types used by the language server protocol.
+//!
+//! LSP positions are a zero-based line and a column measured in the code units of the position
+//! encoding negotiated with the server (UTF-16 unless the server opts in to something else), so
+//! converting to and from character offsets always needs the text the position refers to.
+use crate::buffer::{GapBuffer, Severity};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// The units used to count columns within a line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    pub(crate) fn try_from_str(s: &str) -> Option<Self> {
+        match s {
+            "utf-8" => Some(Self::Utf8),
+            "utf-16" => Some(Self::Utf16),
+            "utf-32" => Some(Self::Utf32),
+            _ => None,
+        }
+    }
+
+    fn units(&self, ch: char) -> usize {
+        match self {
+            Self::Utf8 => ch.len_utf8(),
+            Self::Utf16 => ch.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Position {
+    pub(crate) line: usize,
+    pub(crate) character: usize,
+}
+
+impl Position {
+    pub(crate) fn from_char_idx(txt: &GapBuffer, idx: usize, enc: PositionEncoding) -> Self {
+        let idx = idx.min(txt.len_chars());
+        let line = txt.char_to_line(idx);
+        let character = txt
+            .slice(txt.line_to_char(line), idx)
+            .chars()
+            .map(|ch| enc.units(ch))
+            .sum();
+
+        Self { line, character }
+    }
+
+    /// The character offset of this position within `txt`. Positions beyond the end of a line
+    /// are clamped to the end of that line and those beyond the end of the text to the end of
+    /// the text.
+    pub(crate) fn to_char_idx(self, txt: &GapBuffer, enc: PositionEncoding) -> usize {
+        if self.line >= txt.len_lines() {
+            return txt.len_chars();
+        }
+
+        let mut idx = txt.line_to_char(self.line);
+        let mut units = 0;
+        for ch in txt.line(self.line).chars() {
+            if units >= self.character || ch == '\n' {
+                break;
+            }
+            units += enc.units(ch);
+            idx += 1;
+        }
+
+        idx
+    }
+
+    fn from_value(v: &Value) -> Option<Self> {
+        Some(Self {
+            line: v.get("line")?.as_u64()? as usize,
+            character: v.get("character")?.as_u64()? as usize,
+        })
+    }
+
+    pub(crate) fn to_value(self) -> Value {
+        json!({ "line": self.line, "character": self.character })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Range {
+    pub(crate) start: Position,
+    pub(crate) end: Position,
+}
+
+impl Range {
+    pub(crate) fn from_char_indices(
+        txt: &GapBuffer,
+        from: usize,
+        to: usize,
+        enc: PositionEncoding,
+    ) -> Self {
+        Self {
+            start: Position::from_char_idx(txt, from, enc),
+            end: Position::from_char_idx(txt, to, enc),
+        }
+    }
+
+    pub(crate) fn to_char_indices(self, txt: &GapBuffer, enc: PositionEncoding) -> (usize, usize) {
+        let from = self.start.to_char_idx(txt, enc);
+        let to = self.end.to_char_idx(txt, enc);
+
+        (from, to.max(from))
+    }
+
+    pub(crate) fn from_value(v: &Value) -> Option<Self> {
+        Some(Self {
+            start: Position::from_value(v.get("start")?)?,
+            end: Position::from_value(v.get("end")?)?,
+        })
+    }
+
+    pub(crate) fn to_value(self) -> Value {
+        json!({ "start": self.start.to_value(), "end": self.end.to_value() })
+    }
+}
+
+/// A range within a file on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) path: PathBuf,
+    pub(crate) range: Range,
+}
+
+impl Location {
+    /// Parse either a `Location` or a `LocationLink`.
+    pub(crate) fn from_value(v: &Value) -> Option<Self> {
+        let uri = v.get("uri").or_else(|| v.get("targetUri"))?.as_str()?;
+        let range = v
+            .get("range")
+            .or_else(|| v.get("targetSelectionRange"))
+            .or_else(|| v.get("targetRange"))?;
+
+        Some(Self {
+            path: uri_to_path(uri)?,
+            range: Range::from_value(range)?,
+        })
+    }
+
+    /// Parse the result of a definition or references request which may be `null`, a single
+    /// location or an array of locations.
+    pub(crate) fn parse_all(v: &Value) -> Vec<Self> {
+        match v {
+            Value::Array(locs) => locs.iter().flat_map(Self::from_value).collect(),
+            Value::Null => Vec::new(),
+            v => Self::from_value(v).into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TextEdit {
+    pub(crate) range: Range,
+    pub(crate) new_text: String,
+}
+
+impl TextEdit {
+    fn from_value(v: &Value) -> Option<Self> {
+        Some(Self {
+            range: Range::from_value(v.get("range")?)?,
+            new_text: v.get("newText")?.as_str()?.to_string(),
+        })
+    }
+}
+
+/// A diagnostic published by the server for a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LspDiagnostic {
+    pub(crate) range: Range,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+impl LspDiagnostic {
+    /// Parse a diagnostic, keeping only the first line of its message. Hints are shown as
+    /// information and diagnostics without a severity are treated as errors.
+    pub(crate) fn from_value(v: &Value) -> Option<Self> {
+        let severity = match v.get("severity").and_then(Value::as_u64) {
+            None | Some(1) => Severity::Error,
+            Some(2) => Severity::Warning,
+            Some(_) => Severity::Info,
+        };
+        let message = v
+            .get("message")?
+            .as_str()?
+            .lines()
+            .next()
+            .unwrap_or_default();
+
+        Some(Self {
+            range: Range::from_value(v.get("range")?)?,
+            severity,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// Parse the edits to each file from a `WorkspaceEdit`, supporting both the `changes` and
+/// `documentChanges` forms. Resource operations (creating, renaming and deleting files) are
+/// not supported and are skipped.
+pub(crate) fn parse_workspace_edit(v: &Value) -> Vec<(PathBuf, Vec<TextEdit>)> {
+    let parse_edits = |edits: &Value| -> Vec<TextEdit> {
+        edits
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(TextEdit::from_value)
+            .collect()
+    };
+    let mut files: Vec<(PathBuf, Vec<TextEdit>)> = Vec::new();
+    let mut push = |uri: &str, edits: Vec<TextEdit>| {
+        let path = match uri_to_path(uri) {
+            Some(path) => path,
+            None => return,
+        };
+        match files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, existing)) => existing.extend(edits),
+            None => files.push((path, edits)),
+        }
+    };
+
+    if let Some(changes) = v.get("documentChanges").and_then(Value::as_array) {
+        for change in changes.iter().filter(|c| c.get("kind").is_none()) {
+            if let Some(uri) = change.pointer("/textDocument/uri").and_then(Value::as_str) {
+                push(uri, parse_edits(&change["edits"]));
+            }
+        }
+    } else if let Some(changes) = v.get("changes").and_then(Value::as_object) {
+        for (uri, edits) in changes.iter() {
+            push(uri, parse_edits(edits));
+        }
+    }
+
+    files
+}
+
+/// Extract the text to display from the result of a hover request.
+pub(crate) fn parse_hover(v: &Value) -> String {
+    fn marked_string(v: &Value) -> Option<&str> {
+        match v {
+            Value::String(s) => Some(s),
+            v => v.get("value")?.as_str(),
+        }
+    }
+
+    let contents = match v.get("contents") {
+        Some(Value::Array(items)) => items
+            .iter()
+            .flat_map(marked_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(v) => marked_string(v).unwrap_or_default().to_string(),
+        None => String::new(),
+    };
+
+    // Markdown code fences aren't useful when displayed as plain text
+    let lines: Vec<&str> = contents
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("```"))
+        .collect();
+
+    lines.join("\n").trim().to_string()
+}
+
+const URI_SAFE: &[u8] = b"-._~/";
+
+pub(crate) fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || URI_SAFE.contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{b:02X}"));
+        }
+    }
+
+    uri
+}
+
+pub(crate) fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let raw = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+
+    while i < raw.len() {
+        match raw[i] {
+            b'%' if i + 2 < raw.len() => {
+                let hex = std::str::from_utf8(&raw[i + 1..i + 3]).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b => {
+                bytes.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    const TXT: &str = "fn main() {\n    let s = \"🦀 crab\";\n}\n";
+
+    #[test_case(0, PositionEncoding::Utf16, (0, 0); "start of text")]
+    #[test_case(15, PositionEncoding::Utf16, (1, 3); "within line")]
+    #[test_case(26, PositionEncoding::Utf8, (1, 17); "after emoji utf8")]
+    #[test_case(26, PositionEncoding::Utf16, (1, 15); "after emoji utf16")]
+    #[test_case(26, PositionEncoding::Utf32, (1, 14); "after emoji utf32")]
+    #[test_case(TXT.chars().count(), PositionEncoding::Utf16, (3, 0); "end of text")]
+    #[test]
+    fn position_conversion_round_trips(
+        idx: usize,
+        enc: PositionEncoding,
+        (line, character): (usize, usize),
+    ) {
+        let txt = GapBuffer::from(TXT);
+        let pos = Position::from_char_idx(&txt, idx, enc);
+
+        assert_eq!(pos, Position { line, character });
+        assert_eq!(pos.to_char_idx(&txt, enc), idx);
+    }
+
+    #[test_case(Position { line: 0, character: 100 }, 11; "past end of line")]
+    #[test_case(Position { line: 10, character: 0 }, TXT.chars().count(); "past end of text")]
+    #[test]
+    fn out_of_range_positions_are_clamped(pos: Position, expected: usize) {
+        let txt = GapBuffer::from(TXT);
+        assert_eq!(pos.to_char_idx(&txt, PositionEncoding::Utf16), expected);
+    }
+
+    #[test_case("/home/user/src/main.rs", "file:///home/user/src/main.rs"; "simple")]
+    #[test_case("/tmp/my project/a#b.rs", "file:///tmp/my%20project/a%23b.rs"; "escaped")]
+    #[test]
+    fn uri_conversion_round_trips(path: &str, uri: &str) {
+        assert_eq!(path_to_uri(Path::new(path)), uri);
+        assert_eq!(uri_to_path(uri), Some(PathBuf::from(path)));
+    }
+
+    #[test_case(json!({ "contents": "plain" }), "plain"; "marked string")]
+    #[test_case(
+        json!({ "contents": { "kind": "markdown", "value": "```rust\nfn main()\n```\nentry point" } }),
+        "fn main()\nentry point";
+        "markup content"
+    )]
+    #[test_case(
+        json!({ "contents": [{ "language": "rust", "value": "struct Foo" }, "docs"] }),
+        "struct Foo\ndocs";
+        "array"
+    )]
+    #[test_case(Value::Null, ""; "null")]
+    #[test]
+    fn parse_hover_works(v: Value, expected: &str) {
+        assert_eq!(parse_hover(&v), expected);
+    }
+
+    fn range(l1: usize, c1: usize, l2: usize, c2: usize) -> Range {
+        Range {
+            start: Position {
+                line: l1,
+                character: c1,
+            },
+            end: Position {
+                line: l2,
+                character: c2,
+            },
+        }
+    }
+
+    #[test]
+    fn parse_workspace_edit_works() {
+        let edit =
+            |l, c1, c2, s: &str| json!({ "range": range(l, c1, l, c2).to_value(), "newText": s });
+        let v = json!({
+            "documentChanges": [
+                { "textDocument": { "uri": "file:///a.rs", "version": 1 }, "edits": [edit(0, 3, 6, "bar")] },
+                { "kind": "create", "uri": "file:///c.rs" },
+                { "textDocument": { "uri": "file:///b.rs", "version": null }, "edits": [edit(2, 0, 3, "bar")] },
+                { "textDocument": { "uri": "file:///a.rs", "version": 1 }, "edits": [edit(4, 1, 4, "bar")] },
+            ]
+        });
+
+        let text_edit = |l, c1, c2| TextEdit {
+            range: range(l, c1, l, c2),
+            new_text: "bar".to_string(),
+        };
+
+        assert_eq!(
+            parse_workspace_edit(&v),
+            vec![
+                (
+                    PathBuf::from("/a.rs"),
+                    vec![text_edit(0, 3, 6), text_edit(4, 1, 4)]
+                ),
+                (PathBuf::from("/b.rs"), vec![text_edit(2, 0, 3)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn location_links_are_parsed() {
+        let v = json!([{
+            "targetUri": "file:///src/lib.rs",
+            "targetRange": range(3, 0, 7, 1).to_value(),
+            "targetSelectionRange": range(3, 7, 3, 10).to_value(),
+        }]);
+
+        assert_eq!(
+            Location::parse_all(&v),
+            vec![Location {
+                path: PathBuf::from("/src/lib.rs"),
+                range: range(3, 7, 3, 10)
+            }]
+        );
+    }
+}
@@ -135,7 +135,10 @@ fn run_script(script: &str, files: Vec<String>) {
     let mut prog = match Program::try_parse(script) {
         Ok(prog) => prog,
         Err(e) => {
-            eprintln!("error parsing script: {e:?}");
+            eprintln!("error parsing script: {e}");
+            if let Some((line, caret)) = e.annotate(script) {
+                eprintln!("  {line}\n  {caret}");
+            }
             exit(1);
         }
     };
@@ -146,7 +149,10 @@ fn run_script(script: &str, files: Vec<String>) {
         match prog.execute(&mut CachedStdin::new(), "stdin", &mut io::stdout()) {
             Ok(_) => return,
             Err(e) => {
-                eprintln!("error running script: {e:?}");
+                eprintln!("error running script: {e}");
+                if let Some((line, caret)) = e.annotate(script) {
+                    eprintln!("  {line}\n  {caret}");
+                }
                 exit(1);
             }
         }
@@ -163,7 +169,10 @@ fn run_script(script: &str, files: Vec<String>) {
         };
 
         if let Err(e) = prog.execute(&mut GapBuffer::from(s), path, &mut buf) {
-            eprintln!("error running script: {e:?}");
+            eprintln!("error running script: {e}");
+            if let Some((line, caret)) = e.annotate(script) {
+                eprintln!("  {line}\n  {caret}");
+            }
             exit(1);
         }
     }
@@ -28,6 +28,13 @@ pub(crate) fn insert_mode() -> Mode {
         [ Ctrl('e') ] => [ DotSet(LineEnd, 1) ],
         [ Ctrl('w') ] => [ DotSet(Arr(Left), 1), DotExtendBackward(Word, 1), Delete ],
 
+        // complete the word or file path before the cursor
+        [ Ctrl('n') ] => [ Complete ],
+
+        // expand snippets and move between their tabstops
+        [ Tab ] => [ SnippetNext ],
+        [ BackTab ] => [ SnippetPrevious ],
+
     };
 
     // By default we just let the buffer try to handle this
@@ -1,5 +1,6 @@
 //! Modal editing support.
 use crate::{
+    config_handle,
     editor::Actions,
     key::Input,
     term::CurShape,
@@ -14,7 +15,9 @@ pub(crate) use normal::normal_mode;
 
 /// The modes available for ad
 pub(crate) fn modes() -> Vec<Mode> {
-    vec![normal::normal_mode(), insert::insert_mode()]
+    let repeat_key = config_handle!().repeat_key;
+
+    vec![normal::normal_mode(repeat_key), insert::insert_mode()]
 }
 
 #[derive(Debug)]
@@ -9,7 +9,9 @@ use crate::{
     term::CurShape,
 };
 
-pub(crate) fn normal_mode() -> Mode {
+/// Build NORMAL mode with the last edit being repeated by `repeat_key`. EDIT mode is always
+/// available on Alt-. and also on '.' if that is not being used as the repeat key.
+pub(crate) fn normal_mode(repeat_key: char) -> Mode {
     let leader = Char(' ');
 
     let mut keymap = keymap! {
@@ -19,10 +21,11 @@ pub(crate) fn normal_mode() -> Mode {
 
         // Modes
         [ leader, Char('b') ] => [ SelectBuffer ],
-        [ Char('/') ] => [ SearchInCurrentBuffer ],
+        [ Char('/') ] => [ SearchIncremental ],
+        [ leader, Char('/') ] => [ SearchInCurrentBuffer ],
         [ Char(':') ] => [ CommandMode ],
         [ Char('!') ] => [ RunMode ],
-        [ Char('.') ] => [ SamMode ],
+        [ Alt('.') ] => [ SamMode ],
         [ Char('-') ] => [ FindFile ],
         [ Char('_') ] => [ FindRepoFile ],
 
@@ -63,6 +66,10 @@ pub(crate) fn normal_mode() -> Mode {
         [ Char('g'), Char('e') ] => [ DotSet(BufferEnd, 1) ],
         [ Char('g'), Char('h') ] => [ DotSet(LineStart, 1) ],
         [ Char('g'), Char('l') ] => [ DotSet(LineEnd, 1) ],
+        [ Char('g'), Char('d') ] => [ LspDefinition ],
+        [ Char('g'), Char('r') ] => [ LspReferences ],
+        [ Char('g'), Char('k') ] => [ LspHover ],
+        [ Char('g'), Char('R') ] => [ LspRename { name: None } ],
 
         // Delimited pairs
         [ Alt('i'), Char('(') ] => [ DotSet(Delimited('(', ')'), 1) ],
@@ -121,8 +128,48 @@ pub(crate) fn normal_mode() -> Mode {
         [ Char('@') ] => [ ExecuteDot ],
         [ Char('*') ] => [ ExpandDot ],
 
+        // Searching
+        [ Char('n') ] => [ SearchNext ],
+        [ Char('N') ] => [ SearchPrevious ],
+        [ Esc ] => [ ClearSearchHighlight ],
+
+        // Diagnostics
+        [ Char(']'), Char('d') ] => [ DiagnosticNext ],
+        [ Char('['), Char('d') ] => [ DiagnosticPrevious ],
+
     };
 
+    keymap.extend(vec![(vec![Char(repeat_key)], Actions::Single(Repeat))]);
+    if repeat_key != '.' {
+        keymap.extend(vec![(vec![Char('.')], Actions::Single(SamMode))]);
+    }
+
+    // Marks and macros
+    keymap.extend(
+        ('a'..='z')
+            .flat_map(|name| {
+                [
+                    (
+                        vec![Char('q'), Char(name)],
+                        Actions::Single(MacroRecord { reg: name }),
+                    ),
+                    (
+                        vec![Char('Q'), Char(name)],
+                        Actions::Single(MacroPlay { reg: name }),
+                    ),
+                    (
+                        vec![Char('m'), Char(name)],
+                        Actions::Single(SetMark { name }),
+                    ),
+                    (
+                        vec![Char('\''), Char(name)],
+                        Actions::Single(JumpToMark { name }),
+                    ),
+                ]
+            })
+            .collect(),
+    );
+
     keymap.set_default(|&i| match i {
         Mouse(_) | Arrow(_) | PageUp | PageDown => Some(Actions::Single(RawInput { i })),
         _ => None,
@@ -1,5 +1,5 @@
 //! A simple AST for parsing and manipulating regex strings
-use super::{next_char, CharClass, Error};
+use super::{next_char, CharClass, Error, ErrorKind, Syntax};
 use crate::util::parse_num;
 use std::{iter::Peekable, mem::swap, str::Chars};
 
@@ -301,8 +301,8 @@ impl Rep {
         }
     }
 
-    fn apply(self, nodes: &mut Vec<Ast>) -> Result<(), Error> {
-        let last = nodes.pop().ok_or(Error::InvalidRepetition)?;
+    fn apply(self, nodes: &mut Vec<Ast>) -> Result<(), ErrorKind> {
+        let last = nodes.pop().ok_or(ErrorKind::InvalidRepetition)?;
 
         match (self, last) {
             (Rep::Quest(_), Ast::Rep(mut r, node)) => {
@@ -335,11 +335,14 @@ enum Counted {
 }
 
 impl Counted {
-    fn apply(self, nodes: &mut Vec<Ast>) -> Result<(), Error> {
-        let last = nodes.pop().ok_or(Error::InvalidRepetition)?;
+    fn apply(self, nodes: &mut Vec<Ast>) -> Result<(), ErrorKind> {
+        let last = nodes.pop().ok_or(ErrorKind::InvalidRepetition)?;
 
         match self {
             Counted::Rep(n) => nodes.extend(vec![last; n]),
+            Counted::RepAtLeast(0) => {
+                nodes.push(Ast::Rep(Rep::Star(Greed::Greedy), Box::new(last)))
+            }
             Counted::RepAtLeast(n) => {
                 nodes.extend(vec![last.clone(); n - 1]);
                 nodes.push(Ast::Rep(Rep::Plus(Greed::Greedy), Box::new(last)));
@@ -357,14 +360,41 @@ impl Counted {
     }
 }
 
-pub(super) fn parse(re: &str) -> Result<Ast, Error> {
+pub(super) fn parse(re: &str, syntax: Syntax) -> Result<Ast, Error> {
     let mut nodes = Vec::new();
     let mut it = re.chars().peekable();
 
-    parse_many(&mut it, &mut nodes)?;
+    if let Err(kind) = parse_many(&mut it, &mut nodes, syntax) {
+        // The parser consumes the character that it fails on so in general the error is located
+        // at the last character we have seen. Errors arising from running out of input are
+        // located just past the end of the pattern.
+        let n_chars = re.chars().count();
+        let remaining = it.count();
+        let hit_eof = remaining == 0
+            && matches!(
+                kind,
+                ErrorKind::InvalidClass
+                    | ErrorKind::InvalidEscape('\0')
+                    | ErrorKind::UnbalancedAlt
+                    | ErrorKind::UnbalancedParens
+                    | ErrorKind::UnclosedGroupName(_)
+            );
+        let offset = if hit_eof {
+            n_chars
+        } else {
+            (n_chars - remaining).saturating_sub(1)
+        };
+
+        return Err(Error { kind, offset });
+    }
 
     let mut root = match nodes.len() {
-        0 => return Err(Error::EmptyRegex),
+        0 => {
+            return Err(Error {
+                kind: ErrorKind::EmptyRegex,
+                offset: 0,
+            })
+        }
         _ => Ast::concat_or_node(nodes),
     };
 
@@ -380,17 +410,25 @@ enum ParseEnd {
     Eof,
 }
 
-fn parse1(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<Option<ParseEnd>, Error> {
-    match next_char(it)? {
+fn parse1(
+    it: &mut Peekable<Chars<'_>>,
+    root: &mut Vec<Ast>,
+    syntax: Syntax,
+) -> Result<Option<ParseEnd>, ErrorKind> {
+    match next_char(it, syntax)? {
         Some((ch, true)) => handle_escaped(ch, root).map(|_| None),
-        Some((ch, false)) => handle_char(ch, it, root),
+        Some((ch, false)) => handle_char(ch, it, root, syntax),
         None => Ok(Some(ParseEnd::Eof)),
     }
 }
 
-fn parse_many(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<ParseEnd, Error> {
+fn parse_many(
+    it: &mut Peekable<Chars<'_>>,
+    root: &mut Vec<Ast>,
+    syntax: Syntax,
+) -> Result<ParseEnd, ErrorKind> {
     loop {
-        match parse1(it, root)? {
+        match parse1(it, root, syntax)? {
             Some(p) => return Ok(p),
             None => continue,
         }
@@ -401,23 +439,24 @@ fn handle_char(
     ch: char,
     it: &mut Peekable<Chars<'_>>,
     root: &mut Vec<Ast>,
-) -> Result<Option<ParseEnd>, Error> {
+    syntax: Syntax,
+) -> Result<Option<ParseEnd>, ErrorKind> {
     match ch {
-        '|' => handle_alt(it, root)?,
-        '(' => handle_subexp(it, root)?,
+        '|' => handle_alt(it, root, syntax)?,
+        '(' => handle_subexp(it, root, syntax)?,
         ')' => return Ok(Some(ParseEnd::Rparen)),
 
         '?' => Rep::Quest(Greed::Greedy).apply(root)?,
         '*' => Rep::Star(Greed::Greedy).apply(root)?,
         '+' => Rep::Plus(Greed::Greedy).apply(root)?,
-        '{' => try_parse_counted_repetition(it)?.apply(root)?,
+        '{' => try_parse_counted_repetition(it, syntax)?.apply(root)?,
 
         '^' => root.push(Ast::Assertion(Assertion::LineStart)),
         '$' => root.push(Ast::Assertion(Assertion::LineEnd)),
 
-        '[' => root.push(Ast::Comp(Comp::Class(CharClass::try_parse(it)?))),
+        '[' => root.push(Ast::Comp(Comp::Class(CharClass::try_parse(it, syntax)?))),
         '.' => root.push(Ast::Comp(Comp::Any)),
-        '@' => root.push(Ast::Comp(Comp::TrueAny)),
+        '@' if syntax == Syntax::Sam => root.push(Ast::Comp(Comp::TrueAny)),
         ch => root.push(Ast::Comp(Comp::Char(ch))),
     }
 
@@ -426,23 +465,35 @@ fn handle_char(
 
 /// Three variants of submatch / group are supported by this engine:
 ///   1) "(...)"
-///       Capturing: the position of this sub-expression will be extracted as submatch
-///       and made available through a numeric index based on it's position within the
-///       regular expression.
+///      Capturing: the position of this sub-expression will be extracted as submatch
+///      and made available through a numeric index based on it's position within the
+///      regular expression.
 ///   2) "(?<name>...)"
-///       Named capturing: the position of this subexpression will be extracted as a submatch
-///       and made available through the provided name rather than an index. To avoid confusion
-///       with mixing and matching named capture groups and positional ones, the presence of a
-///       named capture group will mark all unnamed capture groups within that regex to be
-///       treated as non-capturing. If they are required, they will also need to be named.
+///      Named capturing: the position of this subexpression will be extracted as a submatch
+///      and made available through the provided name rather than an index. To avoid confusion
+///      with mixing and matching named capture groups and positional ones, the presence of a
+///      named capture group will mark all unnamed capture groups within that regex to be
+///      treated as non-capturing. If they are required, they will also need to be named.
 ///   3) "(?:...)"
-///       Non-capturing: allows for grouping and application of repetition / alternation
-///       of compund expressions without contributing to the captured sub-expressions.
-fn handle_subexp(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<(), Error> {
+///      Non-capturing: allows for grouping and application of repetition / alternation
+///      of compund expressions without contributing to the captured sub-expressions.
+///
+/// When parsing with [Syntax::Compat], "(?P<name>...)" is also accepted for named groups.
+fn handle_subexp(
+    it: &mut Peekable<Chars<'_>>,
+    root: &mut Vec<Ast>,
+    syntax: Syntax,
+) -> Result<(), ErrorKind> {
     let mut sub = Vec::new();
     let kind = match it.peek() {
         Some('?') => {
             it.next();
+            if syntax == Syntax::Compat && it.peek() == Some(&'P') {
+                it.next();
+                if it.peek() != Some(&'<') {
+                    return Err(ErrorKind::UnknownGroupQualifier('P'));
+                }
+            }
             match it.next() {
                 Some(':') => SmKind::NonCapturing,
                 Some('<') => {
@@ -454,21 +505,21 @@ fn handle_subexp(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<()
                         name.push(ch);
                     }
                     if it.peek().is_none() {
-                        return Err(Error::UnclosedGroupName(name));
+                        return Err(ErrorKind::UnclosedGroupName(name));
                     }
                     SmKind::Named(name)
                 }
-                Some(ch) => return Err(Error::UnknownGroupQualifier(ch)),
-                None => return Err(Error::UnbalancedParens),
+                Some(ch) => return Err(ErrorKind::UnknownGroupQualifier(ch)),
+                None => return Err(ErrorKind::UnbalancedParens),
             }
         }
         _ => SmKind::Normal,
     };
 
-    let node = match parse_many(it, &mut sub)? {
-        ParseEnd::Eof => return Err(Error::UnbalancedParens),
+    let node = match parse_many(it, &mut sub, syntax)? {
+        ParseEnd::Eof => return Err(ErrorKind::UnbalancedParens),
         ParseEnd::Rparen => match sub.len() {
-            0 => return Err(Error::EmptyParens),
+            0 => return Err(ErrorKind::EmptyParens),
             1 => sub.remove(0),
             _ => Ast::Concat(sub),
         },
@@ -479,9 +530,13 @@ fn handle_subexp(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<()
     Ok(())
 }
 
-fn handle_alt(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<(), Error> {
+fn handle_alt(
+    it: &mut Peekable<Chars<'_>>,
+    root: &mut Vec<Ast>,
+    syntax: Syntax,
+) -> Result<(), ErrorKind> {
     if root.is_empty() {
-        return Err(Error::UnbalancedAlt);
+        return Err(ErrorKind::UnbalancedAlt);
     }
     let mut first = Vec::new();
     swap(&mut first, root);
@@ -489,9 +544,9 @@ fn handle_alt(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<(), E
     let mut buf = Vec::new();
 
     loop {
-        if parse1(it, &mut buf)?.is_some() {
+        if parse1(it, &mut buf, syntax)?.is_some() {
             if buf.is_empty() {
-                return Err(Error::UnbalancedAlt);
+                return Err(ErrorKind::UnbalancedAlt);
             }
             alt.push(Ast::concat_or_node(buf));
             break;
@@ -515,7 +570,7 @@ fn handle_alt(it: &mut Peekable<Chars<'_>>, root: &mut Vec<Ast>) -> Result<(), E
     Ok(())
 }
 
-fn handle_escaped(ch: char, root: &mut Vec<Ast>) -> Result<(), Error> {
+fn handle_escaped(ch: char, root: &mut Vec<Ast>) -> Result<(), ErrorKind> {
     match ch {
         'b' => root.push(Ast::Assertion(Assertion::WordBoundary)),
         'B' => root.push(Ast::Assertion(Assertion::NonWordBoundary)),
@@ -533,42 +588,57 @@ fn handle_escaped(ch: char, root: &mut Vec<Ast>) -> Result<(), Error> {
     Ok(())
 }
 
-fn try_parse_counted_repetition(it: &mut Peekable<Chars<'_>>) -> Result<Counted, Error> {
-    let (mut ch, _) = next_char(it)?.ok_or(Error::InvalidRepetition)?;
+/// Counted repetitions in Sam syntax require a lower bound of at least 1. With
+/// [Syntax::Compat] a lower bound of 0 is permitted and may be omitted entirely ("{,m}").
+/// In both syntaxes an upper bound that is smaller than the lower bound is an error.
+fn try_parse_counted_repetition(
+    it: &mut Peekable<Chars<'_>>,
+    syntax: Syntax,
+) -> Result<Counted, ErrorKind> {
+    let next = |it: &mut Peekable<Chars<'_>>| {
+        next_char(it, syntax).and_then(|opt| opt.ok_or(ErrorKind::InvalidRepetition))
+    };
+    let min_lower = if syntax == Syntax::Compat { 0 } else { 1 };
+    let (mut ch, _) = next(it)?;
+
+    let n = if ch.is_ascii_digit() {
+        let n = parse_num(ch, it);
+        (ch, _) = next(it)?;
+        n
+    } else if ch == ',' && syntax == Syntax::Compat {
+        0
+    } else {
+        return Err(ErrorKind::InvalidRepetition);
+    };
 
-    if !ch.is_ascii_digit() {
-        return Err(Error::InvalidRepetition);
-    }
-    let n = parse_num(ch, it);
-    if n == 0 {
-        return Err(Error::InvalidRepetition);
+    if n < min_lower {
+        return Err(ErrorKind::InvalidRepetition);
     }
 
-    (ch, _) = next_char(it)?.ok_or(Error::InvalidRepetition)?;
     if ch == '}' {
         return Ok(Counted::Rep(n));
     } else if ch != ',' {
-        return Err(Error::InvalidRepetition);
+        return Err(ErrorKind::InvalidRepetition);
     }
 
-    (ch, _) = next_char(it)?.ok_or(Error::InvalidRepetition)?;
+    (ch, _) = next(it)?;
     if ch == '}' {
         return Ok(Counted::RepAtLeast(n));
     }
 
     if !ch.is_ascii_digit() {
-        return Err(Error::InvalidRepetition);
+        return Err(ErrorKind::InvalidRepetition);
     }
     let m = parse_num(ch, it);
-    if m == 0 {
-        return Err(Error::InvalidRepetition);
+    if m == 0 || m < n {
+        return Err(ErrorKind::InvalidRepetition);
     }
 
-    (ch, _) = next_char(it)?.ok_or(Error::InvalidRepetition)?;
+    (ch, _) = next(it)?;
     if ch == '}' {
         Ok(Counted::RepBetween(n, m))
     } else {
-        Err(Error::InvalidRepetition)
+        Err(ErrorKind::InvalidRepetition)
     }
 }
 
@@ -638,7 +708,7 @@ mod tests {
     )]
     #[test]
     fn parse_works(re: &str, expected: Ast) {
-        let res = parse(re).unwrap();
+        let res = parse(re, Syntax::Sam).unwrap();
         assert_eq!(res, expected);
     }
 
@@ -651,9 +721,9 @@ mod tests {
     #[test_case("[Gg]oo+gle", "elgo+o[Gg]"; "with class and rep")]
     #[test]
     fn ast_reverse_works(re_fwd: &str, re_bck: &str) {
-        let mut fwd_ast = parse(re_fwd).unwrap();
+        let mut fwd_ast = parse(re_fwd, Syntax::Sam).unwrap();
         fwd_ast.reverse();
-        let bck_ast = parse(re_bck).unwrap();
+        let bck_ast = parse(re_bck, Syntax::Sam).unwrap();
 
         assert_eq!(fwd_ast, bck_ast);
     }
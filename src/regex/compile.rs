@@ -336,7 +336,7 @@ fn strip_unreachable_instructions(ops: &mut Vec<Op>) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::regex::ast::parse;
+    use crate::regex::{ast::parse, Syntax};
     use simple_test_case::test_case;
 
     const BOL: Op = Op::Assertion(Assertion::LineStart);
@@ -376,7 +376,7 @@ mod tests {
     #[test_case("(?<xy>X|Y)(a|b)", vec![sv(2), sp(6, 8), c('X'), jmp(9), c('Y'), sv(3), sp(11, 13), c('a'), jmp(14), c('b')]; "named submatch demoting unnamed")]
     #[test]
     fn ast_compile_works(re: &str, expected: Vec<Op>) {
-        let ast = parse(re).unwrap();
+        let ast = parse(re, Syntax::Sam).unwrap();
         let prog = compile_ast(ast, false).ops;
 
         let mut full = vec![sp(3, 1), Op::Comp(Comp::TrueAny), sp(3, 1), sv(0)];
@@ -394,7 +394,7 @@ mod tests {
     #[test_case("(?<xy>X|Y)(a|b)", vec![sv(2), sp(6, 8), c('X'), jmp(9), c('Y'), sv(3), sp(11, 13), c('a'), jmp(14), c('b')]; "named submatch demoting unnamed")]
     #[test]
     fn opcode_optimise_works(re: &str, expected: Vec<Op>) {
-        let ast = parse(re).unwrap();
+        let ast = parse(re, Syntax::Sam).unwrap();
         let prog = optimise(compile_ast(ast, false).ops);
         let mut full = vec![sp(3, 1), Op::Comp(Comp::TrueAny), sp(3, 1), sv(0)];
         full.extend(expected);
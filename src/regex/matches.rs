@@ -161,6 +161,7 @@ impl Match {
 pub trait IndexedChars {
     type I: Iterator<Item = (usize, char)>;
     fn iter_from(&self, from: usize) -> Option<Self::I>;
+    fn char_at(&self, idx: usize) -> Option<char>;
 }
 
 impl<'a> IndexedChars for &'a str {
@@ -176,6 +177,10 @@ impl<'a> IndexedChars for &'a str {
             Some(self.chars().enumerate().skip(from))
         }
     }
+
+    fn char_at(&self, idx: usize) -> Option<char> {
+        self.chars().nth(idx)
+    }
 }
 
 impl<'a> IndexedChars for &'a GapBuffer {
@@ -191,6 +196,10 @@ impl<'a> IndexedChars for &'a GapBuffer {
             )
         }
     }
+
+    fn char_at(&self, idx: usize) -> Option<char> {
+        self.get_char(idx)
+    }
 }
 
 /// An iterator over sequential, non overlapping matches of a Regex
@@ -212,9 +221,12 @@ where
     type Item = Match;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // The preceding character is needed so that assertions are handled correctly for
+        // matches that do not start at the beginning of the input.
+        let prev = self.from.checked_sub(1).and_then(|i| self.it.char_at(i));
         let m = self
             .r
-            .match_iter(&mut self.it.iter_from(self.from)?, self.from)?;
+            .match_iter_after(&mut self.it.iter_from(self.from)?, self.from, prev)?;
 
         let (_, from) = m.loc();
         if from == self.from {
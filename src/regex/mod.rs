@@ -3,7 +3,7 @@
 //!
 //! Thompson's original paper on writing a regex engine can be found here:
 //!   https://dl.acm.org/doi/pdf/10.1145/363347.363387
-use std::{iter::Peekable, str::Chars};
+use std::{fmt, iter::Peekable, str::Chars, str::FromStr};
 
 mod ast;
 mod compile;
@@ -13,9 +13,26 @@ mod vm;
 pub use matches::{Match, MatchIter};
 pub use vm::Regex;
 
-/// Errors that can be returned by the regex engine
+/// An error encountered while parsing a regular expression.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Error {
+pub struct Error {
+    /// What went wrong
+    pub kind: ErrorKind,
+    /// The character offset into the pattern at which the error was detected
+    pub offset: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at char {})", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The kinds of error that can be returned by the regex engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
     /// Empty parens
     EmptyParens,
     /// Empty string used when creating a [Regex]
@@ -40,6 +57,57 @@ pub enum Error {
     UnknownGroupQualifier(char),
 }
 
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyParens => write!(f, "empty parens"),
+            Self::EmptyRegex => write!(f, "empty regex"),
+            Self::InvalidClass => write!(f, "invalid character class"),
+            Self::InvalidEscape('\0') => write!(f, "trailing escape"),
+            Self::InvalidEscape(ch) => write!(f, "invalid escape sequence '\\{ch}'"),
+            Self::InvalidRepetition => write!(f, "invalid repetition"),
+            Self::ReTooLong => write!(f, "regex is too long"),
+            Self::TooManyParens => write!(f, "too many parens"),
+            Self::UnbalancedAlt => write!(f, "alternation is missing a branch"),
+            Self::UnbalancedParens => write!(f, "unbalanced parens"),
+            Self::UnclosedGroupName(name) => write!(f, "unclosed group name '{name}'"),
+            Self::UnknownGroupQualifier(ch) => write!(f, "unknown group qualifier '?{ch}'"),
+        }
+    }
+}
+
+/// The syntax to use when parsing a regular expression.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// The syntax used by Sam and Acme where '@' matches any character including newline and
+    /// only a fixed set of escape sequences are supported.
+    #[default]
+    Sam,
+    /// A compatibility mode accepting the common subset of POSIX ERE and PCRE used by tools such
+    /// as grep and ripgrep so that patterns can be pasted in unchanged:
+    ///   - '@' is a literal character
+    ///   - any escaped ASCII punctuation is treated as a literal
+    ///   - POSIX bracket expressions ("[[:alpha:]]") and \d, \w, \s inside of classes
+    ///   - a trailing '-' in a class is a literal
+    ///   - counted repetitions with a lower bound of zero ("{0,3}", "{,3}")
+    ///   - Python style named groups ("(?P<name>...)")
+    Compat,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sam" => Ok(Self::Sam),
+            "compat" => Ok(Self::Compat),
+            _ => Err(format!(
+                "unknown regex syntax '{s}': expected 'sam' or 'compat'"
+            )),
+        }
+    }
+}
+
 /// Helper for converting characters to 0 based inicies for looking things up in caches.
 const fn char_ix(ch: char) -> usize {
     ((ch as u16) & 0xFF) as usize
@@ -75,28 +143,51 @@ struct CharClass {
 }
 
 impl CharClass {
-    fn try_parse(it: &mut Peekable<Chars<'_>>) -> Result<Self, Error> {
-        let mut next = || next_char(it)?.ok_or(Error::InvalidClass);
-        let (mut ch, _) = next()?;
+    fn try_parse(it: &mut Peekable<Chars<'_>>, syntax: Syntax) -> Result<Self, ErrorKind> {
+        let mut chars = vec![];
+        let mut ranges = vec![];
 
-        let negated = ch == '^';
+        let negated = it.peek() == Some(&'^');
         if negated {
-            (ch, _) = next()?
-        };
-        let mut chars = vec![ch];
-        let mut ranges = vec![];
+            it.next();
+        }
+
+        // A leading ']' or '-' is always a literal
+        if let Some(&ch @ (']' | '-')) = it.peek() {
+            it.next();
+            chars.push(ch);
+        }
 
         loop {
-            let (ch, escaped) = next()?;
+            if syntax == Syntax::Compat && it.peek() == Some(&'[') {
+                let mut lookahead = it.clone();
+                lookahead.next();
+                if lookahead.peek() == Some(&':') {
+                    it.next();
+                    it.next();
+                    push_posix_class(it, &mut chars, &mut ranges)?;
+                    continue;
+                }
+            }
+
+            let (ch, escaped) = next_char(it, syntax)?.ok_or(ErrorKind::InvalidClass)?;
             match ch {
                 ']' if !escaped => break,
 
+                '-' if !escaped && syntax == Syntax::Compat && it.peek() == Some(&']') => {
+                    chars.push('-')
+                }
+
                 '-' if !escaped => {
-                    let start = chars.pop().ok_or(Error::InvalidClass)?;
-                    let (end, _) = next()?;
+                    let start = chars.pop().ok_or(ErrorKind::InvalidClass)?;
+                    let (end, _) = next_char(it, syntax)?.ok_or(ErrorKind::InvalidClass)?;
                     ranges.push((start, end));
                 }
 
+                'd' | 'w' | 's' if escaped && syntax == Syntax::Compat => {
+                    push_named_class(ch, &mut chars, &mut ranges)
+                }
+
                 ch => chars.push(ch),
             }
         }
@@ -129,7 +220,63 @@ impl CharClass {
     }
 }
 
-fn next_char(it: &mut Peekable<Chars<'_>>) -> Result<Option<(char, bool)>, Error> {
+/// Parse a POSIX bracket expression such as "[:alpha:]" from within a character class. The
+/// leading "[:" is expected to have already been consumed.
+fn push_posix_class(
+    it: &mut Peekable<Chars<'_>>,
+    chars: &mut Vec<char>,
+    ranges: &mut Vec<(char, char)>,
+) -> Result<(), ErrorKind> {
+    let mut name = String::new();
+    loop {
+        match it.next() {
+            Some(':') => break,
+            Some(ch) if ch.is_ascii_alphabetic() => name.push(ch),
+            _ => return Err(ErrorKind::InvalidClass),
+        }
+    }
+    if it.next() != Some(']') {
+        return Err(ErrorKind::InvalidClass);
+    }
+
+    match name.as_str() {
+        "alpha" => ranges.extend([('a', 'z'), ('A', 'Z')]),
+        "digit" => push_named_class('d', chars, ranges),
+        "alnum" => ranges.extend([('a', 'z'), ('A', 'Z'), ('0', '9')]),
+        "word" => push_named_class('w', chars, ranges),
+        "upper" => ranges.push(('A', 'Z')),
+        "lower" => ranges.push(('a', 'z')),
+        "space" => push_named_class('s', chars, ranges),
+        "blank" => chars.extend([' ', '\t']),
+        "xdigit" => ranges.extend([('0', '9'), ('a', 'f'), ('A', 'F')]),
+        "punct" => ranges.extend([('!', '/'), (':', '@'), ('[', '`'), ('{', '~')]),
+        "cntrl" => ranges.extend([('\0', '\x1f'), ('\x7f', '\x7f')]),
+        "print" => ranges.push((' ', '~')),
+        "graph" => ranges.push(('!', '~')),
+        _ => return Err(ErrorKind::InvalidClass),
+    }
+
+    Ok(())
+}
+
+/// Expand one of the \d, \w or \s escapes into the characters it matches for use inside of a
+/// character class.
+fn push_named_class(ch: char, chars: &mut Vec<char>, ranges: &mut Vec<(char, char)>) {
+    match ch {
+        'd' => ranges.push(('0', '9')),
+        'w' => {
+            ranges.extend([('a', 'z'), ('A', 'Z'), ('0', '9')]);
+            chars.push('_');
+        }
+        's' => chars.extend([' ', '\t', '\n', '\r', '\x0b', '\x0c']),
+        _ => unreachable!("only called for d, w and s"),
+    }
+}
+
+fn next_char(
+    it: &mut Peekable<Chars<'_>>,
+    syntax: Syntax,
+) -> Result<Option<(char, bool)>, ErrorKind> {
     match it.next() {
         Some('\\') => (),
         Some(ch) => return Ok(Some((ch, false))),
@@ -138,12 +285,14 @@ fn next_char(it: &mut Peekable<Chars<'_>>) -> Result<Option<(char, bool)>, Error
 
     let ch = match it.next() {
         Some(ch) => ch,
-        None => return Err(Error::InvalidEscape('\0')),
+        None => return Err(ErrorKind::InvalidEscape('\0')),
     };
 
     match ESCAPES[char_ix(ch)] {
         Some(ch) => Ok(Some((ch, true))),
-        None => Err(Error::InvalidEscape(ch)),
+        // Any escaped punctuation is a literal in PCRE and POSIX ERE
+        _ if syntax == Syntax::Compat && ch.is_ascii_punctuation() => Ok(Some((ch, true))),
+        _ => Err(ErrorKind::InvalidEscape(ch)),
     }
 }
 
@@ -163,7 +312,7 @@ mod tests {
         // The outer regex parser consumes the initial '[' before passing through so test cases
         // look a little lopsided due to missing this.
         for (s, negated) in [(format!("{raw}]"), false), (format!("^{raw}]"), true)] {
-            let cls = CharClass::try_parse(&mut s.chars().peekable()).unwrap();
+            let cls = CharClass::try_parse(&mut s.chars().peekable(), Syntax::Sam).unwrap();
             let expected = CharClass {
                 negated,
                 chars: chars.to_vec(),
@@ -12,7 +12,7 @@ use super::{
     ast::{parse, Assertion},
     compile::{compile_ast, optimise, CompiledOps, Inst, Op, Prog},
     matches::{Match, MatchIter},
-    Error,
+    Error, Syntax,
 };
 use crate::buffer::{Buffer, GapBuffer};
 use std::{mem::swap, rc::Rc};
@@ -63,7 +63,12 @@ impl Regex {
     /// This method handles pre-allocation of the memory required for running the VM so
     /// that the allocation cost is paid once up front rather than on each use of the Regex.
     pub fn compile(re: &str) -> Result<Self, Error> {
-        let mut ast = parse(re)?;
+        Self::compile_with_syntax(re, Syntax::Sam)
+    }
+
+    /// Attempt to compile the given regular expression using the specified [Syntax].
+    pub fn compile_with_syntax(re: &str, syntax: Syntax) -> Result<Self, Error> {
+        let mut ast = parse(re, syntax)?;
         ast.optimise();
         let CompiledOps {
             ops,
@@ -79,7 +84,13 @@ impl Regex {
     /// This method handles pre-allocation of the memory required for running the VM so
     /// that the allocation cost is paid once up front rather than on each use of the Regex.
     pub fn compile_reverse(re: &str) -> Result<Self, Error> {
-        let mut ast = parse(re)?;
+        Self::compile_reverse_with_syntax(re, Syntax::Sam)
+    }
+
+    /// Attempt to compile the given regular expression into its reversed form using the
+    /// specified [Syntax].
+    pub fn compile_reverse_with_syntax(re: &str, syntax: Syntax) -> Result<Self, Error> {
+        let mut ast = parse(re, syntax)?;
         ast.optimise();
         let CompiledOps {
             ops,
@@ -150,7 +161,23 @@ impl Regex {
         I: Iterator<Item = (usize, char)>,
     {
         self.track_submatches = true;
-        self._match_iter(input, sp)
+        self._match_iter(input, sp, None)
+    }
+
+    /// As [Regex::match_iter] but with the character preceding the input provided so that
+    /// assertions such as '^' and '\b' are correctly handled when matching from part way
+    /// through a larger input.
+    pub(super) fn match_iter_after<I>(
+        &mut self,
+        input: &mut I,
+        sp: usize,
+        prev: Option<char>,
+    ) -> Option<Match>
+    where
+        I: Iterator<Item = (usize, char)>,
+    {
+        self.track_submatches = true;
+        self._match_iter(input, sp, prev)
     }
 
     /// Determine whether or not this Regex matches the input `&str` without searching for
@@ -167,7 +194,7 @@ impl Regex {
         I: Iterator<Item = (usize, char)>,
     {
         self.track_submatches = false;
-        self._match_iter(input, sp).is_some()
+        self._match_iter(input, sp, None).is_some()
     }
 
     /// This is the main VM implementation that is used by all other matching methods on Regex.
@@ -178,7 +205,7 @@ impl Regex {
     ///  - The Match returned in this case will always point to the null string at the start
     ///    of the string and should only be used for conversion to a bool in `matches_*`
     ///    methods.
-    fn _match_iter<I>(&mut self, input: &mut I, mut sp: usize) -> Option<Match>
+    fn _match_iter<I>(&mut self, input: &mut I, mut sp: usize, prev: Option<char>) -> Option<Match>
     where
         I: Iterator<Item = (usize, char)>,
     {
@@ -192,6 +219,7 @@ impl Regex {
         // We bump the generation to ensure we don't collide with anything from
         // a previous run while initialising the VM.
         self.gen += 1;
+        self.prev = prev;
         // When setting up the initial threads we have our prelude which uses "@" so we provide a
         // null byte for the initial character as it is not needed and it avoids us having to make
         // the "ch" param of add_thread optional.
@@ -206,7 +234,6 @@ impl Regex {
         let mut matched = false;
 
         let mut it = input.peekable();
-        self.prev = None;
         self.next = None;
 
         while let Some((i, ch)) = it.next() {
@@ -330,7 +357,7 @@ impl Regex {
 
     #[inline]
     fn handle_save(&mut self, t: Thread, s: usize, sp: usize, ch: char, initial: bool, rev: bool) {
-        if (!rev && s % 2 == 0) || (rev && s % 2 == 1) {
+        if (!rev && s.is_multiple_of(2)) || (rev && s % 2 == 1) {
             let sm = self.sm_update(t.sm, s, sp, initial, rev);
             let th = match t.assertion {
                 Some(a) => assert_thread(t.pc + 1, sm, a),
@@ -432,8 +459,39 @@ fn assert_thread(pc: usize, sm: usize, a: Assertion) -> Thread {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::regex::ErrorKind;
     use simple_test_case::test_case;
 
+    #[test_case("a@c", "a@c abc", Some("a@c"); "at is a literal")]
+    #[test_case("a\\/b", "a/b", Some("a/b"); "escaped punctuation")]
+    #[test_case("[[:digit:]]+", "abc 123", Some("123"); "posix class")]
+    #[test_case("[[:alpha:]_]+", "99 foo_bar", Some("foo_bar"); "posix class with chars")]
+    #[test_case("[\\d.]+", "v1.2.3", Some("1.2.3"); "escaped class in class")]
+    #[test_case("[a-]+", "b-a-c", Some("-a-"); "trailing dash in class")]
+    #[test_case("ab{0,2}c", "ac", Some("ac"); "counted rep with zero lower bound")]
+    #[test_case("ab{,2}c", "abbc", Some("abbc"); "counted rep with omitted lower bound")]
+    #[test_case("(?P<n>b+)", "abbc", Some("bb"); "python style named group")]
+    #[test]
+    fn compat_match_works(re: &str, s: &str, expected: Option<&str>) {
+        let mut r = Regex::compile_with_syntax(re, Syntax::Compat).unwrap();
+        let m = r.match_str(s).map(|m| m.str_match_text(s));
+        assert_eq!(m.as_deref(), expected);
+    }
+
+    #[test_case("a(b", ErrorKind::UnbalancedParens, 3; "unclosed paren")]
+    #[test_case("a[bc", ErrorKind::InvalidClass, 4; "unclosed class")]
+    #[test_case("a\\/b", ErrorKind::InvalidEscape('/'), 2; "invalid escape")]
+    #[test_case("ab{0,2}", ErrorKind::InvalidRepetition, 4; "zero lower bound")]
+    #[test_case("ab{3,2}", ErrorKind::InvalidRepetition, 5; "upper bound below lower bound")]
+    #[test_case("a(?#b)", ErrorKind::UnknownGroupQualifier('#'), 3; "unknown group qualifier")]
+    #[test_case("a|", ErrorKind::UnbalancedAlt, 2; "trailing alt")]
+    #[test_case("", ErrorKind::EmptyRegex, 0; "empty")]
+    #[test]
+    fn compile_errors_are_located(re: &str, kind: ErrorKind, offset: usize) {
+        let res = Regex::compile(re);
+        assert_eq!(res, Err(Error { kind, offset }));
+    }
+
     #[test_case("foo", "foo", Some("foo"); "literal full string")]
     #[test_case("ba*", "baaaaa", Some("baaaaa"); "zero or more present")]
     #[test_case("ba*", "b", Some("b"); "zero or more not present")]
@@ -552,6 +610,7 @@ mod tests {
     #[test_case("foo|bar|baz", "bazbarfoobar", &["baz", "bar", "foo", "bar"]; "alts back to back in s")]
     #[test_case("(foo|bar|baz)", "foo foobar barfoo baz", &["foo", "foo", "bar", "bar", "foo", "baz"]; "alts in parens")]
     #[test_case("\\b(foo|bar|baz)\\b", "foo foobar barfoo baz", &["foo", "baz"]; "alts with word boundaries")]
+    #[test_case("^a", "aaa\nab", &["a", "a"]; "line start only at start of lines")]
     #[test]
     fn match_all_works(re: &str, s: &str, expected: &[&str]) {
         let mut r = Regex::compile(re).unwrap();
@@ -0,0 +1,372 @@
+//! Snippets of templated text that can be expanded from INSERT mode.
+//!
+//! Snippets are loaded from `$HOME/.ad/snippets/` where `all.snippets` is used for every buffer
+//! and `ext.snippets` is used for files with the extension `ext`. A snippet is a line of the form
+//! `snippet trigger [description]` followed by its body, with every line of the body indented by
+//! a single tab. Lines starting with '#' outside of a snippet body are comments. Snippets files
+//! are read the first time they are needed and re-read when the config is reloaded.
+//!
+//! Bodies may contain tabstops which are visited in order once the snippet has been expanded:
+//!   - `$1`, `$2`... mark positions to visit
+//!   - `${1:default}` marks a position with default text
+//!   - `$0` marks the final position of the cursor (the end of the snippet if it is omitted)
+//!
+//! Repeating a tabstop creates a mirror of it that is kept up to date as the first occurrence is
+//! edited, and `$FILENAME` is replaced with the name of the buffer the snippet is expanded in.
+//! A literal '$' or '}' can be written as `\$` or `\}`.
+use crate::exec::FNAME_VAR;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs, io,
+    path::Path,
+};
+
+const SNIPPET_PREFIX: &str = "snippet ";
+
+/// A single snippet parsed from a snippets file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Snippet {
+    pub(crate) trigger: String,
+    pub(crate) description: String,
+    pub(crate) body: String,
+}
+
+/// The text of an expanded snippet along with the ranges of its tabstops relative to the start
+/// of the text. Tabstops are in the order they should be visited, each holding the range that
+/// is edited followed by any mirrors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Expansion {
+    pub(crate) text: String,
+    pub(crate) stops: Vec<Vec<(usize, usize)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Stop { n: usize, default: Option<String> },
+}
+
+impl Snippet {
+    /// Expand this snippet for a buffer named `fname`. Lines after the first are indented by
+    /// `indent` (the indentation of the line the snippet is expanded on) and tabs used for
+    /// indentation within the body are replaced with `indent_unit`.
+    pub(crate) fn expand(&self, fname: &str, indent: &str, indent_unit: &str) -> Expansion {
+        let lines: Vec<String> = self
+            .body
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                let content = line.trim_start_matches('\t');
+                let n_tabs = line.len() - content.len();
+                let prefix = if i == 0 || line.is_empty() {
+                    ""
+                } else {
+                    indent
+                };
+
+                format!("{prefix}{}{content}", indent_unit.repeat(n_tabs))
+            })
+            .collect();
+        let parts = parse_body(&lines.join("\n").replace(FNAME_VAR, fname));
+
+        let mut defaults: HashMap<usize, &str> = HashMap::new();
+        for part in parts.iter() {
+            if let Part::Stop {
+                n,
+                default: Some(d),
+            } = part
+            {
+                defaults.entry(*n).or_insert(d);
+            }
+        }
+
+        let mut text = String::new();
+        let mut len = 0;
+        let mut ranges: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+
+        for part in parts.iter() {
+            let s = match part {
+                Part::Text(s) => s,
+                Part::Stop { n, .. } => {
+                    let d = defaults.get(n).copied().unwrap_or_default();
+                    let n_chars = d.chars().count();
+                    ranges.entry(*n).or_default().push((len, len + n_chars));
+                    d
+                }
+            };
+            text.push_str(s);
+            len += s.chars().count();
+        }
+
+        let last = ranges.remove(&0).unwrap_or_else(|| vec![(len, len)]);
+        let mut stops: Vec<_> = ranges.into_values().collect();
+        stops.push(last);
+
+        Expansion { text, stops }
+    }
+}
+
+/// Split a snippet body into literal text and tabstops.
+fn parse_body(body: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut s = body;
+
+    while let Some(ch) = s.chars().next() {
+        let rest = &s[ch.len_utf8()..];
+        if ch == '\\' && (rest.starts_with('$') || rest.starts_with('}')) {
+            text.push_str(&rest[..1]);
+            s = &rest[1..];
+            continue;
+        }
+
+        if ch == '$' {
+            if let Some((stop, rest)) = parse_stop(rest) {
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(stop);
+                s = rest;
+                continue;
+            }
+        }
+
+        text.push(ch);
+        s = rest;
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    parts
+}
+
+fn parse_n(s: &str) -> Option<(usize, &str)> {
+    let n_digits = s.chars().take_while(|ch| ch.is_ascii_digit()).count();
+    let n = s[..n_digits].parse::<usize>().ok()?;
+
+    Some((n, &s[n_digits..]))
+}
+
+/// Parse a tabstop from the text following a '$', returning it along with the remaining text.
+fn parse_stop(s: &str) -> Option<(Part, &str)> {
+    let s = match s.strip_prefix('{') {
+        Some(s) => s,
+        None => {
+            let (n, rest) = parse_n(s)?;
+            return Some((Part::Stop { n, default: None }, rest));
+        }
+    };
+
+    let (n, rest) = parse_n(s)?;
+    if let Some(rest) = rest.strip_prefix('}') {
+        return Some((Part::Stop { n, default: None }, rest));
+    }
+
+    let mut rest = rest.strip_prefix(':')?;
+    let mut default = String::new();
+    loop {
+        let ch = rest.chars().next()?;
+        rest = &rest[ch.len_utf8()..];
+        match ch {
+            '}' => break,
+            '\\' if rest.starts_with('$') || rest.starts_with('}') => {
+                default.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+            ch => default.push(ch),
+        }
+    }
+
+    Some((
+        Part::Stop {
+            n,
+            default: Some(default),
+        },
+        rest,
+    ))
+}
+
+/// Parse the contents of a snippets file.
+pub(crate) fn parse_snippets(s: &str) -> Result<Vec<Snippet>, String> {
+    let mut snippets = Vec::new();
+    let mut current: Option<(Snippet, Vec<&str>)> = None;
+
+    let finish = |snippets: &mut Vec<Snippet>, current: Option<(Snippet, Vec<&str>)>| {
+        if let Some((mut snippet, mut body)) = current {
+            while body.last() == Some(&"") {
+                body.pop();
+            }
+            snippet.body = body.join("\n");
+            snippets.push(snippet);
+        }
+    };
+
+    for (n, line) in s.lines().enumerate() {
+        if let Some(line) = line.strip_prefix('\t') {
+            match current.as_mut() {
+                Some((_, body)) => body.push(line),
+                None => return Err(format!("line {}: snippet body without a trigger", n + 1)),
+            }
+        } else if line.trim().is_empty() {
+            if let Some((_, body)) = current.as_mut() {
+                body.push("");
+            }
+        } else if let Some(rest) = line.strip_prefix(SNIPPET_PREFIX) {
+            finish(&mut snippets, current.take());
+            let (trigger, description) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+            let snippet = Snippet {
+                trigger: trigger.to_string(),
+                description: description.trim().to_string(),
+                body: String::new(),
+            };
+            current = Some((snippet, Vec::new()));
+        } else if line.starts_with('#') {
+            finish(&mut snippets, current.take());
+        } else {
+            return Err(format!(
+                "line {}: expected 'snippet trigger [description]' or a tab indented body",
+                n + 1
+            ));
+        }
+    }
+    finish(&mut snippets, current);
+
+    Ok(snippets)
+}
+
+/// Load the snippets for a buffer with the given file extension (if it has one) from
+/// `$HOME/.ad/snippets`. Snippets specific to the extension take precedence over those in
+/// `all.snippets`.
+pub(crate) fn load_snippets(ext: Option<&str>) -> Result<Vec<Snippet>, String> {
+    let home = env::var("HOME").unwrap();
+
+    load_snippets_from(&Path::new(&home).join(".ad/snippets"), ext)
+}
+
+fn load_snippets_from(dir: &Path, ext: Option<&str>) -> Result<Vec<Snippet>, String> {
+    let mut snippets = Vec::new();
+
+    for name in ext.into_iter().chain(Some("all")) {
+        let path = dir.join(format!("{name}.snippets"));
+        let s = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Unable to load snippets: {e}")),
+        };
+
+        match parse_snippets(&s) {
+            Ok(parsed) => snippets.extend(parsed),
+            Err(e) => return Err(format!("Invalid snippets in {}: {e}", path.display())),
+        }
+    }
+
+    Ok(snippets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_test_case::test_case;
+
+    const EXAMPLE_SNIPPETS: &str = include_str!("../data/snippets/rs.snippets");
+
+    fn snippet(body: &str) -> Snippet {
+        Snippet {
+            trigger: "t".to_string(),
+            description: String::new(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn example_snippets_parse() {
+        let snippets = parse_snippets(EXAMPLE_SNIPPETS).unwrap();
+        assert!(snippets.iter().any(|s| s.trigger == "test"));
+    }
+
+    #[test]
+    fn parse_snippets_works() {
+        let s = "\
+# a comment
+snippet fn a function
+\tfn $1() {
+\t\t$0
+\t}
+
+snippet p
+\tprintln!(\"$1\");
+
+
+# trailing comment
+";
+        let snippets = parse_snippets(s).unwrap();
+
+        assert_eq!(
+            snippets,
+            vec![
+                Snippet {
+                    trigger: "fn".to_string(),
+                    description: "a function".to_string(),
+                    body: "fn $1() {\n\t$0\n}".to_string(),
+                },
+                Snippet {
+                    trigger: "p".to_string(),
+                    description: String::new(),
+                    body: "println!(\"$1\");".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test_case("\tbody without trigger"; "body without trigger")]
+    #[test_case("snippet a\n\tok\nnot indented"; "unindented body")]
+    #[test_case("snippet a\n\tok\n# comment\n\tmore"; "body after comment")]
+    #[test]
+    fn invalid_snippets_are_rejected(s: &str) {
+        assert!(parse_snippets(s).is_err());
+    }
+
+    #[test_case("plain text", "plain text", vec![vec![(10, 10)]]; "no tabstops")]
+    #[test_case("a$1b$0c", "abc", vec![vec![(1, 1)], vec![(2, 2)]]; "simple tabstops")]
+    #[test_case("$2 then $1", " then ", vec![vec![(6, 6)], vec![(0, 0)], vec![(6, 6)]]; "visited in order")]
+    #[test_case("${1:foo} = ${2:bar};", "foo = bar;", vec![vec![(0, 3)], vec![(6, 9)], vec![(10, 10)]]; "defaults")]
+    #[test_case("\\$1 costs \\${2}", "$1 costs ${2}", vec![vec![(13, 13)]]; "escaped")]
+    #[test_case("${1:a\\}b} $", "a}b $", vec![vec![(0, 3)], vec![(5, 5)]]; "escaped in default")]
+    #[test_case("${1:unclosed", "${1:unclosed", vec![vec![(12, 12)]]; "unclosed default")]
+    #[test]
+    fn expand_works(body: &str, text: &str, stops: Vec<Vec<(usize, usize)>>) {
+        let exp = snippet(body).expand("foo.rs", "", "    ");
+
+        assert_eq!(exp.text, text);
+        assert_eq!(exp.stops, stops);
+    }
+
+    #[test]
+    fn mirrors_share_the_default_of_the_tabstop() {
+        let exp = snippet("<${1:div}>$0</$1>").expand("foo.rs", "", "    ");
+
+        assert_eq!(exp.text, "<div></div>");
+        assert_eq!(exp.stops, vec![vec![(1, 4), (7, 10)], vec![(5, 5)]]);
+    }
+
+    #[test]
+    fn expansion_is_indented_for_the_buffer() {
+        let exp = snippet("fn $1() {\n\t// $FILENAME\n\n\t$0\n}").expand("foo.rs", "  ", "    ");
+
+        assert_eq!(exp.text, "fn () {\n      // foo.rs\n\n      \n  }");
+        assert_eq!(exp.stops, vec![vec![(3, 3)], vec![(31, 31)]]);
+    }
+
+    #[test]
+    fn missing_snippet_files_are_skipped() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/snippets");
+        let snippets = load_snippets_from(&dir, Some("rs")).unwrap();
+
+        assert!(snippets.iter().any(|s| s.trigger == "test"));
+        assert!(load_snippets_from(&dir, Some("missing"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -54,6 +54,15 @@ where
         }
     }
 
+    /// Add additional mappings to an existing Trie.
+    ///
+    /// Will panic under the same conditions as [Trie::from_pairs].
+    pub fn extend(&mut self, pairs: Vec<(Vec<K>, V)>) {
+        for (k, v) in pairs.into_iter() {
+            insert(k, v, &mut self.roots)
+        }
+    }
+
     /// Set the default handler for unmatched single element keys
     pub fn set_default(&mut self, default: DefaultMapping<K, V>) {
         self.default = Some(default);
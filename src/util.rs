@@ -65,6 +65,32 @@ pub(crate) fn relative_path_from(base: &Path, p: &Path) -> PathBuf {
     comps.iter().collect()
 }
 
+/// Split out the line of `s` containing the given character offset along with a second line
+/// that places a caret beneath the character at that offset. Tabs are preserved in the padding
+/// so that the caret stays aligned when the lines are rendered.
+pub(crate) fn caret_lines(s: &str, offset: usize) -> (String, String) {
+    let mut line_start = 0;
+    for (i, ch) in s.chars().enumerate().take(offset) {
+        if ch == '\n' {
+            line_start = i + 1;
+        }
+    }
+
+    let line: String = s
+        .chars()
+        .skip(line_start)
+        .take_while(|&ch| ch != '\n')
+        .collect();
+    let mut caret: String = line
+        .chars()
+        .take(offset - line_start)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    caret.push('^');
+
+    (line, caret)
+}
+
 // returns the parsed number and following character if there was one.
 // initial must be a valid ascii digit
 pub(crate) fn parse_num(initial: char, it: &mut Peekable<Chars<'_>>) -> usize {
@@ -105,4 +131,15 @@ mod tests {
 
         assert_eq!(rel, PathBuf::from(expected));
     }
+
+    #[test_case("x/a(/ d", 3, "x/a(/ d", "   ^"; "single line")]
+    #[test_case("x/a/ {\n\tc/b(/\n}", 12, "\tc/b(/", "\t    ^"; "multi line with tab")]
+    #[test_case("abc", 3, "abc", "   ^"; "end of input")]
+    #[test]
+    fn caret_lines_works(s: &str, offset: usize, line: &str, caret: &str) {
+        assert_eq!(
+            caret_lines(s, offset),
+            (line.to_string(), caret.to_string())
+        );
+    }
 }